@@ -0,0 +1 @@
+ALTER TABLE metadata ADD COLUMN content_hash TEXT;
@@ -0,0 +1,11 @@
+CREATE TABLE metadata_old (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT NOT NULL,
+    content_hash TEXT
+);
+
+INSERT INTO metadata_old (id, file_path, content_hash)
+SELECT id, file_path, content_hash FROM metadata;
+
+DROP TABLE metadata;
+ALTER TABLE metadata_old RENAME TO metadata;
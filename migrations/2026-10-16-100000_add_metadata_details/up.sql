@@ -0,0 +1,17 @@
+-- Rebuild the table instead of ALTER TABLE ... ADD COLUMN, which SQLite cannot make
+-- conditional, so this also applies to databases that already have some of the columns.
+CREATE TABLE metadata_new (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT NOT NULL,
+    content_hash TEXT,
+    language TEXT,
+    size_bytes BIGINT,
+    modified_at BIGINT,
+    title TEXT
+);
+
+INSERT INTO metadata_new (id, file_path, content_hash)
+SELECT id, file_path, content_hash FROM metadata;
+
+DROP TABLE metadata;
+ALTER TABLE metadata_new RENAME TO metadata;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS html_content;
+DROP TABLE IF EXISTS html_metadata;
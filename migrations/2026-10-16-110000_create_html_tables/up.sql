@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS html_metadata (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT NOT NULL,
+    content_hash TEXT
+);
+
+CREATE TABLE IF NOT EXISTS html_content (
+    id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    FOREIGN KEY (id) REFERENCES html_metadata(id) ON DELETE CASCADE
+);
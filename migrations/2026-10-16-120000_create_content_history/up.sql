@@ -0,0 +1,10 @@
+CREATE TABLE content_history (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    metadata_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    saved_at BIGINT NOT NULL,
+    FOREIGN KEY (metadata_id) REFERENCES metadata(id) ON DELETE CASCADE
+);
+
+CREATE INDEX content_history_metadata_id ON content_history (metadata_id);
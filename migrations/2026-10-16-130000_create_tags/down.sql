@@ -0,0 +1,3 @@
+DROP TRIGGER metadata_delete_file_tags;
+DROP TABLE file_tags;
+DROP TABLE tags;
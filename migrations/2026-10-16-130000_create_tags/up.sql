@@ -0,0 +1,20 @@
+CREATE TABLE tags (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+-- `source` is 'front_matter' for tags read from the file on save, 'manual' for `lila tag add`.
+CREATE TABLE file_tags (
+    metadata_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    source TEXT NOT NULL,
+    PRIMARY KEY (metadata_id, tag_id),
+    FOREIGN KEY (metadata_id) REFERENCES metadata(id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+);
+
+-- Foreign keys are not enforced unless enabled per connection, so clean up explicitly.
+CREATE TRIGGER metadata_delete_file_tags AFTER DELETE ON metadata
+BEGIN
+    DELETE FROM file_tags WHERE metadata_id = OLD.id;
+END;
@@ -0,0 +1,21 @@
+CREATE TABLE file_content_old (
+    id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    FOREIGN KEY (id) REFERENCES metadata(id) ON DELETE CASCADE
+);
+
+INSERT INTO file_content_old (id, content) SELECT id, content FROM file_content;
+
+DROP TABLE file_content;
+ALTER TABLE file_content_old RENAME TO file_content;
+
+CREATE TABLE html_content_old (
+    id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    FOREIGN KEY (id) REFERENCES html_metadata(id) ON DELETE CASCADE
+);
+
+INSERT INTO html_content_old (id, content) SELECT id, content FROM html_content;
+
+DROP TABLE html_content;
+ALTER TABLE html_content_old RENAME TO html_content;
@@ -0,0 +1,24 @@
+-- SQLite cannot change a column's constraints in place, so the content tables are rebuilt
+-- with `id` as their primary key referencing the metadata row.
+-- Only the latest content per id is kept, and none whose metadata row is gone.
+CREATE TABLE file_content_new (
+    id INTEGER NOT NULL PRIMARY KEY REFERENCES metadata(id) ON DELETE CASCADE,
+    content TEXT NOT NULL
+);
+
+INSERT OR REPLACE INTO file_content_new (id, content)
+SELECT id, content FROM file_content WHERE id IN (SELECT id FROM metadata) ORDER BY rowid;
+
+DROP TABLE file_content;
+ALTER TABLE file_content_new RENAME TO file_content;
+
+CREATE TABLE html_content_new (
+    id INTEGER NOT NULL PRIMARY KEY REFERENCES html_metadata(id) ON DELETE CASCADE,
+    content TEXT NOT NULL
+);
+
+INSERT OR REPLACE INTO html_content_new (id, content)
+SELECT id, content FROM html_content WHERE id IN (SELECT id FROM html_metadata) ORDER BY rowid;
+
+DROP TABLE html_content;
+ALTER TABLE html_content_new RENAME TO html_content;
@@ -0,0 +1,22 @@
+CREATE TABLE metadata_old (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT NOT NULL,
+    content_hash TEXT,
+    language TEXT,
+    size_bytes BIGINT,
+    modified_at BIGINT,
+    title TEXT
+);
+
+INSERT INTO metadata_old (id, file_path, content_hash, language, size_bytes, modified_at, title)
+SELECT id, file_path, content_hash, language, size_bytes, modified_at, title FROM metadata;
+
+DROP TABLE metadata;
+ALTER TABLE metadata_old RENAME TO metadata;
+
+CREATE TRIGGER metadata_delete_file_tags AFTER DELETE ON metadata
+BEGIN
+    DELETE FROM file_tags WHERE metadata_id = OLD.id;
+END;
+
+DROP TABLE projects;
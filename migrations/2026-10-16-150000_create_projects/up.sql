@@ -0,0 +1,10 @@
+CREATE TABLE projects (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+-- Rows saved before projects existed are assigned, as this migration is applied, to the
+-- project derived from the database's location, see `utils::database::db`.
+ALTER TABLE metadata ADD COLUMN project_id INTEGER REFERENCES projects(id);
+
+CREATE INDEX metadata_project_id ON metadata (project_id);
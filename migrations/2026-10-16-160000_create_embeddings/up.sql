@@ -0,0 +1,15 @@
+-- One row per chunk of a saved file: its byte range in the content, the heading it is
+-- under, and its vector as little-endian f32s. `content_hash` is the hash of the content
+-- that was chunked, so unchanged files are not embedded again.
+CREATE TABLE embeddings (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    metadata_id INTEGER NOT NULL REFERENCES metadata(id) ON DELETE CASCADE,
+    chunk_start INTEGER NOT NULL,
+    chunk_end INTEGER NOT NULL,
+    heading TEXT,
+    content_hash TEXT NOT NULL,
+    model TEXT NOT NULL,
+    vector BLOB NOT NULL
+);
+
+CREATE INDEX embeddings_metadata_id ON embeddings (metadata_id);
@@ -0,0 +1,13 @@
+DROP TRIGGER metadata_update_blob;
+DROP TRIGGER metadata_delete_blob;
+DROP INDEX metadata_content_hash;
+
+CREATE TABLE file_content (
+    id INTEGER NOT NULL PRIMARY KEY REFERENCES metadata(id) ON DELETE CASCADE,
+    content TEXT NOT NULL
+);
+
+INSERT INTO file_content (id, content)
+SELECT m.id, b.content FROM metadata m JOIN blobs b ON b.hash = m.content_hash;
+
+DROP TABLE blobs;
@@ -0,0 +1,33 @@
+-- Markdown content is stored once per distinct content, in `blobs` keyed by its SHA-256,
+-- and `metadata.content_hash` says which blob a file has.
+-- Rows saved before hashes were recorded are hashed first with `lila_sha256`, which
+-- lila registers on the connection before it migrates.
+UPDATE metadata
+SET content_hash = lila_sha256((SELECT content FROM file_content WHERE file_content.id = metadata.id))
+WHERE content_hash IS NULL AND id IN (SELECT id FROM file_content);
+
+CREATE TABLE blobs (
+    hash TEXT NOT NULL PRIMARY KEY,
+    content TEXT NOT NULL
+);
+
+INSERT OR IGNORE INTO blobs (hash, content)
+SELECT m.content_hash, c.content FROM metadata m JOIN file_content c ON c.id = m.id;
+
+DROP TABLE file_content;
+
+CREATE INDEX metadata_content_hash ON metadata (content_hash);
+
+-- A blob goes when the last file with that content is deleted or changes.
+CREATE TRIGGER metadata_delete_blob AFTER DELETE ON metadata
+BEGIN
+    DELETE FROM blobs WHERE hash = OLD.content_hash
+        AND NOT EXISTS (SELECT 1 FROM metadata WHERE content_hash = OLD.content_hash);
+END;
+
+CREATE TRIGGER metadata_update_blob AFTER UPDATE OF content_hash ON metadata
+WHEN OLD.content_hash IS NOT NEW.content_hash
+BEGIN
+    DELETE FROM blobs WHERE hash = OLD.content_hash
+        AND NOT EXISTS (SELECT 1 FROM metadata WHERE content_hash = OLD.content_hash);
+END;
@@ -0,0 +1,11 @@
+-- Local images referenced by saved Markdown, so pages served from the database keep
+-- them. `file_path` is stored like `metadata.file_path`, relative to the doc folder.
+CREATE TABLE assets (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    file_path TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    content BLOB NOT NULL,
+    UNIQUE (project_id, file_path)
+);
@@ -0,0 +1,17 @@
+-- Conversations with the chat server. Each answered prompt adds a `user` message and the
+-- `assistant` message answering it, so later prompts of the session are asked after them.
+CREATE TABLE chat_sessions (
+    id TEXT NOT NULL PRIMARY KEY,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE chat_messages (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    session_id TEXT NOT NULL REFERENCES chat_sessions(id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX chat_messages_session_id ON chat_messages (session_id);
@@ -1,3 +1,4 @@
+use crate::utils::backup::Backup;
 use colored::Colorize;
 use regex::Regex;
 use std::fs::{self, File};
@@ -21,12 +22,15 @@ fn copy_dir_all(src: &Path, dst: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// A placeholder, `@{file}` or `@{file:identifier}`, with what is between the braces.
+const PLACEHOLDER: &str = r"@\{([^}]+)\}";
+
 /// Inline placeholders in a Markdown file.
 fn inline_placeholders_in_file(file_path: &Path) -> io::Result<()> {
     let content = fs::read_to_string(file_path)?;
     let parent = file_path.parent().unwrap_or_else(|| Path::new(""));
 
-    let re = Regex::new(r"@\{([^}]+)\}").unwrap();
+    let re = Regex::new(PLACEHOLDER).unwrap();
 
     let new_content = re.replace_all(&content, |caps: &regex::Captures| {
         let referenced = caps.get(1).map(|m| m.as_str()).unwrap_or("");
@@ -73,6 +77,66 @@ fn inline_placeholders_in_file(file_path: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// `text` with each placeholder replaced by a fenced block of what it names, the definition
+/// of `identifier` in `file` or the whole file, with paths relative to `root`, and how many
+/// there were. A placeholder naming a missing file or definition is an error, as no
+/// placeholder is left in place.
+pub fn resolve_placeholders(text: &str, root: &Path) -> Result<(String, usize), String> {
+    let re = Regex::new(PLACEHOLDER).unwrap();
+    let mut resolved = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut count = 0;
+    for caps in re.captures_iter(text) {
+        let placeholder = caps.get(0).unwrap();
+        let referenced = &caps[1];
+        let (file_name, identifier) = match referenced.split_once(':') {
+            Some((file_name, identifier)) => (file_name.trim(), Some(identifier.trim())),
+            None => (referenced.trim(), None),
+        };
+        let path = root.join(file_name);
+        if !path.is_file() {
+            return Err(format!(
+                "cannot resolve {}: {} does not exist",
+                placeholder.as_str(),
+                path.display()
+            ));
+        }
+        let unreadable = |e: io::Error| {
+            format!(
+                "cannot resolve {}: cannot read {}: {}",
+                placeholder.as_str(),
+                path.display(),
+                e
+            )
+        };
+        let code = match identifier {
+            Some(identifier) => extract_definition_from_file(&path, identifier)
+                .map_err(unreadable)?
+                .ok_or_else(|| {
+                    format!(
+                        "cannot resolve {}: no definition of {} found in {}",
+                        placeholder.as_str(),
+                        identifier,
+                        path.display()
+                    )
+                })?,
+            None => fs::read_to_string(&path).map_err(unreadable)?,
+        };
+        let ext = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let lang = infer_language_from_extension(&ext).unwrap_or("");
+        resolved.push_str(&text[last..placeholder.start()]);
+        resolved.push_str(&format!("\n\n```{}\n{}\n```\n\n", lang, code.trim_end()));
+        last = placeholder.end();
+        count += 1;
+    }
+    resolved.push_str(&text[last..]);
+    Ok((resolved, count))
+}
+
 /// Recursively inlines placeholders in all Markdown files in the given folder.
 pub fn inline_placeholders_in_readmes_in_folder(folder: &Path) -> io::Result<()> {
     for entry in fs::read_dir(folder)? {
@@ -92,19 +156,24 @@ pub fn inline_placeholders_in_readmes_in_folder(folder: &Path) -> io::Result<()>
 }
 
 /// Recursively copies only Markdown files from the source folder to the destination folder,
-/// preserving the directory structure.
-pub fn copy_markdown_files(src: &Path, dst: &Path) -> io::Result<()> {
+/// preserving the directory structure. Existing files that would change are saved to `backup` first.
+pub fn copy_markdown_files(src: &Path, dst: &Path, backup: Option<&Backup>) -> io::Result<()> {
     fs::create_dir_all(dst)?;
     for entry in fs::read_dir(src)? {
         let entry = entry?;
         let path = entry.path();
         if path.is_dir() {
             let sub_dst = dst.join(entry.file_name());
-            copy_markdown_files(&path, &sub_dst)?;
+            copy_markdown_files(&path, &sub_dst, backup)?;
         } else if path.is_file() {
             if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
                 if ext.eq_ignore_ascii_case("md") {
                     let dest_file = dst.join(entry.file_name());
+                    if let Some(backup) = backup {
+                        if dest_file.is_file() && fs::read(&dest_file)? != fs::read(&path)? {
+                            backup.save(&dest_file)?;
+                        }
+                    }
                     fs::copy(&path, &dest_file)?;
                     println!(
                         "{} Copied {} -> {}",
@@ -121,8 +190,13 @@ pub fn copy_markdown_files(src: &Path, dst: &Path) -> io::Result<()> {
 
 /// Processes book binding by first copying the input folder to a temporary folder,
 /// inlining placeholders in the temporary folder, and then copying only Markdown files
-/// to the final output folder. The original input folder remains untouched.
-pub fn process_bookbinding(input_folder: &str, output_folder: &str) -> io::Result<()> {
+/// to the final output folder. The original input folder remains untouched; Markdown files
+/// already in the output folder are saved to `backup` before being overwritten.
+pub fn process_bookbinding(
+    input_folder: &str,
+    output_folder: &str,
+    backup: Option<&Backup>,
+) -> io::Result<()> {
     let input_path = Path::new(input_folder);
     let output_path = Path::new(output_folder);
 
@@ -138,7 +212,7 @@ pub fn process_bookbinding(input_folder: &str, output_folder: &str) -> io::Resul
     inline_placeholders_in_readmes_in_folder(&temp_folder)?;
 
     // Copy only Markdown files from the temporary folder to the final output folder.
-    copy_markdown_files(&temp_folder, output_path)?;
+    copy_markdown_files(&temp_folder, output_path, backup)?;
 
     // Remove the temporary folder.
     fs::remove_dir_all(&temp_folder)?;
@@ -0,0 +1,497 @@
+use crate::schema::{blobs, html_content, html_metadata, metadata, projects};
+use crate::utils::database::blobs::{content_hash, store_blobs};
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::models::{FileDetails, HtmlMetadata, Metadata};
+use crate::utils::database::projects::project_id;
+use crate::utils::database::search::{ensure_search_index, index_file, SearchError};
+use crate::utils::utils::current_project_name;
+use colored::Colorize;
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel::sqlite::SqliteConnection;
+use serde::ser::{Error as _, SerializeSeq};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Version of the bundle layout written by `export_bundle`. Version 1 had no projects;
+/// its files are imported into the current project.
+const BUNDLE_VERSION: u32 = 2;
+
+/// What `import_bundle` does with a file that is already in the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OnConflict {
+    /// Keep the row in the database.
+    #[default]
+    Skip,
+    /// Replace the row with the one from the bundle.
+    Overwrite,
+}
+
+/// A saved Markdown file: its `metadata` row without the ids, the name of its project, and
+/// its content.
+#[derive(Serialize, Deserialize)]
+struct FileEntry {
+    /// `None` for files saved before projects existed, and in version 1 bundles.
+    #[serde(default)]
+    project: Option<String>,
+    file_path: String,
+    content_hash: Option<String>,
+    language: Option<String>,
+    size_bytes: Option<i64>,
+    modified_at: Option<i64>,
+    title: Option<String>,
+    content: String,
+}
+
+/// A saved HTML page.
+#[derive(Serialize, Deserialize)]
+struct HtmlEntry {
+    file_path: String,
+    content_hash: Option<String>,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct Bundle {
+    version: u32,
+    files: Vec<FileEntry>,
+    #[serde(default)]
+    html_files: Vec<HtmlEntry>,
+}
+
+#[derive(Serialize)]
+struct BundleOut<F, H> {
+    version: u32,
+    files: F,
+    html_files: H,
+}
+
+/// Rows serialized one at a time, so only one file's content is in memory while exporting.
+struct Rows<'a, 'c, L> {
+    conn: &'a RefCell<&'c mut SqliteConnection>,
+    ids: Vec<i32>,
+    load: L,
+}
+
+impl<T, L> Serialize for Rows<'_, '_, L>
+where
+    T: Serialize,
+    L: Fn(&mut SqliteConnection, i32) -> QueryResult<T>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.ids.len()))?;
+        for id in &self.ids {
+            let row = (self.load)(&mut self.conn.borrow_mut(), *id).map_err(S::Error::custom)?;
+            seq.serialize_element(&row)?;
+        }
+        seq.end()
+    }
+}
+
+/// Writes the Markdown and HTML saved in the database at `db_path`, of every project, as
+/// JSON to `output`, or to stdout. Files are sorted by project and path and carry no row
+/// ids, so the same content always gives the same document.
+pub fn export_bundle(db_path: &Path, output: Option<&Path>) -> io::Result<()> {
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+
+    let file_ids: Vec<i32> = metadata::table
+        .left_join(projects::table)
+        .order((
+            projects::name.asc(),
+            metadata::file_path.asc(),
+            metadata::id.asc(),
+        ))
+        .select(metadata::id)
+        .load(&mut conn)
+        .map_err(io::Error::other)?;
+    let html_ids: Vec<i32> = html_metadata::table
+        .order((html_metadata::file_path.asc(), html_metadata::id.asc()))
+        .select(html_metadata::id)
+        .load(&mut conn)
+        .map_err(io::Error::other)?;
+    let (file_count, html_count) = (file_ids.len(), html_ids.len());
+
+    let conn = RefCell::new(&mut conn);
+    let bundle = BundleOut {
+        version: BUNDLE_VERSION,
+        files: Rows {
+            conn: &conn,
+            ids: file_ids,
+            load: load_file,
+        },
+        html_files: Rows {
+            conn: &conn,
+            ids: html_ids,
+            load: load_html,
+        },
+    };
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    serde_json::to_writer_pretty(&mut writer, &bundle).map_err(io::Error::other)?;
+    writeln!(writer)?;
+    writer.flush()?;
+
+    if let Some(path) = output {
+        println!(
+            "{} {} Markdown and {} HTML files to {}",
+            "Exported".green(),
+            file_count,
+            html_count,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+fn load_file(conn: &mut SqliteConnection, id: i32) -> QueryResult<FileEntry> {
+    let record: Metadata = metadata::table.find(id).first(conn)?;
+    let content: String = blobs::table
+        .filter(blobs::hash.nullable().eq(&record.content_hash))
+        .select(blobs::content)
+        .first(conn)?;
+    let project = match record.project_id {
+        Some(id) => Some(
+            projects::table
+                .find(id)
+                .select(projects::name)
+                .first(conn)?,
+        ),
+        None => None,
+    };
+    Ok(FileEntry {
+        project,
+        file_path: record.file_path,
+        content_hash: record.content_hash,
+        language: record.language,
+        size_bytes: record.size_bytes,
+        modified_at: record.modified_at,
+        title: record.title,
+        content,
+    })
+}
+
+fn load_html(conn: &mut SqliteConnection, id: i32) -> QueryResult<HtmlEntry> {
+    let record: HtmlMetadata = html_metadata::table.find(id).first(conn)?;
+    let content: String = html_content::table
+        .filter(html_content::id.eq(id))
+        .select(html_content::content)
+        .first(conn)?;
+    Ok(HtmlEntry {
+        file_path: record.file_path,
+        content_hash: record.content_hash,
+        content,
+    })
+}
+
+/// Counts of what `import_bundle` did.
+#[derive(Debug, Default)]
+struct ImportSummary {
+    inserted: usize,
+    overwritten: usize,
+    skipped: usize,
+}
+
+/// Recreates the rows of the bundle at `input` in the database at `db_path`, creating it
+/// and the projects of its files if needed. Files already in the database, in the same
+/// project, are kept or replaced as `on_conflict` says.
+pub fn import_bundle(db_path: &Path, input: &Path, on_conflict: OnConflict) -> io::Result<()> {
+    let bundle: Bundle =
+        serde_json::from_reader(BufReader::new(File::open(input)?)).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a lila bundle: {}", input.display(), e),
+            )
+        })?;
+    if !(1..=BUNDLE_VERSION).contains(&bundle.version) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has bundle version {}, this lila reads versions up to {}",
+                input.display(),
+                bundle.version,
+                BUNDLE_VERSION
+            ),
+        ));
+    }
+
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    let indexed = match ensure_search_index(&mut conn) {
+        Ok(()) => true,
+        Err(SearchError::FtsUnavailable) => {
+            eprintln!("Warning: {}", SearchError::FtsUnavailable);
+            false
+        }
+        Err(e) => return Err(io::Error::other(e)),
+    };
+
+    let mut summary = ImportSummary::default();
+    conn.transaction::<(), Error, _>(|conn| {
+        let mut project_ids: HashMap<String, i32> = HashMap::new();
+        for entry in &bundle.files {
+            let name = entry.project.clone().unwrap_or_else(current_project_name);
+            let project = match project_ids.get(&name) {
+                Some(id) => *id,
+                None => {
+                    let id = project_id(conn, &name)?;
+                    project_ids.insert(name, id);
+                    id
+                }
+            };
+            import_file(conn, entry, project, on_conflict, indexed, &mut summary)?;
+        }
+        for entry in &bundle.html_files {
+            import_html(conn, entry, on_conflict, &mut summary)?;
+        }
+        Ok(())
+    })
+    .map_err(io::Error::other)?;
+
+    println!(
+        "{} ({} inserted, {} overwritten, {} skipped)",
+        format!("Imported {}", input.display()).green(),
+        summary.inserted,
+        summary.overwritten,
+        summary.skipped
+    );
+    Ok(())
+}
+
+/// Imports `entry` into the project with the id `project`.
+fn import_file(
+    conn: &mut SqliteConnection,
+    entry: &FileEntry,
+    project: i32,
+    on_conflict: OnConflict,
+    indexed: bool,
+    summary: &mut ImportSummary,
+) -> QueryResult<()> {
+    use metadata::dsl as m;
+
+    // Bundles from databases that did not hash every file are hashed here.
+    let hash = entry
+        .content_hash
+        .clone()
+        .unwrap_or_else(|| content_hash(&entry.content));
+
+    let details = FileDetails {
+        language: entry.language.clone(),
+        size_bytes: entry.size_bytes,
+        modified_at: entry.modified_at,
+        title: entry.title.clone(),
+    };
+    let existing = m::metadata
+        .filter(m::project_id.eq(project))
+        .filter(m::file_path.eq(&entry.file_path))
+        .select(m::id)
+        .first::<i32>(conn)
+        .optional()?;
+
+    let id = match (existing, on_conflict) {
+        (Some(_), OnConflict::Skip) => {
+            summary.skipped += 1;
+            return Ok(());
+        }
+        (Some(id), OnConflict::Overwrite) => {
+            diesel::update(m::metadata.find(id))
+                .set((m::content_hash.eq(&hash), &details))
+                .execute(conn)?;
+            summary.overwritten += 1;
+            id
+        }
+        (None, _) => {
+            let id: i32 = diesel::insert_into(m::metadata)
+                .values((
+                    m::project_id.eq(project),
+                    m::file_path.eq(&entry.file_path),
+                    m::content_hash.eq(&hash),
+                    &details,
+                ))
+                .returning(m::id)
+                .get_result(conn)?;
+            summary.inserted += 1;
+            id
+        }
+    };
+    store_blobs(conn, &[(hash.as_str(), entry.content.as_str())])?;
+    if indexed {
+        index_file(conn, id, &entry.file_path, &entry.content)?;
+    }
+    Ok(())
+}
+
+fn import_html(
+    conn: &mut SqliteConnection,
+    entry: &HtmlEntry,
+    on_conflict: OnConflict,
+    summary: &mut ImportSummary,
+) -> QueryResult<()> {
+    use html_content::dsl as c;
+    use html_metadata::dsl as m;
+
+    let existing = m::html_metadata
+        .filter(m::file_path.eq(&entry.file_path))
+        .select(m::id)
+        .first::<i32>(conn)
+        .optional()?;
+
+    match (existing, on_conflict) {
+        (Some(_), OnConflict::Skip) => summary.skipped += 1,
+        (Some(id), OnConflict::Overwrite) => {
+            diesel::update(m::html_metadata.find(id))
+                .set(m::content_hash.eq(&entry.content_hash))
+                .execute(conn)?;
+            diesel::update(c::html_content.filter(c::id.eq(id)))
+                .set(c::content.eq(&entry.content))
+                .execute(conn)?;
+            summary.overwritten += 1;
+        }
+        (None, _) => {
+            let id: i32 = diesel::insert_into(m::html_metadata)
+                .values((
+                    m::file_path.eq(&entry.file_path),
+                    m::content_hash.eq(&entry.content_hash),
+                ))
+                .returning(m::id)
+                .get_result(conn)?;
+            diesel::insert_into(c::html_content)
+                .values((c::id.eq(id), c::content.eq(&entry.content)))
+                .execute(conn)?;
+            summary.inserted += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A migrated database at `path`.
+    fn database(path: &Path) -> SqliteConnection {
+        let mut conn = establish_connection(&path.to_string_lossy()).unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn
+    }
+
+    /// Saves `content` at `file_path` in the project called `project`.
+    fn save(conn: &mut SqliteConnection, project: &str, file_path: &str, content: &str) {
+        let project = project_id(conn, project).unwrap();
+        let hash = content_hash(content);
+        diesel::insert_into(metadata::table)
+            .values((
+                metadata::project_id.eq(project),
+                metadata::file_path.eq(file_path),
+                metadata::content_hash.eq(&hash),
+            ))
+            .execute(conn)
+            .unwrap();
+        store_blobs(conn, &[(hash.as_str(), content)]).unwrap();
+    }
+
+    /// The saved files as (project, path, content), sorted.
+    fn saved(conn: &mut SqliteConnection) -> Vec<(String, String, String)> {
+        let mut rows: Vec<(String, String, String)> = metadata::table
+            .inner_join(projects::table)
+            .inner_join(blobs::table)
+            .select((projects::name, metadata::file_path, blobs::content))
+            .load(conn)
+            .unwrap();
+        rows.sort();
+        rows
+    }
+
+    fn owned(rows: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+        rows.iter()
+            .map(|(a, b, c)| (a.to_string(), b.to_string(), c.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn files_of_two_projects_at_the_same_path_survive_a_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = (dir.path().join("source.db"), dir.path().join("target.db"));
+        let mut conn = database(&source);
+        save(&mut conn, "cookbook", "intro.md", "# Cooking\n");
+        save(&mut conn, "handbook", "intro.md", "# Handling\n");
+        save(&mut conn, "handbook", "tools.md", "# Tools\n");
+        let bundle = dir.path().join("book.json");
+        export_bundle(&source, Some(&bundle)).unwrap();
+
+        import_bundle(&target, &bundle, OnConflict::Skip).unwrap();
+        let expected = owned(&[
+            ("cookbook", "intro.md", "# Cooking\n"),
+            ("handbook", "intro.md", "# Handling\n"),
+            ("handbook", "tools.md", "# Tools\n"),
+        ]);
+        assert_eq!(saved(&mut database(&target)), expected);
+
+        // Importing again finds each file in its own project, whatever the conflict rule.
+        import_bundle(&target, &bundle, OnConflict::Overwrite).unwrap();
+        assert_eq!(saved(&mut database(&target)), expected);
+        let unassigned: i64 = metadata::table
+            .filter(metadata::project_id.is_null())
+            .count()
+            .get_result(&mut database(&target))
+            .unwrap();
+        assert_eq!(unassigned, 0);
+    }
+
+    #[test]
+    fn the_export_is_sorted_by_project_then_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.db");
+        let mut conn = database(&source);
+        save(&mut conn, "zoo", "a.md", "a\n");
+        save(&mut conn, "atlas", "b.md", "b\n");
+        save(&mut conn, "atlas", "a.md", "c\n");
+        let bundle = dir.path().join("book.json");
+        export_bundle(&source, Some(&bundle)).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&bundle).unwrap()).unwrap();
+        assert_eq!(json["version"], BUNDLE_VERSION);
+        let files: Vec<(&str, &str)> = json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| {
+                (
+                    file["project"].as_str().unwrap(),
+                    file["file_path"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [("atlas", "a.md"), ("atlas", "b.md"), ("zoo", "a.md")]
+        );
+    }
+
+    #[test]
+    fn a_version_1_bundle_is_imported_into_the_current_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("old.json");
+        std::fs::write(
+            &bundle,
+            r##"{"version": 1, "files": [{"file_path": "intro.md", "content_hash": null,
+                "language": null, "size_bytes": null, "modified_at": null, "title": null,
+                "content": "# Old\n"}]}"##,
+        )
+        .unwrap();
+        let target = dir.path().join("target.db");
+        import_bundle(&target, &bundle, OnConflict::Skip).unwrap();
+        assert_eq!(
+            saved(&mut database(&target)),
+            owned(&[(&current_project_name(), "intro.md", "# Old\n")])
+        );
+    }
+}
@@ -0,0 +1,1099 @@
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressFinish, ProgressStyle};
+use regex::{Regex, RegexBuilder};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task;
+use walkdir::WalkDir;
+
+use crate::commands::bookbinding::resolve_placeholders;
+use crate::commands::edit::assist::BlockEdit;
+use crate::commands::history::print_diff;
+use crate::commands::list::glob_to_regex;
+use crate::server::backend::{Backend, ChatModel, Isq};
+use crate::server::chat::{assemble_conversation, generate, Answer, ChatArgs, Conversation, Turn};
+use crate::server::context::{
+    configured_context_tokens, documents_context, Retriever, Source, DEFAULT_CONTEXT_TOKENS,
+};
+use crate::server::daemon::{self, DaemonClient};
+use crate::server::generation::GenerationParams;
+use crate::server::gguf::is_gguf_path;
+use crate::server::models::{context_length, token_counter, ModelConfig};
+use crate::server::sessions::{history_tokens, trim_history};
+use crate::utils::backup::Backup;
+use crate::utils::config::Config;
+use crate::utils::database::documents::document_ids_under;
+use crate::utils::database::pool::open_pool;
+use crate::utils::database::projects::ProjectScope;
+
+/// Settings for a `lila chat` run.
+#[derive(Debug)]
+pub struct ChatOptions {
+    /// Question asked first; required unless `interactive`.
+    pub prompt: Option<String>,
+    /// Folder the files of `@{file:identifier}` placeholders in questions are found in.
+    pub root: PathBuf,
+    /// Markdown files, or globs matching them, given to the model as the context instead
+    /// of the saved files.
+    pub files: Vec<String>,
+    /// Model to answer with (default: the server's default model).
+    pub model: Option<String>,
+    /// How to quantize a local model (default: as configured for the server).
+    pub isq: Option<Isq>,
+    /// How to sample the answers, over `[ai.generation]` in `Lila.toml`.
+    pub generation: GenerationParams,
+    /// System message replacing the one built from `Lila.toml`.
+    pub system: Option<String>,
+    /// Database the context is read from; no saved files are used without it.
+    pub db_path: Option<PathBuf>,
+    /// Give the saved files matching all of these case-insensitive regular expressions as
+    /// the context, instead of the parts retrieved for the prompt.
+    pub grep: Vec<String>,
+    /// Give those matching any of `grep` instead.
+    pub grep_any: bool,
+    /// Give the saved files whose paths start with any of these as the context, instead
+    /// of the parts retrieved for the prompt.
+    pub scope: Vec<String>,
+    /// Where the embedding model of retrieval is kept.
+    pub model_dir: PathBuf,
+    /// Tokens of the book given as context (default: see `context_budget`).
+    pub context_tokens: Option<usize>,
+    /// Print the context budget and the parts of the book that fit in it.
+    pub verbose: bool,
+    /// Show neither the spinner before the answer nor its statistics after it.
+    pub quiet: bool,
+    /// How the answer is written to stdout.
+    pub output: ChatOutput,
+    /// Keep asking questions read from the terminal, with the earlier ones as history.
+    pub interactive: bool,
+    /// Answer through the chat daemon when one runs with the model.
+    pub use_daemon: bool,
+    /// Where the lines typed in interactive mode are kept between runs.
+    pub history_file: PathBuf,
+    /// Markdown file whose code block the prompt asks the model to rewrite, instead of
+    /// answering it.
+    pub edit: Option<PathBuf>,
+    /// The block of `edit` to rewrite: its number, from 1, or an identifier it defines.
+    pub block: Option<String>,
+    /// Write the rewritten block without asking.
+    pub yes: bool,
+    /// Print the tokens of each part of the question instead of asking it.
+    pub count_only: bool,
+}
+
+/// How `lila chat` writes the answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ChatOutput {
+    /// Streamed as it is generated, then its sources.
+    #[default]
+    Text,
+    /// One JSON object once the answer is complete, errors included.
+    Json,
+}
+
+/// What `lila chat --daemon` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DaemonAction {
+    /// Load the model in a background process, returning once it answers.
+    Start,
+    /// Stop the running daemon.
+    Stop,
+    /// Print whether a daemon runs, with which model, and for how long it has been idle.
+    Status,
+    /// Run the daemon in the foreground, until Ctrl-C.
+    Run,
+}
+
+/// The answer to a question, as `--output json` prints it.
+#[derive(Debug, Serialize)]
+struct ChatReport<'a> {
+    prompt: &'a str,
+    response: &'a str,
+    model: &'a str,
+    generation: &'a GenerationParams,
+    usage: TokenUsage,
+    sources: &'a [Source],
+    elapsed_ms: u128,
+}
+
+/// Tokens of an answer, `null` where the model does not report them.
+#[derive(Debug, Serialize)]
+struct TokenUsage {
+    prompt_tokens: Option<usize>,
+    completion_tokens: Option<usize>,
+}
+
+/// A chat in the terminal: the model, loaded once, and how each question gets its context.
+struct Chat {
+    /// Not loaded to only count the tokens of a question.
+    model: Option<ChatModel>,
+    model_id: String,
+    /// The model's context window in tokens, when known; questions are cut to fit in it.
+    context_length: Option<usize>,
+    /// Files given with `--file` or `/context`, by path, replacing the saved files.
+    files: Vec<(String, String)>,
+    db_path: Option<PathBuf>,
+    /// Patterns the saved files given as context must match, from `--grep`.
+    grep: Vec<Regex>,
+    grep_any: bool,
+    /// Path prefixes of the saved files given as context, from `--scope`.
+    scope: Vec<String>,
+    retriever: Option<Retriever>,
+    generation: GenerationParams,
+    /// The whole system message, from `--system`, instead of the one built from `Lila.toml`.
+    system_prompt: Option<String>,
+    /// Folder the files of placeholders in questions are found in.
+    root: PathBuf,
+    budget: usize,
+    budget_from: String,
+    verbose: bool,
+    /// Show a spinner until the answer starts and its statistics once it ends, on stderr.
+    progress: bool,
+    /// Write the answer to stdout as it is generated, then its sources.
+    stream: bool,
+}
+
+/// Asks the prompt of `options` the way the server's `/chat` does, with the settings of
+/// `config`, and writes the answer to stdout as it is generated, then its sources. With
+/// `interactive`, keeps asking the questions typed after it. With `edit`, the prompt
+/// says how to rewrite a code block of that file instead.
+pub async fn run_chat(options: ChatOptions, config: &Config) -> Result<(), String> {
+    // Resolve placeholders before the model loads, so a wrong one fails fast.
+    let prompt = match &options.prompt {
+        Some(prompt) => Some(expand_question(prompt, &options.root)?),
+        None => None,
+    };
+    let edit = match &options.edit {
+        Some(path) => Some(BlockEdit::select(path, options.block.as_deref())?),
+        None => None,
+    };
+    let model_id = match options.model {
+        Some(id) => id,
+        None => ModelConfig::load(config)?.default,
+    };
+    let (budget, budget_from) = context_budget(options.context_tokens, &model_id, config)?;
+    // A block is rewritten with the prose around it as the only context.
+    let (files, db_path) = match &edit {
+        Some(edit) => (
+            vec![(edit.path().display().to_string(), edit.context())],
+            None,
+        ),
+        None => (read_files(&expand_files(&options.files)?)?, options.db_path),
+    };
+    let grep = options
+        .grep
+        .iter()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| format!("invalid --grep pattern {:?}: {}", pattern, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let retriever = match &db_path {
+        Some(_) => Some(Retriever::new(options.model_dir, budget)),
+        None => None,
+    };
+    if let (Some(db_path), true) = (&db_path, files.is_empty()) {
+        if !has_saved_documents(db_path)? {
+            eprintln!(
+                "{} no saved documents in {}; run `lila weave && lila save` to give the book as context",
+                "Warning:".yellow(),
+                db_path.display()
+            );
+        }
+    }
+    options.generation.validate_flags()?;
+    let generation = options.generation.or(&GenerationParams::load(config)?);
+    let history_budget = history_tokens(config)?;
+
+    let mut backend = Backend::load(config)?;
+    if let Some(isq) = options.isq {
+        backend.override_isq(isq);
+    }
+    if let (Backend::Local { .. }, Some(_)) = (&backend, generation.seed) {
+        eprintln!(
+            "{} the local backend has no seed; a temperature of 0 makes its answers reproducible",
+            "Warning:".yellow()
+        );
+    }
+    let daemon = match options.use_daemon && !options.count_only {
+        true => daemon_model(&model_id).await,
+        false => None,
+    };
+    let model = match daemon {
+        Some(model) => Some(model),
+        None if options.count_only => None,
+        None => Some(load_model(&backend, &model_id).await?),
+    };
+    let streamed = options.output == ChatOutput::Text && edit.is_none();
+    let mut chat = Chat {
+        model,
+        context_length: context_length(&model_id),
+        model_id,
+        files,
+        db_path,
+        grep,
+        grep_any: options.grep_any,
+        scope: options
+            .scope
+            .iter()
+            .map(|path| path.trim_start_matches("./").to_string())
+            .collect(),
+        retriever,
+        generation,
+        system_prompt: options.system,
+        root: options.root,
+        budget,
+        budget_from,
+        verbose: options.verbose,
+        progress: streamed && !options.quiet && io::stderr().is_terminal(),
+        stream: streamed,
+    };
+    if options.interactive {
+        return repl(&mut chat, prompt, history_budget, &options.history_file).await;
+    }
+    let prompt = prompt.ok_or("no prompt provided")?;
+    if options.count_only {
+        let question = match &edit {
+            Some(edit) => edit.prompt(&prompt),
+            None => prompt,
+        };
+        let conversation = chat.conversation(&question, &[]).await?;
+        let tokens = conversation.tokens(&token_counter(&chat.model_id), chat.context_length);
+        match options.output {
+            ChatOutput::Text => println!("{}", tokens),
+            ChatOutput::Json => {
+                let json = serde_json::to_string_pretty(&tokens).map_err(|e| e.to_string())?;
+                println!("{}", json);
+            }
+        }
+        return Ok(());
+    }
+    if let Some(edit) = edit {
+        let progress = !options.quiet && io::stderr().is_terminal();
+        return edit_block(&chat, &edit, &prompt, options.yes, progress).await;
+    }
+    let started = Instant::now();
+    let (answer, sources) = chat.ask(&prompt, &[], &AtomicBool::new(false)).await?;
+    if options.output == ChatOutput::Json {
+        let report = ChatReport {
+            prompt: options.prompt.as_deref().unwrap_or(&prompt),
+            response: &answer.text,
+            model: &chat.model_id,
+            generation: &chat.generation,
+            usage: TokenUsage {
+                prompt_tokens: answer.prompt_tokens,
+                completion_tokens: answer.tokens,
+            },
+            sources: &sources,
+            elapsed_ms: started.elapsed().as_millis(),
+        };
+        let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    }
+    Ok(())
+}
+
+/// Asks the model of `chat` to rewrite the block of `edit` as `instruction` says, prints
+/// the change as a diff and writes it once confirmed, or right away with `yes`. The
+/// original is kept beside the file as a backup.
+async fn edit_block(
+    chat: &Chat,
+    edit: &BlockEdit,
+    instruction: &str,
+    yes: bool,
+    progress: bool,
+) -> Result<(), String> {
+    let spinner = progress.then(|| {
+        let spinner = waiting_spinner();
+        spinner.set_message("waiting for the new code");
+        spinner
+    });
+    let answer = chat
+        .ask(&edit.prompt(instruction), &[], &AtomicBool::new(false))
+        .await;
+    drop(spinner);
+    let (answer, _) = answer?;
+    let content = edit.apply(&answer.text)?;
+    let diff = edit.diff(&content);
+    if diff.is_empty() {
+        println!("The model left the block as it was; nothing to write");
+        return Ok(());
+    }
+    print_diff(&diff);
+    let path = edit.path().display();
+    if !yes && !confirm(&format!("Write the change to {}?", path))? {
+        eprintln!("Not written; pass --yes to write the change without asking");
+        return Ok(());
+    }
+    let saved = edit
+        .write(&content, &Backup::Beside)
+        .map_err(|e| format!("could not write {}: {}", path, e))?;
+    println!("Wrote {} (the original is in {})", path, saved.display());
+    Ok(())
+}
+
+/// Whether the user answers yes to `question` on the terminal; no when stdin is not one.
+fn confirm(question: &str) -> Result<bool, String> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush().map_err(|e| e.to_string())?;
+    let mut reply = String::new();
+    io::stdin()
+        .read_line(&mut reply)
+        .map_err(|e| e.to_string())?;
+    Ok(matches!(reply.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// `question` with its `@{file:identifier}` placeholders replaced by the code they name,
+/// found under `root`, saying how many there were.
+fn expand_question(question: &str, root: &Path) -> Result<String, String> {
+    let (expanded, count) = resolve_placeholders(question, root)?;
+    if count > 0 {
+        eprintln!("Inlined {} placeholder(s) into the question", count);
+    }
+    Ok(expanded)
+}
+
+/// A dim spinner on stderr while the model has not answered yet, cleared once it does.
+fn waiting_spinner() -> ProgressBar {
+    let spinner = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr())
+        .with_finish(ProgressFinish::AndClear);
+    spinner.set_style(
+        ProgressStyle::with_template("{spinner:.dim} {msg:.dim} {elapsed:.dim}")
+            .expect("valid progress template"),
+    );
+    spinner.set_message("waiting for the first token");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+/// How an answer was generated, as printed after it: the tokens the model reports, else
+/// the chunks streamed, which are about one token each.
+struct AnswerStats<'a> {
+    answer: &'a Answer,
+    started: Instant,
+    first_token: Option<Instant>,
+    chunks: usize,
+}
+
+impl std::fmt::Display for AnswerStats<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let generated = self.answer.tokens.unwrap_or(self.chunks);
+        if let Some(prompt_tokens) = self.answer.prompt_tokens {
+            write!(f, "{} prompt tokens, ", prompt_tokens)?;
+        }
+        write!(
+            f,
+            "{} generated in {:.1}s",
+            generated,
+            self.started.elapsed().as_secs_f64()
+        )?;
+        if let Some(first_token) = self.first_token {
+            let streaming = first_token.elapsed().as_secs_f64();
+            if streaming > 0.0 && generated > 1 {
+                // The first token came at `first_token`; the rest over `streaming`.
+                write!(f, ", {:.1} tokens/s", (generated - 1) as f64 / streaming)?;
+            }
+            write!(
+                f,
+                ", first token after {:.2}s",
+                (first_token - self.started).as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Chat {
+    /// Asks `prompt` after the turns of `history`, writing the answer to stdout as it is
+    /// generated, then its sources, until the model is done or `stop` is set. Returns the
+    /// answer and its sources.
+    async fn ask(
+        &self,
+        prompt: &str,
+        history: &[Turn],
+        stop: &AtomicBool,
+    ) -> Result<(Answer, Vec<Source>), String> {
+        let Some(model) = &self.model else {
+            return Err("no model is loaded".to_string());
+        };
+        let mut conversation = self.conversation(prompt, history).await?;
+        if let Some(length) = self.context_length {
+            let counter = token_counter(&self.model_id);
+            let total = conversation.tokens(&counter, Some(length)).total;
+            if total > length {
+                let (left_out, tokens) = conversation.trim_to(length, &counter);
+                self.warn_overflow(total, &left_out, tokens.total);
+            }
+        }
+        if self.verbose {
+            eprintln!(
+                "Context budget: {} tokens ({})",
+                self.budget, self.budget_from
+            );
+            eprintln!(
+                "{} part(s) of the book made the cut",
+                conversation.sources.len()
+            );
+            eprintln!("Generation: {}", self.generation);
+            for source in &conversation.sources {
+                match &source.heading {
+                    Some(heading) => {
+                        eprintln!("  [{}] {} > {}", source.number, source.path, heading)
+                    }
+                    None => eprintln!("  [{}] {}", source.number, source.path),
+                }
+            }
+            let counter = token_counter(&self.model_id);
+            eprintln!("{}", conversation.tokens(&counter, self.context_length));
+        }
+        let (request, sources) = conversation.into_prompt();
+
+        let mut stdout = io::stdout();
+        let spinner = self.progress.then(waiting_spinner);
+        let started = Instant::now();
+        let mut first_token = None;
+        let mut chunks = 0;
+        let answer = generate(model, request, stop, |delta| {
+            if delta.is_empty() {
+                return;
+            }
+            if first_token.is_none() {
+                first_token = Some(Instant::now());
+                if let Some(spinner) = &spinner {
+                    spinner.finish_and_clear();
+                }
+            }
+            chunks += 1;
+            if self.stream {
+                let _ = write!(stdout, "{}", delta);
+                let _ = stdout.flush();
+            }
+        })
+        .await;
+        drop(spinner);
+        let answer = answer.map_err(|e| e.message)?;
+        if !self.stream {
+            return Ok((answer, sources));
+        }
+        println!();
+
+        if !sources.is_empty() {
+            println!("\nSources:");
+            for source in &sources {
+                match &source.heading {
+                    Some(heading) => println!("[{}] {} ({})", source.number, source.path, heading),
+                    None => println!("[{}] {}", source.number, source.path),
+                }
+            }
+        }
+        if self.progress {
+            let stats = AnswerStats {
+                answer: &answer,
+                started,
+                first_token,
+                chunks,
+            };
+            eprintln!("{}", stats.to_string().dimmed());
+        }
+        Ok((answer, sources))
+    }
+
+    /// The parts of the request asking `prompt` after the turns of `history`, with the
+    /// context of this chat.
+    async fn conversation(&self, prompt: &str, history: &[Turn]) -> Result<Conversation, String> {
+        // Given files are the whole context, cut to the budget like saved files are.
+        let (file_content, file_sources) = if self.files.is_empty() {
+            (None, Vec::new())
+        } else {
+            let (context, sources) = documents_context(self.files.clone(), self.budget);
+            if sources.len() < self.files.len() {
+                eprintln!(
+                    "{} only {} of {} file(s) fit in the {}-token context budget",
+                    "Warning:".yellow(),
+                    sources.len(),
+                    self.files.len(),
+                    self.budget
+                );
+            }
+            (Some(context), sources)
+        };
+        let args = ChatArgs {
+            prompt: Some(prompt.to_string()),
+            file_content,
+            db_path: self.db_path.clone(),
+            pool: None,
+            tags: Vec::new(),
+            project: None,
+            all_projects: false,
+            use_retrieval: false,
+            // Matching files replace retrieval, unless files were given.
+            use_db: self.files.is_empty() && !(self.grep.is_empty() && self.scope.is_empty()),
+            paths: self.scope.clone(),
+            grep: self.grep.clone(),
+            grep_any: self.grep_any,
+            retriever: self.retriever.clone(),
+            generation: self.generation.clone(),
+            system_prompt: self.system_prompt.clone(),
+        };
+        let history = history.to_vec();
+        // Loading the context reads the database, so keep it off the async threads.
+        let mut conversation = task::spawn_blocking(move || assemble_conversation(&args, &history))
+            .await
+            .map_err(|e| format!("the chat task failed: {}", e))?
+            .map_err(|e| e.message)?;
+        // Retrieval is skipped when files are given, so their sources are the only ones.
+        if !file_sources.is_empty() {
+            conversation.sources = file_sources;
+        }
+        Ok(conversation)
+    }
+
+    /// Says that the question came to `total` tokens, more than the model's context window,
+    /// and what was left out of the context so it would fit, `trimmed` tokens being left.
+    fn warn_overflow(&self, total: usize, left_out: &[Source], trimmed: usize) {
+        let length = self.context_length.unwrap_or_default();
+        let mut warning = format!(
+            "the question came to {} tokens, more than the {} of the context window of {}",
+            total, length, self.model_id
+        );
+        let parts: Vec<String> = left_out
+            .iter()
+            .map(|source| format!("[{}] {}", source.number, source.path))
+            .collect();
+        match (parts.is_empty(), trimmed > length) {
+            (true, _) => warning.push_str("; no part of the book is left to leave out, so the model may cut it short"),
+            (false, false) => warning.push_str(&format!(
+                "; left {} out of the context, bringing it to {} tokens",
+                parts.join(", "),
+                trimmed
+            )),
+            (false, true) => warning.push_str(&format!(
+                "; left {} out of the context, but it still comes to {} tokens, which the model may cut short",
+                parts.join(", "),
+                trimmed
+            )),
+        }
+        eprintln!("{} {}", "Warning:".yellow(), warning);
+    }
+
+    /// Adds the files `pattern` names to the context, after those already in it.
+    fn add_files(&mut self, pattern: &str) -> Result<usize, String> {
+        let mut added = 0;
+        for (path, content) in read_files(&expand_files(&[pattern.to_string()])?)? {
+            if !self.files.iter().any(|(known, _)| *known == path) {
+                self.files.push((path, content));
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+}
+
+const REPL_HELP: &str = "\
+End a line with \\ to continue the question on the next one.
+    /context <file>  Add a Markdown file, or the files a glob matches, to the context
+    /context         List the files in the context
+    /clear           Forget the questions asked so far
+    /save <path>     Write the questions and answers so far to a Markdown file
+    /quit            Leave (or Ctrl-D)
+Ctrl-C stops an answer being generated.";
+
+/// Reads questions from the terminal and asks them with the turns before them, trimmed
+/// to `history_budget` tokens, starting with `first` if given. The lines typed are kept in
+/// `history_file`.
+async fn repl(
+    chat: &mut Chat,
+    first: Option<String>,
+    history_budget: usize,
+    history_file: &Path,
+) -> Result<(), String> {
+    let mut editor = DefaultEditor::new().map_err(|e| e.to_string())?;
+    // There is no history before the first run.
+    let _ = editor.load_history(history_file);
+    println!("Type a question, or /help for the commands.");
+
+    let mut transcript: Vec<Turn> = Vec::new();
+    // Turns before this one were cleared and are no longer sent to the model.
+    let mut cleared = 0;
+    // The first question's placeholders are resolved already.
+    let mut pending = first;
+    loop {
+        let (input, typed) = match pending.take() {
+            Some(prompt) => (prompt, false),
+            None => match read_question(&mut editor)? {
+                Some(input) => (input, true),
+                None => break,
+            },
+        };
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if let Some(command) = input.strip_prefix('/') {
+            let (name, argument) = command
+                .split_once(char::is_whitespace)
+                .map_or((command, ""), |(name, argument)| (name, argument.trim()));
+            match (name, argument) {
+                ("quit" | "exit", _) => break,
+                ("help", _) => println!("{}", REPL_HELP),
+                ("clear", _) => {
+                    cleared = transcript.len();
+                    println!("History cleared");
+                }
+                ("context", "") if chat.files.is_empty() => {
+                    println!("No files in the context; the saved book is searched instead")
+                }
+                ("context", "") => {
+                    for (path, _) in &chat.files {
+                        println!("{}", path);
+                    }
+                }
+                ("context", pattern) => match chat.add_files(pattern) {
+                    Ok(added) => println!("Added {} file(s) to the context", added),
+                    Err(e) => eprintln!("Error: {}", e),
+                },
+                ("save", "") => eprintln!("Error: /save needs the path to write to"),
+                ("save", path) => match fs::write(path, transcript_markdown(&transcript)) {
+                    Ok(()) => println!("Saved {} turn(s) to {}", transcript.len(), path),
+                    Err(e) => eprintln!("Error: could not write {}: {}", path, e),
+                },
+                _ => eprintln!("Unknown command /{}; /help lists them", name),
+            }
+            continue;
+        }
+
+        let question = if typed {
+            match expand_question(input, &chat.root) {
+                Ok(question) => question,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    continue;
+                }
+            }
+        } else {
+            input.to_string()
+        };
+        let history = trim_history(transcript[cleared..].to_vec(), history_budget);
+        let stop = AtomicBool::new(false);
+        let asked = chat.ask(&question, &history, &stop);
+        tokio::pin!(asked);
+        let answer = loop {
+            tokio::select! {
+                answer = &mut asked => break answer,
+                _ = tokio::signal::ctrl_c() => stop.store(true, Ordering::SeqCst),
+            }
+        };
+        match answer {
+            Ok((answer, _)) if answer.stopped => println!("(stopped)"),
+            Ok((answer, _)) => transcript.push(Turn {
+                prompt: question.clone(),
+                answer: answer.text,
+            }),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    if let Err(e) = editor.save_history(history_file) {
+        eprintln!(
+            "{} could not save the history to {}: {}",
+            "Warning:".yellow(),
+            history_file.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+/// The next question typed, joining lines that end with a backslash; `None` once the user
+/// leaves with Ctrl-D. Ctrl-C drops what was typed.
+fn read_question(editor: &mut DefaultEditor) -> Result<Option<String>, String> {
+    let mut question = String::new();
+    loop {
+        let prompt = if question.is_empty() { "> " } else { "... " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                match line.strip_suffix('\\') {
+                    Some(start) => {
+                        question.push_str(start);
+                        question.push('\n');
+                    }
+                    None => {
+                        question.push_str(&line);
+                        return Ok(Some(question));
+                    }
+                }
+            }
+            Err(ReadlineError::Interrupted) => question.clear(),
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// `transcript` as Markdown, each question and answer under a heading.
+fn transcript_markdown(transcript: &[Turn]) -> String {
+    transcript
+        .iter()
+        .map(|turn| {
+            format!(
+                "## You\n\n{}\n\n## lila\n\n{}\n",
+                turn.prompt,
+                turn.answer.trim()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The files `patterns` name, in order and each once: a path as it is, and a glob (`*`
+/// stays within a directory, `**` does not) as the files it matches, sorted. Globs that
+/// match nothing are warned about.
+fn expand_files(patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for pattern in patterns {
+        let matched = if pattern.contains(['*', '?']) {
+            let matched = glob_files(pattern)?;
+            if matched.is_empty() {
+                eprintln!("{} {} matches no files", "Warning:".yellow(), pattern);
+            }
+            matched
+        } else {
+            vec![PathBuf::from(pattern)]
+        };
+        for file in matched {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The files matching the glob `pattern`, searched for under the folders it names before
+/// its first wildcard.
+fn glob_files(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let glob = glob_to_regex(pattern).map_err(|e| e.to_string())?;
+    let components: Vec<&str> = pattern.split('/').collect();
+    let literal = components
+        .iter()
+        .take_while(|component| !component.contains(['*', '?']))
+        .count();
+    let base = components[..literal].join("/");
+    let depth = if pattern.contains("**") {
+        usize::MAX
+    } else {
+        components.len() - literal
+    };
+    let root = if base.is_empty() { "." } else { base.as_str() };
+    Ok(WalkDir::new(root)
+        .max_depth(depth)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            let path = entry.path();
+            if base.is_empty() {
+                path.strip_prefix(".").unwrap_or(path).to_path_buf()
+            } else {
+                path.to_path_buf()
+            }
+        })
+        .filter(|path| glob.is_match(&path.to_string_lossy()))
+        .collect())
+}
+
+/// The path, as given, and the content of each of `files`.
+fn read_files(files: &[PathBuf]) -> Result<Vec<(String, String)>, String> {
+    files
+        .iter()
+        .map(|file| {
+            fs::read_to_string(file)
+                .map(|content| (file.display().to_string(), content))
+                .map_err(|e| format!("could not read {}: {}", file.display(), e))
+        })
+        .collect()
+}
+
+/// Loads `model_id` of `backend` in this process, saying so.
+async fn load_model(backend: &Backend, model_id: &str) -> Result<ChatModel, String> {
+    match backend {
+        Backend::Local { .. } if is_gguf_path(model_id) => {
+            eprintln!("Loading model {}...", model_id)
+        }
+        Backend::Local { isq } => eprintln!("Loading model {} ({})...", model_id, isq),
+        Backend::OpenAi { base_url, .. } => eprintln!("Asking {} at {}", model_id, base_url),
+    }
+    ChatModel::load(backend, model_id)
+        .await
+        .map_err(|e| format!("could not load model {}: {}", model_id, e))
+}
+
+/// The chat daemon, if one runs with `model_id` loaded. When it runs with another model,
+/// or cannot be used, the model is loaded in this process and the reason printed.
+async fn daemon_model(model_id: &str) -> Option<ChatModel> {
+    let socket = daemon::socket_path();
+    match daemon::status(&socket).await {
+        Ok(Some(status)) if status.model == model_id => {
+            eprintln!(
+                "Asking {} through the chat daemon (pid {})",
+                model_id, status.pid
+            );
+            Some(ChatModel::Daemon(Arc::new(DaemonClient::new(socket))))
+        }
+        Ok(Some(status)) => {
+            eprintln!(
+                "The chat daemon answers with {}, not {}; loading the model here",
+                status.model, model_id
+            );
+            None
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("{} {}; loading the model here", "Warning:".yellow(), e);
+            None
+        }
+    }
+}
+
+/// Starts, stops, runs or describes the chat daemon, which keeps the model of `options`
+/// loaded for the `lila chat` runs after it, and unloads it after `idle_minutes` without
+/// questions (never if 0).
+pub async fn run_daemon(
+    action: DaemonAction,
+    options: ChatOptions,
+    idle_minutes: u64,
+    config: &Config,
+) -> Result<(), String> {
+    let socket = daemon::socket_path();
+    match action {
+        DaemonAction::Status => match daemon::status(&socket).await? {
+            Some(status) => println!("{}", status),
+            None => println!("No chat daemon is running"),
+        },
+        DaemonAction::Stop => match daemon::stop(&socket).await? {
+            true => println!("Stopped the chat daemon"),
+            false => println!("No chat daemon is running"),
+        },
+        DaemonAction::Start | DaemonAction::Run => {
+            if let Some(status) = daemon::status(&socket).await? {
+                return Err(format!(
+                    "a chat daemon is already running (pid {}) with {}; stop it first with `lila chat --daemon stop`",
+                    status.pid, status.model
+                ));
+            }
+            let model_id = match options.model {
+                Some(id) => id,
+                None => ModelConfig::load(config)?.default,
+            };
+            if action == DaemonAction::Start {
+                return start_daemon(&socket, &model_id, options.isq, idle_minutes).await;
+            }
+            let mut backend = Backend::load(config)?;
+            if let Some(isq) = options.isq {
+                backend.override_isq(isq);
+            }
+            let model = load_model(&backend, &model_id).await?;
+            eprintln!(
+                "Chat daemon (pid {}) answering with {} on {}",
+                std::process::id(),
+                model_id,
+                socket.display()
+            );
+            let idle = (idle_minutes > 0).then(|| Duration::from_secs(idle_minutes * 60));
+            daemon::serve(&socket, backend, model_id, model, idle).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs `lila chat --daemon run` with `model_id` in the background, writing to the daemon
+/// log, and waits until it answers on `socket`.
+async fn start_daemon(
+    socket: &Path,
+    model_id: &str,
+    isq: Option<Isq>,
+    idle_minutes: u64,
+) -> Result<(), String> {
+    let log_path = daemon::log_path();
+    let log = fs::File::create(&log_path)
+        .map_err(|e| format!("cannot create {}: {}", log_path.display(), e))?;
+    let stdout = log.try_clone().map_err(|e| e.to_string())?;
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut command = Command::new(exe);
+    command
+        .args(["chat", "--daemon", "run", "--model", model_id])
+        .args(["--idle-minutes", &idle_minutes.to_string()])
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(log);
+    if let Some(isq) = isq {
+        command.args(["--isq", &isq.to_string()]);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Out of the terminal's process group, so that Ctrl-C there does not stop it.
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NEW_PROCESS_GROUP | DETACHED_PROCESS: out of the console, so that Ctrl-C
+        // there does not stop it.
+        command.creation_flags(0x0000_0200 | 0x0000_0008);
+    }
+    eprintln!("Starting the chat daemon with {}...", model_id);
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("cannot start the chat daemon: {}", e))?;
+    loop {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        if child.try_wait().map_err(|e| e.to_string())?.is_some() {
+            let output = fs::read_to_string(&log_path).unwrap_or_default();
+            let last = output
+                .lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .map(|line| line.trim_start_matches("Error: "));
+            return Err(format!(
+                "the chat daemon stopped before answering: {} (its output is in {})",
+                last.unwrap_or("no output"),
+                log_path.display()
+            ));
+        }
+        if let Ok(Some(status)) = daemon::status(socket).await {
+            println!("{}", status);
+            return Ok(());
+        }
+    }
+}
+
+/// Whether files of the current project are saved in the database at `db_path`; not
+/// when there is no database there.
+fn has_saved_documents(db_path: &Path) -> Result<bool, String> {
+    if !db_path.is_file() {
+        return Ok(false);
+    }
+    let pool = open_pool(&db_path.to_string_lossy())?;
+    let mut conn = pool.get().map_err(|e| e.to_string())?;
+    let Some(project_id) = ProjectScope::project_or_current(None)
+        .resolve(&mut conn)
+        .map_err(|e| e.to_string())?
+    else {
+        return Ok(false);
+    };
+    let ids = document_ids_under(&mut conn, &[], project_id).map_err(|e| e.to_string())?;
+    Ok(!ids.is_empty())
+}
+
+/// The tokens of the book given to `model_id` as context, and where the number comes from:
+/// `requested` (`--context-tokens`), else `context_tokens` in the `[server]` section of
+/// `config`, else half the model's context window if it is known, leaving the other half
+/// to the instructions and the answer, else `DEFAULT_CONTEXT_TOKENS`.
+fn context_budget(
+    requested: Option<usize>,
+    model_id: &str,
+    config: &Config,
+) -> Result<(usize, String), String> {
+    if let Some(tokens) = requested {
+        return Ok((tokens, "--context-tokens".to_string()));
+    }
+    if let Some(tokens) = configured_context_tokens(config)? {
+        return Ok((
+            tokens,
+            format!("server.context_tokens in {}", config.path().display()),
+        ));
+    }
+    Ok(match context_length(model_id) {
+        Some(window) => (
+            window / 2,
+            format!("half the {}-token context of {}", window, model_id),
+        ),
+        None => (DEFAULT_CONTEXT_TOKENS, "the default".to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{FakeModel, FAKE_MODEL};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn a_seed_and_temperature_0_ask_the_same_way_every_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let model = FakeModel::answering(&["Weave renders HTML."]);
+        let received = model.received.clone();
+        let lila_toml = format!(
+            "[ai]\n\
+backend = \"openai\"\n\
+base_url = \"{}\"\n\
+\n\
+[ai.generation]\n\
+temperature = 0.8\n\
+max_tokens = 64\n",
+            model.serve()
+        );
+        let config = Config::parse(&dir.path().join("Lila.toml"), &lila_toml)
+            .unwrap()
+            .0;
+        let chapter = dir.path().join("weave.md");
+        fs::write(&chapter, "# Weave\n\nWeaving renders the book.\n").unwrap();
+
+        for _ in 0..2 {
+            let options = ChatOptions {
+                prompt: Some("What does weave do?".to_string()),
+                root: dir.path().to_path_buf(),
+                files: vec![chapter.to_string_lossy().into_owned()],
+                model: Some(FAKE_MODEL.to_string()),
+                isq: None,
+                generation: GenerationParams {
+                    temperature: Some(0.0),
+                    seed: Some(7),
+                    ..GenerationParams::default()
+                },
+                system: None,
+                db_path: None,
+                grep: Vec::new(),
+                grep_any: false,
+                scope: Vec::new(),
+                model_dir: dir.path().join("models"),
+                context_tokens: None,
+                verbose: false,
+                quiet: true,
+                output: ChatOutput::Json,
+                interactive: false,
+                use_daemon: false,
+                history_file: dir.path().join("history"),
+                edit: None,
+                block: None,
+                yes: false,
+                count_only: false,
+            };
+            run_chat(options, &config).await.unwrap();
+        }
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0], received[1]);
+        // The flags win over [ai.generation], which fills in the rest.
+        assert_eq!(received[0]["seed"], json!(7));
+        assert_eq!(received[0]["temperature"], json!(0.0));
+        assert_eq!(received[0]["max_tokens"], json!(64));
+        assert_eq!(received[0]["model"], FAKE_MODEL);
+    }
+}
@@ -0,0 +1,596 @@
+use crate::commands::edit::formatter::DEFAULT_TIMEOUT_SECS;
+use crate::commands::init::{update_env_value, OUTPUT_PATH_VAR};
+use crate::server::auth::SERVER_TOKEN_VAR;
+use crate::server::backend::{Isq, BACKEND_VAR, DEFAULT_API_KEY_VAR, ISQ_VAR};
+use crate::server::config::{DEFAULT_HOST, DEFAULT_PORT, DEFAULT_WORKERS};
+use crate::server::context::DEFAULT_CONTEXT_TOKENS;
+use crate::server::models::{DEFAULT_MAX_LOADED_MODELS, DEFAULT_MODEL, MODEL_PATH_VAR, MODEL_VAR};
+use crate::server::rate_limit::{
+    DEFAULT_BURST, DEFAULT_MAX_GENERATIONS, DEFAULT_MAX_QUEUED, DEFAULT_REQUESTS_PER_MINUTE,
+};
+use crate::server::render::DEFAULT_MAX_RENDER_BYTES;
+use crate::server::sessions::DEFAULT_HISTORY_TOKENS;
+use crate::server::shutdown::DEFAULT_SHUTDOWN_GRACE;
+use crate::server::start::DEFAULT_MAX_CONTEXT_BYTES;
+use crate::utils::config::Config;
+use crate::utils::database::assets::DEFAULT_MAX_ASSET_BYTES;
+use crate::utils::database::db::DB_KEY_VAR;
+use crate::utils::database::pool::DEFAULT_POOL_SIZE;
+use crate::utils::markdown::DEFAULT_THEME;
+use crate::utils::utils::default_root;
+use clap::ValueEnum;
+use colored::Colorize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+/// Where a setting is kept.
+#[derive(Clone, Copy)]
+enum Store {
+    /// A key of `Lila.toml`.
+    File,
+    /// A key of `Lila.toml`, which the environment variable takes precedence over.
+    FileOrEnv(&'static str),
+    /// Only the environment variable, which `lila config set` writes to `.env`.
+    Env(&'static str),
+}
+
+/// A setting `lila config` knows: where it is kept and its default, if it has one.
+struct Setting {
+    key: &'static str,
+    store: Store,
+    default: Option<Value>,
+    /// Whether `lila config list` hides the value.
+    secret: bool,
+}
+
+/// Where the value in effect of a setting comes from.
+#[derive(Clone, Copy)]
+enum Source {
+    Default,
+    File,
+    DotEnv,
+    Env,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::Default => "default",
+            Source::File => "file",
+            Source::DotEnv => ".env",
+            Source::Env => "env",
+        })
+    }
+}
+
+/// The settings lila reads, by their dotted key. Keys that are not listed here, like the
+/// `[format.<language>]` tables, are read from `Lila.toml` and written to it as they are.
+fn known_settings() -> Vec<Setting> {
+    let setting = |key, store, default: Option<Value>| Setting {
+        key,
+        store,
+        default,
+        secret: false,
+    };
+    let file = |key, default: Option<Value>| setting(key, Store::File, default);
+    let count = |n: u64| Some(Value::from(n as i64));
+    let isq = Isq::default()
+        .to_possible_value()
+        .map(|value| Value::from(value.get_name()));
+    vec![
+        setting("ai.model", Store::Env(MODEL_VAR), None),
+        setting("ai.model_path", Store::Env(MODEL_PATH_VAR), None),
+        setting(
+            "ai.backend",
+            Store::FileOrEnv(BACKEND_VAR),
+            Some("local".into()),
+        ),
+        file("ai.base_url", None),
+        file("ai.api_key_env", Some(DEFAULT_API_KEY_VAR.into())),
+        setting("ai.isq", Store::FileOrEnv(ISQ_VAR), isq),
+        file("assets.max_bytes", count(DEFAULT_MAX_ASSET_BYTES)),
+        Setting {
+            secret: true,
+            ..setting("database.key", Store::Env(DB_KEY_VAR), None)
+        },
+        file("database.key_file", None),
+        file("database.pool_size", count(DEFAULT_POOL_SIZE as u64)),
+        file("format.timeout_secs", count(DEFAULT_TIMEOUT_SECS)),
+        file("format.rust_edition", None),
+        file("format.rustfmt_config", None),
+        file("format.line_width", None),
+        file("history.max_versions", None),
+        file("render.theme", Some(DEFAULT_THEME.into())),
+        setting(
+            "output.path",
+            Store::Env(OUTPUT_PATH_VAR),
+            Some(default_root().to_string_lossy().as_ref().into()),
+        ),
+        file("server.host", Some(DEFAULT_HOST.into())),
+        file("server.port", count(DEFAULT_PORT as u64)),
+        file("server.workers", count(DEFAULT_WORKERS as u64)),
+        Setting {
+            secret: true,
+            ..setting("server.token", Store::FileOrEnv(SERVER_TOKEN_VAR), None)
+        },
+        file("server.tls_cert", None),
+        file("server.tls_key", None),
+        file("server.default_model", Some(DEFAULT_MODEL.into())),
+        file(
+            "server.max_loaded_models",
+            count(DEFAULT_MAX_LOADED_MODELS as u64),
+        ),
+        file(
+            "server.requests_per_minute",
+            count(DEFAULT_REQUESTS_PER_MINUTE),
+        ),
+        file("server.burst", count(DEFAULT_BURST)),
+        file(
+            "server.max_concurrent_generations",
+            count(DEFAULT_MAX_GENERATIONS),
+        ),
+        file("server.max_queued_generations", count(DEFAULT_MAX_QUEUED)),
+        file(
+            "server.max_context_bytes",
+            count(DEFAULT_MAX_CONTEXT_BYTES as u64),
+        ),
+        file(
+            "server.max_render_bytes",
+            count(DEFAULT_MAX_RENDER_BYTES as u64),
+        ),
+        file("server.sanitize_html", Some(false.into())),
+        file(
+            "server.context_tokens",
+            count(DEFAULT_CONTEXT_TOKENS as u64),
+        ),
+        file(
+            "server.history_tokens",
+            count(DEFAULT_HISTORY_TOKENS as u64),
+        ),
+        file(
+            "server.shutdown_grace_seconds",
+            count(DEFAULT_SHUTDOWN_GRACE.as_secs()),
+        ),
+    ]
+}
+
+/// The settings of `config` and `.env` beside it, as `lila config` reads and writes them.
+struct Settings {
+    /// `Lila.toml` as written, `None` when there is none.
+    document: Option<DocumentMut>,
+    /// The variables set in `.env`.
+    dotenv: HashMap<String, String>,
+}
+
+impl Settings {
+    fn load(config: &Config) -> Result<Settings, String> {
+        let path = config.path();
+        let document = match fs::read_to_string(path) {
+            Ok(content) => Some(
+                content
+                    .parse::<DocumentMut>()
+                    .map_err(|e| format!("could not parse {}: {}", path.display(), e))?,
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("could not read {}: {}", path.display(), e)),
+        };
+        let dotenv = match dotenvy::from_path_iter(env_path(config)) {
+            Ok(vars) => vars.filter_map(Result::ok).collect(),
+            Err(_) => HashMap::new(),
+        };
+        Ok(Settings { document, dotenv })
+    }
+
+    /// The value of `key` in `Lila.toml`, if it is there.
+    fn file_value(&self, key: &str) -> Option<Value> {
+        let mut item = self.document.as_ref()?.as_item();
+        for segment in key.split('.') {
+            item = item.get(segment)?;
+        }
+        item.clone().into_value().ok().map(undecorated)
+    }
+
+    /// The value of the environment variable `var` and whether it comes from `.env`.
+    fn env_value(&self, var: &str) -> Option<(Value, Source)> {
+        let value = std::env::var(var).ok().filter(|value| !value.is_empty())?;
+        let source = match self.dotenv.get(var) {
+            Some(set) if *set == value => Source::DotEnv,
+            _ => Source::Env,
+        };
+        Some((Value::from(value), source))
+    }
+
+    /// The value `setting` has, in the order lila reads it.
+    fn effective(&self, setting: &Setting) -> Option<(Value, Source)> {
+        let from_env = match setting.store {
+            Store::Env(var) | Store::FileOrEnv(var) => self.env_value(var),
+            Store::File => None,
+        };
+        let from_file = match setting.store {
+            Store::File | Store::FileOrEnv(_) => self.file_value(setting.key),
+            Store::Env(_) => None,
+        };
+        from_env
+            .or_else(|| from_file.map(|value| (value, Source::File)))
+            .or_else(|| {
+                setting
+                    .default
+                    .clone()
+                    .map(|value| (value, Source::Default))
+            })
+    }
+}
+
+/// Prints the value in effect of `key`: strings as they are, other values as TOML.
+pub fn get(config: &Config, key: &str) -> Result<(), String> {
+    let settings = Settings::load(config)?;
+    let value = match known_setting(key) {
+        Some(setting) => settings
+            .effective(&setting)
+            .map(|(value, _)| value)
+            .ok_or_else(|| format!("{} is not set and has no default", key))?,
+        None => settings
+            .file_value(key)
+            .ok_or_else(|| format!("{} is not set in {}", key, config.path().display()))?,
+    };
+    match value.as_str() {
+        Some(text) => println!("{}", text),
+        None => println!("{}", value),
+    }
+    Ok(())
+}
+
+/// Sets `key` to `value`: the keys kept in environment variables in the `.env` beside
+/// `Lila.toml`, the others in `Lila.toml`, leaving the rest of the file as it was. A value
+/// that is not valid TOML, or not of the type the key takes, is written as a string; one
+/// that lila would refuse is not written at all.
+pub fn set(config: &Config, key: &str, value: &str) -> Result<(), String> {
+    if key.split('.').any(|segment| segment.trim().is_empty()) {
+        return Err(format!(
+            "invalid key {:?}; use a dotted key like server.port",
+            key
+        ));
+    }
+    let settings = Settings::load(config)?;
+    let setting = known_setting(key);
+    if let Some(Store::Env(var)) = setting.as_ref().map(|setting| setting.store) {
+        let path = env_path(config);
+        update_env_value(&path, var, value)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        println!("{} {}={} in {}", "✔".green(), var, value, path.display());
+        warn_overridden(&settings, var);
+        return Ok(());
+    }
+
+    let path = config.path();
+    let document = settings.document.clone().unwrap_or_default();
+    // Try the value as TOML first, so `8080` is a number, then as a string.
+    let candidates = value
+        .parse::<Value>()
+        .ok()
+        .filter(|parsed| !parsed.is_str())
+        .into_iter()
+        .chain([Value::from(value)]);
+    let mut first_error = None;
+    for candidate in candidates {
+        let mut edited = document.clone();
+        set_value(&mut edited, key, candidate.clone())?;
+        let content = edited.to_string();
+        match Config::parse(path, &content) {
+            Ok((_, unknown)) if unknown.iter().any(|ignored| covers(ignored, key)) => {
+                return Err(format!("unknown key {}; lila does not read it", key));
+            }
+            Ok(_) => {
+                fs::write(path, content)
+                    .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+                println!(
+                    "{} {} = {} in {}",
+                    "✔".green(),
+                    key,
+                    undecorated(candidate),
+                    path.display()
+                );
+                if let Some(Store::FileOrEnv(var)) = setting.map(|setting| setting.store) {
+                    warn_overridden(&settings, var);
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| format!("could not set {}", key)))
+}
+
+/// Prints every setting in effect, those of `Lila.toml` and those lila knows the default
+/// of, with where each value comes from. Secrets are hidden.
+pub fn list(config: &Config) -> Result<(), String> {
+    let settings = Settings::load(config)?;
+    let mut values: BTreeMap<String, (String, Source)> = BTreeMap::new();
+    if let Some(document) = &settings.document {
+        collect_file_values(document.as_table(), "", &mut values);
+    }
+    for setting in known_settings() {
+        if let Some((value, source)) = settings.effective(&setting) {
+            let shown = if setting.secret {
+                "\"********\"".to_string()
+            } else {
+                value.to_string()
+            };
+            values.insert(setting.key.to_string(), (shown, source));
+        }
+    }
+
+    let width = values.keys().map(|key| key.len()).max().unwrap_or(0);
+    let value_width = values
+        .values()
+        .map(|(value, _)| value.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(40);
+    for (key, (value, source)) in &values {
+        println!(
+            "{:<width$} = {:<value_width$}  {}",
+            key,
+            value,
+            source.to_string().dimmed(),
+        );
+    }
+    Ok(())
+}
+
+/// The setting named `key`, if lila knows it.
+fn known_setting(key: &str) -> Option<Setting> {
+    known_settings()
+        .into_iter()
+        .find(|setting| setting.key == key)
+}
+
+/// The `.env` beside the configuration file.
+fn env_path(config: &Config) -> PathBuf {
+    config.resolve(Path::new(".env"))
+}
+
+/// Warns that the environment variable `var`, set outside `.env`, takes precedence over
+/// what was just written.
+fn warn_overridden(settings: &Settings, var: &str) {
+    if let Some((value, Source::Env)) = settings.env_value(var) {
+        eprintln!(
+            "{} {} is set to {} in the environment and takes precedence",
+            "Warning:".yellow(),
+            var,
+            value
+        );
+    }
+}
+
+/// Whether the key `ignored`, reported as unknown, is `key` or a table holding it.
+fn covers(ignored: &str, key: &str) -> bool {
+    key == ignored
+        || key
+            .strip_prefix(ignored)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Sets the dotted `key` of `document` to `value`, adding the tables it is in. A value
+/// already there keeps its comments.
+fn set_value(document: &mut DocumentMut, key: &str, value: Value) -> Result<(), String> {
+    let segments: Vec<&str> = key.split('.').collect();
+    let (last, tables) = segments.split_last().expect("split yields a segment");
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    for (depth, segment) in tables.iter().enumerate() {
+        let item = table.entry(segment).or_insert_with(|| {
+            let mut new = Table::new();
+            new.set_implicit(true);
+            Item::Table(new)
+        });
+        table = item
+            .as_table_like_mut()
+            .ok_or_else(|| format!("{} is not a table", segments[..=depth].join(".")))?;
+    }
+    match table.get_mut(last) {
+        Some(Item::Value(old)) => {
+            let decor = old.decor().clone();
+            *old = value;
+            *old.decor_mut() = decor;
+        }
+        _ => {
+            table.insert(last, Item::Value(value));
+        }
+    }
+    Ok(())
+}
+
+/// Adds the values of `table`, under their dotted keys after `prefix`, to `values`.
+fn collect_file_values(
+    table: &Table,
+    prefix: &str,
+    values: &mut BTreeMap<String, (String, Source)>,
+) {
+    for (key, item) in table.iter() {
+        let key = format!("{}{}", prefix, key);
+        match item {
+            Item::Table(table) => collect_file_values(table, &format!("{}.", key), values),
+            item => {
+                if let Ok(value) = item.clone().into_value() {
+                    values.insert(key, (undecorated(value).to_string(), Source::File));
+                }
+            }
+        }
+    }
+}
+
+/// `value` without the spaces and comments around it in the file.
+fn undecorated(mut value: Value) -> Value {
+    value.decor_mut().clear();
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The configuration of a `Lila.toml` in `dir` holding `content`, or of none.
+    fn config_in(dir: &Path, content: Option<&str>) -> Config {
+        let path = dir.join("Lila.toml");
+        if let Some(content) = content {
+            fs::write(&path, content).unwrap();
+        }
+        Config::parse(&path, content.unwrap_or("")).unwrap().0
+    }
+
+    #[test]
+    fn values_are_written_with_the_type_the_key_takes() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_in(dir.path(), None);
+        set(&config, "server.port", "8080").unwrap();
+        set(&config, "server.host", "0.0.0.0").unwrap();
+        set(&config, "server.sanitize_html", "true").unwrap();
+
+        let written = fs::read_to_string(dir.path().join("Lila.toml")).unwrap();
+        assert_eq!(
+            written,
+            "[server]\nport = 8080\nhost = \"0.0.0.0\"\nsanitize_html = true\n"
+        );
+        let config = Config::read(&dir.path().join("Lila.toml")).unwrap();
+        assert_eq!(config.server.port, Some(8080));
+        assert_eq!(config.server.sanitize_html, Some(true));
+    }
+
+    #[test]
+    fn a_value_of_the_wrong_type_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_in(dir.path(), Some("[server]\nport = 8080\n"));
+        let error = set(&config, "server.port", "eighty").unwrap_err();
+        assert!(error.contains("server.port"), "{}", error);
+        let error = set(&config, "server.sanitize_html", "yes").unwrap_err();
+        assert!(error.contains("server.sanitize_html"), "{}", error);
+
+        let written = fs::read_to_string(dir.path().join("Lila.toml")).unwrap();
+        assert_eq!(written, "[server]\nport = 8080\n");
+    }
+
+    #[test]
+    fn an_unknown_key_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_in(dir.path(), None);
+        let error = set(&config, "server.prot", "8080").unwrap_err();
+        assert_eq!(error, "unknown key server.prot; lila does not read it");
+        let error = set(&config, "rendr.theme", "dark").unwrap_err();
+        assert_eq!(error, "unknown key rendr.theme; lila does not read it");
+        let error = set(&config, "server..port", "8080").unwrap_err();
+        assert!(error.starts_with("invalid key"), "{}", error);
+        assert!(!dir.path().join("Lila.toml").exists());
+    }
+
+    #[test]
+    fn a_key_inside_a_value_is_refused() {
+        let mut document = "[server]\nport = 8080\n".parse::<DocumentMut>().unwrap();
+        let error = set_value(&mut document, "server.port.number", Value::from(1)).unwrap_err();
+        assert_eq!(error, "server.port is not a table");
+    }
+
+    #[test]
+    fn environment_settings_go_to_the_env_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_in(dir.path(), Some("[server]\nport = 8080\n"));
+        set(&config, "ai.model", "mistral-7b").unwrap();
+
+        let env = fs::read_to_string(dir.path().join(".env")).unwrap();
+        assert!(
+            env.contains(&format!("{}=mistral-7b\n", MODEL_VAR)),
+            "{}",
+            env
+        );
+        let written = fs::read_to_string(dir.path().join("Lila.toml")).unwrap();
+        assert_eq!(written, "[server]\nport = 8080\n");
+    }
+
+    #[test]
+    fn setting_a_value_keeps_the_comments_and_order_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "\
+# Settings for the book.
+[server]
+host = \"127.0.0.1\" # only this machine
+port = 8080 # the usual one
+# How many at once.
+workers = 2
+
+[project]
+context = \"Physics engine\"
+";
+        let config = config_in(dir.path(), Some(content));
+        set(&config, "server.port", "9090").unwrap();
+        set(&config, "history.max_versions", "5").unwrap();
+
+        let written = fs::read_to_string(dir.path().join("Lila.toml")).unwrap();
+        assert_eq!(
+            written,
+            "\
+# Settings for the book.
+[server]
+host = \"127.0.0.1\" # only this machine
+port = 9090 # the usual one
+# How many at once.
+workers = 2
+
+[project]
+context = \"Physics engine\"
+
+[history]
+max_versions = 5
+"
+        );
+    }
+
+    #[test]
+    fn values_come_from_the_file_else_the_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_in(dir.path(), Some("[server]\nport = 9090 # moved\n"));
+        let settings = Settings::load(&config).unwrap();
+
+        let port = known_setting("server.port").unwrap();
+        let (value, source) = settings.effective(&port).unwrap();
+        assert_eq!(value.to_string(), "9090");
+        assert_eq!(source.to_string(), "file");
+
+        let host = known_setting("server.host").unwrap();
+        let (value, source) = settings.effective(&host).unwrap();
+        assert_eq!(value.as_str(), Some(DEFAULT_HOST));
+        assert_eq!(source.to_string(), "default");
+
+        let tls_cert = known_setting("server.tls_cert").unwrap();
+        assert!(settings.effective(&tls_cert).is_none());
+    }
+
+    #[test]
+    fn listed_file_values_include_keys_lila_does_not_list() {
+        let document = "\
+[server]
+port = 8080
+
+[format.rust]
+command = \"rustfmt\"
+"
+        .parse::<DocumentMut>()
+        .unwrap();
+        let mut values = BTreeMap::new();
+        collect_file_values(document.as_table(), "", &mut values);
+        let keys: Vec<&str> = values.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["format.rust.command", "server.port"]);
+        assert_eq!(values["format.rust.command"].0, "\"rustfmt\"");
+    }
+
+    #[test]
+    fn an_unknown_table_covers_the_keys_in_it() {
+        assert!(covers("rendr", "rendr.theme"));
+        assert!(covers("server.prot", "server.prot"));
+        assert!(!covers("server.port", "server.portal"));
+        assert!(!covers("server.prot", "server.port"));
+    }
+}
@@ -0,0 +1,541 @@
+use crate::commands::edit::formatter::{probe_formatter, FormatConfig};
+use crate::commands::history::load_max_versions;
+use crate::commands::init::{FORMATTER_PROBE_TIMEOUT, OUTPUT_PATH_VAR};
+use crate::server::backend::Backend;
+use crate::server::config::{ServerConfig, ServerFlags};
+use crate::server::gguf::{check_gguf, is_gguf_path};
+use crate::server::hub::{check_offline, is_cached};
+use crate::server::models::{ModelConfig, MODEL_PATH_VAR};
+use crate::utils::config::Config;
+use crate::utils::database::assets::max_asset_bytes;
+use crate::utils::database::db::{establish_connection, pending_migrations, MigrationError};
+use crate::utils::database::pool::pool_size;
+use crate::utils::utils::db_path;
+use colored::Colorize;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How a check came out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// What `lila doctor` found about one thing the other commands rely on.
+struct Check {
+    name: &'static str,
+    status: Status,
+    message: String,
+    /// What to do about a warning or a failure.
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn print(&self) {
+        let (mark, label) = match self.status {
+            Status::Pass => ("✔".green(), "pass".green()),
+            Status::Warn => ("!".yellow(), "warn".yellow()),
+            Status::Fail => ("✘".red(), "fail".red()),
+        };
+        println!("{} {} {}: {}", mark, label, self.name.bold(), self.message);
+        if let Some(hint) = &self.hint {
+            println!("         {} {}", "→".dimmed(), hint);
+        }
+    }
+}
+
+/// Checks what the other commands assume: that `Lila.toml` is valid, the output folder
+/// and the database are usable, and the formatters and the model are there. `config` is
+/// the result of loading `Lila.toml`; `default_root` is the project's folder under
+/// `~/.lila`. Prints a line per check and returns whether none failed.
+pub fn doctor(config: Result<&Config, String>, default_root: &Path) -> bool {
+    let mut checks = Vec::new();
+    let config = match config {
+        Ok(config) => {
+            checks.extend(check_config(config));
+            Some(config)
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "Lila.toml",
+                e,
+                "fix the value it names, by hand or with `lila config set <key> <value>`",
+            ));
+            None
+        }
+    };
+    checks.push(check_output_path(default_root));
+    checks.push(check_output_root(default_root));
+    checks.push(check_database(&db_path(None)));
+    if let Some(config) = config {
+        checks.extend(check_formatters(config));
+        let models = check_model(config);
+        // Loading the server settings checks the models too; report them once, as models.
+        checks.retain(|check| models.iter().all(|model| model.message != check.message));
+        checks.extend(models);
+    }
+
+    for check in &checks {
+        check.print();
+    }
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    let (warned, failed) = (count(Status::Warn), count(Status::Fail));
+    println!();
+    if failed > 0 {
+        println!(
+            "{}",
+            format!("{} failed, {} warned.", failed, warned)
+                .red()
+                .bold()
+        );
+    } else if warned > 0 {
+        println!(
+            "{}",
+            format!("All good, with {} warnings.", warned).yellow()
+        );
+    } else {
+        println!("{}", "All good.".green());
+    }
+    failed == 0
+}
+
+/// Whether `config` was found and its values are in the ranges lila accepts.
+fn check_config(config: &Config) -> Vec<Check> {
+    const NAME: &str = "Lila.toml";
+    let path = config.path();
+    let mut checks = vec![if path.is_file() {
+        Check::pass(NAME, format!("{} parses", path.display()))
+    } else {
+        Check::warn(
+            NAME,
+            "none in this folder or above it; the defaults are used",
+            "run `lila init` to write one",
+        )
+    }];
+
+    let errors: Vec<String> = [
+        ServerConfig::load(config, ServerFlags::default()).err(),
+        FormatConfig::load(config).err().map(|e| e.to_string()),
+        pool_size(config).err(),
+        max_asset_bytes(config).err(),
+        load_max_versions(config).err().map(|e| e.to_string()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    for error in errors {
+        checks.push(Check::fail(
+            NAME,
+            error,
+            "fix the value it names, by hand or with `lila config set <key> <value>`",
+        ));
+    }
+    checks
+}
+
+/// Where tangle, weave and the database write, as `LILA_OUTPUT_PATH` sets them. Unset is
+/// fine: the output then goes to `default_root` and the database is the shared one.
+fn check_output_path(default_root: &Path) -> Check {
+    const NAME: &str = "LILA_OUTPUT_PATH";
+    let db_path = db_path(None);
+    match std::env::var_os(OUTPUT_PATH_VAR).filter(|path| !path.is_empty()) {
+        Some(path) => Check::pass(
+            NAME,
+            format!(
+                "{}, with the project's database, {}",
+                PathBuf::from(path).display(),
+                db_path.display()
+            ),
+        ),
+        None => Check::pass(
+            NAME,
+            format!(
+                "not set; tangle and weave write to {}, and the database is the shared {}",
+                default_root.display(),
+                db_path.display()
+            ),
+        ),
+    }
+}
+
+/// Whether the folder lila writes the project's files to exists and can be written to.
+fn check_output_root(default_root: &Path) -> Check {
+    const NAME: &str = "output folder";
+    if !default_root.is_dir() {
+        return Check::warn(
+            NAME,
+            format!("{} does not exist yet", default_root.display()),
+            "it is created by the next lila command",
+        );
+    }
+    let probe = default_root.join(format!(".lila-doctor-{}", std::process::id()));
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            Check::pass(NAME, format!("{} is writable", default_root.display()))
+        }
+        Err(e) => Check::fail(
+            NAME,
+            format!("cannot write to {}: {}", default_root.display(), e),
+            "fix the folder's permissions, or choose another one with `lila init`",
+        ),
+    }
+}
+
+/// Whether the database opens, with its key if it has one, and is migrated.
+fn check_database(path: &Path) -> Check {
+    const NAME: &str = "database";
+    if !path.exists() {
+        return Check::warn(
+            NAME,
+            format!("{} does not exist yet", path.display()),
+            "`lila save` creates it",
+        );
+    }
+    let mut conn = match establish_connection(&path.to_string_lossy()) {
+        Ok(conn) => conn,
+        Err(e) => {
+            return Check::fail(
+                NAME,
+                format!("cannot open {}: {}", path.display(), e),
+                "check LILA_DB_KEY or database.key_file, or restore the file from a backup",
+            )
+        }
+    };
+    match pending_migrations(&mut conn) {
+        Ok(pending) if pending.is_empty() => {
+            Check::pass(NAME, format!("{} opens and is up to date", path.display()))
+        }
+        Ok(pending) => Check::warn(
+            NAME,
+            format!(
+                "{} has {} migrations to apply: {}",
+                path.display(),
+                pending.len(),
+                pending.join(", ")
+            ),
+            "the next lila command applies them",
+        ),
+        Err(e @ MigrationError::NewerSchema { .. }) => {
+            Check::fail(NAME, format!("{}: {}", path.display(), e), "upgrade lila")
+        }
+        Err(e) => Check::fail(
+            NAME,
+            format!("{}: {}", path.display(), e),
+            "restore the file from a backup",
+        ),
+    }
+}
+
+/// Whether the formatters `Lila.toml` lists as available, or configures for a language,
+/// are installed.
+fn check_formatters(config: &Config) -> Vec<Check> {
+    const NAME: &str = "formatter";
+    let Ok(format) = FormatConfig::load(config) else {
+        return Vec::new();
+    };
+    let mut commands: Vec<String> = format.available.clone().unwrap_or_default();
+    for formatter in &format.overrides {
+        if !commands.contains(&formatter.command) {
+            commands.push(formatter.command.clone());
+        }
+    }
+    if commands.is_empty() {
+        return vec![Check::warn(
+            NAME,
+            "none is listed in [format] available",
+            "run `lila init` to look for the installed formatters",
+        )];
+    }
+    commands
+        .iter()
+        .map(|command| {
+            let probe = probe_formatter(command, FORMATTER_PROBE_TIMEOUT);
+            let configured = format
+                .overrides
+                .iter()
+                .any(|formatter| formatter.command == *command);
+            match (probe.installed, probe.version) {
+                (true, Some(version)) => Check::pass(NAME, format!("{} {}", command, version)),
+                (true, None) => Check::pass(NAME, command.clone()),
+                (false, _) if configured => Check::fail(
+                    NAME,
+                    format!("{}, configured under [format], is not installed", command),
+                    format!(
+                        "install {}, or remove its [format.<language>] table",
+                        command
+                    ),
+                ),
+                (false, _) => Check::warn(
+                    NAME,
+                    format!("{} is listed as available but is not installed", command),
+                    format!(
+                        "install {}, or run `lila init` again to update the list",
+                        command
+                    ),
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Whether the models chat answers with can be loaded: GGUF files exist and are complete,
+/// and hub ids look like `owner/name`.
+fn check_model(config: &Config) -> Vec<Check> {
+    const NAME: &str = "model";
+    let (Ok(models), Ok(backend)) = (ModelConfig::load(config), Backend::load(config)) else {
+        // Already reported with the other values of Lila.toml.
+        return Vec::new();
+    };
+    let hub_id = Regex::new(r"^[A-Za-z0-9][\w.-]*/[A-Za-z0-9][\w.-]*$").expect("valid regex");
+    models
+        .allowed
+        .iter()
+        .map(|id| match backend {
+            Backend::OpenAi { .. } if is_gguf_path(id) => Check::fail(
+                NAME,
+                format!(
+                    "{} is a GGUF file, which only the local backend can load",
+                    id
+                ),
+                "set ai.backend to local, or name a model of the remote server",
+            ),
+            Backend::OpenAi { .. } => Check::pass(NAME, format!("{} (remote)", id)),
+            Backend::Local { .. } if is_gguf_path(id) => match check_gguf(Path::new(id)) {
+                Ok(_) => Check::pass(NAME, format!("{} exists", id)),
+                Err(e) => Check::fail(
+                    NAME,
+                    e,
+                    format!("fix {} or point it at another GGUF file", MODEL_PATH_VAR),
+                ),
+            },
+            Backend::Local { .. } if !hub_id.is_match(id) => Check::fail(
+                NAME,
+                format!("{} is not a Hugging Face model id like owner/name", id),
+                "set ai.model or server.default_model to a model id, or a GGUF file",
+            ),
+            Backend::Local { .. } => match check_offline(id) {
+                Err(e) => Check::fail(NAME, e, "run once without --offline to download it"),
+                Ok(()) if is_cached(id) => Check::pass(NAME, format!("{} (downloaded)", id)),
+                Ok(()) => Check::pass(NAME, format!("{} (downloaded on first use)", id)),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database::pool::build_pool;
+
+    /// The configuration of a `Lila.toml` in `dir` holding `content`.
+    fn config_in(dir: &Path, content: &str) -> Config {
+        let path = dir.join("Lila.toml");
+        fs::write(&path, content).unwrap();
+        Config::parse(&path, content).unwrap().0
+    }
+
+    fn statuses(checks: &[Check]) -> Vec<(Status, &str)> {
+        checks
+            .iter()
+            .map(|check| (check.status, check.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn a_missing_config_file_is_a_warning() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::parse(&dir.path().join("Lila.toml"), "").unwrap().0;
+        let checks = check_config(&config);
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, Status::Warn);
+        assert_eq!(
+            checks[0].hint.as_deref(),
+            Some("run `lila init` to write one")
+        );
+    }
+
+    #[test]
+    fn a_value_out_of_range_fails_the_config_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_in(dir.path(), "[database]\npool_size = 0\n");
+        let checks = check_config(&config);
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].status, Status::Pass);
+        assert_eq!(checks[1].status, Status::Fail);
+        assert!(
+            checks[1].message.contains("database.pool_size"),
+            "{}",
+            checks[1].message
+        );
+
+        let config = config_in(dir.path(), "[database]\npool_size = 4\n");
+        let checks = check_config(&config);
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, Status::Pass);
+    }
+
+    #[test]
+    fn the_output_folder_is_checked_by_writing_to_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let check = check_output_root(&dir.path().join("book"));
+        assert_eq!(check.status, Status::Warn);
+        assert!(check.message.ends_with("does not exist yet"));
+
+        let check = check_output_root(dir.path());
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn the_database_check_tells_missing_pending_and_current_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lila.db");
+        assert_eq!(check_database(&path).status, Status::Warn);
+
+        fs::write(&path, b"").unwrap();
+        let check = check_database(&path);
+        assert_eq!(check.status, Status::Warn);
+        assert!(
+            check.message.contains("migrations to apply"),
+            "{}",
+            check.message
+        );
+
+        build_pool(&path.to_string_lossy(), 1, None).unwrap();
+        let check = check_database(&path);
+        assert_eq!(check.status, Status::Pass, "{}", check.message);
+        assert!(check.message.ends_with("opens and is up to date"));
+    }
+
+    #[test]
+    fn a_file_that_is_not_a_database_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lila.db");
+        fs::write(
+            &path,
+            "not a database, but long enough to have a header. ".repeat(20),
+        )
+        .unwrap();
+        let check = check_database(&path);
+        assert_eq!(check.status, Status::Fail, "{}", check.message);
+    }
+
+    #[test]
+    fn missing_formatters_warn_unless_configured() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_in(dir.path(), "");
+        let checks = check_formatters(&config);
+        assert_eq!(
+            statuses(&checks),
+            [(Status::Warn, "none is listed in [format] available")]
+        );
+
+        let config = config_in(
+            dir.path(),
+            "\
+[format]
+available = [\"lila-no-such-formatter\"]
+
+[format.python]
+command = \"lila-no-such-black\"
+",
+        );
+        let checks = check_formatters(&config);
+        assert_eq!(
+            statuses(&checks),
+            [
+                (
+                    Status::Warn,
+                    "lila-no-such-formatter is listed as available but is not installed"
+                ),
+                (
+                    Status::Fail,
+                    "lila-no-such-black, configured under [format], is not installed"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_remote_backend_cannot_load_a_gguf_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = |model: &str| {
+            format!(
+                "[ai]\nbackend = \"openai\"\nbase_url = \"http://localhost:8000/v1\"\n\n\
+                 [server]\ndefault_model = \"{}\"\n",
+                model
+            )
+        };
+        let config = config_in(dir.path(), &remote("gpt-4o"));
+        assert_eq!(
+            statuses(&check_model(&config)),
+            [(Status::Pass, "gpt-4o (remote)")]
+        );
+
+        let config = config_in(dir.path(), &remote("models/mistral.gguf"));
+        let checks = check_model(&config);
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, Status::Fail);
+        assert!(checks[0].message.contains("only the local backend"));
+    }
+
+    #[test]
+    fn a_local_model_must_be_a_gguf_file_or_a_hub_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let local = |model: &str| {
+            format!(
+                "[ai]\nbackend = \"local\"\n\n[server]\ndefault_model = \"{}\"\n",
+                model
+            )
+        };
+        let missing = dir.path().join("missing.gguf");
+        let config = config_in(dir.path(), &local(&missing.to_string_lossy()));
+        assert_eq!(
+            statuses(&check_model(&config)),
+            [(
+                Status::Fail,
+                format!("model file {} does not exist", missing.display()).as_str()
+            )]
+        );
+
+        let config = config_in(dir.path(), &local("just a name"));
+        assert_eq!(
+            statuses(&check_model(&config)),
+            [(
+                Status::Fail,
+                "just a name is not a Hugging Face model id like owner/name"
+            )]
+        );
+    }
+}
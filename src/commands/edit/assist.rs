@@ -0,0 +1,240 @@
+use super::{
+    detect_language_from_line, fence_tags, find_code_blocks, split_fence, strip_block_prefix,
+    CodeBlock, CodeLanguage, LineLayout,
+};
+use crate::commands::weave::front_matter_range;
+use crate::utils::backup::Backup;
+use regex::Regex;
+use similar::TextDiff;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Lines of prose on each side of the block given to the model along with it.
+const PROSE_LINES: usize = 30;
+
+/// A code block of a Markdown file picked for the model to rewrite.
+pub struct BlockEdit {
+    path: PathBuf,
+    layout: LineLayout,
+    lines: Vec<String>,
+    block: CodeBlock,
+    /// Number of the block in the file, from 1.
+    number: usize,
+}
+
+impl BlockEdit {
+    /// Reads `path` and picks the block `selector` names: its number in the file, from 1, or
+    /// an identifier its code defines. Without a selector the file must hold a single block.
+    pub fn select(path: &Path, selector: Option<&str>) -> Result<BlockEdit, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let (lines, layout) = LineLayout::split(&content);
+        let mut blocks = find_code_blocks(&lines);
+        let index = match selector {
+            None if blocks.len() == 1 => 0,
+            None if blocks.is_empty() => {
+                return Err(format!("{} has no code block", path.display()))
+            }
+            None => {
+                return Err(format!(
+                    "{} has {} code blocks; choose one with --block <N|IDENTIFIER>",
+                    path.display(),
+                    blocks.len()
+                ))
+            }
+            Some(selector) => match selector.parse::<usize>() {
+                Ok(number) if (1..=blocks.len()).contains(&number) => number - 1,
+                Ok(number) => {
+                    return Err(format!(
+                        "{} has no code block {}; its blocks are numbered 1 to {}",
+                        path.display(),
+                        number,
+                        blocks.len()
+                    ))
+                }
+                Err(_) => find_definition(path, &lines, &blocks, selector)?,
+            },
+        };
+        Ok(BlockEdit {
+            path: path.to_path_buf(),
+            layout,
+            block: blocks.swap_remove(index),
+            number: index + 1,
+            lines,
+        })
+    }
+
+    /// The Markdown file the block is in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The block with the prose around it, as far as the neighbouring blocks or
+    /// [`PROSE_LINES`] lines away, leaving out the front matter.
+    pub fn context(&self) -> String {
+        let blocks = find_code_blocks(&self.lines);
+        let close = self.block.content_range().end;
+        let start = blocks
+            .iter()
+            .map(|block| block.content_range().end + 1)
+            .filter(|end| *end <= self.block.fence_index)
+            .chain(front_matter_range(&self.lines).map(|range| range.end + 1))
+            .max()
+            .unwrap_or(0)
+            .max(self.block.fence_index.saturating_sub(PROSE_LINES));
+        let end = blocks
+            .iter()
+            .map(|block| block.fence_index)
+            .find(|fence| *fence > close)
+            .unwrap_or(self.lines.len())
+            .min(close + 1 + PROSE_LINES)
+            .min(self.lines.len());
+        self.lines[start..end].join("\n")
+    }
+
+    /// What to ask the model so it rewrites the block as `instruction` says.
+    pub fn prompt(&self, instruction: &str) -> String {
+        let fence = match self.language_tag() {
+            "" => "```".to_string(),
+            tag => format!("```{}", tag),
+        };
+        format!(
+            "Rewrite code block {} of {}, shown in the context with the text around it: {}\n\n\
+             Answer with the whole new code as a single {} fenced block and nothing else.",
+            self.number,
+            self.path.display(),
+            instruction,
+            fence
+        )
+    }
+
+    /// The file with the code of the block replaced by the one of `answer`, keeping its
+    /// fences, or why `answer` is not a single fenced block of the same language.
+    pub fn apply(&self, answer: &str) -> Result<String, String> {
+        let code = self.answer_code(answer).ok_or_else(|| {
+            let language = match self.language_tag() {
+                "" => "untagged".to_string(),
+                tag => tag.to_string(),
+            };
+            format!(
+                "the model did not answer with a single {} code block, so {} is left as it is; its answer was:\n{}",
+                language,
+                self.path.display(),
+                answer.trim()
+            )
+        })?;
+        let prefix = &self.block.prefix;
+        let range = self.block.content_range();
+        let mut lines = self.lines[..range.start].to_vec();
+        lines.extend(code.iter().map(|line| {
+            if line.is_empty() {
+                prefix.trim_end().to_string()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        }));
+        lines.extend_from_slice(&self.lines[range.end..]);
+        let mut endings = self.layout.endings[..range.start].to_vec();
+        endings.extend(code.iter().map(|_| self.layout.line_ending));
+        endings.extend_from_slice(&self.layout.endings[range.end..]);
+        Ok(LineLayout::join(&lines, &endings))
+    }
+
+    /// The change from the file as it is to `content`, as a unified diff; empty when there
+    /// is none.
+    pub fn diff(&self, content: &str) -> String {
+        let old = LineLayout::join(&self.lines, &self.layout.endings);
+        let name = self.path.display().to_string();
+        TextDiff::from_lines(old.as_str(), content)
+            .unified_diff()
+            .header(&format!("a/{}", name), &format!("b/{}", name))
+            .to_string()
+    }
+
+    /// Writes `content` to the file after saving the original with `backup`, returning
+    /// where the original went.
+    pub fn write(&self, content: &str, backup: &Backup) -> io::Result<PathBuf> {
+        let saved = backup.save(&self.path)?;
+        fs::write(&self.path, content)?;
+        Ok(saved)
+    }
+
+    /// The tag of the block's language, else the first tag of its opening fence.
+    fn language_tag(&self) -> &str {
+        match self.block.language {
+            CodeLanguage::Unknown => self.fence_tag(),
+            language => language.tag(),
+        }
+    }
+
+    /// The first tag of the opening fence, like `rust`.
+    fn fence_tag(&self) -> &str {
+        let fence = &self.lines[self.block.fence_index];
+        let fence = split_fence(fence).map_or(fence.as_str(), |(_, fence)| fence);
+        fence_tags(fence).first().copied().unwrap_or("")
+    }
+
+    /// The code lines of `answer` if it is one fenced block in the language of the block.
+    fn answer_code(&self, answer: &str) -> Option<Vec<String>> {
+        let lines: Vec<&str> = answer.trim().lines().collect();
+        let (opening, rest) = lines.split_first()?;
+        let (closing, code) = rest.split_last()?;
+        if !opening.starts_with("```") || closing.trim() != "```" {
+            return None;
+        }
+        if code.iter().any(|line| line.trim_start().starts_with("```")) {
+            return None;
+        }
+        let same_language = match self.block.language {
+            CodeLanguage::Unknown => {
+                fence_tags(opening).first().copied().unwrap_or("") == self.fence_tag()
+            }
+            language => detect_language_from_line(opening) == language,
+        };
+        same_language.then(|| code.iter().map(|line| line.to_string()).collect())
+    }
+}
+
+/// Index of the block of `path` whose code defines `identifier`, as a function, class or
+/// type.
+fn find_definition(
+    path: &Path,
+    lines: &[String],
+    blocks: &[CodeBlock],
+    identifier: &str,
+) -> Result<usize, String> {
+    let pattern = format!(
+        r"^\s*(?:(?:pub(?:\([^)]*\))?|export|default|async|static|unsafe|abstract)\s+)*(?:fn|def|class|struct|enum|trait|type|interface|function\*?|impl)\s+{}\b",
+        regex::escape(identifier)
+    );
+    let definition = Regex::new(&pattern).map_err(|e| e.to_string())?;
+    let found: Vec<usize> = blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| {
+            lines[block.content_range()]
+                .iter()
+                .any(|line| definition.is_match(strip_block_prefix(line, &block.prefix)))
+        })
+        .map(|(index, _)| index)
+        .collect();
+    match found[..] {
+        [index] => Ok(index),
+        [] => Err(format!(
+            "no code block of {} defines {}",
+            path.display(),
+            identifier
+        )),
+        _ => Err(format!(
+            "code blocks {} of {} all define {}; choose one by number",
+            found
+                .iter()
+                .map(|index| (index + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            path.display(),
+            identifier
+        )),
+    }
+}
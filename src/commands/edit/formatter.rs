@@ -0,0 +1,926 @@
+use super::process::{run_with_timeout, ChildOutcome};
+use crate::utils::config::{Config, FormatLanguageSection};
+use crate::utils::utils::check_program_availability;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Placeholder in formatter arguments that is replaced by the path of the code to format.
+const FILE_PLACEHOLDER: &str = "{file}";
+
+/// How long a formatter may run on one code block before it is killed.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Editions accepted by `rustfmt --edition`.
+const RUST_EDITIONS: [&str; 4] = ["2015", "2018", "2021", "2024"];
+
+/// Edition used for Rust blocks when neither `Lila.toml` nor the project decides one.
+const DEFAULT_RUST_EDITION: &str = "2021";
+
+/// A simple enum to track recognized languages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CodeLanguage {
+    Python,
+    Rust,
+    JavaScript,
+    TypeScript,
+    Json,
+    Css,
+    Markdown,
+    C,
+    Cpp,
+    Go,
+    Shell,
+    Unknown,
+}
+
+impl CodeLanguage {
+    /// Maps a fence tag such as `python`, `py` or `c++` to a language.
+    pub fn from_tag(tag: &str) -> CodeLanguage {
+        match tag.to_lowercase().as_str() {
+            "python" | "py" => CodeLanguage::Python,
+            "rust" | "rs" => CodeLanguage::Rust,
+            "javascript" | "js" | "jsx" => CodeLanguage::JavaScript,
+            "typescript" | "ts" | "tsx" => CodeLanguage::TypeScript,
+            "json" => CodeLanguage::Json,
+            "css" => CodeLanguage::Css,
+            "markdown" | "md" => CodeLanguage::Markdown,
+            "c" | "h" => CodeLanguage::C,
+            "cpp" | "c++" | "cc" | "cxx" | "hpp" => CodeLanguage::Cpp,
+            "go" | "golang" => CodeLanguage::Go,
+            "sh" | "bash" | "shell" => CodeLanguage::Shell,
+            _ => CodeLanguage::Unknown,
+        }
+    }
+
+    /// Maps a file extension such as `py`, `tsx` or `hh` to a language.
+    pub fn from_extension(extension: &str) -> CodeLanguage {
+        match extension.to_lowercase().as_str() {
+            "pyi" => CodeLanguage::Python,
+            "mjs" | "cjs" => CodeLanguage::JavaScript,
+            "mts" | "cts" => CodeLanguage::TypeScript,
+            "hh" | "hxx" => CodeLanguage::Cpp,
+            "zsh" => CodeLanguage::Shell,
+            extension => CodeLanguage::from_tag(extension),
+        }
+    }
+
+    /// Human readable name used in summaries.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CodeLanguage::Python => "Python",
+            CodeLanguage::Rust => "Rust",
+            CodeLanguage::JavaScript => "JavaScript",
+            CodeLanguage::TypeScript => "TypeScript",
+            CodeLanguage::Json => "JSON",
+            CodeLanguage::Css => "CSS",
+            CodeLanguage::Markdown => "Markdown",
+            CodeLanguage::C => "C",
+            CodeLanguage::Cpp => "C++",
+            CodeLanguage::Go => "Go",
+            CodeLanguage::Shell => "shell",
+            CodeLanguage::Unknown => "unknown",
+        }
+    }
+
+    /// Canonical fence tag, also used as the `[format.<language>]` key.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            CodeLanguage::Python => "python",
+            CodeLanguage::Rust => "rust",
+            CodeLanguage::JavaScript => "javascript",
+            CodeLanguage::TypeScript => "typescript",
+            CodeLanguage::Json => "json",
+            CodeLanguage::Css => "css",
+            CodeLanguage::Markdown => "markdown",
+            CodeLanguage::C => "c",
+            CodeLanguage::Cpp => "cpp",
+            CodeLanguage::Go => "go",
+            CodeLanguage::Shell => "shell",
+            CodeLanguage::Unknown => "unknown",
+        }
+    }
+
+    /// File extension of the temporary file, so the formatter picks the right parser.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CodeLanguage::Python => "py",
+            CodeLanguage::Rust => "rs",
+            CodeLanguage::JavaScript => "js",
+            CodeLanguage::TypeScript => "ts",
+            CodeLanguage::Json => "json",
+            CodeLanguage::Css => "css",
+            CodeLanguage::Markdown => "md",
+            CodeLanguage::C => "c",
+            CodeLanguage::Cpp => "cpp",
+            CodeLanguage::Go => "go",
+            CodeLanguage::Shell => "sh",
+            CodeLanguage::Unknown => "txt",
+        }
+    }
+}
+
+/// Describes how to run an external formatter for one language.
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    /// Language whose code blocks this formatter handles.
+    pub language: CodeLanguage,
+    /// Executable looked up on `PATH`.
+    pub command: String,
+    /// Arguments; `{file}` is replaced by the path of a file formatted in place.
+    /// Without `{file}`, the code is piped through stdin and read back from stdout.
+    pub args: Vec<String>,
+    /// Opt-in formatters only run when this variable is set to `true` in `.env` or the environment.
+    pub enable_env: Option<&'static str>,
+}
+
+impl Formatter {
+    fn new(language: CodeLanguage, command: &str, args: &[&str]) -> Self {
+        Formatter {
+            language,
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            enable_env: None,
+        }
+    }
+
+    /// Returns this formatter passing `width` with its native option, or `None` if
+    /// it has no line width option. A width already set in `args` is kept.
+    pub fn with_line_width(&self, width: usize) -> Option<Formatter> {
+        let stem = Path::new(&self.command).file_stem()?.to_str()?;
+        let mut formatter = self.clone();
+        let has_arg = |name: &str| self.args.iter().any(|a| a.starts_with(name));
+        match stem {
+            "black" | "ruff" => {
+                if !has_arg("--line-length") {
+                    formatter
+                        .args
+                        .extend(["--line-length".to_string(), width.to_string()]);
+                }
+            }
+            "prettier" => {
+                if !has_arg("--print-width") {
+                    formatter
+                        .args
+                        .extend(["--print-width".to_string(), width.to_string()]);
+                }
+            }
+            "rustfmt" => {
+                let max_width = format!("max_width={}", width);
+                // rustfmt accepts `--config` once, so join an existing one.
+                match self.args.iter().position(|a| a == "--config") {
+                    Some(i) if i + 1 < self.args.len() => {
+                        if !self.args[i + 1].contains("max_width") {
+                            formatter.args[i + 1] = format!("{},{}", self.args[i + 1], max_width);
+                        }
+                    }
+                    _ => formatter.args.extend(["--config".to_string(), max_width]),
+                }
+            }
+            _ => return None,
+        }
+        Some(formatter)
+    }
+
+    /// Marks the formatter as opt-in via `env_var`.
+    fn opt_in(mut self, env_var: &'static str) -> Self {
+        self.enable_env = Some(env_var);
+        self
+    }
+}
+
+/// Parses a `--lang` value, accepting only languages that have a built-in formatter.
+pub fn parse_language(tag: &str) -> Result<CodeLanguage, String> {
+    let builtin = builtin_formatters();
+    let language = CodeLanguage::from_tag(tag.trim());
+    if builtin.iter().any(|f| f.language == language) {
+        return Ok(language);
+    }
+    let mut known: Vec<&str> = builtin.iter().map(|f| f.language.tag()).collect();
+    known.dedup();
+    Err(format!(
+        "unknown language `{}` (expected one of: {})",
+        tag,
+        known.join(", ")
+    ))
+}
+
+/// All formatters known to `lila edit` out of the box, one per language.
+///
+/// Each of them reads the code from stdin and writes the result to stdout.
+pub fn builtin_formatters() -> Vec<Formatter> {
+    let prettier = |language: CodeLanguage| {
+        let path = format!("block.{}", language.extension());
+        Formatter::new(
+            language,
+            "prettier",
+            &["--log-level", "silent", "--stdin-filepath", &path],
+        )
+        .opt_in("PRETTIER_ENABLED")
+    };
+    let clang_format = |language: CodeLanguage| {
+        let path = format!("--assume-filename=block.{}", language.extension());
+        Formatter::new(language, "clang-format", &[&path])
+    };
+    vec![
+        Formatter::new(CodeLanguage::Python, "black", &["--quiet", "-"]),
+        Formatter::new(CodeLanguage::Rust, "rustfmt", &[]),
+        prettier(CodeLanguage::JavaScript),
+        prettier(CodeLanguage::TypeScript),
+        prettier(CodeLanguage::Json),
+        prettier(CodeLanguage::Css),
+        prettier(CodeLanguage::Markdown),
+        clang_format(CodeLanguage::C),
+        clang_format(CodeLanguage::Cpp),
+        Formatter::new(CodeLanguage::Go, "gofmt", &[]),
+        Formatter::new(CodeLanguage::Shell, "shfmt", &[]),
+    ]
+}
+
+/// Settings from the `[format]` section of `Lila.toml`.
+///
+/// ```toml
+/// [format]
+/// timeout_secs = 30
+/// rust_edition = "2021"
+/// rustfmt_config = "book/rustfmt.toml"
+/// line_width = 80
+/// available = ["black", "rustfmt", "ruff"]
+///
+/// [format.python]
+/// command = "ruff"
+/// args = ["format", "--quiet", "{file}"]
+/// line_width = 88
+/// ```
+#[derive(Debug)]
+pub struct FormatConfig {
+    /// Formatters replacing the built-in one for their language.
+    pub overrides: Vec<Formatter>,
+    /// Maximum run time of a formatter on a single code block.
+    pub timeout: Duration,
+    /// Edition passed to rustfmt instead of the one found in `Cargo.toml`.
+    pub rust_edition: Option<String>,
+    /// rustfmt configuration used instead of the project's `rustfmt.toml`.
+    pub rustfmt_config: Option<PathBuf>,
+    /// Line width for every language, instead of the `.editorconfig` one.
+    pub line_width: Option<usize>,
+    /// Line widths from `[format.<language>]`, taking precedence over `line_width`.
+    pub line_widths: HashMap<CodeLanguage, usize>,
+    /// Formatters `lila init` found installed; `None` when it did not record them.
+    pub available: Option<Vec<String>>,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            overrides: Vec::new(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            rust_edition: None,
+            rustfmt_config: None,
+            line_width: None,
+            line_widths: HashMap::new(),
+            available: None,
+        }
+    }
+}
+
+impl FormatConfig {
+    /// Reads the `[format]` section of `config`.
+    ///
+    /// A missing section yields the defaults; invalid entries are reported
+    /// all at once so they surface when `lila edit` starts, not per code block.
+    pub fn load(config: &Config) -> io::Result<FormatConfig> {
+        let format = &config.format;
+        let mut result = FormatConfig::default();
+        let mut errors = Vec::new();
+
+        if let Some(secs) = format.timeout_secs {
+            match secs {
+                secs if secs > 0 => result.timeout = Duration::from_secs(secs as u64),
+                _ => errors.push("format.timeout_secs: must be a positive integer".to_string()),
+            }
+        }
+        if let Some(edition) = &format.rust_edition {
+            if RUST_EDITIONS.contains(&edition.as_str()) {
+                result.rust_edition = Some(edition.clone());
+            } else {
+                errors.push(format!(
+                    "format.rust_edition: must be one of {}",
+                    RUST_EDITIONS.join(", ")
+                ));
+            }
+        }
+        if let Some(config_path) = &format.rustfmt_config {
+            let resolved = config.resolve(config_path);
+            if resolved.exists() {
+                result.rustfmt_config = Some(resolved);
+            } else {
+                errors.push(format!(
+                    "format.rustfmt_config: {} does not exist",
+                    config_path.display()
+                ));
+            }
+        }
+        if let Some(width) = format.line_width {
+            match parse_line_width(width) {
+                Ok(width) => result.line_width = Some(width),
+                Err(e) => errors.push(format!("format.line_width: {}", e)),
+            }
+        }
+        result.available = format.available.clone();
+
+        for (key, entry) in &format.languages {
+            let language = CodeLanguage::from_tag(key);
+            if language == CodeLanguage::Unknown {
+                errors.push(format!("format.{}: unknown language", key));
+                continue;
+            }
+
+            if let Some(width) = entry.line_width {
+                match parse_line_width(width) {
+                    Ok(width) => {
+                        result.line_widths.insert(language, width);
+                    }
+                    Err(e) => errors.push(format!("format.{}.line_width: {}", key, e)),
+                }
+                // A table with only a line width keeps the built-in formatter.
+                if entry.command.is_none() && entry.args.is_none() {
+                    continue;
+                }
+            }
+
+            match parse_formatter_entry(language, entry) {
+                Ok(formatter) => result.overrides.push(formatter),
+                Err(e) => errors.push(format!("format.{}: {}", key, e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(invalid_config(config.path(), errors));
+        }
+        Ok(result)
+    }
+}
+
+/// Edition and configuration rustfmt uses for the Rust blocks of a project.
+///
+/// rustfmt runs on a temporary file outside the project, so it would neither find the
+/// project's `rustfmt.toml` nor know its edition; both are passed explicitly instead.
+#[derive(Debug)]
+pub struct RustfmtSettings {
+    /// Edition passed with `--edition`; `None` leaves it to the config file.
+    pub edition: Option<String>,
+    /// Where the edition came from, for verbose output.
+    pub edition_source: String,
+    /// Configuration file passed with `--config-path`.
+    pub config_path: Option<PathBuf>,
+}
+
+impl RustfmtSettings {
+    /// Resolves the settings for the project in `project_dir`, preferring the
+    /// `[format]` overrides over `rustfmt.toml`/`.rustfmt.toml` and `Cargo.toml`.
+    pub fn resolve(config: &FormatConfig, project_dir: &Path) -> RustfmtSettings {
+        let config_path = config.rustfmt_config.clone().or_else(|| {
+            ["rustfmt.toml", ".rustfmt.toml"]
+                .iter()
+                .map(|name| project_dir.join(name))
+                .find(|path| path.is_file())
+        });
+
+        let (edition, edition_source) = if let Some(edition) = &config.rust_edition {
+            (Some(edition.clone()), "Lila.toml".to_string())
+        } else if let Some(edition) = cargo_edition(&project_dir.join("Cargo.toml")) {
+            (Some(edition), "Cargo.toml".to_string())
+        } else if let Some(path) = config_path
+            .as_deref()
+            .filter(|path| read_toml(path).is_some_and(|v| v.get("edition").is_some()))
+        {
+            (None, path.display().to_string())
+        } else {
+            (
+                Some(DEFAULT_RUST_EDITION.to_string()),
+                "default".to_string(),
+            )
+        };
+
+        RustfmtSettings {
+            edition,
+            edition_source,
+            config_path,
+        }
+    }
+
+    /// Returns `formatter` with `--edition` and `--config-path` added in front of its
+    /// arguments. Formatters other than rustfmt and options already set are left alone.
+    pub fn apply(&self, formatter: &Formatter) -> Formatter {
+        let mut formatter = formatter.clone();
+        let is_rustfmt = Path::new(&formatter.command)
+            .file_stem()
+            .is_some_and(|stem| stem == "rustfmt");
+        if !is_rustfmt {
+            return formatter;
+        }
+
+        let has_arg = |name: &str| formatter.args.iter().any(|a| a.starts_with(name));
+        let mut extra = Vec::new();
+        if let Some(edition) = &self.edition {
+            if !has_arg("--edition") {
+                extra.extend(["--edition".to_string(), edition.clone()]);
+            }
+        }
+        if let Some(config_path) = &self.config_path {
+            if !has_arg("--config-path") {
+                extra.extend([
+                    "--config-path".to_string(),
+                    config_path.to_string_lossy().to_string(),
+                ]);
+            }
+        }
+        formatter.args.splice(0..0, extra);
+        formatter
+    }
+
+    /// One-line description for verbose output.
+    pub fn describe(&self) -> String {
+        let edition = match &self.edition {
+            Some(edition) => format!("edition {} ({})", edition, self.edition_source),
+            None => format!("edition from {}", self.edition_source),
+        };
+        match &self.config_path {
+            Some(path) => format!("{}, config {}", edition, path.display()),
+            None => format!("{}, no rustfmt config", edition),
+        }
+    }
+}
+
+/// Reads the edition of the package described by `cargo_toml`, following
+/// `edition.workspace = true` to the workspace table of the same file.
+fn cargo_edition(cargo_toml: &Path) -> Option<String> {
+    let manifest = read_toml(cargo_toml)?;
+    let edition = manifest.get("package")?.get("edition")?;
+    if let Some(edition) = edition.as_str() {
+        return Some(edition.to_string());
+    }
+    manifest
+        .get("workspace")?
+        .get("package")?
+        .get("edition")?
+        .as_str()
+        .map(|s| s.to_string())
+}
+
+fn read_toml(path: &Path) -> Option<toml::Value> {
+    let content = fs::read_to_string(path).ok()?;
+    toml::from_str(&content).ok()
+}
+
+fn parse_line_width(width: i64) -> Result<usize, String> {
+    match width {
+        width if width > 0 => Ok(width as usize),
+        _ => Err("must be a positive integer".to_string()),
+    }
+}
+
+/// Builds a formatter from a `[format.<language>]` table, validating command and placeholders.
+fn parse_formatter_entry(
+    language: CodeLanguage,
+    entry: &FormatLanguageSection,
+) -> Result<Formatter, String> {
+    let command = match &entry.command {
+        Some(s) if !s.trim().is_empty() => s.trim().to_string(),
+        Some(_) => return Err("`command` must be a non-empty string".to_string()),
+        None => return Err("missing `command`".to_string()),
+    };
+    let args = entry
+        .args
+        .clone()
+        .unwrap_or_else(|| vec![FILE_PLACEHOLDER.to_string()]);
+
+    let placeholder_re = Regex::new(r"\{[^}]*\}").unwrap();
+    for arg in &args {
+        if let Some(bad) = placeholder_re
+            .find_iter(arg)
+            .find(|m| m.as_str() != FILE_PLACEHOLDER)
+        {
+            return Err(format!(
+                "unknown placeholder `{}` (only {} is supported)",
+                bad.as_str(),
+                FILE_PLACEHOLDER
+            ));
+        }
+    }
+    Ok(Formatter {
+        language,
+        command,
+        args,
+        enable_env: None,
+    })
+}
+
+fn invalid_config(path: &Path, errors: Vec<String>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "invalid [format] configuration in {}:\n  {}",
+            path.display(),
+            errors.join("\n  ")
+        ),
+    )
+}
+
+/// Name of the `.env` flag `lila init` records for a formatter, e.g. `CLANG_FORMAT_INSTALLED`.
+pub fn installed_env_var(command: &str) -> String {
+    format!("{}_INSTALLED", command.to_uppercase().replace('-', "_"))
+}
+
+/// Name of the `.env` entry `lila init` records a formatter's version in, e.g.
+/// `CLANG_FORMAT_VERSION`.
+pub fn version_env_var(command: &str) -> String {
+    format!("{}_VERSION", command.to_uppercase().replace('-', "_"))
+}
+
+/// Commands of the formatters `lila init` looks for: the built-in ones, then ruff, which
+/// projects often configure instead of black.
+pub fn known_formatter_commands() -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    for formatter in builtin_formatters() {
+        if !commands.contains(&formatter.command) {
+            commands.push(formatter.command);
+        }
+    }
+    commands.push("ruff".to_string());
+    commands
+}
+
+/// What `lila init` found out about a formatter.
+pub struct FormatterProbe {
+    pub command: String,
+    /// Whether the formatter answered; one that hangs counts as missing.
+    pub installed: bool,
+    /// Version it printed, like "24.2.0".
+    pub version: Option<String>,
+    /// How long it ran before it was killed, if it hung.
+    pub timed_out: Option<Duration>,
+}
+
+/// Runs `command --version`, killing it after `timeout`, to find whether the formatter is
+/// installed and which version. gofmt has no `--version` and is released with Go, so its
+/// version is the one of `go version`.
+pub fn probe_formatter(command: &str, timeout: Duration) -> FormatterProbe {
+    let mut probe = FormatterProbe {
+        command: command.to_string(),
+        installed: false,
+        version: None,
+        timed_out: None,
+    };
+    match version_output(command, &["--version"], timeout) {
+        Ok(ChildOutcome::Exited(_, output)) => {
+            probe.installed = true;
+            probe.version = parse_version(&output);
+        }
+        Ok(ChildOutcome::TimedOut(elapsed)) => probe.timed_out = Some(elapsed),
+        Err(_) => {}
+    }
+    if probe.installed && command == "gofmt" {
+        if let Ok(ChildOutcome::Exited(_, output)) = version_output("go", &["version"], timeout) {
+            probe.version = parse_version(&output);
+        }
+    }
+    probe
+}
+
+/// Runs `program` with `args` and no input, returning what it printed on stdout.
+fn version_output(program: &str, args: &[&str], timeout: Duration) -> io::Result<ChildOutcome> {
+    let mut command = Command::new(program);
+    command.args(args).stderr(Stdio::null());
+    run_with_timeout(&mut command, timeout, Some(""))
+}
+
+/// The first version number in `output`, like "1.22.1" for "go version go1.22.1 linux/amd64".
+fn parse_version(output: &[u8]) -> Option<String> {
+    let version = Regex::new(r"\d+\.\d+(?:\.\d+)?").ok()?;
+    let output = String::from_utf8_lossy(output);
+    version.find(&output).map(|m| m.as_str().to_string())
+}
+
+/// Checks whether a formatter can be used, trusting the `<FORMATTER>_INSTALLED` flag
+/// written by `lila init` and only probing the system when the flag is unset.
+pub fn formatter_installed(formatter: &Formatter) -> bool {
+    match std::env::var(installed_env_var(&formatter.command)) {
+        Ok(val) => val.trim().eq_ignore_ascii_case("true"),
+        Err(_) => check_program_availability(&formatter.command),
+    }
+}
+
+/// Returns true if `name` is set to `true` in `.env` or the environment.
+pub fn env_flag_is_true(name: &str) -> bool {
+    matches!(std::env::var(name), Ok(val) if val.to_lowercase() == "true")
+}
+
+/// Format the snippet in `code_lines` using the given `formatter`.
+///
+/// The code is piped through the formatter, or written to a temporary file for formatters
+/// whose arguments name `{file}`. The formatter is killed if it runs longer than `timeout`.
+/// A failing, crashing or timed-out formatter is reported as an error so the caller can
+/// keep the original block.
+pub fn format_code_snippet(
+    code_lines: &[String],
+    formatter: &Formatter,
+    timeout: Duration,
+) -> io::Result<Vec<String>> {
+    let mut code = code_lines.join("\n");
+    code.push('\n');
+
+    let mut command = Command::new(&formatter.command);
+    command.stderr(Stdio::null());
+
+    let uses_file = formatter.args.iter().any(|a| a.contains(FILE_PLACEHOLDER));
+    let temp_file = if uses_file {
+        // Keep the extension so the formatter picks the right parser.
+        let mut temp_file = tempfile::Builder::new()
+            .suffix(&format!(".{}", formatter.language.extension()))
+            .tempfile()?;
+        temp_file.write_all(code.as_bytes())?;
+        temp_file.flush()?;
+        let temp_path = temp_file.path().to_string_lossy().to_string();
+        command.args(
+            formatter
+                .args
+                .iter()
+                .map(|a| a.replace(FILE_PLACEHOLDER, &temp_path)),
+        );
+        Some(temp_file)
+    } else {
+        command.args(&formatter.args);
+        None
+    };
+
+    let input = if uses_file { None } else { Some(code.as_str()) };
+    let outcome = run_with_timeout(&mut command, timeout, input).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("could not run {}: {}", formatter.command, e),
+        )
+    })?;
+
+    match outcome {
+        ChildOutcome::Exited(s, stdout) if s.success() => {
+            // Read back the newly formatted code.
+            let formatted_code = match &temp_file {
+                Some(temp_file) => fs::read_to_string(temp_file.path())?,
+                None => String::from_utf8(stdout).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} printed invalid UTF-8", formatter.command),
+                    )
+                })?,
+            };
+            let formatted_lines = formatted_code
+                .lines()
+                .map(|s| s.to_string())
+                .collect::<Vec<String>>();
+            Ok(formatted_lines)
+        }
+        ChildOutcome::Exited(s, _) => Err(io::Error::other(match s.code() {
+            Some(code) => format!("{} exited {}", formatter.command, code),
+            None => format!("{} was terminated by a signal", formatter.command),
+        })),
+        ChildOutcome::TimedOut(elapsed) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "{} was killed after {:.1}s",
+                formatter.command,
+                elapsed.as_secs_f64()
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A formatter running the shell `script`, which gets `args` after it.
+    fn script_formatter(dir: &Path, script: &str, args: &[&str]) -> Formatter {
+        let path = dir.join("formatter.sh");
+        fs::write(&path, script).unwrap();
+        let mut all_args = vec![path.to_string_lossy().into_owned()];
+        all_args.extend(args.iter().map(|a| a.to_string()));
+        Formatter {
+            language: CodeLanguage::Python,
+            command: "sh".to_string(),
+            args: all_args,
+            enable_env: None,
+        }
+    }
+
+    fn lines(code: &str) -> Vec<String> {
+        code.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn custom_formatter_gets_its_arguments_and_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("args.log");
+        let script = format!(
+            "echo \"$@\" > '{}'\ntr a-z A-Z < \"$2\" > \"$2.out\" && mv \"$2.out\" \"$2\"\n",
+            log.display()
+        );
+        let formatter = script_formatter(dir.path(), &script, &["--check-style", "{file}"]);
+
+        let formatted = format_code_snippet(
+            &lines("x = 1\nprint(x)"),
+            &formatter,
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+        assert_eq!(formatted, lines("X = 1\nPRINT(X)"));
+        let logged = fs::read_to_string(&log).unwrap();
+        let args: Vec<&str> = logged.split_whitespace().collect();
+        assert_eq!(args[0], "--check-style");
+        assert!(args[1].ends_with(".py"), "{:?}", args);
+        assert!(!args[1].contains(FILE_PLACEHOLDER));
+    }
+
+    #[test]
+    fn formatter_without_file_placeholder_reads_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let formatter = script_formatter(dir.path(), "tr a-z A-Z\n", &[]);
+        let formatted =
+            format_code_snippet(&lines("fn main() {}"), &formatter, Duration::from_secs(10))
+                .unwrap();
+        assert_eq!(formatted, lines("FN MAIN() {}"));
+    }
+
+    #[test]
+    fn failing_formatter_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let formatter = script_formatter(dir.path(), "exit 3\n", &[]);
+        let error = format_code_snippet(&lines("x"), &formatter, Duration::from_secs(10))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "sh exited 3");
+    }
+
+    #[test]
+    fn slow_formatter_is_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let formatter = script_formatter(dir.path(), "sleep 10\n", &[]);
+        let error =
+            format_code_snippet(&lines("x"), &formatter, Duration::from_millis(200)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn format_section_overrides_the_builtin_formatter() {
+        let toml = r#"
+[format]
+timeout_secs = 5
+
+[format.python]
+command = "ruff"
+args = ["format", "--quiet", "{file}"]
+"#;
+        let (config, _) = Config::parse(Path::new("Lila.toml"), toml).unwrap();
+        let format = FormatConfig::load(&config).unwrap();
+        assert_eq!(format.timeout, Duration::from_secs(5));
+        assert_eq!(format.overrides.len(), 1);
+        assert_eq!(format.overrides[0].language, CodeLanguage::Python);
+        assert_eq!(format.overrides[0].command, "ruff");
+        assert_eq!(format.overrides[0].args, ["format", "--quiet", "{file}"]);
+    }
+
+    #[test]
+    fn invalid_format_entries_are_reported_together() {
+        let toml = r#"
+[format.python]
+args = ["{file}"]
+
+[format.rust]
+command = "rustfmt"
+args = ["{path}"]
+"#;
+        let (config, _) = Config::parse(Path::new("Lila.toml"), toml).unwrap();
+        let error = FormatConfig::load(&config).unwrap_err().to_string();
+        assert!(
+            error.contains("format.python: missing `command`"),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("format.rust: unknown placeholder `{path}`"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn line_width_joins_an_existing_rustfmt_config() {
+        let rustfmt = Formatter::new(CodeLanguage::Rust, "rustfmt", &["--config", "tab_spaces=2"]);
+        let with_width = rustfmt.with_line_width(80).unwrap();
+        assert_eq!(with_width.args, ["--config", "tab_spaces=2,max_width=80"]);
+        let black = Formatter::new(CodeLanguage::Python, "black", &["-"]);
+        assert_eq!(
+            black.with_line_width(88).unwrap().args,
+            ["-", "--line-length", "88"]
+        );
+        let gofmt = Formatter::new(CodeLanguage::Go, "gofmt", &[]);
+        assert!(gofmt.with_line_width(100).is_none());
+    }
+
+    fn builtin_rustfmt() -> Formatter {
+        builtin_formatters()
+            .into_iter()
+            .find(|f| f.language == CodeLanguage::Rust)
+            .unwrap()
+    }
+
+    #[test]
+    fn rustfmt_gets_the_project_edition_and_config() {
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("Cargo.toml"),
+            "[package]\nname = \"book\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        let rustfmt_toml = project.path().join(".rustfmt.toml");
+        fs::write(&rustfmt_toml, "max_width = 100\n").unwrap();
+
+        let settings = RustfmtSettings::resolve(&FormatConfig::default(), project.path());
+        assert_eq!(settings.edition.as_deref(), Some("2021"));
+        assert_eq!(settings.edition_source, "Cargo.toml");
+        assert_eq!(
+            settings.config_path.as_deref(),
+            Some(rustfmt_toml.as_path())
+        );
+        assert_eq!(
+            settings.apply(&builtin_rustfmt()).args,
+            [
+                "--edition".to_string(),
+                "2021".to_string(),
+                "--config-path".to_string(),
+                rustfmt_toml.to_string_lossy().into_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn lila_toml_edition_wins_and_workspace_editions_are_followed() {
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("Cargo.toml"),
+            "[package]\nname = \"book\"\nedition.workspace = true\n\n\
+             [workspace.package]\nedition = \"2018\"\n",
+        )
+        .unwrap();
+        let settings = RustfmtSettings::resolve(&FormatConfig::default(), project.path());
+        assert_eq!(settings.edition.as_deref(), Some("2018"));
+
+        let config = FormatConfig {
+            rust_edition: Some("2024".to_string()),
+            ..FormatConfig::default()
+        };
+        let settings = RustfmtSettings::resolve(&config, project.path());
+        assert_eq!(settings.edition.as_deref(), Some("2024"));
+        assert_eq!(settings.edition_source, "Lila.toml");
+        assert!(settings.config_path.is_none());
+    }
+
+    #[test]
+    fn edition_2021_code_formats_with_the_project_edition() {
+        if !check_program_availability("rustfmt") {
+            eprintln!("rustfmt is not installed; skipping");
+            return;
+        }
+        let code = [
+            "async fn fetch() -> u32 {",
+            "let Some(x) = None::<u32> else { return 0 };",
+            "    x }",
+        ]
+        .map(String::from)
+        .to_vec();
+        let timeout = Duration::from_secs(30);
+        // Bare rustfmt parses Rust 2015, which has no `async fn`.
+        assert!(format_code_snippet(&code, &builtin_rustfmt(), timeout).is_err());
+
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("Cargo.toml"),
+            "[package]\nname = \"book\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        let settings = RustfmtSettings::resolve(&FormatConfig::default(), project.path());
+        let formatted = format_code_snippet(&code, &settings.apply(&builtin_rustfmt()), timeout);
+        assert_eq!(
+            formatted.unwrap(),
+            lines(
+                "async fn fetch() -> u32 {\n    let Some(x) = None::<u32> else { return 0 };\n    x\n}"
+            )
+        );
+    }
+}
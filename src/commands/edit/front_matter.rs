@@ -0,0 +1,178 @@
+use crate::commands::weave::{front_matter_range, parse_front_matter_yaml, FRONT_MATTER_KEYS};
+use serde_yaml::{Mapping, Value};
+
+/// Result of normalizing the front matter of one file.
+pub enum Normalized {
+    /// The file has no front matter.
+    Missing,
+    /// The front matter is already in canonical form.
+    Unchanged,
+    /// The front matter lines should be replaced by these.
+    Rewritten(Vec<String>),
+    /// The front matter is kept as written, for the given reason.
+    Skipped(String),
+}
+
+/// Re-serializes the YAML front matter of `lines` with lila's keys first and custom keys
+/// sorted alphabetically after them.
+///
+/// Front matter is only rewritten when the result parses to the same YAML; anything that
+/// is not valid lila front matter is reported as an error with its line in the file.
+pub fn normalize_front_matter(lines: &[String]) -> Result<Normalized, String> {
+    let Some(range) = front_matter_range(lines) else {
+        return Ok(Normalized::Missing);
+    };
+    if lines[range.clone()].iter().any(|l| has_comment(l)) {
+        return Ok(Normalized::Skipped(
+            "front matter has comments, which re-serializing would drop".to_string(),
+        ));
+    }
+    let yaml = lines[range.clone()].join("\n");
+    // Line of the first YAML line in the file, for error messages.
+    let first_line = range.start + 1;
+
+    let located = |e: serde_yaml::Error| match e.location() {
+        Some(loc) => format!("line {}: {}", first_line + loc.line() - 1, e),
+        None => format!("line {}: {}", first_line, e),
+    };
+
+    let original: Value = serde_yaml::from_str(&yaml).map_err(located)?;
+    let Value::Mapping(mapping) = &original else {
+        return Err(format!(
+            "line {}: front matter must be a mapping",
+            first_line
+        ));
+    };
+    parse_front_matter_yaml(&yaml).map_err(located)?;
+
+    let mut known = Vec::new();
+    let mut custom = Vec::new();
+    for (key, value) in mapping {
+        match key
+            .as_str()
+            .and_then(|k| FRONT_MATTER_KEYS.iter().position(|f| *f == k))
+        {
+            Some(order) => known.push((order, key, value)),
+            None => custom.push((key, value)),
+        }
+    }
+    known.sort_by_key(|(order, _, _)| *order);
+    custom.sort_by_key(|(key, _)| yaml_key(key));
+
+    let mut sorted = Mapping::new();
+    for (_, key, value) in known {
+        sorted.insert(key.clone(), value.clone());
+    }
+    for (key, value) in custom {
+        sorted.insert(key.clone(), value.clone());
+    }
+
+    let serialized = serde_yaml::to_string(&Value::Mapping(sorted)).map_err(|e| e.to_string())?;
+    let reparsed: Value = serde_yaml::from_str(&serialized).map_err(|e| e.to_string())?;
+    if reparsed != original {
+        return Err(format!(
+            "line {}: front matter would change meaning when re-serialized, left as is",
+            first_line
+        ));
+    }
+
+    let normalized: Vec<String> = serialized.lines().map(|l| l.to_string()).collect();
+    if normalized[..] == lines[range] {
+        Ok(Normalized::Unchanged)
+    } else {
+        Ok(Normalized::Rewritten(normalized))
+    }
+}
+
+/// Whether a YAML `line` has a comment: a `#` at its start or after a blank, outside
+/// quoted scalars. Quotes only open a scalar where one can start, so the apostrophe in
+/// `title: Don't # draft` does not hide the comment.
+fn has_comment(line: &str) -> bool {
+    let mut quote: Option<char> = None;
+    let mut previous: Option<char> = None;
+    // Last non-blank character, to tell where a scalar can start.
+    let mut last_token: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some('"') if c == '\\' => {
+                chars.next();
+            }
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '#' if previous.is_none_or(char::is_whitespace) => return true,
+                '"' | '\''
+                    if last_token
+                        .is_none_or(|t| matches!(t, ':' | '-' | '?' | '[' | '{' | ','))
+                        && previous
+                            .is_none_or(|p| p.is_whitespace() || matches!(p, '[' | '{' | ',')) =>
+                {
+                    quote = Some(c)
+                }
+                _ => {}
+            },
+        }
+        if !c.is_whitespace() {
+            last_token = Some(c);
+        }
+        previous = Some(c);
+    }
+    false
+}
+
+/// Sort key for custom keys; non-string keys sort by their YAML form.
+fn yaml_key(key: &Value) -> String {
+    match key.as_str() {
+        Some(s) => s.to_string(),
+        None => serde_yaml::to_string(key).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(content: &str) -> Vec<String> {
+        content.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn comments_are_found_outside_quoted_scalars() {
+        for line in [
+            "# draft",
+            "  # indented",
+            "title: Intro  # shown in TOC",
+            "title: \"Intro\" # quoted, then a comment",
+            "title: Don't # the apostrophe is not a quote",
+            "tags: [a, b] # flow sequence",
+        ] {
+            assert!(has_comment(line), "{line}");
+        }
+        for line in [
+            "title: \"Issue #4\"",
+            "title: 'Part # two'",
+            "title: \"say \\\" # still quoted\"",
+            "language: C#",
+            "url: https://example.com/#anchor",
+            "tags: [a, \"b # c\"]",
+        ] {
+            assert!(!has_comment(line), "{line}");
+        }
+    }
+
+    #[test]
+    fn front_matter_with_an_inline_comment_is_skipped() {
+        let lines = split("---\ntitle: Intro  # shown in TOC\nauthor: Ada\n---\n\n# Intro");
+        assert!(matches!(
+            normalize_front_matter(&lines),
+            Ok(Normalized::Skipped(_))
+        ));
+
+        let lines = split("---\ntitle: \"Issue #4\"\n---\n\n# Intro");
+        assert!(!matches!(
+            normalize_front_matter(&lines),
+            Ok(Normalized::Skipped(_))
+        ));
+    }
+}
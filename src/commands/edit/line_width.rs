@@ -0,0 +1,122 @@
+use super::formatter::{CodeLanguage, FormatConfig};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Maximum line length for code blocks, from `Lila.toml` or the project's `.editorconfig`.
+pub struct LineWidths {
+    default: Option<usize>,
+    per_language: HashMap<CodeLanguage, usize>,
+    editorconfig: EditorConfig,
+}
+
+impl LineWidths {
+    /// Collects the widths configured for the project in `project_dir`.
+    pub fn resolve(config: &FormatConfig, project_dir: &Path) -> LineWidths {
+        LineWidths {
+            default: config.line_width,
+            per_language: config.line_widths.clone(),
+            editorconfig: EditorConfig::load(&project_dir.join(".editorconfig")),
+        }
+    }
+
+    /// Width for `language` and where it came from: `[format.<language>]`, then
+    /// `[format]`, then the `.editorconfig` section matching the language's files.
+    pub fn for_language(&self, language: CodeLanguage) -> Option<(usize, String)> {
+        if let Some(width) = self.per_language.get(&language) {
+            return Some((*width, format!("Lila.toml [format.{}]", language.tag())));
+        }
+        if let Some(width) = self.default {
+            return Some((width, "Lila.toml [format]".to_string()));
+        }
+        let file_name = format!("block.{}", language.extension());
+        self.editorconfig
+            .max_line_length(&file_name)
+            .map(|(width, section)| (width, format!(".editorconfig [{}]", section)))
+    }
+}
+
+/// The `max_line_length` settings of an `.editorconfig` file.
+struct EditorConfig {
+    /// Section glob, its pattern, and its width; `None` for `max_line_length = off`.
+    sections: Vec<(String, Regex, Option<usize>)>,
+}
+
+impl EditorConfig {
+    /// Reads `path`; a missing or unreadable file has no sections.
+    fn load(path: &Path) -> EditorConfig {
+        let mut sections = Vec::new();
+        let Ok(content) = fs::read_to_string(path) else {
+            return EditorConfig { sections };
+        };
+
+        let mut current: Option<(String, Regex)> = None;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                current = glob_to_regex(glob).map(|re| (glob.to_string(), re));
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            if !key.trim().eq_ignore_ascii_case("max_line_length") {
+                continue;
+            }
+            if let Some((glob, re)) = &current {
+                let value = value.trim();
+                let width = if value.eq_ignore_ascii_case("off") {
+                    None
+                } else {
+                    match value.parse::<usize>() {
+                        Ok(width) if width > 0 => Some(width),
+                        _ => continue,
+                    }
+                };
+                sections.push((glob.clone(), re.clone(), width));
+            }
+        }
+        EditorConfig { sections }
+    }
+
+    /// Width for a file called `file_name`; later sections override earlier ones.
+    fn max_line_length(&self, file_name: &str) -> Option<(usize, &str)> {
+        let (glob, _, width) = self
+            .sections
+            .iter()
+            .rev()
+            .find(|(_, re, _)| re.is_match(file_name))?;
+        width.map(|width| (width, glob.as_str()))
+    }
+}
+
+/// Translates an `.editorconfig` section glob into a regex matching file names.
+fn glob_to_regex(glob: &str) -> Option<Regex> {
+    // Globs without a slash match the file name in any directory.
+    let glob = glob.rsplit('/').next().unwrap_or(glob);
+    let mut pattern = String::from("^");
+    let mut in_braces = false;
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '{' => {
+                in_braces = true;
+                pattern.push_str("(?:");
+            }
+            '}' if in_braces => {
+                in_braces = false;
+                pattern.push(')');
+            }
+            ',' if in_braces => pattern.push('|'),
+            '[' | ']' => pattern.push(c),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).ok()
+}
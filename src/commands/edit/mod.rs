@@ -0,0 +1,1028 @@
+pub mod assist;
+pub mod formatter;
+mod front_matter;
+mod line_width;
+pub mod process;
+pub mod report;
+mod watch;
+
+use crate::commands::weave::front_matter_range;
+use crate::utils::backup::Backup;
+use crate::utils::config::Config;
+use colored::Colorize;
+use formatter::{
+    builtin_formatters, env_flag_is_true, format_code_snippet, formatter_installed, CodeLanguage,
+    FormatConfig, Formatter, RustfmtSettings,
+};
+use front_matter::{normalize_front_matter, Normalized};
+use line_width::LineWidths;
+use process::{install_interrupt_handler, interrupted};
+use report::{
+    BlockAction, BlockResult, EditReport, FileReport, FrontMatterAction, FrontMatterResult,
+    ReportFormat,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use walkdir::WalkDir;
+
+pub use watch::watch_folder;
+
+/// Options for a `lila edit` run.
+#[derive(Debug, Clone)]
+pub struct EditOptions {
+    /// The project's settings, whose `[format]` section picks the formatters.
+    pub config: &'static Config,
+    /// Fail before touching any file if a code block's formatter is not installed.
+    pub require_formatters: bool,
+    /// Number of files formatted concurrently; defaults to the number of CPUs.
+    pub jobs: Option<usize>,
+    /// Print which tool settings are used, such as the rustfmt edition and config.
+    pub verbose: bool,
+    /// Only report which files would change; don't write anything.
+    pub check: bool,
+    /// Only format blocks in these languages; empty means all.
+    pub languages: Vec<CodeLanguage>,
+    /// Also normalize the YAML front matter of each file.
+    pub front_matter: bool,
+    /// Put a comment above each block whose formatter failed, and drop it once it succeeds.
+    pub annotate_failures: bool,
+    /// Emit a machine-readable report of every code block.
+    pub report: Option<ReportFormat>,
+    /// Write the report here instead of stdout.
+    pub report_file: Option<PathBuf>,
+    /// Save a copy of each file before rewriting it.
+    pub backup: Option<Backup>,
+}
+
+/// Returns true if the `--lang` selection in `languages` lets blocks in `language` be
+/// formatted.
+fn selects(languages: &[CodeLanguage], language: CodeLanguage) -> bool {
+    languages.is_empty() || languages.contains(&language)
+}
+
+impl EditOptions {
+    /// Prints a progress message, on stderr when stdout carries the report.
+    fn status(&self, message: impl Display) {
+        if self.report.is_some() && self.report_file.is_none() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+/// State shared by every file formatted during one `lila edit` run.
+struct EditSession {
+    /// Usable formatter for each language found in this run; absent if it can't be used.
+    resolved: HashMap<CodeLanguage, Formatter>,
+    /// Maximum run time of a formatter on a single code block.
+    timeout: Duration,
+    /// Leave files untouched and only report what would change.
+    check: bool,
+    /// Languages selected with `--lang`; empty means all.
+    languages: Vec<CodeLanguage>,
+    /// Where to save files before they are rewritten.
+    backup: Option<Backup>,
+    /// Normalize YAML front matter as well as code blocks.
+    front_matter: bool,
+    /// Keep failure comments above code blocks up to date.
+    annotate_failures: bool,
+}
+
+impl EditSession {
+    /// Decides once per run which of the languages in `block_counts` can be formatted,
+    /// preferring formatters configured in `config` over the built-in ones.
+    ///
+    /// Prints a single notice per language that will be skipped. With
+    /// `require_formatters`, a missing (not merely disabled) formatter is an error.
+    fn new(
+        block_counts: &BTreeMap<CodeLanguage, usize>,
+        config: &FormatConfig,
+        options: &EditOptions,
+    ) -> io::Result<Self> {
+        let builtin = builtin_formatters();
+        let line_widths = LineWidths::resolve(config, Path::new("."));
+        let mut installed: HashMap<String, bool> = HashMap::new();
+        let mut resolved = HashMap::new();
+        let mut missing = Vec::new();
+
+        for (&lang, &count) in block_counts {
+            let Some(formatter) = config
+                .overrides
+                .iter()
+                .chain(builtin.iter())
+                .find(|f| f.language == lang)
+            else {
+                continue;
+            };
+
+            if let Some(env_var) = formatter.enable_env {
+                if !env_flag_is_true(env_var) {
+                    options.status(
+                        format!(
+                            "{} disabled, skipping {} {} block(s) (set {}=true to enable).",
+                            formatter.command,
+                            count,
+                            lang.name(),
+                            env_var
+                        )
+                        .yellow(),
+                    );
+                    continue;
+                }
+            }
+
+            // A formatter set in Lila.toml that `lila init` did not find may be missing.
+            let configured = config.overrides.iter().any(|f| f.language == lang);
+            let found_by_init = |available: &Vec<String>| {
+                let stem = Path::new(&formatter.command).file_stem();
+                available
+                    .iter()
+                    .any(|command| stem == Some(OsStr::new(command)))
+            };
+            if configured && config.available.as_ref().is_some_and(|a| !found_by_init(a)) {
+                options.status(
+                    format!(
+                        "{} is configured for {} blocks but lila init did not find it (see format.available in Lila.toml); re-run lila init after installing it.",
+                        formatter.command,
+                        lang.name()
+                    )
+                    .yellow(),
+                );
+            }
+
+            let is_installed = *installed
+                .entry(formatter.command.clone())
+                .or_insert_with(|| formatter_installed(formatter));
+
+            if !is_installed {
+                options.status(
+                    format!(
+                        "{} not found, skipping {} {} block(s).",
+                        formatter.command,
+                        count,
+                        lang.name()
+                    )
+                    .yellow(),
+                );
+                missing.push(format!(
+                    "{} ({} {} blocks)",
+                    formatter.command,
+                    count,
+                    lang.name()
+                ));
+                continue;
+            }
+
+            let mut formatter = formatter.clone();
+            if lang == CodeLanguage::Rust {
+                let settings = RustfmtSettings::resolve(config, Path::new("."));
+                if options.verbose {
+                    options.status(format!("{}: {}", formatter.command, settings.describe()));
+                }
+                formatter = settings.apply(&formatter);
+            }
+            match line_widths.for_language(lang) {
+                Some((width, source)) => match formatter.with_line_width(width) {
+                    Some(with_width) => {
+                        if options.verbose {
+                            options.status(format!(
+                                "{}: line width {} ({})",
+                                lang.name(),
+                                width,
+                                source
+                            ));
+                        }
+                        formatter = with_width;
+                    }
+                    None if options.verbose => options.status(format!(
+                        "{}: line width {} ({}) not applied, {} has no line width option",
+                        lang.name(),
+                        width,
+                        source,
+                        formatter.command
+                    )),
+                    None => {}
+                },
+                None if options.verbose => options.status(format!(
+                    "{}: default line width of {}",
+                    lang.name(),
+                    formatter.command
+                )),
+                None => {}
+            }
+            resolved.insert(lang, formatter);
+        }
+
+        if options.require_formatters && !missing.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("required formatters are missing: {}", missing.join(", ")),
+            ));
+        }
+
+        Ok(EditSession {
+            resolved,
+            timeout: config.timeout,
+            check: options.check,
+            languages: options.languages.clone(),
+            backup: options.backup.clone(),
+            front_matter: options.front_matter,
+            annotate_failures: options.annotate_failures,
+        })
+    }
+
+    /// Returns the formatter to use for `lang`, or `None` if it is disabled or not installed.
+    fn formatter_for(&self, lang: CodeLanguage) -> Option<&Formatter> {
+        self.resolved.get(&lang)
+    }
+}
+
+/// Returns the language candidates declared by a fence line.
+///
+/// For a plain info string (```` ```python title="x" ````) this is only its first word.
+/// For a pandoc attribute block (```` ```{.python .cb-code} ````) it is every `.class`,
+/// plus a leading bare word as used by R Markdown (```` ```{python} ````).
+fn fence_tags(line: &str) -> Vec<&str> {
+    let info = line.trim_start().trim_start_matches('`').trim();
+
+    if let Some(attributes) = info.strip_prefix('{') {
+        let attributes = attributes.split('}').next().unwrap_or("");
+        return attributes
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .enumerate()
+            .filter_map(|(i, token)| match token.strip_prefix('.') {
+                Some(class) => Some(class),
+                None if i == 0 && !token.contains(['=', '#']) => Some(token),
+                None => None,
+            })
+            .collect();
+    }
+
+    info.split_whitespace().next().into_iter().collect()
+}
+
+/// Detect the language from a Markdown fence line (e.g. ```{.python}).
+///
+/// Only the declared tags are matched, exactly, against known language names and aliases,
+/// so free text such as ```` ```text rust output ```` is not mistaken for code.
+pub(crate) fn detect_language_from_line(line: &str) -> CodeLanguage {
+    fence_tags(line)
+        .into_iter()
+        .map(CodeLanguage::from_tag)
+        .find(|lang| *lang != CodeLanguage::Unknown)
+        .unwrap_or(CodeLanguage::Unknown)
+}
+
+/// HTML comment that, placed on the line directly above a fence, keeps that block unformatted.
+const NO_FORMAT_MARKER: &str = "<!-- lila:no-format -->";
+
+/// Start of the comment `--annotate-failures` puts above a block its formatter rejected.
+const FAILURE_ANNOTATION: &str = "<!-- lila: format failed:";
+
+/// Returns true if the fence line carries the `no-format` attribute,
+/// e.g. ```` ```{.python no-format} ```` or ```` ```python no-format ````.
+fn fence_has_no_format(line: &str) -> bool {
+    line.trim_start()
+        .trim_start_matches('`')
+        .split(|c: char| c.is_whitespace() || matches!(c, '{' | '}' | ','))
+        .any(|token| token.trim_start_matches('.') == "no-format")
+}
+
+/// A fenced code block found in a Markdown file.
+struct CodeBlock {
+    /// Index of the opening fence line.
+    fence_index: usize,
+    /// Number of content lines between the opening and closing fence.
+    len: usize,
+    /// Indentation and blockquote markers in front of the fence, e.g. `"  > "`.
+    prefix: String,
+    language: CodeLanguage,
+    /// Set by a `no-format` attribute or marker comment.
+    opt_out: bool,
+}
+
+impl CodeBlock {
+    /// Range of the content lines in the file's lines.
+    fn content_range(&self) -> std::ops::Range<usize> {
+        self.fence_index + 1..self.fence_index + 1 + self.len
+    }
+}
+
+/// Splits an opening fence line into its prefix (indentation and `>` markers) and the fence.
+fn split_fence(line: &str) -> Option<(&str, &str)> {
+    let fence_start = line.find("```")?;
+    let (prefix, fence) = line.split_at(fence_start);
+    if prefix.chars().all(|c| c == ' ' || c == '\t' || c == '>') {
+        Some((prefix, fence))
+    } else {
+        None
+    }
+}
+
+/// Removes as much of `prefix` from the start of `line` as it shares,
+/// so blank lines like a bare `>` inside a blockquote are handled too.
+fn strip_block_prefix<'a>(line: &'a str, prefix: &str) -> &'a str {
+    let shared = line
+        .char_indices()
+        .zip(prefix.chars())
+        .take_while(|((_, a), b)| a == b)
+        .last()
+        .map_or(0, |((i, c), _)| i + c.len_utf8());
+    &line[shared..]
+}
+
+/// Finds all closed fenced code blocks, including ones nested in lists and blockquotes.
+fn find_code_blocks(lines: &[String]) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut open: Option<CodeBlock> = None;
+
+    for (index, line) in lines.iter().enumerate() {
+        match open.take() {
+            Some(mut block) => {
+                if strip_block_prefix(line, &block.prefix)
+                    .trim_start()
+                    .starts_with("```")
+                {
+                    block.len = index - block.fence_index - 1;
+                    blocks.push(block);
+                } else {
+                    open = Some(block);
+                }
+            }
+            None => {
+                if let Some((prefix, fence)) = split_fence(line) {
+                    let marker_above = index > 0 && lines[index - 1].trim() == NO_FORMAT_MARKER;
+                    open = Some(CodeBlock {
+                        fence_index: index,
+                        len: 0,
+                        prefix: prefix.to_string(),
+                        language: detect_language_from_line(fence),
+                        opt_out: marker_above || fence_has_no_format(fence),
+                    });
+                }
+            }
+        }
+    }
+
+    // A trailing block without a closing fence is never formatted.
+    blocks
+}
+
+/// How a Markdown file ends its lines, so a rewrite reproduces it: every line keeps its own
+/// ending, even in a file mixing `\n` and `\r\n`, and the lines a rewrite changes or adds
+/// take the file's first one.
+struct LineLayout {
+    /// `\r\n` if the file's first line break is one, `\n` otherwise.
+    line_ending: &'static str,
+    /// The ending of each line: `\n`, `\r\n`, or nothing for an unterminated last line.
+    endings: Vec<&'static str>,
+}
+
+impl LineLayout {
+    /// The lines of `content`, without their endings, and how they end.
+    fn split(content: &str) -> (Vec<String>, LineLayout) {
+        let (lines, endings): (Vec<String>, Vec<&'static str>) = content
+            .split_inclusive('\n')
+            .map(|line| match line.strip_suffix('\n') {
+                Some(text) => match text.strip_suffix('\r') {
+                    Some(text) => (text.to_string(), "\r\n"),
+                    None => (text.to_string(), "\n"),
+                },
+                None => (line.to_string(), ""),
+            })
+            .unzip();
+        let line_ending = match endings.first() {
+            Some(&"\r\n") => "\r\n",
+            _ => "\n",
+        };
+        (
+            lines,
+            LineLayout {
+                line_ending,
+                endings,
+            },
+        )
+    }
+
+    /// Joins `lines` back into file content, each followed by the ending in `endings`.
+    fn join(lines: &[String], endings: &[&str]) -> String {
+        let mut content = String::new();
+        for (line, ending) in lines.iter().zip(endings) {
+            content.push_str(line);
+            content.push_str(ending);
+        }
+        content
+    }
+}
+
+/// Auto-format code blocks in a single Markdown file in-place, sharing `session` state.
+///
+/// Everything outside reformatted blocks is written back byte for byte, and a file
+/// whose blocks are all unchanged is not written at all.
+fn format_markdown_file(file_path: &str, session: &EditSession) -> io::Result<FileReport> {
+    let path = Path::new(file_path);
+    let content = fs::read_to_string(path)?;
+    let (lines, layout) = LineLayout::split(&content);
+
+    let mut report = FileReport::new(file_path);
+    let mut output: Vec<String> = Vec::with_capacity(lines.len());
+    // The ending of each line of `output`.
+    let mut endings: Vec<&'static str> = Vec::with_capacity(lines.len());
+    let mut cursor = 0;
+
+    for block in find_code_blocks(&lines) {
+        let content = block.content_range();
+        output.extend_from_slice(&lines[cursor..content.start]);
+        endings.extend_from_slice(&layout.endings[cursor..content.start]);
+        cursor = content.start;
+        let fence_position = output.len() - (content.start - block.fence_index);
+
+        if block.language == CodeLanguage::Unknown {
+            continue;
+        }
+        let mut result = BlockResult {
+            language: block.language.name(),
+            line: block.fence_index + 1,
+            action: BlockAction::OptOut,
+            error: None,
+        };
+        if block.opt_out {
+            report.blocks.push(result);
+            continue;
+        }
+        if !selects(&session.languages, block.language) {
+            result.action = BlockAction::Filtered;
+            report.blocks.push(result);
+            continue;
+        }
+        let Some(formatter) = session.formatter_for(block.language) else {
+            result.action = BlockAction::NoFormatter;
+            report.blocks.push(result);
+            continue;
+        };
+
+        // Format the code without the list indentation or blockquote markers around it.
+        let code_lines: Vec<String> = lines[content.clone()]
+            .iter()
+            .map(|l| strip_block_prefix(l, &block.prefix).to_string())
+            .collect();
+
+        match format_code_snippet(&code_lines, formatter, session.timeout) {
+            Ok(formatted_lines) => {
+                let formatted_lines: Vec<String> = formatted_lines
+                    .iter()
+                    .map(|l| {
+                        if l.is_empty() {
+                            block.prefix.trim_end().to_string()
+                        } else {
+                            format!("{}{}", block.prefix, l)
+                        }
+                    })
+                    .collect();
+                if formatted_lines[..] == lines[content.clone()] {
+                    result.action = BlockAction::Unchanged;
+                    endings.extend_from_slice(&layout.endings[content.clone()]);
+                } else {
+                    result.action = BlockAction::Formatted;
+                    endings.extend(formatted_lines.iter().map(|_| layout.line_ending));
+                }
+                output.extend(formatted_lines);
+                cursor = content.end;
+            }
+            Err(e) => {
+                result.action = BlockAction::Failed;
+                result.error = Some(e.to_string());
+            }
+        }
+        if session.annotate_failures {
+            update_failure_annotation(
+                &mut output,
+                &mut endings,
+                layout.line_ending,
+                fence_position,
+                &block.prefix,
+                result.error.as_deref(),
+            );
+        }
+        report.blocks.push(result);
+    }
+    output.extend_from_slice(&lines[cursor..]);
+    endings.extend_from_slice(&layout.endings[cursor..]);
+
+    if session.front_matter {
+        // Front matter sits above every code block, so `output` still has it unchanged.
+        let (action, error) = match normalize_front_matter(&output) {
+            Ok(Normalized::Missing) => (None, None),
+            Ok(Normalized::Unchanged) => (Some(FrontMatterAction::Unchanged), None),
+            Ok(Normalized::Skipped(reason)) => (Some(FrontMatterAction::Skipped), Some(reason)),
+            Ok(Normalized::Rewritten(yaml)) => {
+                let range = front_matter_range(&output).expect("front matter was just parsed");
+                endings.splice(range.clone(), yaml.iter().map(|_| layout.line_ending));
+                output.splice(range, yaml);
+                (Some(FrontMatterAction::Normalized), None)
+            }
+            Err(e) => (Some(FrontMatterAction::Invalid), Some(e)),
+        };
+        report.front_matter = action.map(|action| FrontMatterResult { action, error });
+    }
+
+    report.changed = output != lines;
+    if report.changed && !session.check {
+        if let Some(backup) = &session.backup {
+            backup.save(path)?;
+        }
+        // Overwrite the original file with updated lines.
+        fs::write(path, LineLayout::join(&output, &endings))?;
+    }
+
+    Ok(report)
+}
+
+/// Returns true if `line` is a comment left by `--annotate-failures`.
+fn is_failure_annotation(line: &str, prefix: &str) -> bool {
+    let comment = strip_block_prefix(line, prefix).trim();
+    comment.starts_with(FAILURE_ANNOTATION) && comment.ends_with("-->")
+}
+
+/// Adds, updates or removes the failure comment above the fence at `fence_position` in `output`,
+/// and its line ending in `endings`: `line_ending` for a comment added.
+fn update_failure_annotation(
+    output: &mut Vec<String>,
+    endings: &mut Vec<&'static str>,
+    line_ending: &'static str,
+    fence_position: usize,
+    prefix: &str,
+    error: Option<&str>,
+) {
+    let existing = fence_position > 0 && is_failure_annotation(&output[fence_position - 1], prefix);
+    // Keep the comment on one line and valid HTML: comments can't contain `--`.
+    let annotation = error.map(|e| {
+        let message = e.lines().next().unwrap_or("").replace("--", "- -");
+        format!("{}{} {} -->", prefix, FAILURE_ANNOTATION, message)
+    });
+
+    match (existing, annotation) {
+        (true, Some(annotation)) => output[fence_position - 1] = annotation,
+        (true, None) => {
+            output.remove(fence_position - 1);
+            endings.remove(fence_position - 1);
+        }
+        (false, Some(annotation)) => {
+            output.insert(fence_position, annotation);
+            endings.insert(fence_position, line_ending);
+        }
+        (false, None) => {}
+    }
+}
+
+/// Counts the fenced code blocks per language in a Markdown file.
+fn count_code_blocks(
+    path: &Path,
+    options: &EditOptions,
+    counts: &mut BTreeMap<CodeLanguage, usize>,
+) -> io::Result<()> {
+    let lines = BufReader::new(File::open(path)?)
+        .lines()
+        .collect::<io::Result<Vec<String>>>()?;
+
+    for block in find_code_blocks(&lines) {
+        if !block.opt_out
+            && block.language != CodeLanguage::Unknown
+            && selects(&options.languages, block.language)
+        {
+            *counts.entry(block.language).or_insert(0) += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Collects all `.md` files under `folder_path`, sorted for a stable processing order.
+fn collect_markdown_files(folder_path: &str) -> Vec<PathBuf> {
+    WalkDir::new(folder_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| p.is_file() && p.extension().and_then(|s| s.to_str()) == Some("md"))
+        .collect()
+}
+
+/// Formats every file in `files`, deciding up front which formatters are usable.
+///
+/// Files are processed by a bounded pool of `options.jobs` workers, so at most that many
+/// formatter processes run at once. Each file is still rewritten by a single worker, and
+/// results are reported in the order of `files` regardless of which worker finished first.
+fn format_markdown_files(files: &[PathBuf], options: &EditOptions) -> io::Result<EditReport> {
+    let config = FormatConfig::load(options.config)?;
+
+    let mut block_counts = BTreeMap::new();
+    for path in files {
+        count_code_blocks(path, options, &mut block_counts)?;
+    }
+
+    let session = EditSession::new(&block_counts, &config, options)?;
+    install_interrupt_handler();
+
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, files.len().max(1));
+    let next_file = AtomicUsize::new(0);
+
+    let mut results: Vec<(usize, FileReport)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    while !interrupted() {
+                        let index = next_file.fetch_add(1, Ordering::SeqCst);
+                        let Some(path) = files.get(index) else {
+                            break;
+                        };
+                        let path = path.to_string_lossy();
+                        options.status(format!("Auto-formatting {:?}", path));
+                        let report = format_markdown_file(&path, &session).unwrap_or_else(|e| {
+                            let mut report = FileReport::new(&path);
+                            report.error = Some(e.to_string());
+                            report
+                        });
+                        done.push((index, report));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("edit worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+
+    let report = EditReport {
+        check: options.check,
+        interrupted: interrupted(),
+        files: results.into_iter().map(|(_, report)| report).collect(),
+    };
+    print_summary(&report, options);
+
+    if let Some(ReportFormat::Json) = options.report {
+        report.write_json(options.report_file.as_deref())?;
+    }
+    Ok(report)
+}
+
+/// Prints failures and a one-line summary of `report` for humans.
+fn print_summary(report: &EditReport, options: &EditOptions) {
+    let mut formatted = 0;
+    let mut skipped_opt_out = 0;
+    let mut skipped_filtered = 0;
+    let mut front_matter_normalized = 0;
+    let mut failed = 0;
+    for file in &report.files {
+        if let Some(e) = &file.error {
+            eprintln!("Error formatting {}: {}", file.path, e);
+            failed += 1;
+            continue;
+        }
+        formatted += file.count(BlockAction::Formatted) + file.count(BlockAction::Unchanged);
+        skipped_opt_out += file.count(BlockAction::OptOut);
+        skipped_filtered += file.count(BlockAction::Filtered);
+        if let Some(front_matter) = &file.front_matter {
+            match (front_matter.action, &front_matter.error) {
+                (FrontMatterAction::Normalized, _) => front_matter_normalized += 1,
+                (FrontMatterAction::Invalid, Some(e)) => {
+                    eprintln!(
+                        "{} {}: invalid front matter, left as is: {}",
+                        "Warning:".bright_red(),
+                        file.path,
+                        e
+                    );
+                    failed += 1;
+                }
+                (FrontMatterAction::Skipped, Some(reason)) => {
+                    options.status(format!("{}: {}, left as is", file.path, reason))
+                }
+                _ => {}
+            }
+        }
+        for block in &file.blocks {
+            if let Some(e) = &block.error {
+                eprintln!(
+                    "{} {}: line {}: {} block left unformatted: {}",
+                    "Warning:".bright_red(),
+                    file.path,
+                    block.line,
+                    block.language,
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    let mut summary = if report.check {
+        let changed: Vec<&str> = report
+            .files
+            .iter()
+            .filter(|f| f.changed)
+            .map(|f| f.path.as_str())
+            .collect();
+        for path in &changed {
+            options.status(format!("Would reformat {}", path));
+        }
+        format!(
+            "Checked {} code block(s) in {} file(s), {} would be reformatted.",
+            formatted,
+            report.files.len(),
+            changed.len()
+        )
+    } else {
+        format!(
+            "Formatted {} code block(s) in {} file(s).",
+            formatted,
+            report.files.len()
+        )
+    };
+    if skipped_opt_out > 0 {
+        summary.push_str(&format!(" {} skipped (opt-out).", skipped_opt_out));
+    }
+    if skipped_filtered > 0 {
+        summary.push_str(&format!(" {} skipped (filtered).", skipped_filtered));
+    }
+    if front_matter_normalized > 0 {
+        summary.push_str(&format!(
+            " Normalized front matter in {} file(s).",
+            front_matter_normalized
+        ));
+    }
+    if failed > 0 {
+        options.status(format!(
+            "{} {}",
+            summary,
+            format!("{} failed.", failed).red()
+        ));
+    } else {
+        options.status(format!("{} {}", "✔".green(), summary));
+    }
+    if report.interrupted {
+        eprintln!("Interrupted by user.");
+    }
+}
+
+/// Auto-format code blocks (Python, Rust, etc.) in a single Markdown file in-place.
+pub fn edit_format_code_in_markdown(
+    file_path: &str,
+    options: &EditOptions,
+) -> io::Result<EditReport> {
+    format_markdown_files(&[PathBuf::from(file_path)], options)
+}
+
+/// Recursively auto-format code blocks in all `.md` files under `folder_path`.
+pub fn edit_format_code_in_folder(
+    folder_path: &str,
+    options: &EditOptions,
+) -> io::Result<EditReport> {
+    if !Path::new(folder_path).is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", folder_path),
+        ));
+    }
+    format_markdown_files(&collect_markdown_files(folder_path), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A session formatting Python blocks with a formatter that strips leading spaces.
+    fn dedenting_session(check: bool) -> EditSession {
+        let formatter = Formatter {
+            language: CodeLanguage::Python,
+            command: "sed".to_string(),
+            args: vec!["s/^ *//".to_string()],
+            enable_env: None,
+        };
+        EditSession {
+            resolved: HashMap::from([(CodeLanguage::Python, formatter)]),
+            timeout: Duration::from_secs(10),
+            check,
+            languages: Vec::new(),
+            backup: None,
+            front_matter: false,
+            annotate_failures: false,
+        }
+    }
+
+    /// Writes `content` to a Markdown file in `dir` and formats it with `session`.
+    fn format(dir: &Path, content: &str, session: &EditSession) -> (String, FileReport) {
+        let path = dir.join("chapter.md");
+        fs::write(&path, content).unwrap();
+        let report = format_markdown_file(&path.to_string_lossy(), session).unwrap();
+        (fs::read_to_string(&path).unwrap(), report)
+    }
+
+    fn actions(report: &FileReport) -> Vec<BlockAction> {
+        report.blocks.iter().map(|block| block.action).collect()
+    }
+
+    #[test]
+    fn no_format_blocks_are_kept_byte_for_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "\
+# Before black
+
+```{.python no-format}
+def f( a ):
+      return   a
+```
+
+<!-- lila:no-format -->
+```python
+   x  =  1
+```
+
+```python
+   y = 2
+```
+";
+        let (formatted, report) = format(dir.path(), content, &dedenting_session(false));
+
+        assert_eq!(
+            actions(&report),
+            [
+                BlockAction::OptOut,
+                BlockAction::OptOut,
+                BlockAction::Formatted
+            ]
+        );
+        assert_eq!(formatted, content.replace("   y = 2", "y = 2"));
+    }
+
+    #[test]
+    fn no_format_marker_only_applies_to_the_fence_below_it() {
+        let lines: Vec<String> = ["<!-- lila:no-format -->", "", "```python", "x = 1", "```"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        let blocks = find_code_blocks(&lines);
+        assert_eq!(blocks.len(), 1);
+        assert!(!blocks[0].opt_out);
+    }
+
+    #[test]
+    fn no_format_attribute_is_recognized_in_both_fence_styles() {
+        assert!(fence_has_no_format("```{.python no-format}"));
+        assert!(fence_has_no_format("```{.python .no-format}"));
+        assert!(fence_has_no_format("```python no-format"));
+        assert!(!fence_has_no_format("```python"));
+        assert!(!fence_has_no_format("```python no-formatting"));
+        assert_eq!(
+            detect_language_from_line("```{.python no-format}"),
+            CodeLanguage::Python
+        );
+    }
+
+    #[test]
+    fn free_text_after_the_fence_is_not_a_language() {
+        for fence in [
+            "```text rust output from cargo",
+            "```console pytest run",
+            "```output python traceback",
+            "```",
+            "```rustacean",
+        ] {
+            assert_eq!(
+                detect_language_from_line(fence),
+                CodeLanguage::Unknown,
+                "{}",
+                fence
+            );
+        }
+    }
+
+    #[test]
+    fn fence_tags_are_matched_exactly_with_their_aliases() {
+        for (fence, language) in [
+            ("```python", CodeLanguage::Python),
+            ("```py title=\"setup.py\"", CodeLanguage::Python),
+            ("```Rust", CodeLanguage::Rust),
+            ("```rs", CodeLanguage::Rust),
+            ("  ```c++", CodeLanguage::Cpp),
+            ("```{.python no-format}", CodeLanguage::Python),
+            ("```{.cb-code .rust}", CodeLanguage::Rust),
+            ("```{python}", CodeLanguage::Python),
+            ("```{#listing-1 .ts}", CodeLanguage::TypeScript),
+        ] {
+            assert_eq!(detect_language_from_line(fence), language, "{}", fence);
+        }
+    }
+
+    #[test]
+    fn attribute_fences_only_take_classes_and_a_leading_word() {
+        assert_eq!(
+            fence_tags("```{.python .numberLines}"),
+            ["python", "numberLines"]
+        );
+        assert_eq!(fence_tags("```{r, echo=FALSE}"), ["r"]);
+        assert_eq!(fence_tags("```{#id key=rust}"), Vec::<&str>::new());
+        assert_eq!(fence_tags("```bash $ cargo run"), ["bash"]);
+    }
+
+    #[test]
+    fn only_tagged_blocks_are_formatted() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "\
+```text python output
+   kept as written
+```
+
+```python
+   x = 1
+```
+";
+        let (formatted, report) = format(dir.path(), content, &dedenting_session(false));
+        assert_eq!(actions(&report), [BlockAction::Formatted]);
+        assert_eq!(formatted, content.replace("   x = 1", "x = 1"));
+    }
+
+    #[test]
+    fn line_layout_round_trips_line_endings_and_the_final_newline() {
+        for content in [
+            "a\nb\n",
+            "a\r\nb\r\n",
+            "a\r\n\r\nb",
+            "a\nb",
+            "a\n\n",
+            "a\r\nb\nc",
+            "",
+        ] {
+            let (lines, layout) = LineLayout::split(content);
+            assert_eq!(
+                LineLayout::join(&lines, &layout.endings),
+                content,
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn crlf_files_stay_crlf_when_a_block_is_formatted() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "# Title\r\n\r\n```python\r\n   x = 1\r\n```\r\nThe end.";
+        let (formatted, report) = format(dir.path(), content, &dedenting_session(false));
+        assert!(report.changed);
+        assert_eq!(formatted, content.replace("   x = 1", "x = 1"));
+    }
+
+    #[test]
+    fn unchanged_files_are_left_bit_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        for content in [
+            "# Title\n\n```python\nx = 1\n```\n\n",
+            "# Title\r\n```python\r\nx = 1\r\n```",
+            "# Title\r\n\n```python\nx = 1\r\n```\n",
+        ] {
+            let (formatted, report) = format(dir.path(), content, &dedenting_session(false));
+            assert!(!report.changed, "{:?}", content);
+            assert_eq!(formatted.as_bytes(), content.as_bytes());
+        }
+    }
+
+    #[test]
+    fn mixed_line_endings_outside_a_changed_block_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "# Title\r\n\n```python\n   x = 1\n```\n\n```python\r\ny = 2\n```\r\nEnd";
+        let (formatted, report) = format(dir.path(), content, &dedenting_session(false));
+        assert_eq!(
+            actions(&report),
+            [BlockAction::Formatted, BlockAction::Unchanged]
+        );
+        // Only the reformatted line takes the file's first line ending.
+        assert_eq!(
+            formatted,
+            "# Title\r\n\n```python\nx = 1\r\n```\n\n```python\r\ny = 2\n```\r\nEnd"
+        );
+    }
+
+    #[test]
+    fn check_mode_never_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "```python\n   x = 1\n```\n";
+        let (formatted, report) = format(dir.path(), content, &dedenting_session(true));
+        assert!(report.changed);
+        assert_eq!(formatted, content);
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running formatter is polled for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Process ids of formatter children that are currently running.
+static RUNNING_CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+/// Set by the first Ctrl-C; the edit loop stops before the next file.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INTERRUPT_HANDLER: Once = Once::new();
+
+/// How a formatter process ended.
+pub enum ChildOutcome {
+    /// The process exited; carries what it wrote to stdout if `input` was piped in.
+    Exited(ExitStatus, Vec<u8>),
+    TimedOut(Duration),
+}
+
+/// Installs a Ctrl-C handler that kills in-flight formatter children instead of orphaning them.
+///
+/// The first Ctrl-C stops the children and lets the current file finish writing;
+/// a second one exits immediately.
+pub fn install_interrupt_handler() {
+    INTERRUPT_HANDLER.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            eprintln!("\nInterrupted, stopping running formatters...");
+            if let Ok(children) = RUNNING_CHILDREN.lock() {
+                for pid in children.iter() {
+                    kill_process_tree(*pid);
+                }
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: could not install Ctrl-C handler: {}", e);
+        }
+    });
+}
+
+/// Returns true once the user pressed Ctrl-C.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Runs `command` and kills it (and anything it spawned) if it is still running after `timeout`.
+///
+/// With `input`, the text is written to the child's stdin and its stdout is captured;
+/// otherwise both are closed.
+pub fn run_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+    input: Option<&str>,
+) -> io::Result<ChildOutcome> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Put the formatter in its own process group so it and its children can be killed together.
+        command.process_group(0);
+    }
+    if input.is_some() {
+        command.stdin(Stdio::piped()).stdout(Stdio::piped());
+    } else {
+        command.stdin(Stdio::null()).stdout(Stdio::null());
+    }
+
+    let start = Instant::now();
+    let mut child = command.spawn()?;
+    let pid = child.id();
+    if let Ok(mut children) = RUNNING_CHILDREN.lock() {
+        children.push(pid);
+    }
+
+    // Feed stdin and drain stdout on their own threads so a large block can't fill a
+    // pipe buffer and deadlock against the child.
+    let outcome = thread::scope(|scope| {
+        let stdin = child.stdin.take();
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            scope.spawn(move || {
+                // A formatter that exits early closes the pipe; its exit status tells why.
+                let _ = stdin.write_all(input.as_bytes());
+            });
+        }
+        let reader = child.stdout.take().map(|mut stdout| {
+            scope.spawn(move || {
+                let mut output = Vec::new();
+                stdout.read_to_end(&mut output).map(|_| output)
+            })
+        });
+
+        let outcome = wait_with_deadline(&mut child, start, timeout)?;
+        let output = match reader {
+            Some(reader) => reader.join().expect("stdout reader panicked")?,
+            None => Vec::new(),
+        };
+        Ok(match outcome {
+            ChildOutcome::Exited(status, _) => ChildOutcome::Exited(status, output),
+            timed_out => timed_out,
+        })
+    });
+
+    if let Ok(mut children) = RUNNING_CHILDREN.lock() {
+        children.retain(|p| *p != pid);
+    }
+    outcome
+}
+
+fn wait_with_deadline(
+    child: &mut Child,
+    start: Instant,
+    timeout: Duration,
+) -> io::Result<ChildOutcome> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(ChildOutcome::Exited(status, Vec::new()));
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            kill_process_tree(child.id());
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(ChildOutcome::TimedOut(elapsed));
+        }
+
+        thread::sleep(POLL_INTERVAL.min(timeout - elapsed));
+    }
+}
+
+/// Kills the process group led by `pid`.
+#[cfg(unix)]
+fn kill_process_tree(pid: u32) {
+    // SAFETY: `kill` has no memory-safety preconditions; a stale group id only yields ESRCH.
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Kills the process `pid` and its children.
+#[cfg(not(unix))]
+fn kill_process_tree(pid: u32) {
+    let _ = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+}
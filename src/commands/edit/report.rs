@@ -0,0 +1,147 @@
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Exit code when every file already was, or now is, formatted.
+pub const EXIT_CLEAN: i32 = 0;
+/// Exit code when files were reformatted, or would be with `--check`.
+pub const EXIT_CHANGED: i32 = 1;
+/// Exit code when a formatter failed or a file could not be processed.
+pub const EXIT_FAILED: i32 = 2;
+
+/// Machine-readable report formats of `lila edit --report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Json,
+}
+
+/// What happened to a single code block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockAction {
+    /// The formatter changed the block (or would, with `--check`).
+    Formatted,
+    /// The formatter ran and left the block as it was.
+    Unchanged,
+    /// The author marked the block with `no-format`.
+    OptOut,
+    /// The block's language was excluded with `--lang`.
+    Filtered,
+    /// The block's formatter is disabled or not installed.
+    NoFormatter,
+    /// The formatter failed; the block was left as written.
+    Failed,
+}
+
+/// Result for one code block, located by the line of its opening fence.
+#[derive(Debug, Serialize)]
+pub struct BlockResult {
+    pub language: &'static str,
+    pub line: usize,
+    pub action: BlockAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What `--front-matter` did with a file's YAML front matter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrontMatterAction {
+    /// Keys were reordered or values re-quoted (or would be, with `--check`).
+    Normalized,
+    /// The front matter already was in canonical form.
+    Unchanged,
+    /// The front matter was kept as written, e.g. because it has comments.
+    Skipped,
+    /// The YAML is not valid lila front matter and was not touched.
+    Invalid,
+}
+
+/// Result for a file's front matter.
+#[derive(Debug, Serialize)]
+pub struct FrontMatterResult {
+    pub action: FrontMatterAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of formatting one Markdown file.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: String,
+    /// Whether the file was rewritten (or would be, with `--check`).
+    pub changed: bool,
+    pub blocks: Vec<BlockResult>,
+    /// Present with `--front-matter` when the file has front matter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub front_matter: Option<FrontMatterResult>,
+    /// Set when the file could not be read or written; `blocks` is then empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    pub fn new(path: &str) -> Self {
+        FileReport {
+            path: path.to_string(),
+            changed: false,
+            blocks: Vec::new(),
+            front_matter: None,
+            error: None,
+        }
+    }
+
+    /// True if a block's formatter failed or the front matter is invalid.
+    pub fn has_failures(&self) -> bool {
+        self.count(BlockAction::Failed) > 0
+            || self
+                .front_matter
+                .as_ref()
+                .is_some_and(|f| f.action == FrontMatterAction::Invalid)
+    }
+
+    /// Number of blocks that ended with `action`.
+    pub fn count(&self, action: BlockAction) -> usize {
+        self.blocks.iter().filter(|b| b.action == action).count()
+    }
+}
+
+/// Results of a whole `lila edit` run.
+#[derive(Debug, Serialize)]
+pub struct EditReport {
+    /// True for `--check` runs, where no file was written.
+    pub check: bool,
+    pub interrupted: bool,
+    pub files: Vec<FileReport>,
+}
+
+impl EditReport {
+    /// Process exit code for this run: failures win over changes.
+    pub fn exit_code(&self) -> i32 {
+        let failed = self.interrupted
+            || self
+                .files
+                .iter()
+                .any(|f| f.error.is_some() || f.has_failures());
+        if failed {
+            EXIT_FAILED
+        } else if self.files.iter().any(|f| f.changed) {
+            EXIT_CHANGED
+        } else {
+            EXIT_CLEAN
+        }
+    }
+
+    /// Writes the report as JSON to `path`, or to stdout if no path is given.
+    pub fn write_json(&self, path: Option<&Path>) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        match path {
+            Some(path) => fs::write(path, json + "\n"),
+            None => {
+                println!("{}", json);
+                Ok(())
+            }
+        }
+    }
+}
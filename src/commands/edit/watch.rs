@@ -0,0 +1,157 @@
+use super::formatter::FormatConfig;
+use super::process::{install_interrupt_handler, interrupted};
+use super::report::{BlockAction, FileReport};
+use super::{
+    collect_markdown_files, count_code_blocks, format_markdown_file, EditOptions, EditSession,
+};
+use colored::Colorize;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Quiet period after the last file event before a change is acted on, so the several
+/// writes of one editor save trigger a single run.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How often the watch loop wakes up to notice Ctrl-C.
+const IDLE_POLL: Duration = Duration::from_millis(200);
+
+/// Formats each Markdown file under `folder_path` again whenever it changes, until Ctrl-C.
+///
+/// Files are only acted on when their content differs from what was last seen, which
+/// also keeps lila's own rewrites from triggering another run.
+pub fn watch_folder(folder_path: &str, options: &EditOptions) -> io::Result<()> {
+    if !Path::new(folder_path).is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", folder_path),
+        ));
+    }
+
+    let config = FormatConfig::load(options.config)?;
+    let mut block_counts = BTreeMap::new();
+    let mut hashes = HashMap::new();
+    for path in collect_markdown_files(folder_path) {
+        count_code_blocks(&path, options, &mut block_counts)?;
+        if let Some(hash) = content_hash(&path) {
+            hashes.insert(path.canonicalize()?, hash);
+        }
+    }
+    let mut session = EditSession::new(&block_counts, &config, options)?;
+    install_interrupt_handler();
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    watcher
+        .watch(Path::new(folder_path), RecursiveMode::Recursive)
+        .map_err(io::Error::other)?;
+    println!("Watching {} for changes (Ctrl-C to stop)...", folder_path);
+
+    while !interrupted() {
+        let mut changed = BTreeSet::new();
+        match receiver.recv_timeout(IDLE_POLL) {
+            Ok(event) => collect_changed(event, &mut changed),
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            collect_changed(event, &mut changed);
+        }
+
+        for path in changed {
+            let Some(hash) = content_hash(&path) else {
+                continue;
+            };
+            if hashes.get(&path) == Some(&hash) {
+                continue;
+            }
+
+            // A language seen for the first time needs its formatter looked up.
+            let known = block_counts.len();
+            count_code_blocks(&path, options, &mut block_counts)?;
+            if block_counts.len() != known {
+                session = EditSession::new(&block_counts, &config, options)?;
+            }
+
+            let display = display_path(&path);
+            match format_markdown_file(&path.to_string_lossy(), &session) {
+                Ok(report) => println!("{}", summarize(&display, &report, options.check)),
+                Err(e) => eprintln!("{} {}: {}", "✘".red(), display, e),
+            }
+            if let Some(hash) = content_hash(&path) {
+                hashes.insert(path, hash);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds the Markdown files created or modified by `event` to `changed`.
+fn collect_changed(event: notify::Result<notify::Event>, changed: &mut BTreeSet<PathBuf>) {
+    let Ok(event) = event else {
+        return;
+    };
+    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+        return;
+    }
+    for path in event.paths {
+        if path.extension().and_then(|s| s.to_str()) == Some("md") && path.is_file() {
+            if let Ok(path) = path.canonicalize() {
+                changed.insert(path);
+            }
+        }
+    }
+}
+
+fn content_hash(path: &Path) -> Option<u64> {
+    let content = fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// `path` relative to the current directory when it is below it.
+fn display_path(path: &Path) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| cwd.canonicalize().ok())
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(|p| p.to_path_buf()))
+        .unwrap_or_else(|| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// One-line result of a watch run on a single file.
+fn summarize(path: &str, report: &FileReport, check: bool) -> String {
+    let failed = report.count(BlockAction::Failed);
+    let changed = report.count(BlockAction::Formatted);
+    let symbol = if failed > 0 {
+        "✘".red()
+    } else if report.changed && check {
+        "✘".yellow()
+    } else {
+        "✔".green()
+    };
+    let mut line = match (report.changed, check) {
+        (true, true) => format!("{} {}: would reformat {} block(s)", symbol, path, changed),
+        (true, false) => format!("{} {}: formatted {} block(s)", symbol, path, changed),
+        (false, _) if failed > 0 => format!("{} {}: unchanged", symbol, path),
+        (false, _) => format!("{} {}: already formatted", symbol, path),
+    };
+    if failed > 0 {
+        line.push_str(&format!(
+            ", {}",
+            format!("{} block(s) failed", failed).red()
+        ));
+        if let Some(error) = report.blocks.iter().find_map(|b| b.error.as_ref()) {
+            line.push_str(&format!(" ({})", error));
+        }
+    }
+    line
+}
@@ -0,0 +1,134 @@
+use crate::commands::save::{resolve_stored_path, stored_path};
+use crate::utils::config::Config;
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::history::file_versions;
+use crate::utils::database::models::ContentHistory;
+use colored::Colorize;
+use similar::TextDiff;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Reads `max_versions` from the `[history]` section of `config`; `None` keeps every
+/// version.
+pub fn load_max_versions(config: &Config) -> io::Result<Option<usize>> {
+    match config.history.max_versions {
+        None => Ok(None),
+        Some(max) if max > 0 => Ok(Some(max as usize)),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "invalid {}: history.max_versions must be a positive integer",
+                config.path().display()
+            ),
+        )),
+    }
+}
+
+/// Prints the saved versions of `file`, oldest first.
+pub fn list_history(db_path: &Path, file: &str, doc_root: &Path) -> io::Result<()> {
+    let versions = load_versions(db_path, file, doc_root)?;
+    if versions.is_empty() {
+        println!("No saved versions of {}.", file);
+        return Ok(());
+    }
+
+    let now = unix_now();
+    println!("{:>5}  {:<16}  {:>9}  Hash", "Rev", "Saved", "Lines");
+    for version in &versions {
+        println!(
+            "{}  {:<16}  {:>9}  {}",
+            format!("{:>5}", version.id).cyan(),
+            age(now - version.saved_at),
+            version.content.lines().count(),
+            &version.content_hash[..version.content_hash.len().min(12)]
+        );
+    }
+    Ok(())
+}
+
+/// Prints a unified diff from revision `rev` of `file` to its current content on disk,
+/// or to its latest saved version when the file no longer exists.
+pub fn diff_revision(db_path: &Path, file: &str, rev: i32, doc_root: &Path) -> io::Result<()> {
+    let versions = load_versions(db_path, file, doc_root)?;
+    let Some(old) = versions.iter().find(|v| v.id == rev) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "{} has no revision {} (see `lila history {}`)",
+                file, rev, file
+            ),
+        ));
+    };
+
+    let on_disk = fs::read_to_string(file)
+        .or_else(|_| fs::read_to_string(resolve_stored_path(file, doc_root)));
+    let (current, label) = match on_disk {
+        Ok(content) => (content, file.to_string()),
+        Err(_) => {
+            let latest = versions.last().expect("versions contains `old`");
+            (latest.content.clone(), format!("{}@{}", file, latest.id))
+        }
+    };
+
+    let diff = TextDiff::from_lines(&old.content, &current);
+    let unified = diff
+        .unified_diff()
+        .header(&format!("{}@{}", file, rev), &label)
+        .to_string();
+    if unified.is_empty() {
+        println!("No differences between revision {} and {}.", rev, label);
+        return Ok(());
+    }
+    print_diff(&unified);
+    Ok(())
+}
+
+/// Prints the unified diff `unified`, its added lines in green and removed ones in red.
+pub fn print_diff(unified: &str) {
+    for line in unified.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Versions saved for `file`, matched by the path as given, as an absolute path, or as
+/// stored relative to `doc_root`.
+fn load_versions(db_path: &Path, file: &str, doc_root: &Path) -> io::Result<Vec<ContentHistory>> {
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    let mut paths = vec![file.to_string(), stored_path(file, doc_root)];
+    if let Ok(absolute) = Path::new(file).canonicalize() {
+        paths.push(absolute.to_string_lossy().into_owned());
+    }
+    file_versions(&mut conn, &paths).map_err(io::Error::other)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// How long ago something happened, `secs` seconds back, in rough words.
+fn age(secs: i64) -> String {
+    let (value, unit) = match secs.max(0) {
+        s if s < 60 => return "just now".to_string(),
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86400 => (s / 3600, "hour"),
+        s => (s / 86400, "day"),
+    };
+    let plural = if value == 1 { "" } else { "s" };
+    format!("{} {}{} ago", value, unit, plural)
+}
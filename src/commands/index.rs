@@ -0,0 +1,125 @@
+use crate::schema::{blobs, metadata};
+use crate::utils::database::blobs::content_hash;
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::embeddings::{
+    chunk_markdown, indexed_hash, replace_embeddings, retrieve,
+};
+use crate::utils::database::projects::ProjectScope;
+use crate::utils::embedding::{Embedder, LocalEmbedder};
+use colored::Colorize;
+use diesel::prelude::*;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/// Chunks and embeds the Markdown of `project` saved in the database at `db_path`, so
+/// chat can pick the chunks relevant to a question. Files indexed from the same content
+/// are skipped unless `force` is set. The model is cached in `model_dir`.
+pub fn index_saved_files(
+    db_path: &Path,
+    model_dir: &Path,
+    project: &ProjectScope,
+    force: bool,
+) -> io::Result<()> {
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    let Some(project_id) = project.resolve(&mut conn).map_err(io::Error::other)? else {
+        println!(
+            "No files of {} are saved in {}.",
+            project,
+            db_path.display()
+        );
+        return Ok(());
+    };
+
+    let mut query = metadata::table
+        .inner_join(blobs::table)
+        .select((metadata::id, metadata::file_path, blobs::content))
+        .order(metadata::file_path.asc())
+        .into_boxed();
+    if let Some(id) = project_id {
+        query = query.filter(metadata::project_id.eq(id));
+    }
+    let files: Vec<(i32, String, String)> = query.load(&mut conn).map_err(io::Error::other)?;
+
+    let mut embedder = LocalEmbedder::load(model_dir)?;
+    let model = embedder.model_name().to_string();
+    let started = Instant::now();
+    let (mut indexed, mut unchanged, mut chunk_count) = (0, 0, 0);
+    for (id, path, content) in &files {
+        let hash = content_hash(content);
+        let previous = indexed_hash(&mut conn, *id, &model).map_err(io::Error::other)?;
+        if !force && previous.as_deref() == Some(hash.as_str()) {
+            unchanged += 1;
+            continue;
+        }
+
+        let chunks = chunk_markdown(content);
+        let texts: Vec<&str> = chunks.iter().map(|c| &content[c.start..c.end]).collect();
+        let vectors = if texts.is_empty() {
+            Vec::new()
+        } else {
+            embedder.embed(&texts)?
+        };
+        conn.transaction(|conn| replace_embeddings(conn, *id, &hash, &model, &chunks, &vectors))
+            .map_err(io::Error::other)?;
+
+        tracing::info!("Indexed {} chunks of {}", chunks.len(), path);
+        indexed += 1;
+        chunk_count += chunks.len();
+    }
+
+    println!(
+        "{} ({} files indexed into {} chunks, {} unchanged; {:.1}s)",
+        "Index is up to date!".green(),
+        indexed,
+        chunk_count,
+        unchanged,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Prints the `k` indexed chunks of `project` closest to `query`, which is what chat
+/// would get as context for it.
+pub fn print_retrieved(
+    db_path: &Path,
+    model_dir: &Path,
+    project: &ProjectScope,
+    query: &str,
+    k: usize,
+) -> io::Result<()> {
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    let Some(project_id) = project.resolve(&mut conn).map_err(io::Error::other)? else {
+        println!(
+            "No files of {} are saved in {}.",
+            project,
+            db_path.display()
+        );
+        return Ok(());
+    };
+    let mut embedder = LocalEmbedder::load(model_dir)?;
+
+    let chunks = retrieve(&mut conn, &mut embedder, query, k, project_id)?;
+    if chunks.is_empty() {
+        println!("Nothing indexed yet. Run `lila index` first.");
+        return Ok(());
+    }
+    for chunk in &chunks {
+        let location = match &chunk.heading {
+            Some(heading) => format!("{} > {}", chunk.file_path, heading),
+            None => chunk.file_path.clone(),
+        };
+        println!(
+            "{} {}",
+            format!("{:.3}", chunk.score).cyan(),
+            location.bold()
+        );
+        for line in chunk.text.trim().lines() {
+            println!("  {}", line);
+        }
+        println!();
+    }
+    Ok(())
+}
@@ -1,67 +1,265 @@
+use crate::commands::edit::formatter::{
+    installed_env_var, known_formatter_commands, probe_formatter, version_env_var, CodeLanguage,
+    FormatterProbe,
+};
+use crate::server::backend::{Isq, ISQ_VAR};
+use crate::server::models::{CatalogModel, MODEL_CATALOG, MODEL_VAR};
+use crate::utils::dependencies::{node_dependencies, python_dependencies, rust_dependencies};
+use crate::utils::detect::{detect_languages, DetectedLanguage, Detection};
+use crate::utils::hardware::{detect_gpu, Benchmark};
 use colored::Colorize;
 use dirs::home_dir;
+use regex::Regex;
 use std::ffi::OsStr;
-use std::fs::{create_dir_all, File};
-use std::io::{self, Write};
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::Command;
+use std::time::Duration;
 use sysinfo::System;
+use toml_edit::{DocumentMut, Item, Key, Table};
 
-/// Checks if a given command is available on the user's system
-/// by attempting `command --version` (or another trivial arg).
-fn check_program_availability(program: &str) -> bool {
-    Command::new(program)
-        .arg("--version")
-        .stderr(Stdio::null())
-        .stdout(Stdio::null())
-        .status()
-        .is_ok()
+/// Environment variable naming the folder lila writes a project's files to.
+pub const OUTPUT_PATH_VAR: &str = "LILA_OUTPUT_PATH";
+/// How long `lila init` waits for a formatter to print its version before skipping it.
+pub const FORMATTER_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Tokens a second a model is recommended at, at least; slower ones are tiring to chat with.
+const MIN_TOKENS_PER_SECOND: f64 = 8.0;
+/// The 3B models `lila init --skip-benchmark` offers, the first being the default.
+const MODELS_3B: [&str; 3] = [
+    "microsoft/Phi-3.5-mini-instruct",
+    "microsoft/Phi-4-mini-instruct",
+    "Qwen/Qwen2.5-Coder-3B-Instruct",
+];
+
+/// Answers to the questions of `lila init` given on the command line; the questions left
+/// out are asked, or take their defaults with `yes`.
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    /// Take the default answer of every question left out instead of asking it.
+    pub yes: bool,
+    /// Folder lila writes the project's files to.
+    pub output_path: Option<PathBuf>,
+    /// Chat model to set as the default.
+    pub model: Option<String>,
+    /// The project context of `Lila.toml`.
+    pub context: Option<String>,
+    /// The deployment description of `Lila.toml`.
+    pub deployment: Option<String>,
+    /// Programming languages of the project; asked when empty.
+    pub languages: Vec<String>,
+    /// Leave compliance guidelines out of `Lila.toml`.
+    pub no_compliance: bool,
+    /// ISO and BSI compliance guidelines; either answers that there are some.
+    pub iso: Vec<String>,
+    pub bsi: Vec<String>,
+    /// Leave `Lila.toml` alone.
+    pub no_toml: bool,
+    /// Write `Lila.toml` from scratch rather than merging into the existing one.
+    pub force_new: bool,
+    /// Leave `.gitignore` alone.
+    pub no_gitignore: bool,
+    /// Recommend a model from the number of cores and the RAM instead of measuring them.
+    pub skip_benchmark: bool,
 }
 
-/// Updates or inserts a key-value pair into the `.env` file.
-///
-/// If the key is found, it replaces that line. Otherwise, it appends at the end.
-fn update_env_value(key: &str, value: &str) -> io::Result<()> {
-    let env_path = Path::new(".env");
+/// Asks the questions of `lila init` on the terminal, or takes their defaults.
+struct Questions {
+    /// Take the default answer of every question instead of asking it.
+    defaults: bool,
+}
 
+impl Questions {
+    /// The line typed after `question`, trimmed; empty, the answer standing for the
+    /// default, when taking defaults.
+    fn ask(&self, question: &str) -> io::Result<String> {
+        if self.defaults {
+            return Ok(String::new());
+        }
+        print!("{}", question);
+        io::stdout().flush()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        Ok(input.trim().to_string())
+    }
+
+    /// The items of the comma-separated list typed after `question`.
+    fn ask_list(&self, question: &str) -> io::Result<Vec<String>> {
+        Ok(self
+            .ask(question)?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect())
+    }
+}
+
+/// First line of the keys `lila init` adds to a `.env` file.
+const ENV_HEADER: &str = "# lila environment settings";
+
+/// Updates or inserts a key-value pair into the `.env` file at `env_path`.
+///
+/// If the key is found, only its value is replaced: the rest of the line, such as an
+/// `export` or a comment after the value, and the rest of the file are left byte for byte.
+/// Otherwise, it is added under the lila header, which is appended when the file lacks it.
+/// The value is quoted when dotenv would not read it back as it is.
+pub fn update_env_value(env_path: &Path, key: &str, value: &str) -> io::Result<()> {
+    let value = quote_env_value(value);
     // If .env does not exist, create it.
     if !env_path.exists() {
-        let mut file = File::create(env_path)?;
-        writeln!(file, "# lila environment settings")?;
-        writeln!(file, "{}={}", key, value)?;
-        return Ok(());
+        return fs::write(env_path, format!("{}\n{}={}\n", ENV_HEADER, key, value));
     }
 
-    // If .env exists, either update or append the key=value pair.
-    let content = std::fs::read_to_string(env_path)?;
-    let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
-    let mut found = false;
+    let mut content = fs::read_to_string(env_path)?;
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    match find_env_value(&content, key) {
+        Some(span) => content.replace_range(span, &value),
+        None => {
+            let line = format!("{}={}{}", key, value, newline);
+            match env_header_end(&content) {
+                Some(end) if end < content.len() || content.ends_with('\n') => {
+                    content.insert_str(end, &line)
+                }
+                Some(_) => {
+                    content.push_str(newline);
+                    content.push_str(&line);
+                }
+                None => {
+                    if !content.is_empty() && !content.ends_with('\n') {
+                        content.push_str(newline);
+                    }
+                    if !content.trim().is_empty() {
+                        content.push_str(newline);
+                    }
+                    content.push_str(ENV_HEADER);
+                    content.push_str(newline);
+                    content.push_str(&line);
+                }
+            }
+        }
+    }
+    fs::write(env_path, content)
+}
 
-    for line in &mut lines {
-        if line.trim_start().starts_with(&format!("{}=", key)) {
-            *line = format!("{}={}", key, value);
-            found = true;
-            break;
+/// `value` as written after `=` in a `.env` file: as it is when dotenv reads it back
+/// unchanged, else in single quotes, which dotenv takes literally, or in double quotes with
+/// escapes when it holds a single quote or a line break.
+fn quote_env_value(value: &str) -> String {
+    let plain = value
+        .chars()
+        .all(|c| !c.is_whitespace() && !matches!(c, '#' | '\'' | '"' | '\\' | '$'));
+    if plain {
+        value.to_string()
+    } else if !value.contains(['\'', '\n', '\r']) {
+        format!("'{}'", value)
+    } else {
+        let mut quoted = String::from("\"");
+        for c in value.chars() {
+            match c {
+                '\\' | '"' | '$' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                '\n' => quoted.push_str("\\n"),
+                // dotenv has no escape for it; a Windows line break reads as a Unix one.
+                '\r' => {}
+                c => quoted.push(c),
+            }
         }
+        quoted.push('"');
+        quoted
     }
+}
 
-    if !found {
-        lines.push(format!("{}={}", key, value));
+/// Where the value of the first `key=` or `export key=` line of `content`, a `.env` file,
+/// is: after `=` and the blanks following it, up to the blank, the line break or the end
+/// of the file that ends it outside quotes.
+fn find_env_value(content: &str, key: &str) -> Option<Range<usize>> {
+    let mut start = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = start;
+        start += line.len();
+        let rest = line.trim_start();
+        let rest = match rest.strip_prefix("export") {
+            Some(after) if after.starts_with([' ', '\t']) => after.trim_start(),
+            _ => rest,
+        };
+        let Some(rest) = rest.strip_prefix(key) else {
+            continue;
+        };
+        let Some(after) = rest.trim_start_matches([' ', '\t']).strip_prefix('=') else {
+            continue;
+        };
+        let mut value_start = line_start + line.len() - after.len();
+        // Blanks before a value are not part of it; before a comment, they stay before it.
+        let blanks = after.len() - after.trim_start_matches([' ', '\t']).len();
+        if !after[blanks..].starts_with(['#', '\r', '\n']) && blanks < after.len() {
+            value_start += blanks;
+        }
+        return Some(value_start..env_value_end(content, value_start));
     }
+    None
+}
 
-    // Rewrite .env
-    let mut file = File::create(env_path)?;
-    for line in lines {
-        writeln!(file, "{}", line)?;
+/// The end of the value starting at `start` in `content`, reading quotes and escapes as
+/// dotenv does, so that a quoted value may hold blanks and span lines.
+fn env_value_end(content: &str, start: usize) -> usize {
+    let (mut single, mut double, mut escaped) = (false, false, false);
+    for (offset, c) in content[start..].char_indices() {
+        if escaped {
+            escaped = false;
+        } else if single {
+            single = c != '\'';
+        } else if double {
+            match c {
+                '"' => double = false,
+                '\\' => escaped = true,
+                _ => {}
+            }
+        } else {
+            match c {
+                '\'' => single = true,
+                '"' => double = true,
+                '\\' => escaped = true,
+                ' ' | '\t' | '\r' | '\n' => return start + offset,
+                _ => {}
+            }
+        }
     }
+    content.len()
+}
 
-    Ok(())
+/// Where a line goes to join the lines under the lila header of `content`, a `.env` file:
+/// after the last of the lines following the header up to a blank one.
+fn env_header_end(content: &str) -> Option<usize> {
+    let mut start = 0;
+    let mut end = None;
+    for line in content.split_inclusive('\n') {
+        start += line.len();
+        match end {
+            None if line.trim() == ENV_HEADER => end = Some(start),
+            Some(_) if line.trim().is_empty() => break,
+            Some(_) => end = Some(start),
+            None => {}
+        }
+    }
+    end
 }
 
-/// Gathers system info and recommends an AI model (1B or 3B).
-/// If 3B is recommended, let the user choose between two 3B models
-/// and write that choice into `.env`.
-fn run_recommend() -> io::Result<()> {
+/// Gathers system info and recommends an AI model from `MODEL_CATALOG`, measuring how
+/// fast the machine runs each one, and writes the chosen model and its quantization into
+/// `.env`. With `skip_benchmark`, a 1B or 3B model is recommended from the number of cores
+/// and the RAM instead. A `model` given is written instead, whatever is recommended.
+fn run_recommend(
+    model: Option<&str>,
+    questions: &Questions,
+    skip_benchmark: bool,
+) -> io::Result<()> {
     let mut sys = System::new_all();
     sys.refresh_all();
 
@@ -74,8 +272,8 @@ fn run_recommend() -> io::Result<()> {
         .unwrap_or_else(|| "Unknown".to_string());
 
     // Gather memory information (in GB)
-    let total_memory_kb = sys.total_memory(); // in KiB
-    let total_memory_gb = total_memory_kb as f64 / 1_048_576.0; // KiB -> GiB
+    let total_memory_bytes = sys.total_memory(); // in bytes
+    let total_memory_gb = total_memory_bytes as f64 / 1_073_741_824.0; // bytes -> GiB
 
     // Display system information
     println!("\nSystem Recommendation:");
@@ -83,63 +281,234 @@ fn run_recommend() -> io::Result<()> {
     println!("CPU: {} cores ({})", cpu_count, cpu_name);
     println!("Total Memory: {:.2} GB", total_memory_gb);
 
+    if skip_benchmark {
+        recommend_by_cores(cpu_count, total_memory_gb, model, questions)?;
+    } else {
+        recommend_from_catalog(total_memory_gb, model, questions)?;
+    }
+    println!(
+        "Offline? Set {} to a GGUF file on disk (or pass --model-path to lila chat and lila server) to load it instead of downloading a model.",
+        "LILA_AI_MODEL_PATH".yellow()
+    );
+
+    Ok(())
+}
+
+/// Recommends a 3B model on a machine with 8 cores and 16 GB of RAM, else a 1B one, and
+/// lets the user choose between the 3B models, writing that choice into `.env`.
+fn recommend_by_cores(
+    cpu_count: usize,
+    total_memory_gb: f64,
+    model: Option<&str>,
+    questions: &Questions,
+) -> io::Result<()> {
     // Define heuristic thresholds
     let min_cpu_for_3b = 8;
     let min_memory_for_3b = 16.0; // GB
 
     // Determine recommendation
-    let recommendation =
-        if cpu_count as u64 >= min_cpu_for_3b && total_memory_gb >= min_memory_for_3b {
-            "3B model".green()
-        } else {
-            "1B model".yellow()
-        };
+    let recommendation = if cpu_count >= min_cpu_for_3b && total_memory_gb >= min_memory_for_3b {
+        "3B model".green()
+    } else {
+        "1B model".yellow()
+    };
 
     println!("\nRecommended AI Model: {}", recommendation);
+    let isq = recommended_isq(total_memory_gb);
+    println!(
+        "Recommended quantization: {} (set {}={} or pass --isq {})",
+        isq.to_string().green(),
+        ISQ_VAR.yellow(),
+        isq,
+        isq
+    );
 
     // Additional suggestions
-    if recommendation.to_string().contains("3B") {
+    let three_b = recommendation.to_string().contains("3B");
+    if three_b {
         println!("You have a powerful system! You can efficiently run the 3B model.\n");
+    } else {
+        println!("Your system is suitable for a smaller than 3B model. Consider upgrading CPU or RAM for better performance.\n");
+    }
+    let model_selected = match model {
+        Some(model) => Some(model.to_string()),
+        None if three_b => Some(choose_3b_model(questions)?),
+        None => None,
+    };
+    if let Some(model_selected) = model_selected {
+        set_env_value(MODEL_VAR, &model_selected)?;
+    }
+    Ok(())
+}
 
-        // Ask the user which 3B model they'd like to set in .env
+/// Measures the machine, shows the models of `MODEL_CATALOG` with the speed to expect of
+/// each, and writes the one chosen, by default the largest that fits in `total_memory_gb`
+/// and is fast enough, into `.env` with its quantization.
+fn recommend_from_catalog(
+    total_memory_gb: f64,
+    model: Option<&str>,
+    questions: &Questions,
+) -> io::Result<()> {
+    let gpu = detect_gpu(total_memory_gb);
+    match &gpu {
+        Some(gpu) => println!("GPU: {} ({:.1} GB)", gpu.name, gpu.memory_gb),
+        None => println!("GPU: none found"),
+    }
+    println!("\nMeasuring the CPU and memory speed...");
+    let benchmark = Benchmark::run();
+    println!(
+        "CPU: {:.0} GFLOPS, memory: {:.1} GB/s",
+        benchmark.gflops, benchmark.bandwidth_gbs
+    );
+
+    let speed =
+        |entry: &CatalogModel| benchmark.tokens_per_second(entry.parameters_b, entry.weights_gb());
+    let fits = |entry: &CatalogModel| total_memory_gb >= entry.min_ram_gb;
+    let recommended = MODEL_CATALOG
+        .iter()
+        .rposition(|entry| fits(entry) && speed(entry) >= MIN_TOKENS_PER_SECOND)
+        .unwrap_or(0);
+
+    println!(
+        "\n{}",
+        "Models and the speed to expect on this machine:".bold()
+    );
+    for (index, entry) in MODEL_CATALOG.iter().enumerate() {
+        let mut line = format!(
+            "{}) {:<34} {:>4.1}B  {:<4}  ~{:.1} tokens/s",
+            index + 1,
+            entry.id,
+            entry.parameters_b,
+            entry.isq.to_string(),
+            speed(entry)
+        );
+        if !fits(entry) {
+            line.push_str(&format!(", needs {:.0} GB of RAM", entry.min_ram_gb));
+        }
+        if gpu
+            .as_ref()
+            .is_some_and(|gpu| gpu.memory_gb >= entry.min_vram_gb)
+        {
+            line.push_str(", fits the GPU");
+        }
+        if index == recommended {
+            println!("{} {}", "→".green(), line.green());
+        } else if fits(entry) {
+            println!("  {}", line);
+        } else {
+            println!("  {}", line.dimmed());
+        }
+    }
+    if gpu.is_some() {
         println!(
             "{}",
-            "Which 3B model do you want to set as your default?".bold()
+            "Local models run on the CPU; to use the GPU, serve the model with an OpenAI-compatible server and set ai.backend = \"openai\"."
+                .dimmed()
         );
-        println!("1) microsoft/Phi-3.5-mini-instruct");
-        println!("2) microsoft/Phi-4-mini-instruct");
-        println!("3) Qwen/Qwen2.5-Coder-3B-Instruct");
-
-        print!("Enter one of the numbers: ");
-        io::stdout().flush()?;
+    }
+    println!();
 
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let choice = input.trim();
-
-        // Default to the first if invalid input
-        let model_selected = match choice {
-            "1" => "microsoft/Phi-3.5-mini-instruct",
-            "2" => "microsoft/Phi-4-mini-instruct",
-            "3" => "Qwen/Qwen2.5-Coder-3B-Instruct",
-            _ => {
-                println!("Invalid choice, defaulting to 1.");
-                "microsoft/Phi-3.5-mini-instruct"
+    let current = std::env::var(MODEL_VAR)
+        .ok()
+        .filter(|model| !model.is_empty());
+    let chosen = match model {
+        Some(model) => model.to_string(),
+        None => {
+            let default = current.unwrap_or_else(|| MODEL_CATALOG[recommended].id.to_string());
+            let verb = if default == MODEL_CATALOG[recommended].id {
+                "takes"
+            } else {
+                "keeps"
+            };
+            let choice = questions.ask(&format!(
+                "Enter the number of the model to set as your default (ENTER {} {}): ",
+                verb, default
+            ))?;
+            match choice.parse::<usize>() {
+                Ok(number) if (1..=MODEL_CATALOG.len()).contains(&number) => {
+                    MODEL_CATALOG[number - 1].id.to_string()
+                }
+                _ if choice.is_empty() => default,
+                _ => {
+                    println!("Invalid choice, keeping {}.", default);
+                    default
+                }
             }
-        };
+        }
+    };
+    set_env_value(MODEL_VAR, &chosen)?;
+    match MODEL_CATALOG.iter().find(|entry| entry.id == chosen) {
+        Some(entry) => set_env_value(ISQ_VAR, &entry.isq.to_string())?,
+        None => {
+            let isq = recommended_isq(total_memory_gb);
+            println!(
+                "Recommended quantization: {} (set {}={} or pass --isq {})",
+                isq.to_string().green(),
+                ISQ_VAR.yellow(),
+                isq,
+                isq
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Writes `var=value` into `.env` and says so.
+fn set_env_value(var: &str, value: &str) -> io::Result<()> {
+    update_env_value(Path::new(".env"), var, value)?;
+    println!(
+        "{} {} {}",
+        "Set".green(),
+        format!("{}=", var).yellow(),
+        value.green()
+    );
+    Ok(())
+}
 
-        update_env_value("LILA_AI_MODEL", model_selected)?;
+/// Asks which of the 3B models to set as the default. Without an answer, the model
+/// `LILA_AI_MODEL` already names is kept, else the first one is taken.
+fn choose_3b_model(questions: &Questions) -> io::Result<String> {
+    let default = std::env::var(MODEL_VAR)
+        .ok()
+        .filter(|model| !model.is_empty())
+        .unwrap_or_else(|| MODELS_3B[0].to_string());
+    if !questions.defaults {
+        // Ask the user which 3B model they'd like to set in .env
         println!(
-            "{} {} {}",
-            "Set".green(),
-            "LILA_AI_MODEL=".yellow(),
-            model_selected.green()
+            "{}",
+            "Which 3B model do you want to set as your default?".bold()
         );
-    } else {
-        println!("Your system is suitable for a smaller than 3B model. Consider upgrading CPU or RAM for better performance.\n");
+        for (number, model) in MODELS_3B.iter().enumerate() {
+            println!("{}) {}", number + 1, model);
+        }
     }
+    let choice = questions.ask(&format!(
+        "Enter one of the numbers (ENTER keeps {}): ",
+        default
+    ))?;
+    let chosen = match choice.parse::<usize>() {
+        Ok(number) if (1..=MODELS_3B.len()).contains(&number) => MODELS_3B[number - 1].to_string(),
+        _ if choice.is_empty() => default,
+        _ => {
+            println!("Invalid choice, keeping {}.", default);
+            default
+        }
+    };
+    Ok(chosen)
+}
 
-    Ok(())
+/// The quantization that fits the recommended model in `memory_gb` of RAM with room to
+/// spare: 4-bit below 12 GB, none from 32 GB.
+fn recommended_isq(memory_gb: f64) -> Isq {
+    if memory_gb < 12.0 {
+        Isq::Q4K
+    } else if memory_gb < 20.0 {
+        Isq::Q5K
+    } else if memory_gb < 32.0 {
+        Isq::Q8_0
+    } else {
+        Isq::None
+    }
 }
 
 /// Helper function to run `rustc --version` and extract the major.minor version.
@@ -157,70 +526,292 @@ fn get_rustc_version() -> Option<String> {
     }
 }
 
-/// Interactively creates a `Lila.toml` file with several sections:
-/// - [project]: asks for context and deployment description
-/// - [compliance]: added only if the user chooses to include compliance guidelines
-/// - [ai_guidance]: always includes a fixed code_of_conduct
-/// - [development]: detects the programming languages, operating system, and architecture
-/// - [dependencies]: for example, if Rust is selected, attempts to parse Cargo.toml for dependencies
-fn create_lila_toml() -> io::Result<()> {
-    // 1. [project] section
-    let mut project_context = String::new();
-    println!("\nEnter the project context (e.g. \"Physics engine for tissue simulation\"):");
-    io::stdin().read_line(&mut project_context)?;
-    let project_context = {
-        let trimmed = project_context.trim();
-        if trimmed.is_empty() {
-            "Default project context".to_string()
+/// The major.minor Rust version of the project in `dir`: the `rust-version` its `Cargo.toml`
+/// requires, else the one of `rustc`.
+fn get_rust_version(dir: &Path) -> Option<String> {
+    let required = std::fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|content| toml::from_str::<toml::Value>(&content).ok())
+        .and_then(|manifest| {
+            // Example: rust-version = "1.74" under [package] or [workspace.package]
+            let package = manifest
+                .get("package")
+                .or_else(|| manifest.get("workspace")?.get("package"))?;
+            major_minor(package.get("rust-version")?.as_str()?)
+        });
+    required.or_else(get_rustc_version)
+}
+
+/// The major.minor Go version of the project in `dir`: the `go` line of its `go.mod`, else
+/// the one of `go version`.
+fn get_go_version(dir: &Path) -> Option<String> {
+    if let Ok(content) = std::fs::read_to_string(dir.join("go.mod")) {
+        // Example line: "go 1.22.1"
+        let version = content
+            .lines()
+            .find_map(|line| major_minor(line.trim().strip_prefix("go ")?));
+        if version.is_some() {
+            return version;
+        }
+    }
+    let output = Command::new("go").arg("version").output().ok()?;
+    // Example output: "go version go1.22.1 linux/amd64"
+    major_minor(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The first major.minor version in `text`, like "3.11" for "Python 3.11.4" or ">=3.11,<4".
+fn major_minor(text: &str) -> Option<String> {
+    let version = Regex::new(r"(\d+)\.(\d+)").ok()?;
+    let captures = version.captures(text)?;
+    Some(format!("{}.{}", &captures[1], &captures[2]))
+}
+
+/// The major.minor Python version of the project in `dir`, like "3.11". The version pinned
+/// in `.python-version` or in `requires-python` of `pyproject.toml` comes first, then the one
+/// of the active virtualenv, then `python3 --version` or `python --version`.
+fn get_python_version(dir: &Path) -> Option<String> {
+    pinned_python_version(dir)
+        .or_else(virtualenv_python_version)
+        .or_else(|| ["python3", "python"].iter().find_map(python_version))
+}
+
+/// The Python version pinned by `.python-version` or by `requires-python` in `pyproject.toml`
+/// of `dir`.
+fn pinned_python_version(dir: &Path) -> Option<String> {
+    if let Ok(content) = std::fs::read_to_string(dir.join(".python-version")) {
+        // Example content: "3.11.4", possibly followed by other versions on further lines
+        if let Some(version) = content.lines().find_map(major_minor) {
+            return Some(version);
+        }
+    }
+    let pyproject = std::fs::read_to_string(dir.join("pyproject.toml")).ok()?;
+    let pyproject: toml::Value = toml::from_str(&pyproject).ok()?;
+    // Example requirement: ">=3.10"
+    let requirement = pyproject.get("project")?.get("requires-python")?.as_str()?;
+    major_minor(requirement)
+}
+
+/// The Python version of the virtualenv in `VIRTUAL_ENV`, from its `pyvenv.cfg` or else its
+/// interpreter.
+fn virtualenv_python_version() -> Option<String> {
+    let venv = PathBuf::from(std::env::var_os("VIRTUAL_ENV")?);
+    if let Ok(config) = std::fs::read_to_string(venv.join("pyvenv.cfg")) {
+        // Example line: "version = 3.11.4", or "version_info = 3.11.4.final.0" with uv
+        let version = config.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            matches!(key.trim(), "version" | "version_info")
+                .then(|| major_minor(value))
+                .flatten()
+        });
+        if version.is_some() {
+            return version;
+        }
+    }
+    let interpreter = if cfg!(windows) {
+        venv.join("Scripts").join("python.exe")
+    } else {
+        venv.join("bin").join("python")
+    };
+    python_version(interpreter.as_os_str())
+}
+
+/// Runs `python --version` and extracts the major.minor version.
+fn python_version(python: impl AsRef<OsStr>) -> Option<String> {
+    let output = Command::new(python).arg("--version").output().ok()?;
+    // Example output: "Python 3.11.4", printed on stderr before Python 3.4
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    [stdout, stderr]
+        .iter()
+        .find_map(|text| text.trim().strip_prefix("Python ").and_then(major_minor))
+}
+
+/// Prints the languages found in the project, those proposed first, with their files and
+/// marker files.
+fn print_detected_languages(detection: &Detection) {
+    if detection.languages.is_empty() {
+        println!("\nNo source files were found in this folder.");
+        return;
+    }
+    let describe = |found: &DetectedLanguage| {
+        let files = match found.files {
+            1 => "1 file".to_string(),
+            count => format!("{} files", count),
+        };
+        let markers: Vec<String> = found
+            .markers
+            .iter()
+            .map(|marker| {
+                marker
+                    .strip_prefix(".")
+                    .unwrap_or(marker)
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        if markers.is_empty() {
+            format!("{} ({})", found.language.tag(), files)
         } else {
-            trimmed.to_string()
+            format!(
+                "{} ({}, {})",
+                found.language.tag(),
+                files,
+                markers.join(", ")
+            )
         }
     };
+    let (proposed, others): (Vec<_>, Vec<_>) =
+        detection.languages.iter().partition(|found| found.proposed);
+    println!();
+    if !proposed.is_empty() {
+        let found: Vec<String> = proposed.into_iter().map(describe).collect();
+        println!("Detected languages: {}", found.join(", ").bold());
+    }
+    if !others.is_empty() {
+        let found: Vec<String> = others.into_iter().map(describe).collect();
+        println!("Also seen: {}", found.join(", "));
+    }
+    if detection.truncated {
+        println!(
+            "{}",
+            "(the project is large; only part of it was looked at)".dimmed()
+        );
+    }
+}
 
-    let mut deployment = String::new();
+/// Prints which formatters were found, with their versions.
+fn print_formatter_summary(probes: &[FormatterProbe]) {
+    let width = probes
+        .iter()
+        .map(|probe| probe.command.len())
+        .chain(["Formatter".len()])
+        .max()
+        .unwrap_or(0);
     println!(
-        "Enter the deployment description (e.g. \"on-premise with enterprise intranet-only\"):"
+        "\n{:<width$}  Installed  Version",
+        "Formatter",
+        width = width
     );
-    io::stdin().read_line(&mut deployment)?;
-    let deployment = {
-        let trimmed = deployment.trim();
-        if trimmed.is_empty() {
-            "on-premise with enterprise intranet-only".to_string()
+    for probe in probes {
+        let installed = if probe.installed {
+            format!("{:<9}", "yes").green()
         } else {
-            trimmed.to_string()
-        }
+            format!("{:<9}", "no").yellow()
+        };
+        let version = match (&probe.version, probe.timed_out) {
+            (_, Some(elapsed)) => format!("no answer within {}s", elapsed.as_secs()),
+            (Some(version), None) => version.clone(),
+            (None, None) if probe.installed => "unknown".to_string(),
+            (None, None) => "-".to_string(),
+        };
+        println!(
+            "{:<width$}  {}  {}",
+            probe.command,
+            installed,
+            version,
+            width = width
+        );
+    }
+    println!();
+}
+
+/// Creates a `Lila.toml` file from `options` and the answers to `questions`, or merges them
+/// into the existing one unless `options.force_new` is set: questions left unanswered keep
+/// what the file holds. Its sections are:
+/// - [project]: asks for context and deployment description
+/// - [compliance]: added only if the user chooses to include compliance guidelines
+/// - [ai_guidance]: always includes a fixed code_of_conduct
+/// - [development]: detects the programming languages, operating system, and architecture
+/// - [format]: lists the `available` formatters, so `lila edit` can warn about missing ones
+/// - [dependencies]: the dependencies in the manifests of the chosen Rust, Python and
+///   JavaScript or TypeScript projects
+fn create_lila_toml(
+    options: &InitOptions,
+    questions: &Questions,
+    available: &[String],
+) -> io::Result<()> {
+    let existing = read_lila_toml(options.force_new)?;
+    let current = existing.as_ref();
+    // Keys given a new value, as `table.key`; an existing Lila.toml keeps the others.
+    let mut answered = vec!["format.available"];
+
+    // 1. [project] section
+    let current_context = current_string(current, "project", "context");
+    let project_context = match &options.context {
+        Some(context) => context.trim().to_string(),
+        None => questions.ask(&format!(
+            "\nEnter the project context (e.g. \"Physics engine for tissue simulation\"){}:\n",
+            keeps(current_context.as_deref())
+        ))?,
+    };
+    let project_context = if project_context.is_empty() {
+        "Default project context".to_string()
+    } else {
+        answered.push("project.context");
+        project_context
     };
 
-    // 2. [compliance] section (optional)
-    let mut compliance_input = String::new();
-    println!("Do you have compliance guidelines to follow? (y/N):");
-    io::stdout().flush()?;
-    io::stdin().read_line(&mut compliance_input)?;
-    let compliance_input = compliance_input.trim().to_lowercase();
-    let compliance_section = if compliance_input == "y" || compliance_input == "yes" {
-        // Ask for ISO guidelines
-        let mut iso = String::new();
-        println!("Enter ISO compliance guidelines separated by comma (e.g. ISO/IEC 22989:2022):");
-        io::stdin().read_line(&mut iso)?;
-        let iso: Vec<&str> = iso
-            .trim()
-            .split(',')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .collect();
+    let current_deployment = current_string(current, "project", "deployment");
+    let deployment = match &options.deployment {
+        Some(deployment) => deployment.trim().to_string(),
+        None => questions.ask(&format!(
+            "Enter the deployment description (e.g. \"on-premise with enterprise intranet-only\"){}:\n",
+            keeps(current_deployment.as_deref())
+        ))?,
+    };
+    let deployment = if deployment.is_empty() {
+        "on-premise with enterprise intranet-only".to_string()
+    } else {
+        answered.push("project.deployment");
+        deployment
+    };
 
-        // Ask for BSI guidelines
-        let mut bsi = String::new();
-        println!(
-            "Enter BSI compliance guidelines separated by comma (e.g. APP.6 Allgemeine Software):"
-        );
-        io::stdin().read_line(&mut bsi)?;
-        let bsi: Vec<&str> = bsi
-            .trim()
-            .split(',')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .collect();
+    // 2. [compliance] section (optional)
+    let current_iso = current_list(current, "compliance", "iso");
+    let current_bsi = current_list(current, "compliance", "bsi");
+    let has_current_compliance = current.is_some_and(|doc| doc.contains_key("compliance"));
+    let guidelines_given = !options.iso.is_empty() || !options.bsi.is_empty();
+    // None when the question is left unanswered, keeping the guidelines there are.
+    let has_compliance = if options.no_compliance {
+        Some(false)
+    } else if guidelines_given {
+        Some(true)
+    } else {
+        let choices = if has_current_compliance {
+            "y/n, ENTER keeps the current ones"
+        } else {
+            "y/N"
+        };
+        let answer = questions
+            .ask(&format!(
+                "Do you have compliance guidelines to follow? ({}):\n",
+                choices
+            ))?
+            .to_lowercase();
+        (!answer.is_empty()).then(|| answer == "y" || answer == "yes")
+    };
+    let remove_compliance = has_compliance == Some(false);
+    let compliance_section = if has_compliance.unwrap_or(false) {
+        let (iso, bsi) = if guidelines_given {
+            (options.iso.clone(), options.bsi.clone())
+        } else {
+            // Ask for ISO guidelines, then BSI guidelines
+            let iso = questions.ask_list(&format!(
+                "Enter ISO compliance guidelines separated by comma (e.g. ISO/IEC 22989:2022){}:\n",
+                keeps(current_iso.map(|iso| iso.join(", ")).as_deref())
+            ))?;
+            let bsi = questions.ask_list(&format!(
+                "Enter BSI compliance guidelines separated by comma (e.g. APP.6 Allgemeine Software){}:\n",
+                keeps(current_bsi.map(|bsi| bsi.join(", ")).as_deref())
+            ))?;
+            (iso, bsi)
+        };
+        if !iso.is_empty() {
+            answered.push("compliance.iso");
+        }
+        if !bsi.is_empty() {
+            answered.push("compliance.bsi");
+        }
 
         // Format arrays for TOML
         let iso_array = format!(
@@ -249,25 +840,77 @@ fn create_lila_toml() -> io::Result<()> {
 
     // 4. [development] section
     // Ask for the programming languages used (we will auto-detect OS and architecture)
-    let mut languages_input = String::new();
-    println!("Enter the programming languages used in this project (comma separated, e.g. rust, python):");
-    io::stdin().read_line(&mut languages_input)?;
-    let languages: Vec<&str> = languages_input
-        .trim()
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
+    let current_languages: Vec<String> = current_list(current, "development", "languages")
+        .unwrap_or_default()
+        .iter()
+        .map(|entry| language_name(entry).to_string())
         .collect();
+    let detection = detect_languages(Path::new("."));
+    let new_languages: Vec<String> = if options.languages.is_empty() {
+        print_detected_languages(&detection);
+        // Without languages to keep, ENTER accepts the ones detected.
+        let proposed = detection.proposed();
+        let default = if !current_languages.is_empty() {
+            keeps(Some(&current_languages.join(", ")))
+        } else if !proposed.is_empty() {
+            format!(" [ENTER accepts \"{}\"]", proposed.join(", "))
+        } else {
+            String::new()
+        };
+        let answer = questions.ask_list(&format!(
+            "Enter the programming languages used in this project (comma separated, e.g. rust, python){}:\n",
+            default
+        ))?;
+        if answer.is_empty() && current_languages.is_empty() {
+            proposed
+        } else {
+            answer
+        }
+    } else {
+        options
+            .languages
+            .iter()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    };
+    let languages = if new_languages.is_empty() {
+        current_languages
+    } else {
+        answered.push("development.languages");
+        new_languages.clone()
+    };
 
-    // For each language, if "rust" is chosen, auto-detect the installed rustc version.
+    // For "rust", "python" and "go", auto-detect the version in use, in the folder of the
+    // language's marker file if one was found.
+    let root = |language| {
+        detection
+            .get(language)
+            .and_then(|found| found.root())
+            .unwrap_or(Path::new("."))
+    };
     let mut language_entries = Vec::new();
-    for lang in languages.iter() {
+    for lang in new_languages.iter() {
         if lang.eq_ignore_ascii_case("rust") {
-            let version = get_rustc_version().unwrap_or_else(|| "1.71".to_string());
+            let version =
+                get_rust_version(root(CodeLanguage::Rust)).unwrap_or_else(|| "1.71".to_string());
             language_entries.push(format!("\"rust~={}\"", version));
+        } else if CodeLanguage::from_tag(lang) == CodeLanguage::Go {
+            match get_go_version(root(CodeLanguage::Go)) {
+                Some(version) => language_entries.push(format!("\"go~={}\"", version)),
+                None => language_entries.push("\"go\"".to_string()),
+            }
         } else if lang.eq_ignore_ascii_case("python") {
-            // TODO: add auto-detection here as well.
-            language_entries.push("\"python~=3.10\"".to_string());
+            match get_python_version(root(CodeLanguage::Python)) {
+                Some(version) => language_entries.push(format!("\"python~={}\"", version)),
+                None => {
+                    eprintln!(
+                        "{} No Python version was found; writing \"python\" without one.",
+                        "Warning:".yellow()
+                    );
+                    language_entries.push("\"python\"".to_string());
+                }
+            }
         } else {
             language_entries.push(format!("\"{}\"", lang));
         }
@@ -291,41 +934,35 @@ fn create_lila_toml() -> io::Result<()> {
     let architecture = std::env::consts::ARCH;
     let arch_array = format!("[\"{}\"]", architecture);
 
-    // 5. [dependencies] section
-    // We'll build two subsections: one for Python and one for Rust.
-    let mut dependencies_rust = String::new();
-    let mut dependencies_python = String::new();
-
-    // If Rust is among the chosen languages, try to parse Cargo.toml
-    if languages
-        .iter()
-        .any(|&lang| lang.eq_ignore_ascii_case("rust"))
-    {
-        let cargo_path = Path::new("Cargo.toml");
-        if cargo_path.exists() {
-            let cargo_content = std::fs::read_to_string(cargo_path)?;
-            // Parse using the toml crate (make sure to add toml = "0.5" to your Cargo.toml dependencies)
-            let cargo_toml: toml::Value =
-                toml::from_str(&cargo_content).unwrap_or(toml::Value::Table(Default::default()));
-            if let Some(deps) = cargo_toml.get("dependencies") {
-                if let Some(deps_table) = deps.as_table() {
-                    for (key, value) in deps_table {
-                        // Format each dependency as: key = <value>
-                        dependencies_rust.push_str(&format!("{} = {}\n", key, value));
-                    }
-                }
-            }
-        } else {
-            println!("No Cargo.toml found in the current directory, skipping Rust dependencies extraction.");
+    // 5. [dependencies] section, from the manifests of the chosen languages
+    let chosen = |language| {
+        languages
+            .iter()
+            .any(|lang| CodeLanguage::from_tag(lang) == language)
+    };
+    let mut dependencies: Vec<(&str, toml::Table)> = Vec::new();
+    if chosen(CodeLanguage::Python) {
+        let found = python_dependencies(root(CodeLanguage::Python));
+        if let Some(table) = found_dependencies(found, "pyproject.toml or requirements.txt") {
+            dependencies.push(("python", table));
         }
     }
-
-    // If Python is chosen, use a default list (you might later extend this to auto-detect)
-    if languages
-        .iter()
-        .any(|&lang| lang.eq_ignore_ascii_case("python"))
-    {
-        dependencies_python.push_str("");
+    if chosen(CodeLanguage::Rust) {
+        let found = rust_dependencies(root(CodeLanguage::Rust));
+        if let Some(table) = found_dependencies(found, "Cargo.toml") {
+            dependencies.push(("rust", table));
+        }
+    }
+    if chosen(CodeLanguage::JavaScript) || chosen(CodeLanguage::TypeScript) {
+        // A TypeScript project's package.json is beside its tsconfig.json.
+        let dir = detection
+            .get(CodeLanguage::JavaScript)
+            .or_else(|| detection.get(CodeLanguage::TypeScript))
+            .and_then(|found| found.root())
+            .unwrap_or(Path::new("."));
+        if let Some(table) = found_dependencies(node_dependencies(dir), "package.json") {
+            dependencies.push(("node", table));
+        }
     }
 
     // 6. Build the complete Lila.toml content
@@ -349,42 +986,342 @@ fn create_lila_toml() -> io::Result<()> {
     lila_toml.push_str(&format!("languages = {}\n", languages_array));
     lila_toml.push_str(&format!("operating_systems = {}\n", os_array));
     lila_toml.push_str(&format!("architecture = {}\n\n", arch_array));
+    // [format] section
+    lila_toml.push_str("[format]\n");
+    lila_toml.push_str(
+        "# Formatters lila init found; lila edit warns when one configured below is missing.\n",
+    );
+    lila_toml.push_str(&format!(
+        "available = [{}]\n\n",
+        available
+            .iter()
+            .map(|command| format!("\"{}\"", command))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
     // [dependencies] section
     lila_toml.push_str("[dependencies]\n\n");
-    if !dependencies_python.is_empty() {
-        lila_toml.push_str("  [dependencies.python]\n");
-        for line in dependencies_python.lines() {
-            lila_toml.push_str("  ");
-            lila_toml.push_str(line);
-            lila_toml.push('\n');
-        }
-        lila_toml.push('\n');
-    }
-    if !dependencies_rust.is_empty() {
-        lila_toml.push_str("  [dependencies.rust]\n");
-        for line in dependencies_rust.lines() {
-            lila_toml.push_str("  ");
-            lila_toml.push_str(line);
-            lila_toml.push('\n');
+    for (name, table) in &dependencies {
+        lila_toml.push_str(&format!("  [dependencies.{}]\n", name));
+        for (key, value) in table {
+            lila_toml.push_str(&format!(
+                "  {} = {}\n",
+                Key::new(key.as_str()).display_repr(),
+                value
+            ));
         }
         lila_toml.push('\n');
     }
 
-    // Write Lila.toml to the current directory
+    // Write Lila.toml to the current directory, merging into the existing one
+    let (lila_toml, done) = match existing {
+        Some(mut doc) => {
+            let fresh: DocumentMut = lila_toml.parse().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("could not merge the answers into Lila.toml: {}", e),
+                )
+            })?;
+            if remove_compliance {
+                doc.remove("compliance");
+            }
+            merge_lila_toml(&mut doc, &fresh, &answered);
+            (
+                doc.to_string(),
+                "Lila.toml updated; what you kept is left as it was.",
+            )
+        }
+        None => (lila_toml, "Lila.toml created successfully."),
+    };
     let mut file = File::create("Lila.toml")?;
     file.write_all(lila_toml.as_bytes())?;
-    println!("\n{}", "Lila.toml created successfully.".bright_green());
+    println!("\n{}", done.bright_green());
+    Ok(())
+}
+
+/// Reads the `Lila.toml` the answers of `lila init` are merged into; `None` when there is
+/// none or with `force_new`.
+fn read_lila_toml(force_new: bool) -> io::Result<Option<DocumentMut>> {
+    let path = Path::new("Lila.toml");
+    if force_new || !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path)?;
+    content.parse().map(Some).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "could not parse Lila.toml: {}\nFix it, or pass --force-new to write a new one.",
+                e
+            ),
+        )
+    })
+}
+
+/// The string `table.key` holds in `doc`.
+fn current_string(doc: Option<&DocumentMut>, table: &str, key: &str) -> Option<String> {
+    Some(doc?.get(table)?.get(key)?.as_str()?.to_string())
+}
+
+/// The strings of the array `table.key` holds in `doc`.
+fn current_list(doc: Option<&DocumentMut>, table: &str, key: &str) -> Option<Vec<String>> {
+    let array = doc?.get(table)?.get(key)?.as_array()?;
+    Some(
+        array
+            .iter()
+            .filter_map(|value| value.as_str().map(|s| s.to_string()))
+            .collect(),
+    )
+}
+
+/// The dependencies `found` in a language's `manifest`, as named in messages; `None`, after
+/// saying why, when there is none or it cannot be read, which leaves the language out.
+fn found_dependencies(
+    found: Result<Option<toml::Table>, String>,
+    manifest: &str,
+) -> Option<toml::Table> {
+    match found {
+        Ok(Some(table)) if !table.is_empty() => Some(table),
+        Ok(Some(_)) => None,
+        Ok(None) => {
+            println!(
+                "No {} found, skipping the extraction of its dependencies.",
+                manifest
+            );
+            None
+        }
+        Err(e) => {
+            eprintln!(
+                "{} {}; its dependencies are left out of Lila.toml.",
+                "Warning:".yellow(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// The language of an entry of `languages`, like "rust" for "rust~=1.71".
+fn language_name(entry: &str) -> &str {
+    entry
+        .split(['~', '=', '<', '>', '!'])
+        .next()
+        .unwrap_or(entry)
+        .trim()
+}
+
+/// The end of a question telling what ENTER keeps, when there is a `current` value.
+fn keeps(current: Option<&str>) -> String {
+    match current {
+        Some(current) if !current.is_empty() => format!(" [ENTER keeps \"{}\"]", current),
+        _ => String::new(),
+    }
+}
+
+/// Merges `fresh`, the `Lila.toml` written from the answers, into `doc`, the existing one:
+/// the `answered` keys, as `table.key`, are replaced and the tables and keys `doc` lacks are
+/// added. The rest of `doc`, tables lila does not know included, keeps its comments and order.
+fn merge_lila_toml(doc: &mut DocumentMut, fresh: &DocumentMut, answered: &[&str]) {
+    let mut next = next_position(doc.as_table());
+    for (name, fresh_item) in fresh.iter() {
+        let Some(item) = doc.get_mut(name) else {
+            doc.insert(name, placed(fresh_item, &mut next));
+            continue;
+        };
+        let (Some(table), Some(fresh_table)) =
+            (item.as_table_like_mut(), fresh_item.as_table_like())
+        else {
+            continue;
+        };
+        for (key, value) in fresh_table.iter() {
+            let replace = answered.contains(&format!("{}.{}", name, key).as_str());
+            match table.get_mut(key) {
+                Some(current) if replace => {
+                    // Keep the comment after the value being replaced.
+                    let decor = current.as_value().map(|value| value.decor().clone());
+                    *current = value.clone();
+                    if let (Some(value), Some(decor)) = (current.as_value_mut(), decor) {
+                        *value.decor_mut() = decor;
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    table.insert(key, placed(value, &mut next));
+                }
+            }
+        }
+    }
+}
+
+/// The position after those of `table` and the tables in it.
+fn next_position(table: &Table) -> usize {
+    table
+        .iter()
+        .filter_map(|(_, item)| item.as_table())
+        .map(|table| {
+            let after = table.position().map_or(0, |position| position + 1);
+            after.max(next_position(table))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// A copy of `item` whose tables take the positions from `next` on, so that tables added
+/// to a file are written after the ones it has.
+fn placed(item: &Item, next: &mut usize) -> Item {
+    fn place(table: &mut Table, next: &mut usize) {
+        table.set_position(*next);
+        *next += 1;
+        for (_, item) in table.iter_mut() {
+            if let Some(table) = item.as_table_mut() {
+                place(table, next);
+            }
+        }
+    }
+    let mut item = item.clone();
+    if let Some(table) = item.as_table_mut() {
+        place(table, next);
+    }
+    item
+}
+
+/// First line of the block `lila init` writes to `.gitignore`.
+const GITIGNORE_BEGIN: &str = "# >>> lila (managed by `lila init`; entries added here are kept)";
+/// Last line of the block `lila init` writes to `.gitignore`.
+const GITIGNORE_END: &str = "# <<< lila";
+
+/// The folder of the git repository `dir` is in, if it is in one.
+fn git_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors().find(|dir| dir.join(".git").exists())
+}
+
+/// The `.gitignore` patterns for what lila writes into the repository at `repo`: the `.env`
+/// file, the `.md.bak` backups of `lila edit`, the lists of saved files, and the output
+/// folder `output` when it is inside the repository.
+fn gitignore_entries(repo: &Path, output: &Path) -> Vec<String> {
+    let mut entries: Vec<String> = [".env", "*.md.bak", "created_markdown_files.txt"]
+        .iter()
+        .map(|entry| entry.to_string())
+        .collect();
+    let (Ok(repo), Ok(output)) = (repo.canonicalize(), output.canonicalize()) else {
+        return entries;
+    };
+    let Ok(relative) = output.strip_prefix(&repo) else {
+        return entries;
+    };
+    let relative: Vec<String> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if relative.is_empty() {
+        // The repository is the output folder: ignore what is written into it, not all of it.
+        entries.push("/doc/".to_string());
+        entries.push("/.app/".to_string());
+    } else {
+        entries.push(format!("/{}/", relative.join("/")));
+    }
+    entries
+}
+
+/// `content`, a `.gitignore`, with lila's block holding `entries`, or `None` when it
+/// already ignores them all. Entries of the block are kept, new ones added after them;
+/// those already written outside the block are not repeated in it.
+fn merge_gitignore(content: &str, entries: &[String]) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let begin = lines.iter().position(|line| line.trim() == GITIGNORE_BEGIN);
+    let (before, block, after) = match begin {
+        Some(begin) => {
+            // A block whose end was deleted runs to the end of the file.
+            let end = lines[begin..]
+                .iter()
+                .position(|line| line.trim() == GITIGNORE_END)
+                .map_or(lines.len(), |end| begin + end);
+            (
+                &lines[..begin],
+                &lines[begin + 1..end],
+                lines.get(end + 1..).unwrap_or(&[]),
+            )
+        }
+        None => (&lines[..], &[][..], &[][..]),
+    };
+    let outside: Vec<&str> = before.iter().chain(after).map(|line| line.trim()).collect();
+    let mut kept: Vec<String> = block
+        .iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let missing: Vec<&String> = entries
+        .iter()
+        .filter(|entry| !outside.contains(&entry.as_str()) && !kept.contains(entry))
+        .collect();
+    if missing.is_empty() {
+        return None;
+    }
+    kept.extend(missing.into_iter().cloned());
+
+    let mut merged: Vec<String> = before.iter().map(|line| line.to_string()).collect();
+    if begin.is_none() && merged.last().is_some_and(|line| !line.trim().is_empty()) {
+        merged.push(String::new());
+    }
+    merged.push(GITIGNORE_BEGIN.to_string());
+    merged.extend(kept);
+    merged.push(GITIGNORE_END.to_string());
+    merged.extend(after.iter().map(|line| line.to_string()));
+    Some(merged.join("\n") + "\n")
+}
+
+/// Offers to add lila's files to the `.gitignore` of the repository the current folder is
+/// in, `output` being the output folder. Outside a git repository, does nothing.
+fn offer_gitignore(output: &Path, questions: &Questions) -> io::Result<()> {
+    let current_dir = std::env::current_dir()?;
+    let Some(repo) = git_root(&current_dir) else {
+        return Ok(());
+    };
+    let path = repo.join(".gitignore");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let entries = gitignore_entries(repo, &current_dir.join(output));
+    let Some(merged) = merge_gitignore(&content, &entries) else {
+        return Ok(());
+    };
+    let answer = questions
+        .ask(&format!(
+            "\nAdd .env, backups and lila's output to {}? (Y/n):\n",
+            path.display()
+        ))?
+        .to_lowercase();
+    if answer == "n" || answer == "no" {
+        return Ok(());
+    }
+    fs::write(&path, merged)?;
+    println!(
+        "\n{}",
+        format!("{} now ignores lila's files.", path.display()).bright_green()
+    );
     Ok(())
 }
 
 /// Initializes the project for Lila:
 /// 1) Sets a default LILA_OUTPUT_PATH (i.e. ~/.lila/<project_name>)
-/// 2) Checks for `black` / `rustfmt` and sets environment flags
+/// 2) Checks for the known code formatters and records them and their versions in `.env`
 /// 3) Runs AI model recommendation
 /// 4) Creates a Lila.toml file for project configuration
-pub fn init() -> io::Result<()> {
+/// 5) Offers to keep `.env` and lila's output out of git, in `.gitignore`
+///
+/// The questions `options` does not answer are asked, unless it says to take their
+/// defaults or stdin is not a terminal. The same answers write the same files either way.
+pub fn init(options: InitOptions) -> io::Result<()> {
     println!("{}", "Welcome to lila init!".bright_green());
     println!("This will check for code formatters and record them in your .env file.\n");
+    let questions = Questions {
+        defaults: options.yes || !io::stdin().is_terminal(),
+    };
+    if !options.yes && questions.defaults {
+        println!("stdin is not a terminal, so every question left out takes its default (as with --yes).\n");
+    }
 
     // 1) Set the default LILA_OUTPUT_PATH
     let home = home_dir().expect("Could not determine the home directory");
@@ -395,19 +1332,20 @@ pub fn init() -> io::Result<()> {
         .to_string_lossy()
         .to_string();
     let lila_root = home.join(".lila");
-    let default_root = lila_root.join(&project_name);
+    // A path set before, in the environment or the .env file, is kept by default.
+    let default_root = match std::env::var_os(OUTPUT_PATH_VAR).filter(|path| !path.is_empty()) {
+        Some(path) => PathBuf::from(path),
+        None => lila_root.join(&project_name),
+    };
 
     // Give the user a chance to override or accept
-    println!(
-        "Default project output path is: {}\nPress ENTER to accept or type a different path:",
-        default_root.display()
-    );
-    print!("> ");
-    io::stdout().flush()?;
-
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let path_input = input.trim();
+    let path_input = match &options.output_path {
+        Some(path) => path.to_string_lossy().into_owned(),
+        None => questions.ask(&format!(
+            "Default project output path is: {}\nPress ENTER to accept or type a different path:\n> ",
+            default_root.display()
+        ))?,
+    };
 
     let final_path = if path_input.is_empty() {
         default_root
@@ -419,48 +1357,356 @@ pub fn init() -> io::Result<()> {
     create_dir_all(&final_path)?;
 
     // Write LILA_OUTPUT_PATH to .env
-    update_env_value("LILA_OUTPUT_PATH", &final_path.to_string_lossy())?;
-
-    // 2) Check for black
-    let black_installed = check_program_availability("black");
-    let black_msg = if black_installed {
-        "Detected 'black' on this system."
-    } else {
-        "Could NOT detect 'black' on this system."
-    };
-    println!("{}", black_msg.bright_yellow());
     update_env_value(
-        "BLACK_INSTALLED",
-        if black_installed { "true" } else { "false" },
+        Path::new(".env"),
+        OUTPUT_PATH_VAR,
+        &final_path.to_string_lossy(),
     )?;
 
-    // 2a) Check for rustfmt
-    let rustfmt_installed = check_program_availability("rustfmt");
-    let rustfmt_msg = if rustfmt_installed {
-        "Detected 'rustfmt' on this system."
-    } else {
-        "Could NOT detect 'rustfmt' on this system."
-    };
-    println!("{}", rustfmt_msg.bright_yellow());
-    update_env_value(
-        "RUSTFMT_INSTALLED",
-        if rustfmt_installed { "true" } else { "false" },
-    )?;
+    // 2) Look for the known formatters and record them in .env
+    let probes: Vec<FormatterProbe> = known_formatter_commands()
+        .iter()
+        .map(|command| probe_formatter(command, FORMATTER_PROBE_TIMEOUT))
+        .collect();
+    for probe in &probes {
+        update_env_value(
+            Path::new(".env"),
+            &installed_env_var(&probe.command),
+            if probe.installed { "true" } else { "false" },
+        )?;
+        if let Some(version) = &probe.version {
+            update_env_value(Path::new(".env"), &version_env_var(&probe.command), version)?;
+        }
+    }
+    print_formatter_summary(&probes);
+    let available: Vec<String> = probes
+        .iter()
+        .filter(|probe| probe.installed)
+        .map(|probe| probe.command.clone())
+        .collect();
 
     // 3) Run system-based recommendation for AI model
-    run_recommend()?;
+    run_recommend(options.model.as_deref(), &questions, options.skip_benchmark)?;
 
     // 4) Create Lila.toml configuration file
-    println!(
-        "\n{}",
-        "Now let’s configure your project via Lila.toml.".bright_green()
-    );
-    create_lila_toml()?;
+    if !options.no_toml {
+        println!(
+            "\n{}",
+            "Now let’s configure your project via Lila.toml.".bright_green()
+        );
+        create_lila_toml(&options, &questions, &available)?;
+    }
 
-    println!(
-        "\n{}",
-        "Done! Your .env and Lila.toml files have been updated.".bright_green()
-    );
+    // 5) Keep lila's files out of git
+    if !options.no_gitignore {
+        offer_gitignore(&final_path, &questions)?;
+    }
+
+    if options.no_toml {
+        println!(
+            "\n{}",
+            "Done! Your .env file has been updated.".bright_green()
+        );
+    } else {
+        println!(
+            "\n{}",
+            "Done! Your .env and Lila.toml files have been updated.".bright_green()
+        );
+    }
     println!("You can re-run `lila init` anytime if you install new formatters or want to update your configuration.\n");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Lila.toml edited by hand after `lila init` wrote it.
+    const EDITED: &str = "\
+# Our book
+[project]
+context = \"Tissue simulation\" # keep it short
+deployment = \"intranet\"
+
+[render]
+# Pages of the handbook
+theme = \"dark\"
+toc_depth = 3
+extra_css = [\"print.css\",
+             \"brand.css\"]
+
+[format]
+available = [\"rustfmt\"]
+";
+
+    /// What `lila init` writes from its answers.
+    const FRESH: &str = "\
+[project]
+context = \"Physics engine\"
+deployment = \"on-premise with enterprise intranet-only\"
+
+[ai_guidance]
+code_of_conduct = \"Be careful.\"
+
+[format]
+available = [\"rustfmt\", \"black\"]
+";
+
+    fn merged(answered: &[&str]) -> String {
+        let mut doc: DocumentMut = EDITED.parse().unwrap();
+        merge_lila_toml(&mut doc, &FRESH.parse().unwrap(), answered);
+        doc.to_string()
+    }
+
+    #[test]
+    fn a_custom_render_table_survives_a_re_init_untouched() {
+        let merged = merged(&["project.context", "format.available"]);
+        let render = &EDITED[EDITED.find("[render]").unwrap()..EDITED.find("[format]").unwrap()];
+        assert!(merged.contains(render), "{}", merged);
+    }
+
+    #[test]
+    fn only_answered_keys_are_replaced() {
+        let merged = merged(&["project.context", "format.available"]);
+        assert!(merged.starts_with("# Our book\n[project]\n"), "{}", merged);
+        // The comment after a replaced value stays.
+        assert!(merged.contains("context = \"Physics engine\" # keep it short\n"));
+        assert!(merged.contains("deployment = \"intranet\"\n"));
+        assert!(merged.contains("available = [\"rustfmt\", \"black\"]"));
+    }
+
+    #[test]
+    fn missing_tables_are_added_after_the_existing_ones() {
+        let merged = merged(&[]);
+        let added = merged.find("[ai_guidance]").unwrap();
+        assert!(added > merged.find("[format]").unwrap(), "{}", merged);
+        assert!(merged.contains("available = [\"rustfmt\"]\n"));
+    }
+
+    #[test]
+    fn unreadable_or_empty_manifests_leave_the_language_out() {
+        let error = Err("could not parse package.json: expected value".to_string());
+        assert!(found_dependencies(error, "package.json").is_none());
+        assert!(found_dependencies(Ok(None), "package.json").is_none());
+        assert!(found_dependencies(Ok(Some(toml::Table::new())), "package.json").is_none());
+        let mut table = toml::Table::new();
+        table.insert("react".to_string(), "^18".into());
+        assert_eq!(
+            found_dependencies(Ok(Some(table.clone())), "package.json"),
+            Some(table)
+        );
+    }
+
+    #[test]
+    fn major_minor_takes_the_first_version_in_the_text() {
+        assert_eq!(major_minor("Python 3.11.4").as_deref(), Some("3.11"));
+        assert_eq!(major_minor(">=3.10,<4.0").as_deref(), Some("3.10"));
+        assert_eq!(major_minor("go1.22.1 linux/amd64").as_deref(), Some("1.22"));
+        assert_eq!(major_minor("3"), None);
+        assert_eq!(major_minor("stable"), None);
+    }
+
+    #[test]
+    fn the_rust_version_is_the_one_cargo_toml_requires() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("Cargo.toml");
+        fs::write(
+            &manifest,
+            "[package]\nname = \"book\"\nrust-version = \"1.74.1\"\n",
+        )
+        .unwrap();
+        assert_eq!(get_rust_version(dir.path()).as_deref(), Some("1.74"));
+        fs::write(
+            &manifest,
+            "[workspace]\nmembers = [\"core\"]\n\n[workspace.package]\nrust-version = \"1.80\"\n",
+        )
+        .unwrap();
+        assert_eq!(get_rust_version(dir.path()).as_deref(), Some("1.80"));
+    }
+
+    #[test]
+    fn the_go_version_is_the_go_line_of_go_mod() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("go.mod"),
+            "module example.com/book\n\ngo 1.22.1\n\ntoolchain go1.23.0\n",
+        )
+        .unwrap();
+        assert_eq!(get_go_version(dir.path()).as_deref(), Some("1.22"));
+    }
+
+    #[test]
+    fn the_python_version_file_comes_before_requires_python() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(pinned_python_version(dir.path()), None);
+        fs::write(
+            dir.path().join("pyproject.toml"),
+            "[project]\nname = \"book\"\nrequires-python = \">=3.10\"\n",
+        )
+        .unwrap();
+        assert_eq!(pinned_python_version(dir.path()).as_deref(), Some("3.10"));
+        fs::write(dir.path().join(".python-version"), "3.12.2\n3.11\n").unwrap();
+        assert_eq!(pinned_python_version(dir.path()).as_deref(), Some("3.12"));
+    }
+
+    /// A `.env` file edited by hand.
+    const MESSY_ENV: &str = "\
+# Database
+DATABASE_URL=sqlite://book.db   # local copy
+export LILA_AI_MODEL=\"Qwen/Qwen2.5-3B-Instruct\"
+
+  LILA_OUTPUT_PATH = '/home/me/My Books/out'
+GREETING=\"line one\\nline two\"
+EMPTY=
+# LILA_AI_ISQ=Q4K (disabled)
+
+# lila environment settings
+LILA_AI_BACKEND=openai
+
+# Tail
+TAIL=1
+";
+
+    /// The variables dotenv reads from the `.env` file at `path`, in order.
+    fn read_env(path: &Path) -> Vec<(String, String)> {
+        dotenvy::from_path_iter(path)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect()
+    }
+
+    fn with_env(path: &Path, expected: &[(&str, &str)]) {
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(read_env(path), expected);
+    }
+
+    const MESSY_VALUES: [(&str, &str); 7] = [
+        ("DATABASE_URL", "sqlite://book.db"),
+        ("LILA_AI_MODEL", "Qwen/Qwen2.5-3B-Instruct"),
+        ("LILA_OUTPUT_PATH", "/home/me/My Books/out"),
+        ("GREETING", "line one\nline two"),
+        ("EMPTY", ""),
+        ("LILA_AI_BACKEND", "openai"),
+        ("TAIL", "1"),
+    ];
+
+    #[test]
+    fn the_messy_fixture_reads_as_expected() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join(".env");
+        fs::write(&env, MESSY_ENV).unwrap();
+        with_env(&env, &MESSY_VALUES);
+    }
+
+    #[test]
+    fn updating_a_key_only_touches_its_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join(".env");
+        fs::write(&env, MESSY_ENV).unwrap();
+
+        update_env_value(&env, "DATABASE_URL", "sqlite://other.db").unwrap();
+        update_env_value(&env, "LILA_AI_MODEL", "Qwen/Qwen2.5-1.5B-Instruct").unwrap();
+        update_env_value(&env, "LILA_OUTPUT_PATH", "C:\\Users\\me\\My Books #2").unwrap();
+        update_env_value(&env, "EMPTY", "filled").unwrap();
+
+        let expected = MESSY_ENV
+            .replace("sqlite://book.db   #", "sqlite://other.db   #")
+            .replace("\"Qwen/Qwen2.5-3B-Instruct\"", "Qwen/Qwen2.5-1.5B-Instruct")
+            .replace("'/home/me/My Books/out'", "'C:\\Users\\me\\My Books #2'")
+            .replace("EMPTY=\n", "EMPTY=filled\n");
+        assert_eq!(fs::read_to_string(&env).unwrap(), expected);
+        let mut values = MESSY_VALUES.to_vec();
+        values[0].1 = "sqlite://other.db";
+        values[1].1 = "Qwen/Qwen2.5-1.5B-Instruct";
+        values[2].1 = "C:\\Users\\me\\My Books #2";
+        values[4].1 = "filled";
+        with_env(&env, &values);
+    }
+
+    #[test]
+    fn a_commented_out_key_is_not_updated() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join(".env");
+        fs::write(&env, MESSY_ENV).unwrap();
+        update_env_value(&env, "LILA_AI_ISQ", "Q8_0").unwrap();
+        let content = fs::read_to_string(&env).unwrap();
+        assert!(content.contains("# LILA_AI_ISQ=Q4K (disabled)\n"));
+        assert!(content.contains(
+            "# lila environment settings\nLILA_AI_BACKEND=openai\nLILA_AI_ISQ=Q8_0\n\n# Tail\n"
+        ));
+    }
+
+    #[test]
+    fn new_keys_go_under_the_lila_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join(".env");
+        fs::write(&env, "# Mine\nTOKEN=abc").unwrap();
+        update_env_value(&env, "LILA_AI_MODEL", "a model with spaces").unwrap();
+        update_env_value(&env, "LILA_AI_ISQ", "Q4K").unwrap();
+        assert_eq!(
+            fs::read_to_string(&env).unwrap(),
+            "# Mine\nTOKEN=abc\n\n# lila environment settings\nLILA_AI_MODEL='a model with spaces'\nLILA_AI_ISQ=Q4K\n"
+        );
+        with_env(
+            &env,
+            &[
+                ("TOKEN", "abc"),
+                ("LILA_AI_MODEL", "a model with spaces"),
+                ("LILA_AI_ISQ", "Q4K"),
+            ],
+        );
+
+        let missing = dir.path().join("new.env");
+        update_env_value(&missing, "LILA_AI_ISQ", "Q4K").unwrap();
+        assert_eq!(
+            fs::read_to_string(&missing).unwrap(),
+            "# lila environment settings\nLILA_AI_ISQ=Q4K\n"
+        );
+    }
+
+    #[test]
+    fn crlf_files_stay_crlf() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join(".env");
+        let crlf = MESSY_ENV.replace('\n', "\r\n");
+        fs::write(&env, &crlf).unwrap();
+
+        update_env_value(&env, "TAIL", "2").unwrap();
+        update_env_value(&env, "LILA_AI_ISQ", "Q4K").unwrap();
+
+        let expected = crlf.replace("TAIL=1\r\n", "TAIL=2\r\n").replace(
+            "LILA_AI_BACKEND=openai\r\n",
+            "LILA_AI_BACKEND=openai\r\nLILA_AI_ISQ=Q4K\r\n",
+        );
+        assert_eq!(fs::read_to_string(&env).unwrap(), expected);
+        let values = read_env(&env);
+        assert!(values.contains(&("TAIL".to_string(), "2".to_string())));
+        assert!(values.contains(&("LILA_AI_ISQ".to_string(), "Q4K".to_string())));
+    }
+
+    #[test]
+    fn values_dotenv_would_misread_are_quoted() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join(".env");
+        let values = [
+            ("PLAIN", "Qwen/Qwen2.5-3B"),
+            ("SPACES", "/mnt/My Books"),
+            ("HASH", "a#b"),
+            ("SINGLE", "it's"),
+            ("DOUBLE", "say \"hi\""),
+            ("DOLLAR", "$HOME/x"),
+            ("BACKSLASH", "C:\\lila"),
+            ("LINES", "one\ntwo"),
+        ];
+        for (key, value) in values {
+            update_env_value(&env, key, value).unwrap();
+        }
+        assert!(fs::read_to_string(&env)
+            .unwrap()
+            .contains("PLAIN=Qwen/Qwen2.5-3B\n"));
+        with_env(&env, &values);
+    }
+}
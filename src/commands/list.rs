@@ -0,0 +1,232 @@
+use crate::schema::{content_history, metadata};
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::models::Metadata;
+use crate::utils::database::projects::{project_names, ProjectScope};
+use crate::utils::database::tags::{normalize_tag, tags_by_file};
+use crate::utils::utils::format_timestamp;
+use colored::Colorize;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::BigInt;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Order of the files printed by `lila list`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SortKey {
+    #[default]
+    Path,
+    /// Most recently modified first.
+    Modified,
+    /// Largest first.
+    Size,
+}
+
+/// A file saved in the database, as listed by `lila list`.
+#[derive(Debug, Serialize)]
+pub struct SavedFile {
+    pub id: i32,
+    /// Path relative to the current directory when the file is below it.
+    pub path: String,
+    pub language: Option<String>,
+    pub size_bytes: Option<i64>,
+    /// File modification time, in seconds since the Unix epoch.
+    pub modified_at: Option<i64>,
+    /// When lila last saved a new version of the file, in seconds since the Unix epoch.
+    pub saved_at: Option<i64>,
+    pub tags: Vec<String>,
+    pub project: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+/// Prints the files saved in the database at `db_path` as a table or, with `json`, as
+/// a JSON array. `filter` is a glob on the listed path (`*` stays within a directory,
+/// `**` does not); with `tags`, only files carrying all of them are listed. Only the files
+/// of `project` are listed; for all projects, the table has a Project column.
+pub fn list_saved_files(
+    db_path: &Path,
+    filter: Option<&str>,
+    tags: &[String],
+    sort: SortKey,
+    json: bool,
+    project: &ProjectScope,
+) -> io::Result<()> {
+    // Connecting would create an empty database, so check first.
+    if !db_path.exists() {
+        println!(
+            "No database at {} yet. Run `lila save` to create it.",
+            db_path.display()
+        );
+        return Ok(());
+    }
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    let tables: Count = sql_query(
+        "SELECT COUNT(*) AS count FROM sqlite_master WHERE type = 'table' AND name = 'metadata'",
+    )
+    .get_result(&mut conn)
+    .map_err(io::Error::other)?;
+    if tables.count == 0 {
+        println!(
+            "{} has no saved files yet. Run `lila save` first.",
+            db_path.display()
+        );
+        return Ok(());
+    }
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+
+    // Nothing is saved of a project the database does not know.
+    let records: Vec<Metadata> = match project.resolve(&mut conn).map_err(io::Error::other)? {
+        Some(project_id) => {
+            let mut query = metadata::table.into_boxed();
+            if let Some(id) = project_id {
+                query = query.filter(metadata::project_id.eq(id));
+            }
+            query.load(&mut conn).map_err(io::Error::other)?
+        }
+        None => Vec::new(),
+    };
+    let projects = project_names(&mut conn).map_err(io::Error::other)?;
+    let saved_at: HashMap<i32, Option<i64>> = content_history::table
+        .group_by(content_history::metadata_id)
+        .select((
+            content_history::metadata_id,
+            diesel::dsl::max(content_history::saved_at),
+        ))
+        .load::<(i32, Option<i64>)>(&mut conn)
+        .map_err(io::Error::other)?
+        .into_iter()
+        .collect();
+    let mut tags_by_file = tags_by_file(&mut conn).map_err(io::Error::other)?;
+    let wanted: Vec<String> = tags.iter().map(|t| normalize_tag(t)).collect();
+
+    let pattern = filter.map(glob_to_regex).transpose()?;
+    let mut files: Vec<SavedFile> = records
+        .into_iter()
+        .map(|record| SavedFile {
+            id: record.id,
+            path: relative_path(&record.file_path),
+            language: record.language,
+            size_bytes: record.size_bytes,
+            modified_at: record.modified_at,
+            saved_at: saved_at.get(&record.id).copied().flatten(),
+            tags: tags_by_file.remove(&record.id).unwrap_or_default(),
+            project: record.project_id.and_then(|id| projects.get(&id).cloned()),
+        })
+        .filter(|file| pattern.as_ref().is_none_or(|re| re.is_match(&file.path)))
+        .filter(|file| wanted.iter().all(|tag| file.tags.contains(tag)))
+        .collect();
+    match sort {
+        SortKey::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+        SortKey::Modified => files.sort_by_key(|f| std::cmp::Reverse(f.modified_at)),
+        SortKey::Size => files.sort_by_key(|f| std::cmp::Reverse(f.size_bytes)),
+    }
+
+    if json {
+        let out = serde_json::to_string_pretty(&files).map_err(io::Error::other)?;
+        println!("{}", out);
+        return Ok(());
+    }
+    if files.is_empty() {
+        println!("No saved files match.");
+        return Ok(());
+    }
+
+    let path_width = files.iter().map(|f| f.path.len()).max().unwrap_or(0).max(4);
+    // Which project a file belongs to only needs saying when several are listed.
+    let project_width = match project {
+        ProjectScope::All => files
+            .iter()
+            .map(|f| f.project.as_deref().unwrap_or("-").len() + 2)
+            .max()
+            .unwrap_or(0)
+            .max(9),
+        ProjectScope::Project(_) => 0,
+    };
+    let project_column = |name: &str| match project_width {
+        0 => String::new(),
+        _ => format!("{:<width$}", name, width = project_width),
+    };
+    println!(
+        "{}",
+        format!(
+            "{:>5}  {}{:<path_width$}  {:<10}  {:>9}  {:<16}  {:<16}  Tags",
+            "Id",
+            project_column("Project"),
+            "Path",
+            "Language",
+            "Size",
+            "Modified",
+            "Saved"
+        )
+        .bold()
+    );
+    for file in &files {
+        println!(
+            "{:>5}  {}{:<path_width$}  {:<10}  {:>9}  {:<16}  {:<16}  {}",
+            file.id,
+            project_column(file.project.as_deref().unwrap_or("-")),
+            file.path,
+            file.language.as_deref().unwrap_or("-"),
+            file.size_bytes.map_or("-".to_string(), human_size),
+            file.modified_at.map_or("-".to_string(), format_timestamp),
+            file.saved_at.map_or("-".to_string(), format_timestamp),
+            file.tags.join(", "),
+        );
+    }
+    println!("{} file(s)", files.len());
+    Ok(())
+}
+
+/// `path` relative to the current directory when it is below it.
+fn relative_path(path: &str) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| {
+            Path::new(path)
+                .strip_prefix(cwd)
+                .ok()
+                .map(|p| p.to_path_buf())
+        })
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Translates a path glob into a regex matching the whole path.
+pub(crate) fn glob_to_regex(glob: &str) -> io::Result<Regex> {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid filter {}: {}", glob, e),
+        )
+    })
+}
+
+pub(crate) fn human_size(bytes: i64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
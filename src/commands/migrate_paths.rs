@@ -0,0 +1,138 @@
+use crate::schema::{html_metadata, metadata};
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::search::rename_indexed_file;
+use colored::Colorize;
+use diesel::prelude::*;
+use diesel::result::Error;
+use std::collections::HashSet;
+use std::io;
+use std::path::{Component, Path};
+
+/// Counts of what `migrate_paths` did.
+#[derive(Debug, Default)]
+struct MigrateSummary {
+    rewritten: usize,
+    kept: usize,
+    conflicts: usize,
+}
+
+/// Rewrites the absolute paths saved by older versions of lila in the database at
+/// `db_path` to paths relative to `doc_root` (Markdown) and `html_root` (HTML).
+///
+/// With `prefix`, that prefix is stripped from both tables instead. Otherwise the roots
+/// are stripped, or the `.lila/<project>/doc/` and `.lila/<project>/html/` part of paths
+/// saved on another machine. A row whose relative path is already saved is left alone.
+pub fn migrate_paths(
+    db_path: &Path,
+    doc_root: &Path,
+    html_root: &Path,
+    prefix: Option<&str>,
+) -> io::Result<()> {
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+
+    let mut summary = MigrateSummary::default();
+    conn.transaction::<(), Error, _>(|conn| {
+        let rows: Vec<(i32, String)> = metadata::table
+            .select((metadata::id, metadata::file_path))
+            .order(metadata::id.asc())
+            .load(conn)?;
+        for (id, path) in plan_renames(&rows, doc_root, "doc", prefix, &mut summary) {
+            diesel::update(metadata::table.find(id))
+                .set(metadata::file_path.eq(&path))
+                .execute(conn)?;
+            rename_indexed_file(conn, id, &path)?;
+        }
+
+        let rows: Vec<(i32, String)> = html_metadata::table
+            .select((html_metadata::id, html_metadata::file_path))
+            .order(html_metadata::id.asc())
+            .load(conn)?;
+        for (id, path) in plan_renames(&rows, html_root, "html", prefix, &mut summary) {
+            diesel::update(html_metadata::table.find(id))
+                .set(html_metadata::file_path.eq(&path))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+    .map_err(io::Error::other)?;
+
+    println!(
+        "{} ({} rewritten, {} kept, {} conflicts)",
+        format!("Migrated paths in {}", db_path.display()).green(),
+        summary.rewritten,
+        summary.kept,
+        summary.conflicts
+    );
+    Ok(())
+}
+
+/// The new paths of the absolute `rows` of one table that can be made relative.
+fn plan_renames(
+    rows: &[(i32, String)],
+    root: &Path,
+    folder: &str,
+    prefix: Option<&str>,
+    summary: &mut MigrateSummary,
+) -> Vec<(i32, String)> {
+    let mut taken: HashSet<String> = rows.iter().map(|(_, path)| path.clone()).collect();
+    let mut renames = Vec::new();
+    for (id, path) in rows {
+        if !Path::new(path).is_absolute() {
+            continue;
+        }
+        let relative = match prefix {
+            Some(prefix) => strip_root(path, Path::new(prefix)),
+            None => strip_root(path, root).or_else(|| strip_project_folder(path, folder)),
+        };
+        let Some(relative) = relative else {
+            summary.kept += 1;
+            continue;
+        };
+        if taken.contains(&relative) {
+            eprintln!(
+                "{} {} is already saved as {}; kept as is",
+                "Conflict:".yellow(),
+                path,
+                relative
+            );
+            summary.conflicts += 1;
+            continue;
+        }
+        taken.remove(path);
+        taken.insert(relative.clone());
+        summary.rewritten += 1;
+        renames.push((*id, relative));
+    }
+    renames
+}
+
+/// `path` relative to `root`, as given or canonicalized, with "/" separators.
+fn strip_root(path: &str, root: &Path) -> Option<String> {
+    let path = Path::new(path);
+    let relative = path
+        .strip_prefix(root)
+        .ok()
+        .or_else(|| path.strip_prefix(root.canonicalize().ok()?).ok())?;
+    join_components(relative.components())
+}
+
+/// The part of `path` after `.lila/<project>/<folder>/`.
+fn strip_project_folder(path: &str, folder: &str) -> Option<String> {
+    let components: Vec<Component> = Path::new(path).components().collect();
+    let start = components
+        .windows(3)
+        .position(|window| window[0].as_os_str() == ".lila" && window[2].as_os_str() == folder)?;
+    join_components(components[start + 3..].iter().copied())
+}
+
+fn join_components<'a>(components: impl Iterator<Item = Component<'a>>) -> Option<String> {
+    let parts: Vec<String> = components
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("/"))
+    }
+}
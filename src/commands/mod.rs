@@ -1,13 +1,34 @@
 pub mod bookbinding;
+pub mod bundle;
+pub mod chat;
+pub mod config;
+pub mod doctor;
 pub mod edit;
+pub mod history;
+pub mod index;
 pub mod init;
+pub mod list;
+pub mod migrate_paths;
 pub mod prepare;
 pub mod remove;
+pub mod restore;
 pub mod save;
+pub mod search;
+pub mod show;
+pub mod stats;
+pub mod tag;
 pub mod tangle;
+pub mod verify;
 pub mod weave;
 
+use crate::server::backend::Isq;
+use bundle::OnConflict;
+use chat::{ChatOutput, DaemonAction};
 use clap::{Parser, Subcommand};
+use edit::formatter::{parse_language, CodeLanguage};
+use edit::report::ReportFormat;
+use list::SortKey;
+use std::path::PathBuf;
 
 const HELP_TEMPLATE: &str = "\
 {about}
@@ -26,9 +47,20 @@ Working with code:
 
 Code Literat:
     server       Start the AI Server for chatting with your rendered book about their underlying Markdown files
+    chat         Ask the Code Literat a question and stream the answer to the terminal
 
 Project management:
     save         Save the Markdown code into a SQLite database
+    list         List the files saved in the database
+    show         Print the content of a file saved in the database
+    tag          Add or remove tags of a file saved in the database
+    search       Full-text search the Markdown saved in the database
+    index        Embed the saved Markdown for retrieval in chat
+    verify       Check the saved files against the doc folder
+    stats        Show statistics about the saved files and the database
+    history      List the saved versions of a Markdown file
+    diff         Diff a saved version of a Markdown file against its current content
+    db           Export or import a JSON bundle, or migrate saved paths
     rm           Remove files created by tangle and render. Use -a to remove all output folders
 
 Prepare:
@@ -37,6 +69,10 @@ Prepare:
 Book binding:
     bookbinding  Inline placeholders and create a book folder with only Markdown files
 
+Recovery:
+    restore         Write the saved Markdown back to a folder
+    restore-backup  Copy backups written with --backup or --backup-dir back in place
+
 {after-help}";
 
 #[derive(Parser, Debug)]
@@ -50,10 +86,57 @@ pub struct Args {
     pub command: Commands,
 }
 
+// Parsed once per run, so the size of its largest variant does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Initialize lila environment
-    Init,
+    Init {
+        /// Take the default answer of every question no flag answers instead of asking it,
+        /// as is done when stdin is not a terminal.
+        #[arg(short, long)]
+        yes: bool,
+        /// Folder lila writes the project's files to (default: LILA_OUTPUT_PATH, else
+        /// ~/.lila/<name of the current folder>).
+        #[arg(long, value_name = "DIR")]
+        output_path: Option<PathBuf>,
+        /// Chat model to set as LILA_AI_MODEL in .env, whatever model is recommended
+        /// (default: LILA_AI_MODEL, else the model recommended for the machine).
+        #[arg(long, value_name = "ID")]
+        model: Option<String>,
+        /// The project context in Lila.toml, like "Physics engine for tissue simulation".
+        #[arg(long, value_name = "TEXT")]
+        context: Option<String>,
+        /// The deployment description in Lila.toml, like "on-premise with enterprise
+        /// intranet-only".
+        #[arg(long, value_name = "TEXT")]
+        deployment: Option<String>,
+        /// Programming languages of the project, like `rust,python`.
+        #[arg(long, value_name = "LANGS", value_delimiter = ',')]
+        languages: Vec<String>,
+        /// Leave compliance guidelines out of Lila.toml without asking.
+        #[arg(long, conflicts_with_all = ["iso", "bsi"])]
+        no_compliance: bool,
+        /// ISO compliance guidelines for Lila.toml, like `ISO/IEC 22989:2022`.
+        #[arg(long, value_name = "GUIDELINES", value_delimiter = ',')]
+        iso: Vec<String>,
+        /// BSI compliance guidelines for Lila.toml, like `APP.6 Allgemeine Software`.
+        #[arg(long, value_name = "GUIDELINES", value_delimiter = ',')]
+        bsi: Vec<String>,
+        /// Only update .env, leaving Lila.toml alone.
+        #[arg(long, conflicts_with_all = ["context", "deployment", "languages", "no_compliance", "iso", "bsi"])]
+        no_toml: bool,
+        /// Write Lila.toml from scratch instead of merging the answers into the existing one.
+        #[arg(long, conflicts_with = "no_toml")]
+        force_new: bool,
+        /// Leave .gitignore alone instead of offering to ignore the files lila writes.
+        #[arg(long)]
+        no_gitignore: bool,
+        /// Recommend a model from the number of cores and the RAM instead of measuring the
+        /// machine's speed, which takes a moment.
+        #[arg(long)]
+        skip_benchmark: bool,
+    },
 
     /// Extract pure source code from Markdown files.
     Tangle {
@@ -82,6 +165,12 @@ pub enum Commands {
         /// Specify the output directory for the resulting Markdown files.
         #[arg(short, long, value_name = "OUTPUT_DIR")]
         output: Option<String>,
+        /// Save the generated Markdown to the database right away, like `lila save`.
+        #[arg(long)]
+        save: bool,
+        /// Database for --save (default: lila.db in LILA_OUTPUT_PATH, else ~/.lila/lila.db)
+        #[arg(long, requires = "save")]
+        db: Option<String>,
     },
 
     /// Auto-format code blocks (Python, Rust, etc.) in a Markdown file or folder.
@@ -92,17 +181,188 @@ pub enum Commands {
         /// Specify a folder containing Markdown files (conflicts with file)
         #[arg(short, long, conflicts_with = "file")]
         folder: Option<String>,
+        /// Fail instead of skipping code blocks whose formatter is not installed.
+        #[arg(long)]
+        require_formatters: bool,
+        /// Number of files to format in parallel (default: number of CPUs).
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<usize>,
+        /// Print the formatter settings used, such as the rustfmt edition and config.
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only format blocks in these languages (comma-separated, e.g. python,rust).
+        #[arg(long, value_name = "LANGS", value_delimiter = ',', value_parser = parse_language)]
+        lang: Vec<CodeLanguage>,
+        /// Also normalize YAML front matter: lila keys first, then the others alphabetically.
+        #[arg(long)]
+        front_matter: bool,
+        /// Put a `<!-- lila: format failed: ... -->` comment above blocks that fail to format.
+        #[arg(long)]
+        annotate_failures: bool,
+        /// Keep running and re-format each Markdown file in the folder when it changes.
+        #[arg(long, requires = "folder", conflicts_with = "report")]
+        watch: bool,
+        /// Don't write files; exit with 1 if any would be reformatted.
+        #[arg(long)]
+        check: bool,
+        /// Print a machine-readable report of every code block.
+        #[arg(long, value_name = "FORMAT")]
+        report: Option<ReportFormat>,
+        /// Write the report to this file instead of stdout.
+        #[arg(long, value_name = "PATH", requires = "report")]
+        report_file: Option<PathBuf>,
+        #[command(flatten)]
+        backup: BackupArgs,
     },
 
     /// Save the weaved code and metadata into a SQLite database.
-    Save {
+    Save(SaveArgs),
+
+    /// List the files saved with `lila save`.
+    List {
+        /// Only list files whose path matches this glob (e.g. "doc/**/*.md").
+        #[arg(long, value_name = "GLOB")]
+        filter: Option<String>,
+        /// Only list files with this tag; repeat or separate with commas to require several.
+        #[arg(long = "tag", value_name = "TAG", value_delimiter = ',')]
+        tags: Vec<String>,
+        /// Order of the files.
+        #[arg(long, value_enum, default_value_t = SortKey::Path)]
+        sort: SortKey,
+        /// Print the files as JSON.
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        project: ProjectArgs,
         /// Optional path to the SQLite database
         #[arg(short, long)]
         db: Option<String>,
+    },
 
-        /// Specify the input directory of the Markdown files.
+    /// Print the content of a file saved with `lila save`.
+    Show {
+        /// Id (see `lila list`) or the end of the saved path of the file.
+        file: String,
+        /// Show the rendered HTML saved with `lila save --html` instead.
+        #[arg(long)]
+        html: bool,
+        /// Write the bytes of an image saved with the Markdown that references it instead.
+        #[arg(long, conflicts_with = "html")]
+        asset: bool,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+
+    /// Add or remove tags of a file saved with `lila save`.
+    Tag {
+        #[command(subcommand)]
+        command: TagCommand,
+    },
+
+    /// Full-text search the Markdown saved with `lila save`.
+    Search {
+        /// Words that must all appear in a file.
+        query: String,
+        /// Maximum number of files to show.
+        #[arg(short, long, default_value_t = 10)]
+        limit: usize,
+        /// Print the results as JSON.
+        #[arg(long)]
+        json: bool,
+        /// Only search files with this tag; repeat or separate with commas to require several.
+        #[arg(long = "tag", value_name = "TAG", value_delimiter = ',')]
+        tags: Vec<String>,
+        /// Only search files whose saved path starts with this prefix.
+        #[arg(short, long, value_name = "PREFIX")]
+        path: Option<String>,
+        #[command(flatten)]
+        project: ProjectArgs,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+
+    /// Chunk and embed the saved Markdown so chat can pick the relevant parts.
+    Index {
+        /// Print the indexed chunks closest to this text instead of indexing.
+        #[arg(long)]
+        query: Option<String>,
+        /// Number of chunks printed for --query.
+        #[arg(short = 'k', long, default_value_t = 5)]
+        top: usize,
+        /// Embed files again even if their content has not changed.
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        project: ProjectArgs,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+
+    /// Check the files saved in the database against the doc folder.
+    Verify {
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+        /// The doc folder the files were saved from.
         #[arg(short, long, value_name = "INPUT_DIR")]
         input: Option<String>,
+        /// Project the files were saved to (default: the current directory's name).
+        #[arg(long, value_name = "NAME")]
+        project: Option<String>,
+        /// Report differences without exiting with an error.
+        #[arg(long)]
+        warn_only: bool,
+    },
+
+    /// Show statistics about the saved files and the database.
+    Stats {
+        /// Print the statistics as JSON.
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        project: ProjectArgs,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+
+    /// List the versions of a Markdown file kept by `lila save`.
+    History {
+        /// The file, as saved (see created_markdown_files.txt).
+        file: String,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+
+    /// Show a unified diff between a saved version of a Markdown file and its current content.
+    Diff {
+        /// The file, as saved (see created_markdown_files.txt).
+        file: String,
+        /// Revision to compare, as listed by `lila history`.
+        #[arg(short, long)]
+        rev: i32,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+
+    /// Check Lila.toml, the output folder, the database, the formatters and the model.
+    Doctor,
+
+    /// Read or change a setting of Lila.toml or .env, or list those in effect.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Export the saved files to a portable JSON bundle, import one, or migrate saved paths.
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
     },
 
     /// Remove files created by tangle and render. Use -a to remove all output folders.
@@ -116,13 +376,172 @@ pub enum Commands {
     },
 
     /// Start the AI Server for chatting with your rendered book
-    Server,
+    ///
+    /// Set LILA_SERVER_TOKEN, or `token` in the [server] section of Lila.toml, to require
+    /// `Authorization: Bearer <token>` on every route but /ping.
+    ///
+    /// Ctrl-C or SIGTERM lets chat answers in progress finish for up to
+    /// `shutdown_grace_seconds` (default 30) before the server stops.
+    Server {
+        /// Listen on this TCP address (default: `host` and `port` in [server], else
+        /// 127.0.0.1:8080).
+        #[arg(long, value_name = "ADDRESS")]
+        bind: Option<String>,
+        /// Listen on a Unix domain socket at this path instead of TCP (Unix only).
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["bind", "tls_cert", "tls_key"])]
+        socket: Option<PathBuf>,
+        /// Serve HTTPS with this PEM certificate chain (or `tls_cert` in [server]).
+        #[arg(long, value_name = "PEM")]
+        tls_cert: Option<PathBuf>,
+        /// The PEM private key of --tls-cert (or `tls_key` in [server]).
+        #[arg(long, value_name = "PEM")]
+        tls_key: Option<PathBuf>,
+        /// Answer with this GGUF file by default instead of a model downloaded from the
+        /// Hugging Face hub (or LILA_AI_MODEL_PATH).
+        #[arg(long, value_name = "PATH")]
+        model_path: Option<PathBuf>,
+        /// Quantize local models to this level as they load (default: LILA_AI_ISQ, else
+        /// `isq` in [ai], else q8_0); GGUF files are quantized already.
+        #[arg(long, value_name = "LEVEL")]
+        isq: Option<Isq>,
+        /// Load models from the Hugging Face cache only, without trying the network (or
+        /// HF_HUB_OFFLINE=1).
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Ask the Code Literat a question and stream the answer to the terminal
+    ///
+    /// The context is chosen as for the server's /chat: the --file(s) given, else the parts of
+    /// the saved book most relevant to the prompt, by embeddings when `lila index` ran and
+    /// by keyword otherwise, while they fit in the context budget.
+    Chat {
+        /// The question to ask. `@{engine.rs:integrate_forces}` in it is replaced by the
+        /// definition of integrate_forces in engine.rs, and `@{engine.rs}` by the whole
+        /// file, as bookbinding does.
+        #[arg(short, long, required_unless_present_any = ["interactive", "daemon"])]
+        prompt: Option<String>,
+        /// Folder the files of `@{file:identifier}` placeholders are found in (default: the
+        /// current one).
+        #[arg(long, value_name = "DIR")]
+        root: Option<PathBuf>,
+        /// Give this Markdown file, or the files this glob matches (`doc/physics/*.md`), to
+        /// the model as the context instead of the saved files; repeat for several.
+        #[arg(short, long = "file", value_name = "FILE")]
+        files: Vec<String>,
+        /// Model to answer with (default: LILA_AI_MODEL, else `default_model` in [server]).
+        #[arg(short, long, value_name = "ID")]
+        model: Option<String>,
+        /// Answer with this GGUF file instead of a model downloaded from the Hugging Face
+        /// hub (or LILA_AI_MODEL_PATH).
+        #[arg(long, value_name = "PATH", conflicts_with = "model")]
+        model_path: Option<PathBuf>,
+        /// Quantize the model to this level as it loads (default: LILA_AI_ISQ, else `isq`
+        /// in [ai], else q8_0); GGUF files are quantized already.
+        #[arg(long, value_name = "LEVEL")]
+        isq: Option<Isq>,
+        /// Load the model from the Hugging Face cache only, without trying the network (or
+        /// HF_HUB_OFFLINE=1).
+        #[arg(long)]
+        offline: bool,
+        /// Don't read context from the database.
+        #[arg(long)]
+        no_db: bool,
+        /// Optional path to the SQLite database
+        #[arg(short, long, conflicts_with = "no_db")]
+        db: Option<String>,
+        /// Give the saved files whose path starts with this, such as a chapter folder of
+        /// content.md, as the context, whole while they fit, instead of the parts relevant
+        /// to the prompt; repeat for several, of which any may match.
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["files", "no_db"])]
+        scope: Vec<String>,
+        /// Give the saved files whose content matches this regular expression
+        /// (case-insensitive) as the context, whole while they fit, instead of the parts
+        /// relevant to the prompt; repeat for several, which must all match.
+        #[arg(long, value_name = "REGEX", conflicts_with_all = ["files", "no_db"])]
+        grep: Vec<String>,
+        /// Give the saved files matching any of the --grep patterns instead of all of them.
+        #[arg(long, requires = "grep")]
+        grep_any: bool,
+        /// Tokens of the book given as context (default: `context_tokens` in [server], else
+        /// half the model's context window).
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        context_tokens: Option<u32>,
+        /// Randomness of the answer, from 0 (always the likeliest token) to 2 (default:
+        /// `temperature` in [ai.generation], else the model's).
+        #[arg(long, value_name = "T")]
+        temperature: Option<f64>,
+        /// Only sample from the likeliest tokens making up this share of the probability,
+        /// above 0 and up to 1.
+        #[arg(long, value_name = "P")]
+        top_p: Option<f64>,
+        /// Most tokens to generate.
+        #[arg(long, value_name = "N")]
+        max_tokens: Option<usize>,
+        /// Penalize tokens already in the answer, more the more often they appear, from -2
+        /// to 2 (0 for none).
+        #[arg(long, value_name = "PENALTY", allow_negative_numbers = true)]
+        repeat_penalty: Option<f64>,
+        /// Seed the sampling, so the answer can be generated again (OpenAI-compatible
+        /// backends only; locally, --temperature 0 does that).
+        #[arg(long, value_name = "N")]
+        seed: Option<u64>,
+        /// Use this as the whole system message, instead of `system_prompt` in
+        /// [ai_guidance] and the sections of Lila.toml appended to it.
+        #[arg(long, value_name = "TEXT")]
+        system: Option<String>,
+        /// Print the context budget and the parts of the book that made it in.
+        #[arg(short, long)]
+        verbose: bool,
+        /// Show neither the spinner while the model starts answering nor the statistics
+        /// of the answer (neither is shown when stderr is not a terminal).
+        #[arg(short, long)]
+        quiet: bool,
+        /// Print the answer as it is generated (text), or once complete as a JSON object
+        /// with its sources, token usage and timing (json); errors are then JSON too.
+        #[arg(long, value_name = "FORMAT", value_enum, default_value_t = ChatOutput::Text, conflicts_with = "interactive")]
+        output: ChatOutput,
+        /// Keep asking the questions typed in the terminal, with the earlier ones as
+        /// history; /help lists the commands.
+        #[arg(short, long)]
+        interactive: bool,
+        /// Have the model rewrite a code block of this Markdown file as the prompt says,
+        /// with the prose around the block as the context; the change is shown as a diff
+        /// and written in place once confirmed, the original kept as `<file>.bak`.
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["interactive", "files", "scope", "grep", "output", "daemon"])]
+        edit: Option<PathBuf>,
+        /// The code block of --edit to rewrite: its number in the file, from 1, or an
+        /// identifier its code defines, like `parse_header` (default: the only one).
+        #[arg(long, value_name = "N|IDENTIFIER", requires = "edit")]
+        block: Option<String>,
+        /// Write the rewritten block without asking.
+        #[arg(short, long, requires = "edit")]
+        yes: bool,
+        /// Print the tokens of the system message, each Lila.toml section, each part of the
+        /// book in the context and the prompt, against the model's context window, without
+        /// loading the model or asking anything.
+        #[arg(long, conflicts_with_all = ["interactive", "daemon"])]
+        count_only: bool,
+        /// Keep the model loaded in a background process that later runs answer through:
+        /// start it, stop it, print its status, or run it in the foreground.
+        #[arg(long, value_name = "ACTION", value_enum, conflicts_with_all = ["prompt", "interactive"])]
+        daemon: Option<DaemonAction>,
+        /// Minutes without a question after which the daemon unloads its model, loading it
+        /// again for the next one; 0 keeps it loaded [default: 30].
+        #[arg(long, value_name = "MINUTES", requires = "daemon", conflicts_with_all = ["prompt", "interactive"])]
+        idle_minutes: Option<u64>,
+        /// Load the model in this process even when the chat daemon runs with it.
+        #[arg(long, conflicts_with = "daemon")]
+        no_daemon: bool,
+    },
 
     /// Prepare the folder structure by ensuring each folder has a README.md with file mentions.
     Prepare {
         /// Specify the folder to prepare.
         #[arg(short, long, value_name = "FOLDER")]
         folder: String,
+        #[command(flatten)]
+        backup: BackupArgs,
     },
 
     /// Book binding: inline placeholders and create a book folder with only Markdown files.
@@ -133,5 +552,197 @@ pub enum Commands {
         /// Specify the output folder for the book.
         #[arg(short, long, value_name = "OUTPUT_FOLDER")]
         output: String,
+        #[command(flatten)]
+        backup: BackupArgs,
+    },
+
+    /// Write the saved Markdown files back to a folder, recreating their folders.
+    Restore {
+        /// Folder to write the files to.
+        #[arg(short, long, value_name = "FOLDER")]
+        output: String,
+        /// Write the files as they were at this revision, as listed by `lila history`.
+        #[arg(short, long)]
+        rev: Option<i32>,
+        /// Replace existing files whose content differs from the saved one.
+        #[arg(short, long)]
+        force: bool,
+        /// Project to restore (default: the current directory's name).
+        #[arg(long, value_name = "NAME")]
+        project: Option<String>,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+
+    /// Copy backups written with --backup or --backup-dir back over the files.
+    RestoreBackup {
+        /// Restore the `<file>.md.bak` backups under this folder instead of ~/.lila/<project>/backups.
+        #[arg(short, long, value_name = "FOLDER")]
+        folder: Option<String>,
+        /// Restore this timestamp from ~/.lila/<project>/backups (default: the latest).
+        #[arg(short, long, value_name = "TIMESTAMP", conflicts_with = "folder")]
+        timestamp: Option<String>,
+    },
+}
+
+/// Flags of `lila save`.
+#[derive(clap::Args, Debug)]
+pub struct SaveArgs {
+    /// Optional path to the SQLite database
+    #[arg(short, long)]
+    pub db: Option<String>,
+
+    /// Specify the input directory of the Markdown files.
+    #[arg(short, long, value_name = "INPUT_DIR")]
+    pub input: Option<String>,
+
+    /// Read the Markdown files to save from stdin, one path per line.
+    #[arg(long, conflicts_with_all = ["input", "scan"])]
+    pub stdin: bool,
+
+    /// Also save the rendered `.html` files found under this folder.
+    #[arg(long, value_name = "DOC_FOLDER")]
+    pub html: Option<String>,
+
+    /// Save every `.md` file under the input folder, ignoring created_markdown_files.txt.
+    #[arg(long)]
+    pub scan: bool,
+
+    /// Project to save the files to (default: the current directory's name).
+    #[arg(long, value_name = "NAME")]
+    pub project: Option<String>,
+
+    /// Print progress as a line of text every few seconds instead of a progress bar.
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+/// Backup flags shared by commands that rewrite Markdown in place.
+#[derive(clap::Args, Debug)]
+pub struct BackupArgs {
+    /// Save `<file>.md.bak` next to each file before changing it.
+    #[arg(long)]
+    pub backup: bool,
+    /// Save backups to ~/.lila/<project>/backups/<timestamp>/ instead of next to the files.
+    #[arg(long)]
+    pub backup_dir: bool,
+}
+
+/// Project flags shared by commands that read saved files.
+#[derive(clap::Args, Debug)]
+pub struct ProjectArgs {
+    /// Only use the files of this project (default: the current directory's name).
+    #[arg(long, value_name = "NAME")]
+    pub project: Option<String>,
+    /// Use the files of every project in the database.
+    #[arg(long, conflicts_with = "project")]
+    pub all_projects: bool,
+}
+
+impl Commands {
+    /// The database given with `--db`, if any.
+    pub fn db(&self) -> Option<&str> {
+        match self {
+            Commands::Weave { db, .. }
+            | Commands::Save(SaveArgs { db, .. })
+            | Commands::List { db, .. }
+            | Commands::Show { db, .. }
+            | Commands::Search { db, .. }
+            | Commands::Index { db, .. }
+            | Commands::Verify { db, .. }
+            | Commands::Stats { db, .. }
+            | Commands::History { db, .. }
+            | Commands::Diff { db, .. }
+            | Commands::Restore { db, .. }
+            | Commands::Chat { db, .. }
+            | Commands::Tag {
+                command: TagCommand::Add { db, .. } | TagCommand::Remove { db, .. },
+            }
+            | Commands::Db {
+                command:
+                    DbCommand::Export { db, .. }
+                    | DbCommand::Import { db, .. }
+                    | DbCommand::MigratePaths { db, .. },
+            } => db.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Write the saved Markdown and HTML as a sorted JSON bundle.
+    Export {
+        /// File to write the bundle to (default: stdout).
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+    /// Recreate the rows of a JSON bundle in the database.
+    Import {
+        /// The bundle written by `lila db export`.
+        input: String,
+        /// What to do with files already in the database.
+        #[arg(long, value_enum, default_value_t = OnConflict::Skip)]
+        on_conflict: OnConflict,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+    /// Rewrite absolute paths saved by older versions to project-relative ones.
+    MigratePaths {
+        /// Prefix to strip (default: the doc and html folders, or `.lila/<project>/doc/`).
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the value in effect of a setting.
+    Get {
+        /// Dotted key of the setting, like `server.port` or `ai.model`.
+        key: String,
+    },
+    /// Change a setting, in Lila.toml or .env depending on the key.
+    Set {
+        /// Dotted key of the setting, like `server.port` or `ai.model`.
+        key: String,
+        /// New value, as TOML (`8080`, `true`, `["a", "b"]`) or as a plain string.
+        value: String,
+    },
+    /// Print every setting in effect and where its value comes from.
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagCommand {
+    /// Tag a saved file.
+    Add {
+        /// Id (see `lila list`) or the end of the saved path of the file.
+        file: String,
+        /// Tags to add.
+        #[arg(required = true)]
+        tags: Vec<String>,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
+    },
+    /// Remove tags from a saved file.
+    Remove {
+        /// Id (see `lila list`) or the end of the saved path of the file.
+        file: String,
+        /// Tags to remove.
+        #[arg(required = true)]
+        tags: Vec<String>,
+        /// Optional path to the SQLite database
+        #[arg(short, long)]
+        db: Option<String>,
     },
 }
@@ -1,3 +1,4 @@
+use crate::utils::backup::Backup;
 use std::collections::HashSet;
 use std::fs::{self, OpenOptions};
 use std::io::{self, Write};
@@ -5,8 +6,8 @@ use std::path::Path;
 
 /// Recursively ensures that each folder in the given directory has a README.md file.
 /// If a README.md exists, it updates it by appending file mentions (in the format "@{filename}")
-/// for any files not already mentioned.
-pub fn prepare_readme_in_folder(folder: &Path) -> io::Result<()> {
+/// for any files not already mentioned. Existing READMEs are saved to `backup` before they change.
+pub fn prepare_readme_in_folder(folder: &Path, backup: Option<&Backup>) -> io::Result<()> {
     if folder.is_dir() {
         let readme_path = folder.join("README.md");
         let mut existing_mentions = HashSet::new();
@@ -46,6 +47,9 @@ pub fn prepare_readme_in_folder(folder: &Path) -> io::Result<()> {
         }
 
         if !new_mentions.is_empty() {
+            if let (Some(backup), false) = (backup, existing_content.is_empty()) {
+                backup.save(&readme_path)?;
+            }
             let mut file = OpenOptions::new().append(true).open(&readme_path)?;
             for mention in new_mentions {
                 writeln!(file, "@{{{}}}", mention)?;
@@ -58,7 +62,7 @@ pub fn prepare_readme_in_folder(folder: &Path) -> io::Result<()> {
         let entry = entry?;
         let path = entry.path();
         if path.is_dir() {
-            prepare_readme_in_folder(&path)?;
+            prepare_readme_in_folder(&path, backup)?;
         }
     }
     Ok(())
@@ -0,0 +1,221 @@
+use crate::schema::{assets, blobs, content_history, metadata};
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::projects::ProjectScope;
+use colored::{ColoredString, Colorize};
+use diesel::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// What `restore_saved_files` did with each saved file.
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    /// Files that did not exist and were written.
+    pub written: usize,
+    /// Files that already had the saved content.
+    pub unchanged: usize,
+    /// Existing files with other content, left alone.
+    pub conflicts: Vec<String>,
+    /// Existing files with other content, replaced because of `force`.
+    pub overwritten: Vec<String>,
+    /// Saved files that could not be restored below the output folder.
+    pub skipped: Vec<String>,
+}
+
+/// Writes every file of `project` saved in the database at `db_path` to its stored path
+/// below `output`, creating folders as needed, prints what was done, and returns it.
+///
+/// Existing files with different content are only replaced with `force`. With `rev`, each
+/// Markdown file is written as it was at that revision (see `lila history`): its latest
+/// version numbered `rev` or lower, and files without such a version are left out.
+/// Without `rev`, the images saved with the Markdown are written too.
+pub fn restore_saved_files(
+    db_path: &Path,
+    output: &Path,
+    project: &ProjectScope,
+    rev: Option<i32>,
+    force: bool,
+) -> io::Result<RestoreReport> {
+    // Connecting would create an empty database, so check first.
+    if !db_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no database at {}", db_path.display()),
+        ));
+    }
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    let Some(project_id) = project.resolve(&mut conn).map_err(io::Error::other)? else {
+        println!(
+            "No files of {} are saved in {}.",
+            project,
+            db_path.display()
+        );
+        return Ok(RestoreReport::default());
+    };
+
+    let files = match rev {
+        None => saved_contents(&mut conn, project_id).map_err(io::Error::other)?,
+        Some(rev) => contents_at_revision(&mut conn, project_id, rev)?,
+    };
+    let mut report = RestoreReport::default();
+    for (path, content) in &files {
+        restore_file(output, path, content.as_bytes(), force, &mut report)?;
+    }
+    if rev.is_none() {
+        let mut query = assets::table
+            .select((assets::file_path, assets::content))
+            .order(assets::file_path.asc())
+            .into_boxed();
+        if let Some(id) = project_id {
+            query = query.filter(assets::project_id.eq(id));
+        }
+        let images: Vec<(String, Vec<u8>)> = query.load(&mut conn).map_err(io::Error::other)?;
+        for (path, content) in &images {
+            restore_file(output, path, content, force, &mut report)?;
+        }
+    }
+
+    print_report(&report, output);
+    Ok(report)
+}
+
+/// The path and current content of every saved Markdown file.
+fn saved_contents(
+    conn: &mut SqliteConnection,
+    project_id: Option<i32>,
+) -> QueryResult<Vec<(String, String)>> {
+    let mut query = metadata::table
+        .inner_join(blobs::table)
+        .select((metadata::file_path, blobs::content))
+        .order(metadata::file_path.asc())
+        .into_boxed();
+    if let Some(id) = project_id {
+        query = query.filter(metadata::project_id.eq(id));
+    }
+    query.load(conn)
+}
+
+/// The path and content at revision `rev` of every saved Markdown file that has a
+/// version numbered `rev` or lower.
+fn contents_at_revision(
+    conn: &mut SqliteConnection,
+    project_id: Option<i32>,
+    rev: i32,
+) -> io::Result<Vec<(String, String)>> {
+    use content_history::dsl as h;
+
+    let exists: i64 = h::content_history
+        .filter(h::id.eq(rev))
+        .count()
+        .get_result(conn)
+        .map_err(io::Error::other)?;
+    if exists == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("there is no revision {} (see `lila history <file>`)", rev),
+        ));
+    }
+
+    let mut latest = h::content_history
+        .inner_join(metadata::table)
+        .filter(h::id.le(rev))
+        .group_by(h::metadata_id)
+        .select(diesel::dsl::max(h::id))
+        .into_boxed();
+    if let Some(id) = project_id {
+        latest = latest.filter(metadata::project_id.eq(id));
+    }
+    let ids: Vec<Option<i32>> = latest.load(conn).map_err(io::Error::other)?;
+    let ids: Vec<i32> = ids.into_iter().flatten().collect();
+
+    let mut versions: Vec<(String, String)> = h::content_history
+        .inner_join(metadata::table)
+        .filter(h::id.eq_any(&ids))
+        .select((metadata::file_path, h::content))
+        .load(conn)
+        .map_err(io::Error::other)?;
+    versions.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(versions)
+}
+
+/// Writes `content` to the stored `path` below `output`, unless it is there already or,
+/// without `force`, another file is.
+fn restore_file(
+    output: &Path,
+    path: &str,
+    content: &[u8],
+    force: bool,
+    report: &mut RestoreReport,
+) -> io::Result<()> {
+    let Some(target) = target_path(output, path) else {
+        eprintln!(
+            "{} {} is not below the doc folder; skipped",
+            "Warning:".yellow(),
+            path
+        );
+        report.skipped.push(path.to_string());
+        return Ok(());
+    };
+    match fs::read(&target) {
+        Ok(existing) if existing == content => {
+            report.unchanged += 1;
+            return Ok(());
+        }
+        Ok(_) if !force => {
+            report.conflicts.push(path.to_string());
+            return Ok(());
+        }
+        Ok(_) => report.overwritten.push(path.to_string()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => report.written += 1,
+        Err(e) => {
+            eprintln!(
+                "{} cannot read {}: {}; skipped",
+                "Warning:".yellow(),
+                path,
+                e
+            );
+            report.skipped.push(path.to_string());
+            return Ok(());
+        }
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&target, content)
+}
+
+/// `output` joined with the stored `path`, or `None` when the path is absolute or leaves
+/// `output`, as paths saved from outside the doc folder do.
+fn target_path(output: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    let below = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    below.then(|| output.join(relative))
+}
+
+fn print_report(report: &RestoreReport, output: &Path) {
+    println!(
+        "{} {} files to {}",
+        "Written:".green(),
+        report.written,
+        output.display()
+    );
+    println!("{} {}", "Unchanged:".green(), report.unchanged);
+    if !report.overwritten.is_empty() {
+        print_bucket("Overwritten:".yellow(), &report.overwritten);
+    }
+    print_bucket("Conflicts:".red(), &report.conflicts);
+    print_bucket("Skipped:".cyan(), &report.skipped);
+    if !report.conflicts.is_empty() {
+        println!("Run again with --force to replace the conflicting files.");
+    }
+}
+
+fn print_bucket(title: ColoredString, paths: &[String]) {
+    println!("{} {}", title, paths.len());
+    for path in paths {
+        println!("  {}", path);
+    }
+}
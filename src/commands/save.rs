@@ -1,125 +1,431 @@
-use crate::schema::{file_content, metadata};
-use crate::utils::database::models::Metadata;
+use crate::commands::edit::detect_language_from_line;
+use crate::commands::edit::formatter::CodeLanguage;
+use crate::commands::weave::front_matter_range;
+use crate::schema::{html_content, html_metadata, metadata};
+use crate::utils::database::assets::{image_references, mime_type, save_asset};
+use crate::utils::database::blobs::{bytes_hash, content_hash, store_blobs};
+use crate::utils::database::db::run_migrations;
+use crate::utils::database::history::record_version;
+use crate::utils::database::models::{FileDetails, HtmlMetadata, Metadata};
+use crate::utils::database::search::{ensure_search_index, index_file, SearchError};
+use crate::utils::database::tags::sync_front_matter_tags;
+use crate::utils::progress::{SaveOutcome, SaveProgress};
 use colored::Colorize;
 use diesel::prelude::*;
 use diesel::result::Error;
-use diesel::sql_query;
-use diesel::sql_types::{BigInt, Text};
 use diesel::sqlite::SqliteConnection;
-use dotenvy::dotenv;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use std::process::Command;
-
-/// Small struct for checking if a table exists.
-#[derive(QueryableByName)]
-struct Exists {
-    #[diesel(sql_type = Text)]
-    #[allow(dead_code)]
-    name: String,
-}
-
-/// To fetch the SQLite `last_insert_rowid()` result.
-#[derive(QueryableByName)]
-struct LastInsertRowId {
-    #[diesel(sql_type = BigInt)]
-    last_insert_rowid: i64,
-}
-
-/// Establish a DB connection using the `DATABASE_URL` env variable.
-pub fn establish_connection(database_url: &str) -> SqliteConnection {
-    dotenv().ok();
-    SqliteConnection::establish(database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {database_url}"))
-}
-
-/// Check if a given table exists in SQLite.
-fn table_exists(conn: &mut SqliteConnection, table_name: &str) -> bool {
-    let query =
-        format!("SELECT name FROM sqlite_master WHERE type='table' AND name='{table_name}';");
-    let result: Result<Option<Exists>, _> = sql_query(query).get_result(conn);
-    result.map(|res| res.is_some()).unwrap_or(false)
-}
-
-/// Run Diesel migrations. Panics if migrations fail.
-fn run_migrations(database_url: &str) {
-    let output = Command::new("diesel")
-        .arg("migration")
-        .arg("run")
-        .env("DATABASE_URL", database_url)
-        .output()
-        .expect("Failed to run migrations via Diesel CLI");
-
-    if !output.status.success() {
-        panic!(
-            "Migration failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// What `save_files_to_db` did with the files it was given.
+#[derive(Debug, Default)]
+pub struct SaveSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    /// Files whose content hash matched the saved one, so their rows were left alone.
+    pub unchanged: usize,
+    /// Listed files that are missing or unreadable; they are not saved.
+    pub skipped: usize,
+    /// Saved files that are not below the doc folder, so they keep the path given.
+    pub outside_root: Vec<String>,
+    /// Referenced images that were saved or changed.
+    pub assets: usize,
+}
+
+/// How `save_files_to_db` saves files.
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    /// Versions kept per file in `content_history`; `None` keeps them all.
+    pub max_versions: Option<usize>,
+    /// Referenced images larger than this are not saved.
+    pub max_asset_bytes: u64,
+    /// Print progress as plain text even on a terminal, instead of a progress bar.
+    pub quiet: bool,
+}
+
+/// Files saved per transaction, so a failure only rolls back its own batch.
+const SAVE_BATCH_SIZE: usize = 500;
+
+/// A file read from disk, ready to be saved.
+struct PendingFile {
+    /// The path stored in the database, see `stored_path`.
+    path: String,
+    content: String,
+    hash: String,
+    details: FileDetails,
+    tags: Vec<String>,
+    /// Whether the file is outside the doc folder.
+    outside_root: bool,
+}
+
+impl PendingFile {
+    fn read(source: &str, doc_root: &Path) -> io::Result<PendingFile> {
+        let content = fs::read_to_string(source)?;
+        let relative = relative_to_root(source, doc_root);
+        Ok(PendingFile {
+            outside_root: relative.is_none(),
+            path: relative.unwrap_or_else(|| source.to_string()),
+            // Compare content, not mtime: a file can change without its mtime moving.
+            hash: content_hash(&content),
+            details: file_details(Path::new(source), &content),
+            tags: front_matter_tags(&content),
+            content,
+        })
     }
 }
 
 /// Generic function to insert or update any text files in the DB
-/// (whether they're HTML or Markdown).
+/// (whether they're HTML or Markdown), skipping files whose content hash is unchanged.
+///
+/// Every new or changed content is also kept in `content_history`, at most
+/// `options.max_versions` versions per file. Files are saved in batches of
+/// `SAVE_BATCH_SIZE`, each in its own transaction; on an error the earlier batches stay
+/// saved. Transactions take the write lock when they begin, so a concurrent save waits
+/// for the batch instead of failing when its first read turns into a write.
+///
+/// Files below `doc_root` are stored by their path relative to it, so the database does
+/// not depend on where the project lives, and belong to the project `project_id`.
+///
+/// Progress is shown while saving, see `SaveProgress`; warnings are printed through it.
 pub fn save_files_to_db(
     file_paths: &[String],
+    doc_root: &Path,
+    project_id: i32,
     conn: &mut SqliteConnection,
-    database_url: &str,
-) -> Result<(), Error> {
+    options: &SaveOptions,
+) -> Result<SaveSummary, Box<dyn std::error::Error + Send + Sync>> {
+    // 1) Ensure the `metadata` and `blobs` tables exist
+    run_migrations(conn)?;
+
+    // Keep the full-text index in step; without FTS5, saving still works but search doesn't.
+    let indexed = match ensure_search_index(conn) {
+        Ok(()) => true,
+        Err(SearchError::FtsUnavailable) => {
+            eprintln!("Warning: {}", SearchError::FtsUnavailable);
+            false
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let started = Instant::now();
+
+    // 2) Look up every saved file at once instead of one query per file
+    let mut existing: HashMap<String, Metadata> = HashMap::new();
+    for record in metadata::table
+        .filter(metadata::project_id.eq(project_id))
+        .order(metadata::id)
+        .load::<Metadata>(conn)?
+    {
+        existing.entry(record.file_path.clone()).or_insert(record);
+    }
+
+    // The same file can be listed under several spellings; save it once.
+    let mut seen = HashSet::new();
+    let file_paths: Vec<&str> = file_paths
+        .iter()
+        .map(|p| p.as_str())
+        .filter(|p| seen.insert(stored_path(p, doc_root)))
+        .collect();
+
+    // 3) Save batch by batch
+    let mut summary = SaveSummary::default();
+    let mut progress = SaveProgress::new(file_paths.len(), options.quiet);
+    for batch in file_paths.chunks(SAVE_BATCH_SIZE) {
+        let pending: Vec<PendingFile> = batch
+            .iter()
+            .filter_map(|p| match PendingFile::read(p, doc_root) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    progress.skipped(p, &e.to_string());
+                    summary.skipped += 1;
+                    None
+                }
+            })
+            .collect();
+        let saved = conn.immediate_transaction::<SaveSummary, Error, _>(|trx_conn| {
+            let mut saved = save_batch(
+                trx_conn,
+                &pending,
+                &existing,
+                project_id,
+                indexed,
+                options.max_versions,
+                &mut progress,
+            )?;
+            saved.assets = save_referenced_assets(
+                trx_conn,
+                &pending,
+                doc_root,
+                project_id,
+                options.max_asset_bytes,
+                &progress,
+            )?;
+            Ok(saved)
+        });
+        match saved {
+            Ok(saved) => {
+                summary.inserted += saved.inserted;
+                summary.updated += saved.updated;
+                summary.unchanged += saved.unchanged;
+                summary.assets += saved.assets;
+                summary.outside_root.extend(
+                    pending
+                        .iter()
+                        .filter(|file| file.outside_root)
+                        .map(|file| file.path.clone()),
+                );
+            }
+            Err(e) => {
+                return Err(format!(
+                    "{} (the {} files before the failing batch are saved)",
+                    e,
+                    summary.inserted + summary.updated + summary.unchanged
+                )
+                .into())
+            }
+        }
+    }
+
+    progress.finish();
+    let secs = started.elapsed().as_secs_f64();
+    println!(
+        "{} ({} inserted, {} updated, {} unchanged, {} skipped; {:.0} files/sec)",
+        "All files saved successfully!".green(),
+        summary.inserted,
+        summary.updated,
+        summary.unchanged,
+        summary.skipped,
+        file_paths.len() as f64 / secs.max(f64::EPSILON)
+    );
+    if summary.assets > 0 {
+        println!("Saved {} images referenced by the Markdown", summary.assets);
+    }
+    if !summary.outside_root.is_empty() {
+        println!(
+            "{} {} files are outside {} and were saved under the path given:",
+            "Note:".yellow(),
+            summary.outside_root.len(),
+            doc_root.display()
+        );
+        for path in &summary.outside_root {
+            println!("  {}", path);
+        }
+    }
+    Ok(summary)
+}
+
+/// Saves one batch of files: updates the metadata of changed ones row by row, inserts new
+/// ones, then stores the content not saved yet with one multi-row insert. A new file whose
+/// content is already stored only gets its metadata row. Each file is counted in
+/// `progress` once its rows are written.
+fn save_batch(
+    conn: &mut SqliteConnection,
+    files: &[PendingFile],
+    existing: &HashMap<String, Metadata>,
+    project_id: i32,
+    indexed: bool,
+    max_versions: Option<usize>,
+    progress: &mut SaveProgress,
+) -> QueryResult<SaveSummary> {
     // Bring in the DSL so we have access to the table and columns
-    use file_content::dsl as c;
     use metadata::dsl as m;
 
-    // 1) Ensure the `metadata` and `file_content` tables exist
-    if !table_exists(conn, "metadata") || !table_exists(conn, "file_content") {
-        tracing::info!("Tables 'metadata' or 'file_content' do not exist. Running migrations...");
-        run_migrations(database_url);
-        *conn = establish_connection(database_url);
+    let mut summary = SaveSummary::default();
+    let mut new_files = Vec::new();
+    for file in files {
+        let record = match existing.get(&file.path) {
+            Some(record) => record,
+            None => {
+                new_files.push(file);
+                continue;
+            }
+        };
+        if record.content_hash.as_deref() == Some(file.hash.as_str()) {
+            // Also fills in the details of rows saved before they were recorded.
+            if record.details() != file.details {
+                diesel::update(m::metadata.find(record.id))
+                    .set(&file.details)
+                    .execute(conn)?;
+            }
+            summary.unchanged += 1;
+            sync_front_matter_tags(conn, record.id, &file.tags)?;
+            progress.saved(&file.path, SaveOutcome::Unchanged);
+        } else {
+            // Record already exists -> point it at the new content
+            diesel::update(m::metadata.find(record.id))
+                .set((m::content_hash.eq(&file.hash), &file.details))
+                .execute(conn)?;
+            if indexed {
+                index_file(conn, record.id, &file.path, &file.content)?;
+            }
+            record_version(conn, record.id, &file.content, &file.hash, max_versions)?;
+
+            sync_front_matter_tags(conn, record.id, &file.tags)?;
+
+            summary.updated += 1;
+            tracing::info!("Updated content for {}", file.path);
+            progress.saved(&file.path, SaveOutcome::Updated);
+        }
     }
 
-    // 2) Use a transaction to insert/update all files at once
-    conn.transaction::<(), Error, _>(|trx_conn| {
-        for path_str in file_paths {
-            let path_obj = Path::new(path_str);
-            let file_data = fs::read_to_string(path_obj)
-                .unwrap_or_else(|_| "<empty or unreadable>".to_string());
+    for file in &new_files {
+        let id: i32 = diesel::insert_into(m::metadata)
+            .values((
+                m::file_path.eq(&file.path),
+                m::content_hash.eq(&file.hash),
+                &file.details,
+                m::project_id.eq(project_id),
+            ))
+            .returning(m::id)
+            .get_result(conn)?;
+        if indexed {
+            index_file(conn, id, &file.path, &file.content)?;
+        }
+        record_version(conn, id, &file.content, &file.hash, max_versions)?;
+        sync_front_matter_tags(conn, id, &file.tags)?;
+
+        summary.inserted += 1;
+        tracing::info!("Inserted metadata for {}", file.path);
+        progress.saved(&file.path, SaveOutcome::Inserted);
+    }
 
-            // Check if there's already a row in `metadata` for this file_path
-            let existing = m::metadata
+    // Stored last: a blob another file of the batch moved away from was deleted by the
+    // `metadata_update_blob` trigger, and is stored again here if a file now has it.
+    let changed: Vec<(&str, &str)> = files
+        .iter()
+        .filter(|file| {
+            existing
+                .get(&file.path)
+                .and_then(|r| r.content_hash.as_deref())
+                != Some(file.hash.as_str())
+        })
+        .map(|file| (file.hash.as_str(), file.content.as_str()))
+        .collect();
+    store_blobs(conn, &changed)?;
+    Ok(summary)
+}
+
+/// Saves the local images the Markdown `files` reference, found relative to each file (or
+/// to `doc_root` for paths starting with `/`), and returns how many were saved or changed.
+/// Missing images and images over `max_bytes` are reported through `progress` and skipped.
+fn save_referenced_assets(
+    conn: &mut SqliteConnection,
+    files: &[PendingFile],
+    doc_root: &Path,
+    project_id: i32,
+    max_bytes: u64,
+    progress: &SaveProgress,
+) -> QueryResult<usize> {
+    let mut seen = HashSet::new();
+    let mut saved = 0;
+    for file in files {
+        let source = resolve_stored_path(&file.path, doc_root);
+        let folder = source.parent().unwrap_or(Path::new(""));
+        for reference in image_references(&file.content) {
+            let image = match reference.strip_prefix('/') {
+                Some(from_root) => doc_root.join(from_root),
+                None => folder.join(&reference),
+            };
+            let size = match fs::metadata(&image) {
+                Ok(info) if info.is_file() => info.len(),
+                _ => {
+                    progress.warn(&format!(
+                        "image {} referenced by {} is missing",
+                        reference, file.path
+                    ));
+                    continue;
+                }
+            };
+            let path = stored_path(&image.to_string_lossy(), doc_root);
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            if size > max_bytes {
+                progress.warn(&format!(
+                    "skipping image {} referenced by {}: {} bytes is over the limit of {} (assets.max_bytes)",
+                    path, file.path, size, max_bytes
+                ));
+                continue;
+            }
+            let content = match fs::read(&image) {
+                Ok(content) => content,
+                Err(e) => {
+                    progress.warn(&format!("skipping {}: {}", path, e));
+                    continue;
+                }
+            };
+            let hash = bytes_hash(&content);
+            if save_asset(conn, project_id, &path, mime_type(&image), &hash, &content)? {
+                saved += 1;
+            }
+        }
+    }
+    Ok(saved)
+}
+
+/// Inserts or updates rendered HTML pages in the `html_metadata`/`html_content` tables,
+/// the same way `save_files_to_db` does for Markdown, so they can be served from the DB.
+/// Pages are stored by their path relative to `html_root`.
+pub fn save_html_files_to_db(
+    file_paths: &[String],
+    html_root: &Path,
+    conn: &mut SqliteConnection,
+) -> Result<SaveSummary, Box<dyn std::error::Error + Send + Sync>> {
+    use html_content::dsl as c;
+    use html_metadata::dsl as m;
+
+    run_migrations(conn)?;
+
+    let mut summary = SaveSummary::default();
+    conn.immediate_transaction::<(), Error, _>(|trx_conn| {
+        for source in file_paths {
+            let file_data = match fs::read_to_string(source) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn_skipped(source, &e);
+                    summary.skipped += 1;
+                    continue;
+                }
+            };
+            let path_str = &stored_path(source, html_root);
+            let hash = content_hash(&file_data);
+
+            let existing = m::html_metadata
                 .filter(m::file_path.eq(path_str))
-                .first::<Metadata>(trx_conn);
+                .first::<HtmlMetadata>(trx_conn);
 
             match existing {
+                Ok(record) if record.content_hash.as_deref() == Some(hash.as_str()) => {
+                    summary.unchanged += 1;
+                }
                 Ok(record) => {
-                    // Record already exists -> update the file_content table
-                    diesel::update(c::file_content.find(record.id))
-                        .set(c::content.eq(file_data))
+                    diesel::update(c::html_content.filter(c::id.eq(record.id)))
+                        .set(c::content.eq(&file_data))
+                        .execute(trx_conn)?;
+                    diesel::update(m::html_metadata.find(record.id))
+                        .set(m::content_hash.eq(&hash))
                         .execute(trx_conn)?;
 
-                    tracing::info!("Updated content for {}", path_str);
+                    summary.updated += 1;
+                    tracing::info!("Updated HTML content for {}", path_str);
                 }
                 Err(diesel::result::Error::NotFound) => {
-                    // Insert new metadata row first
-                    diesel::insert_into(m::metadata)
-                        .values(m::file_path.eq(path_str))
-                        .execute(trx_conn)?;
-
-                    // Then fetch that new row's `id`
-                    let row: LastInsertRowId =
-                        sql_query("SELECT last_insert_rowid() as last_insert_rowid")
-                            .get_result(trx_conn)?;
-
-                    // Insert content using that same `id`
-                    diesel::insert_into(c::file_content)
-                        .values((
-                            c::id.eq(row.last_insert_rowid as i32),
-                            c::content.eq(file_data),
-                        ))
+                    let id: i32 = diesel::insert_into(m::html_metadata)
+                        .values((m::file_path.eq(path_str), m::content_hash.eq(&hash)))
+                        .returning(m::id)
+                        .get_result(trx_conn)?;
+                    diesel::insert_into(c::html_content)
+                        .values((c::id.eq(id), c::content.eq(file_data)))
                         .execute(trx_conn)?;
 
-                    tracing::info!("Inserted metadata + content for {}", path_str);
+                    summary.inserted += 1;
+                    tracing::info!("Inserted HTML metadata + content for {}", path_str);
                 }
                 Err(e) => {
-                    tracing::error!("Error looking up metadata for '{}': {:?}", path_str, e);
+                    tracing::error!("Error looking up HTML metadata for '{}': {:?}", path_str, e);
                     return Err(e);
                 }
             }
@@ -128,6 +434,374 @@ pub fn save_files_to_db(
         Ok(())
     })?;
 
-    println!("{}", "All files saved successfully!".green());
-    Ok(())
+    println!(
+        "{} ({} inserted, {} updated, {} unchanged, {} skipped)",
+        "All HTML files saved successfully!".green(),
+        summary.inserted,
+        summary.updated,
+        summary.unchanged,
+        summary.skipped
+    );
+    Ok(summary)
+}
+
+/// The `.html` files under `folder`, sorted.
+pub fn collect_html_files(folder: &Path) -> Vec<String> {
+    collect_files(folder, "html")
+}
+
+/// Every `.md` file below `folder`, sorted, for saving a doc folder without a file list.
+pub fn collect_markdown_files(folder: &Path) -> Vec<String> {
+    collect_files(folder, "md")
+}
+
+fn collect_files(folder: &Path, extension: &str) -> Vec<String> {
+    let mut files: Vec<String> = WalkDir::new(folder)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some(extension))
+        .map(|e| e.path().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+fn warn_skipped(path: &str, error: &io::Error) {
+    eprintln!("{} skipping {}: {}", "Warning:".yellow(), path, error);
+}
+
+/// `path` as stored in the database: relative to `root`, with `/` separators, when the
+/// file is inside it, else unchanged.
+pub fn stored_path(path: &str, root: &Path) -> String {
+    relative_to_root(path, root).unwrap_or_else(|| path.to_string())
+}
+
+/// `path` relative to `root`, with `/` separators, or `None` when it is not below it.
+pub fn relative_to_root(path: &str, root: &Path) -> Option<String> {
+    let absolute = |p: &Path| {
+        fs::canonicalize(p)
+            .ok()
+            .or_else(|| std::env::current_dir().ok().map(|cwd| cwd.join(p)))
+    };
+    let (file, root) = (absolute(Path::new(path))?, absolute(root)?);
+    match file.strip_prefix(&root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => Some(
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        ),
+        _ => None,
+    }
+}
+
+/// The file a path stored by `stored_path` refers to.
+pub fn resolve_stored_path(stored: &str, root: &Path) -> PathBuf {
+    let path = Path::new(stored);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        root.join(path)
+    }
+}
+
+/// Language, size, modification time, and title of the file at `path` with `content`.
+fn file_details(path: &Path, content: &str) -> FileDetails {
+    let stat = fs::metadata(path).ok();
+    let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+    let front_matter = parse_front_matter(&lines);
+    let front_matter_str = |key: &str| {
+        front_matter
+            .as_ref()
+            .and_then(|fm| fm.get(key))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    };
+
+    FileDetails {
+        language: front_matter_str("language").or_else(|| main_code_language(&lines)),
+        size_bytes: stat.as_ref().map(|s| s.len() as i64),
+        modified_at: stat
+            .and_then(|s| s.modified().ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64),
+        title: front_matter_str("title").or_else(|| {
+            lines
+                .iter()
+                .find_map(|l| l.strip_prefix("# "))
+                .map(|t| t.trim().to_string())
+        }),
+    }
+}
+
+/// The `tags:` of the file's front matter, given as a list or a comma-separated string.
+fn front_matter_tags(content: &str) -> Vec<String> {
+    let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+    match parse_front_matter(&lines)
+        .as_ref()
+        .and_then(|fm| fm.get("tags"))
+    {
+        Some(serde_yaml::Value::Sequence(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .map(|tag| tag.to_string())
+            .collect(),
+        Some(serde_yaml::Value::String(tags)) => tags.split(',').map(|t| t.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The front matter of `lines` as YAML, if it has any that parses.
+fn parse_front_matter(lines: &[String]) -> Option<serde_yaml::Value> {
+    let range = front_matter_range(lines)?;
+    serde_yaml::from_str(&lines[range].join("\n")).ok()
+}
+
+/// The language of most of the file's fenced code blocks, if any is known.
+fn main_code_language(lines: &[String]) -> Option<String> {
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    let mut in_block = false;
+    for line in lines {
+        if !line.trim_start().starts_with("```") {
+            continue;
+        }
+        if !in_block {
+            let language = detect_language_from_line(line);
+            if language != CodeLanguage::Unknown {
+                *counts.entry(language.tag()).or_default() += 1;
+            }
+        }
+        in_block = !in_block;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(tag, count)| (*count, std::cmp::Reverse(*tag)))
+        .map(|(tag, _)| tag.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::blobs;
+    use crate::utils::database::db::ConnectionCustomizer;
+    use crate::utils::database::pool::build_pool;
+    use crate::utils::database::projects::project_id;
+    use diesel::sql_query;
+    use diesel::sql_types::Text;
+    use std::thread;
+
+    const OPTIONS: SaveOptions = SaveOptions {
+        max_versions: None,
+        max_asset_bytes: 1 << 20,
+        quiet: true,
+    };
+
+    /// A connection to a database at `path`, set up like lila's but without a key.
+    fn connect(path: &Path) -> SqliteConnection {
+        let mut conn = SqliteConnection::establish(&path.to_string_lossy()).unwrap();
+        ConnectionCustomizer::default().apply(&mut conn).unwrap();
+        conn
+    }
+
+    /// Writes `files`, `(path, content)` pairs, below `root` and returns their paths.
+    fn write_files(root: &Path, files: &[(impl AsRef<Path>, impl AsRef<str>)]) -> Vec<String> {
+        files
+            .iter()
+            .map(|(path, content)| {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, content.as_ref()).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect()
+    }
+
+    /// Saved paths with their content, by path.
+    fn saved_contents(conn: &mut SqliteConnection) -> Vec<(String, String)> {
+        metadata::table
+            .inner_join(blobs::table)
+            .select((metadata::file_path, blobs::content))
+            .order(metadata::file_path)
+            .load(conn)
+            .unwrap()
+    }
+
+    #[derive(QueryableByName)]
+    struct TableName {
+        #[diesel(sql_type = Text)]
+        name: String,
+    }
+
+    #[test]
+    fn save_creates_the_tables_in_a_fresh_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("fresh").join("lila.db");
+        fs::create_dir_all(db_path.parent().unwrap()).unwrap();
+        let doc = dir.path().join("doc");
+        let files = write_files(&doc, &[("intro.md", "# Intro\n")]);
+
+        let mut conn = connect(&db_path);
+        run_migrations(&mut conn).unwrap();
+        let project = project_id(&mut conn, "book").unwrap();
+        let summary = save_files_to_db(&files, &doc, project, &mut conn, &OPTIONS).unwrap();
+
+        assert_eq!(summary.inserted, 1);
+        let tables: Vec<String> = sql_query("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .load::<TableName>(&mut conn)
+            .unwrap()
+            .into_iter()
+            .map(|table| table.name)
+            .collect();
+        for table in [
+            "metadata",
+            "blobs",
+            "content_history",
+            "projects",
+            "html_metadata",
+        ] {
+            assert!(
+                tables.iter().any(|t| t == table),
+                "{} in {:?}",
+                table,
+                tables
+            );
+        }
+        assert_eq!(
+            saved_contents(&mut conn),
+            [("intro.md".to_string(), "# Intro\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn markdown_and_html_saved_together_stay_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let doc = dir.path().join("doc");
+        let html = dir.path().join("html");
+        let markdown = write_files(&doc, &[("intro.md", "# Intro\n")]);
+        let pages = write_files(
+            &html,
+            &[
+                ("intro.html", "<h1>Intro</h1>\n"),
+                ("usage.html", "<h1>Usage</h1>\n"),
+            ],
+        );
+
+        let mut conn = connect(&dir.path().join("lila.db"));
+        run_migrations(&mut conn).unwrap();
+        let project = project_id(&mut conn, "book").unwrap();
+        save_files_to_db(&markdown, &doc, project, &mut conn, &OPTIONS).unwrap();
+        let summary = save_html_files_to_db(&pages, &html, &mut conn).unwrap();
+        assert_eq!(summary.inserted, 2);
+
+        fs::write(&pages[1], "<h1>Usage, revised</h1>\n").unwrap();
+        let summary = save_html_files_to_db(&pages, &html, &mut conn).unwrap();
+        assert_eq!((summary.updated, summary.unchanged), (1, 1));
+        let summary = save_files_to_db(&markdown, &doc, project, &mut conn, &OPTIONS).unwrap();
+        assert_eq!((summary.inserted, summary.unchanged), (0, 1));
+
+        let saved_pages: Vec<(String, String)> = html_metadata::table
+            .inner_join(html_content::table)
+            .select((html_metadata::file_path, html_content::content))
+            .order(html_metadata::file_path)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(
+            saved_pages,
+            [
+                ("intro.html".to_string(), "<h1>Intro</h1>\n".to_string()),
+                (
+                    "usage.html".to_string(),
+                    "<h1>Usage, revised</h1>\n".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            saved_contents(&mut conn),
+            [("intro.md".to_string(), "# Intro\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn a_thousand_files_save_across_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let doc = dir.path().join("doc");
+        let corpus: Vec<(String, String)> = (0..1000)
+            .map(|i| {
+                let path = format!("part-{}/chapter-{:04}.md", i % 7, i);
+                (path, format!("# Chapter {}\n", i))
+            })
+            .collect();
+        let mut files = write_files(&doc, &corpus);
+
+        let mut conn = connect(&dir.path().join("lila.db"));
+        run_migrations(&mut conn).unwrap();
+        let project = project_id(&mut conn, "book").unwrap();
+        let summary = save_files_to_db(&files, &doc, project, &mut conn, &OPTIONS).unwrap();
+        assert_eq!(summary.inserted, 1000);
+
+        // Changes on both sides of the first batch boundary, and files the database lacks.
+        let mut expected = corpus.clone();
+        for i in (490..510).step_by(2) {
+            expected[i].1 = format!("# Chapter {}, revised\n", i);
+            fs::write(&files[i], &expected[i].1).unwrap();
+        }
+        let added: Vec<(String, String)> = (0..5)
+            .map(|i| {
+                (
+                    format!("extra/appendix-{}.md", i),
+                    format!("# Appendix {}\n", i),
+                )
+            })
+            .collect();
+        files.extend(write_files(&doc, &added));
+        expected.extend(added);
+
+        let summary = save_files_to_db(&files, &doc, project, &mut conn, &OPTIONS).unwrap();
+        assert_eq!(
+            (summary.inserted, summary.updated, summary.unchanged),
+            (5, 10, 990)
+        );
+        expected.sort();
+        assert_eq!(saved_contents(&mut conn), expected);
+    }
+
+    #[test]
+    fn concurrent_saves_attach_content_to_the_right_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let doc = dir.path().join("doc");
+        let sets: Vec<Vec<String>> = ["left", "right"]
+            .iter()
+            .map(|side| {
+                let files: Vec<(String, String)> = (0..200)
+                    .map(|i| {
+                        let path = format!("{}/note-{:03}.md", side, i);
+                        let content = format!("# {}\n", path);
+                        (path, content)
+                    })
+                    .collect();
+                write_files(&doc, &files)
+            })
+            .collect();
+        let url = dir.path().join("lila.db").to_string_lossy().into_owned();
+        let pool = build_pool(&url, 2, None).unwrap();
+        let project = project_id(&mut pool.get().unwrap(), "book").unwrap();
+
+        thread::scope(|scope| {
+            for files in &sets {
+                let (pool, doc) = (&pool, &doc);
+                scope.spawn(move || {
+                    let mut conn = pool.get().unwrap();
+                    save_files_to_db(files, doc, project, &mut conn, &OPTIONS).unwrap();
+                });
+            }
+        });
+
+        let saved = saved_contents(&mut pool.get().unwrap());
+        assert_eq!(saved.len(), 400);
+        for (path, content) in saved {
+            assert_eq!(content, format!("# {}\n", path));
+        }
+    }
 }
@@ -0,0 +1,99 @@
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::projects::ProjectScope;
+use crate::utils::database::search::{query_words, search_files, SearchFilter, SearchHit};
+use colored::Colorize;
+use std::io;
+use std::path::Path;
+
+/// Settings for a `lila search` run.
+#[derive(Debug)]
+pub struct SearchOptions {
+    /// Maximum number of files to show.
+    pub limit: usize,
+    /// Print the matches as a JSON array instead of highlighted text.
+    pub json: bool,
+    /// Only search files whose saved path starts with this prefix.
+    pub path_prefix: Option<String>,
+    /// Only search files carrying all of these tags.
+    pub tags: Vec<String>,
+    pub project: ProjectScope,
+}
+
+/// Searches the Markdown saved in the database at `db_path` and prints the matches.
+pub fn search_saved_files(db_path: &Path, query: &str, options: &SearchOptions) -> io::Result<()> {
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    // Nothing is saved of a project the database does not know.
+    let hits = match options
+        .project
+        .resolve(&mut conn)
+        .map_err(io::Error::other)?
+    {
+        Some(project_id) => {
+            let filter = SearchFilter {
+                path_prefix: options.path_prefix.as_deref(),
+                tags: &options.tags,
+                project_id,
+            };
+            search_files(&mut conn, query, options.limit, &filter).map_err(io::Error::other)?
+        }
+        None => Vec::new(),
+    };
+
+    if options.json {
+        let out = serde_json::to_string_pretty(&hits).map_err(io::Error::other)?;
+        println!("{}", out);
+        return Ok(());
+    }
+
+    if hits.is_empty() {
+        println!("No matches for \"{}\".", query);
+        return Ok(());
+    }
+    let words = query_words(query);
+    for hit in &hits {
+        print_hit(hit, &words, options.project == ProjectScope::All);
+    }
+    Ok(())
+}
+
+fn print_hit(hit: &SearchHit, words: &[String], show_project: bool) {
+    match (&hit.project, show_project) {
+        (Some(project), true) => println!("{} ({})", hit.file_path.bold(), project),
+        _ => println!("{}", hit.file_path.bold()),
+    }
+    for line in &hit.lines {
+        println!(
+            "  {}: {}",
+            line.line.to_string().cyan(),
+            highlight(&line.text, words)
+        );
+    }
+}
+
+/// Returns `text` with every occurrence of `words` (case-insensitive) highlighted.
+fn highlight(text: &str, words: &[String]) -> String {
+    let lower = text.to_lowercase();
+    // Lower-casing can change byte lengths outside ASCII; don't risk misaligned slices.
+    if lower.len() != text.len() {
+        return text.to_string();
+    }
+
+    let mut out = String::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let found = words
+            .iter()
+            .filter(|w| !w.is_empty())
+            .filter_map(|w| lower[pos..].find(w.as_str()).map(|i| (pos + i, w.len())))
+            .min();
+        let Some((start, len)) = found else {
+            break;
+        };
+        out.push_str(&text[pos..start]);
+        out.push_str(&text[start..start + len].yellow().bold().to_string());
+        pos = start + len;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
@@ -0,0 +1,175 @@
+use crate::schema::{assets, blobs, html_content, html_metadata, metadata};
+use crate::utils::database::assets::find_asset;
+use crate::utils::database::db::establish_connection;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+/// Theme used when highlighting stored content on a terminal.
+const THEME: &str = "base16-ocean.dark";
+
+/// Prints the stored content of the file `target` from the database at `db_path`:
+/// the Markdown saved by `lila save`, or with `html` the page saved by `lila save --html`.
+///
+/// `target` is a row id, as printed by `lila list`, or the end of a saved path. The
+/// content is syntax highlighted when stdout is a terminal.
+pub fn show_saved_file(db_path: &Path, target: &str, html: bool) -> io::Result<()> {
+    if !db_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no database at {}; run `lila save` first",
+                db_path.display()
+            ),
+        ));
+    }
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    let rows = if html {
+        html_metadata::table
+            .select((html_metadata::id, html_metadata::file_path))
+            .load::<(i32, String)>(&mut conn)
+    } else {
+        metadata::table
+            .select((metadata::id, metadata::file_path))
+            .load::<(i32, String)>(&mut conn)
+    }
+    .map_err(io::Error::other)?;
+
+    let &(id, _) = resolve_saved_file(&rows, target)?;
+    let content = load_content(&mut conn, id, html).map_err(io::Error::other)?;
+
+    let mut stdout = io::stdout().lock();
+    if stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none() {
+        let extension = if html { "html" } else { "md" };
+        write!(stdout, "{}", highlight(&content, extension))?;
+    } else {
+        write!(stdout, "{}", content)?;
+    }
+    stdout.flush()
+}
+
+/// Writes the bytes of the image `target` saved by `lila save` from the database at
+/// `db_path` to stdout. `target` is an asset id or the end of its saved path. On a
+/// terminal only the path, type and size are printed.
+pub fn show_saved_asset(db_path: &Path, target: &str) -> io::Result<()> {
+    if !db_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no database at {}; run `lila save` first",
+                db_path.display()
+            ),
+        ));
+    }
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    let rows = assets::table
+        .select((assets::id, assets::file_path))
+        .load::<(i32, String)>(&mut conn)
+        .map_err(io::Error::other)?;
+    let &(id, _) = resolve_saved_file(&rows, target)?;
+    let asset = find_asset(&mut conn, id)
+        .map_err(io::Error::other)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the asset was deleted"))?;
+
+    let mut stdout = io::stdout().lock();
+    if stdout.is_terminal() {
+        writeln!(
+            stdout,
+            "{} ({}, {} bytes); redirect stdout to a file to get its content",
+            asset.file_path,
+            asset.mime_type,
+            asset.content.len()
+        )?;
+    } else {
+        stdout.write_all(&asset.content)?;
+    }
+    stdout.flush()
+}
+
+/// The row `target` names: an id, else the one path equal to or ending in `target`.
+pub(crate) fn resolve_saved_file<'a>(
+    rows: &'a [(i32, String)],
+    target: &str,
+) -> io::Result<&'a (i32, String)> {
+    if let Ok(id) = target.parse::<i32>() {
+        if let Some(row) = rows.iter().find(|(row_id, _)| *row_id == id) {
+            return Ok(row);
+        }
+    }
+
+    let candidates: Vec<&(i32, String)> = rows
+        .iter()
+        .filter(|(_, path)| is_path_suffix(path, target))
+        .collect();
+    if let Some(exact) = candidates.iter().find(|(_, path)| path == target) {
+        return Ok(exact);
+    }
+    match candidates[..] {
+        [] => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no saved file matches {}", target),
+        )),
+        [row] => Ok(row),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} matches several saved files, use a longer path or an id:\n{}",
+                target,
+                candidates
+                    .iter()
+                    .map(|(id, path)| format!("  {:>5}  {}", id, path))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+        )),
+    }
+}
+
+/// True if `suffix` is `path` or its last path components.
+fn is_path_suffix(path: &str, suffix: &str) -> bool {
+    let suffix = suffix.trim_start_matches("./");
+    match path.strip_suffix(suffix) {
+        Some(rest) => !suffix.is_empty() && (rest.is_empty() || rest.ends_with('/')),
+        None => false,
+    }
+}
+
+fn load_content(conn: &mut SqliteConnection, id: i32, html: bool) -> QueryResult<String> {
+    if html {
+        html_content::table
+            .filter(html_content::id.eq(id))
+            .select(html_content::content)
+            .first(conn)
+    } else {
+        metadata::table
+            .inner_join(blobs::table)
+            .filter(metadata::id.eq(id))
+            .select(blobs::content)
+            .first(conn)
+    }
+}
+
+/// `content` with terminal colors for the syntax of files with `extension`.
+fn highlight(content: &str, extension: &str) -> String {
+    let syntaxes = SyntaxSet::load_defaults_newlines();
+    let themes = ThemeSet::load_defaults();
+    let syntax = syntaxes
+        .find_syntax_by_extension(extension)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, &themes.themes[THEME]);
+
+    let mut out = String::new();
+    for line in LinesWithEndings::from(content) {
+        match highlighter.highlight_line(line, &syntaxes) {
+            Ok(ranges) => out.push_str(&as_24_bit_terminal_escaped(&ranges, false)),
+            Err(_) => out.push_str(line),
+        }
+    }
+    out.push_str("\x1b[0m");
+    out
+}
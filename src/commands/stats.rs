@@ -0,0 +1,219 @@
+use crate::commands::history::load_max_versions;
+use crate::commands::list::human_size;
+use crate::schema::{blobs, content_history, metadata};
+use crate::utils::config::Config;
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::projects::ProjectScope;
+use crate::utils::utils::format_timestamp;
+use colored::Colorize;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Documents listed under "Largest".
+const LARGEST_COUNT: usize = 5;
+
+/// What `lila stats` reports about a database.
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub documents: usize,
+    /// Bytes of saved Markdown, without history, counting content shared by several
+    /// documents once.
+    pub stored_bytes: i64,
+    /// Documents per language; rows saved before languages were recorded count as "unknown".
+    pub languages: BTreeMap<String, usize>,
+    pub largest: Vec<DocumentSize>,
+    /// When lila last saved a new version, in seconds since the Unix epoch.
+    pub last_saved_at: Option<i64>,
+    /// `None` when no versions are kept.
+    pub history: Option<HistoryStats>,
+    /// Size of the database file and its write-ahead log.
+    pub db_file_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DocumentSize {
+    pub path: String,
+    pub bytes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryStats {
+    /// Versions kept, for all documents.
+    pub versions: usize,
+    /// Versions kept of the document with the most.
+    pub deepest: usize,
+    /// `history.max_versions` from `Lila.toml`, if set.
+    pub max_versions: Option<usize>,
+}
+
+/// A saved document, with the hash and size of its content.
+#[derive(Queryable)]
+struct DocumentRow {
+    id: i32,
+    path: String,
+    language: Option<String>,
+    hash: Option<String>,
+    bytes: Option<i64>,
+}
+
+/// Prints statistics about the documents of `project` saved in the database at
+/// `db_path`, as text or, with `json`, as a JSON object, with the history retention of
+/// `config`. A missing database is reported as an empty one.
+pub fn print_stats(
+    db_path: &Path,
+    project: &ProjectScope,
+    json: bool,
+    config: &Config,
+) -> io::Result<()> {
+    // Connecting would create an empty database, so check first.
+    let stats = if db_path.exists() {
+        collect_stats(db_path, project, config)?
+    } else {
+        Stats::default()
+    };
+
+    if json {
+        let out = serde_json::to_string_pretty(&stats).map_err(io::Error::other)?;
+        println!("{}", out);
+        return Ok(());
+    }
+    print_text(db_path, &stats);
+    Ok(())
+}
+
+fn collect_stats(db_path: &Path, project: &ProjectScope, config: &Config) -> io::Result<Stats> {
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    let Some(project_id) = project.resolve(&mut conn).map_err(io::Error::other)? else {
+        return Ok(Stats {
+            db_file_bytes: db_file_bytes(db_path),
+            ..Stats::default()
+        });
+    };
+
+    let mut query = metadata::table
+        .left_join(blobs::table)
+        .select((
+            metadata::id,
+            metadata::file_path,
+            metadata::language,
+            blobs::hash.nullable(),
+            sql::<Nullable<BigInt>>("LENGTH(CAST(blobs.content AS BLOB))"),
+        ))
+        .into_boxed();
+    if let Some(id) = project_id {
+        query = query.filter(metadata::project_id.eq(id));
+    }
+    let documents: Vec<DocumentRow> = query.load(&mut conn).map_err(io::Error::other)?;
+
+    let mut stats = Stats {
+        documents: documents.len(),
+        db_file_bytes: db_file_bytes(db_path),
+        ..Stats::default()
+    };
+    for document in &documents {
+        let language = document
+            .language
+            .clone()
+            .unwrap_or_else(|| "unknown".to_string());
+        *stats.languages.entry(language).or_default() += 1;
+    }
+    let blob_sizes: HashMap<&str, i64> = documents
+        .iter()
+        .filter_map(|d| Some((d.hash.as_deref()?, d.bytes?)))
+        .collect();
+    stats.stored_bytes = blob_sizes.values().sum();
+    let mut by_size: Vec<DocumentSize> = documents
+        .iter()
+        .map(|d| DocumentSize {
+            path: d.path.clone(),
+            bytes: d.bytes.unwrap_or(0),
+        })
+        .collect();
+    by_size.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    by_size.truncate(LARGEST_COUNT);
+    stats.largest = by_size;
+
+    // Versions per document, for the documents of the project.
+    let versions: HashMap<i32, (i64, Option<i64>)> = content_history::table
+        .group_by(content_history::metadata_id)
+        .select((
+            content_history::metadata_id,
+            diesel::dsl::count_star(),
+            diesel::dsl::max(content_history::saved_at),
+        ))
+        .load::<(i32, i64, Option<i64>)>(&mut conn)
+        .map_err(io::Error::other)?
+        .into_iter()
+        .map(|(id, count, saved_at)| (id, (count, saved_at)))
+        .collect();
+    let kept: Vec<(i64, Option<i64>)> = documents
+        .iter()
+        .filter_map(|d| versions.get(&d.id).copied())
+        .collect();
+    stats.last_saved_at = kept.iter().filter_map(|(_, saved_at)| *saved_at).max();
+    if !kept.is_empty() {
+        stats.history = Some(HistoryStats {
+            versions: kept.iter().map(|(count, _)| *count as usize).sum(),
+            deepest: kept
+                .iter()
+                .map(|(count, _)| *count as usize)
+                .max()
+                .unwrap_or(0),
+            max_versions: load_max_versions(config)?,
+        });
+    }
+    Ok(stats)
+}
+
+/// Size of the database file, with its `-wal` file if there is one.
+fn db_file_bytes(db_path: &Path) -> u64 {
+    let size = |path: &Path| fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let mut wal = db_path.as_os_str().to_owned();
+    wal.push("-wal");
+    size(db_path) + size(Path::new(&wal))
+}
+
+fn print_text(db_path: &Path, stats: &Stats) {
+    println!("{}", format!("Statistics of {}", db_path.display()).bold());
+    println!("Documents:      {}", stats.documents);
+    println!("Stored:         {}", human_size(stats.stored_bytes));
+    println!("Database file:  {}", human_size(stats.db_file_bytes as i64));
+    println!(
+        "Last save:      {}",
+        stats
+            .last_saved_at
+            .map_or("never".to_string(), format_timestamp)
+    );
+    match &stats.history {
+        Some(history) => println!(
+            "History:        {} versions, up to {} per document{}",
+            history.versions,
+            history.deepest,
+            history
+                .max_versions
+                .map_or(String::new(), |max| format!(" (keeping {})", max))
+        ),
+        None => println!("History:        no versions kept"),
+    }
+    if stats.documents == 0 {
+        println!("No saved documents yet. Run `lila save` to add some.");
+        return;
+    }
+
+    println!("{}", "Languages:".bold());
+    let width = stats.languages.keys().map(|l| l.len()).max().unwrap_or(0);
+    for (language, count) in &stats.languages {
+        println!("  {:<width$}  {}", language, count);
+    }
+    println!("{}", "Largest:".bold());
+    for document in &stats.largest {
+        println!("  {:>9}  {}", human_size(document.bytes), document.path);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::commands::show::resolve_saved_file;
+use crate::schema::{file_tags, metadata, tags};
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::tags::{
+    add_tag, normalize_tag, remove_tag, SOURCE_FRONT_MATTER, SOURCE_MANUAL,
+};
+use colored::Colorize;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::io;
+use std::path::Path;
+
+/// Adds `names` to the tags of the saved file `target` (an id or the end of its path).
+pub fn add_tags(db_path: &Path, target: &str, names: &[String]) -> io::Result<()> {
+    let (mut conn, id, path) = open_saved_file(db_path, target)?;
+    for name in names {
+        let added = add_tag(&mut conn, id, name, SOURCE_MANUAL).map_err(io::Error::other)?;
+        if added {
+            println!("{} {}: tagged {}", "✔".green(), path, normalize_tag(name));
+        } else {
+            println!("{}: already tagged {}", path, normalize_tag(name));
+        }
+    }
+    Ok(())
+}
+
+/// Removes `names` from the tags of the saved file `target` (an id or the end of its path).
+pub fn remove_tags(db_path: &Path, target: &str, names: &[String]) -> io::Result<()> {
+    let (mut conn, id, path) = open_saved_file(db_path, target)?;
+    for name in names {
+        let from_front_matter = tag_source(&mut conn, id, name).map_err(io::Error::other)?
+            == Some(SOURCE_FRONT_MATTER.to_string());
+        if !remove_tag(&mut conn, id, name).map_err(io::Error::other)? {
+            println!("{}: not tagged {}", path, normalize_tag(name));
+            continue;
+        }
+        println!("{} {}: untagged {}", "✔".green(), path, normalize_tag(name));
+        if from_front_matter {
+            println!(
+                "  {} the tag is in the file's front matter and returns on the next save",
+                "note:".yellow()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Connects to the database at `db_path` and resolves `target` to a saved file's id and path.
+fn open_saved_file(db_path: &Path, target: &str) -> io::Result<(SqliteConnection, i32, String)> {
+    if !db_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no database at {}; run `lila save` first",
+                db_path.display()
+            ),
+        ));
+    }
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+    let rows = metadata::table
+        .select((metadata::id, metadata::file_path))
+        .load::<(i32, String)>(&mut conn)
+        .map_err(io::Error::other)?;
+    let (id, path) = resolve_saved_file(&rows, target)?.clone();
+    Ok((conn, id, path))
+}
+
+/// How the file saved under `metadata_id` got tagged `name`, if it is.
+fn tag_source(
+    conn: &mut SqliteConnection,
+    metadata_id: i32,
+    name: &str,
+) -> QueryResult<Option<String>> {
+    file_tags::table
+        .inner_join(tags::table)
+        .filter(file_tags::metadata_id.eq(metadata_id))
+        .filter(tags::name.eq(normalize_tag(name)))
+        .select(file_tags::source)
+        .first(conn)
+        .optional()
+}
@@ -15,8 +15,25 @@ pub fn extract_code_from_markdown(
 ) -> io::Result<Result<HashMap<String, String>, String>> {
     let path = Path::new(file_path);
     let file = File::open(&path)?;
-    let reader = BufReader::new(file);
+    Ok(
+        extract_code_and_meta(BufReader::new(file))?.map(|(meta_data, code)| {
+            println!("Extracted YAML metadata:\n{}", meta_data);
+            code
+        }),
+    )
+}
+
+/// The code of the Markdown read from `reader`, by output file name, as
+/// `extract_code_from_markdown` extracts it from a file; `Err` when it has no front matter.
+pub fn extract_code(reader: impl BufRead) -> io::Result<Result<HashMap<String, String>, String>> {
+    Ok(extract_code_and_meta(reader)?.map(|(_, code)| code))
+}
 
+/// The front matter of a Markdown file, and its code by output file name.
+type MetaAndCode = (String, HashMap<String, String>);
+
+/// The front matter and the code of the Markdown read from `reader`.
+fn extract_code_and_meta(reader: impl BufRead) -> io::Result<Result<MetaAndCode, String>> {
     let mut meta_data = String::new();
     let mut in_front_matter = false;
     let mut found_meta = false;
@@ -62,8 +79,6 @@ pub fn extract_code_from_markdown(
         return Ok(Err("No metadata found".to_string()));
     }
 
-    println!("Extracted YAML metadata:\n{}", meta_data);
-
     let cleaned_meta_data = meta_data.trim_end_matches("---").trim();
     let meta: MarkdownMeta = serde_yaml::from_str(cleaned_meta_data).map_err(|e| {
         io::Error::new(
@@ -87,7 +102,7 @@ pub fn extract_code_from_markdown(
         result.insert(output_filename, code);
     }
 
-    Ok(Ok(result))
+    Ok(Ok((meta_data, result)))
 }
 
 pub fn extract_code_from_folder(folder_path: &str, app_folder: &str) -> io::Result<()> {
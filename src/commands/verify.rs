@@ -0,0 +1,116 @@
+use crate::commands::save::{collect_markdown_files, resolve_stored_path, stored_path};
+use crate::schema::{blobs, metadata};
+use crate::utils::database::blobs::content_hash;
+use crate::utils::database::db::{establish_connection, run_migrations};
+use crate::utils::database::projects::ProjectScope;
+use colored::{ColoredString, Colorize};
+use diesel::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How the Markdown saved in the database compares to the files in the doc folder.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub in_sync: Vec<String>,
+    /// Saved files whose content on disk has a different hash.
+    pub modified: Vec<String>,
+    /// Saved files that no longer exist on disk.
+    pub missing: Vec<String>,
+    /// Markdown files in the doc folder that are not saved.
+    pub unsaved: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_in_sync(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.unsaved.is_empty()
+    }
+}
+
+/// Compares every file of `project` saved in the database at `db_path` with the file it
+/// was saved from below `doc_root`, prints the result, and returns it.
+///
+/// Paths are normalized as `lila save` stores them, so a file saved from the doc folder
+/// matches the same file found on disk.
+pub fn verify_saved_files(
+    db_path: &Path,
+    doc_root: &Path,
+    project: &ProjectScope,
+) -> io::Result<VerifyReport> {
+    // Connecting would create an empty database, so check first.
+    if !db_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no database at {}; run `lila save` first",
+                db_path.display()
+            ),
+        ));
+    }
+    let mut conn = establish_connection(&db_path.to_string_lossy()).map_err(io::Error::other)?;
+    run_migrations(&mut conn).map_err(io::Error::other)?;
+
+    // Nothing is saved of a project the database does not know.
+    let rows: Vec<(String, Option<String>, Option<String>)> =
+        match project.resolve(&mut conn).map_err(io::Error::other)? {
+            Some(project_id) => {
+                let mut query = metadata::table.left_join(blobs::table).into_boxed();
+                if let Some(id) = project_id {
+                    query = query.filter(metadata::project_id.eq(id));
+                }
+                query
+                    .select((
+                        metadata::file_path,
+                        metadata::content_hash,
+                        blobs::content.nullable(),
+                    ))
+                    .order(metadata::file_path.asc())
+                    .load(&mut conn)
+                    .map_err(io::Error::other)?
+            }
+            None => Vec::new(),
+        };
+
+    let mut report = VerifyReport::default();
+    let mut saved = HashSet::new();
+    for (path, hash, content) in rows {
+        // Rows saved before hashes were recorded are compared by their content.
+        let hash = hash.or_else(|| content.as_deref().map(content_hash));
+        match fs::read_to_string(resolve_stored_path(&path, doc_root)) {
+            Ok(on_disk) if Some(content_hash(&on_disk)) == hash => {
+                report.in_sync.push(path.clone())
+            }
+            Ok(_) => report.modified.push(path.clone()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => report.missing.push(path.clone()),
+            Err(e) => {
+                eprintln!("{} cannot read {}: {}", "Warning:".yellow(), path, e);
+                report.modified.push(path.clone());
+            }
+        }
+        saved.insert(path);
+    }
+    report.unsaved = collect_markdown_files(doc_root)
+        .iter()
+        .map(|file| stored_path(file, doc_root))
+        .filter(|path| !saved.contains(path))
+        .collect();
+    report.unsaved.sort();
+
+    print_report(&report);
+    Ok(report)
+}
+
+fn print_report(report: &VerifyReport) {
+    println!("{} {}", "In sync:".green(), report.in_sync.len());
+    print_bucket("Modified on disk:".yellow(), &report.modified);
+    print_bucket("Missing on disk:".red(), &report.missing);
+    print_bucket("Not saved:".cyan(), &report.unsaved);
+}
+
+fn print_bucket(title: ColoredString, paths: &[String]) {
+    println!("{} {}", title, paths.len());
+    for path in paths {
+        println!("  {}", path);
+    }
+}
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 /// Simple struct for YAML front matter.
@@ -47,8 +48,11 @@ fn infer_language_from_extension(ext: &str) -> Option<&'static str> {
     }
 }
 
-/// Attempt to parse the front matter of a Markdown file,
-/// returning Some(MarkdownMeta) if successful, else None.
+/// Keys of `MarkdownMeta`, in the order they are written to front matter.
+pub const FRONT_MATTER_KEYS: [&str; 3] = ["output_filename", "brief", "details"];
+
+/// Returns the range of YAML lines between the `---` delimiters at the top of a
+/// Markdown file, or `None` if the file has no (closed) front matter.
 ///
 /// We assume front matter is delimited by:
 ///
@@ -59,40 +63,33 @@ fn infer_language_from_extension(ext: &str) -> Option<&'static str> {
 /// ```
 ///
 /// at the top of the file.
-fn parse_markdown_front_matter(file_path: &Path) -> io::Result<Option<MarkdownMeta>> {
-    let f = File::open(file_path)?;
-    let mut reader = BufReader::new(f);
-
-    let mut first_line = String::new();
-    // Read the first line; if it's not "---", no front matter.
-    if reader.read_line(&mut first_line)? == 0 {
-        return Ok(None);
-    }
-    if !first_line.trim().eq("---") {
-        return Ok(None);
+pub fn front_matter_range(lines: &[String]) -> Option<Range<usize>> {
+    if lines.first()?.trim() != "---" {
+        return None;
     }
+    let end = lines[1..].iter().position(|l| l.trim() == "---")? + 1;
+    Some(1..end)
+}
 
-    // Accumulate lines until we find another "---".
-    let mut yaml_lines = Vec::new();
-    loop {
-        let mut line = String::new();
-        let bytes_read = reader.read_line(&mut line)?;
-        if bytes_read == 0 {
-            // No closing "---"; no valid front matter.
-            return Ok(None);
-        }
-        if line.trim().eq("---") {
-            // Reached the end of front matter.
-            break;
-        }
-        yaml_lines.push(line);
-    }
+/// Parses lila front matter; this is the single definition of what `weave` accepts.
+pub fn parse_front_matter_yaml(yaml: &str) -> Result<MarkdownMeta, serde_yaml::Error> {
+    serde_yaml::from_str::<MarkdownMeta>(yaml)
+}
 
-    // Join those lines into a single YAML string.
-    let yaml_string = yaml_lines.join("");
+/// Attempt to parse the front matter of a Markdown file,
+/// returning Some(MarkdownMeta) if successful, else None.
+fn parse_markdown_front_matter(file_path: &Path) -> io::Result<Option<MarkdownMeta>> {
+    let lines = BufReader::new(File::open(file_path)?)
+        .lines()
+        .collect::<io::Result<Vec<String>>>()?;
+
+    let Some(range) = front_matter_range(&lines) else {
+        return Ok(None);
+    };
+    let yaml_string = lines[range].join("\n");
 
     // Try parsing as MarkdownMeta
-    match serde_yaml::from_str::<MarkdownMeta>(&yaml_string) {
+    match parse_front_matter_yaml(&yaml_string) {
         Ok(meta) => Ok(Some(meta)),
         Err(_) => Ok(None),
     }
@@ -1,10 +1,9 @@
 use clap::Parser;
 use colored::Colorize;
-use dirs::home_dir;
+use diesel::sqlite::SqliteConnection;
 use std::env;
-use std::ffi::OsStr;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
 mod commands;
@@ -13,35 +12,115 @@ mod server;
 mod utils;
 
 use commands::bookbinding;
-use commands::edit::{edit_format_code_in_folder, edit_format_code_in_markdown};
+use commands::chat::{ChatOptions, ChatOutput};
+use commands::edit::report::EXIT_FAILED;
+use commands::edit::{
+    edit_format_code_in_folder, edit_format_code_in_markdown, watch_folder, EditOptions,
+};
+use commands::init::InitOptions;
 use commands::prepare::prepare_readme_in_folder;
+use commands::save::SaveOptions;
+use commands::search::SearchOptions;
 use commands::tangle::{extract_code_from_folder, extract_code_from_markdown};
 use commands::weave::{convert_file_to_markdown, convert_folder_to_markdown, copy_dir_all};
-use commands::{Args, Commands};
+use commands::{
+    Args, BackupArgs, Commands, ConfigCommand, DbCommand, ProjectArgs, SaveArgs, TagCommand,
+};
+use server::config::{ServerConfig, ServerFlags};
+use server::daemon::DEFAULT_IDLE_MINUTES;
+use server::generation::GenerationParams;
+use server::hub;
 use server::start as server_start;
-use utils::database::db;
-use utils::utils::process_protocol_aimm;
+use utils::backup::{self, Backup};
+use utils::config::Config;
+use utils::database::assets;
+use utils::database::db::{self, MigrationError};
+use utils::database::pool::open_pool;
+use utils::database::projects::{self, ProjectScope};
+use utils::utils::{current_project_name, db_path, default_root, process_protocol_aimm};
 
 fn main() {
     // Parse CLI args and load .env
     let args = Args::parse();
     dotenvy::dotenv().ok();
+    let loaded = Config::load();
+    let default_root = default_root();
 
-    let default_root = get_default_root();
-    let db_path = default_root.join("lila.db");
+    // The doctor reports what the steps below would stop at, so it runs before them.
+    if let Commands::Doctor = args.command {
+        let config = loaded.map(Config::install);
+        if config.is_err() {
+            Config::install(Config::default());
+        }
+        let healthy = commands::doctor::doctor(config, &default_root);
+        std::process::exit(if healthy { 0 } else { 1 });
+    }
+
+    let config = match loaded {
+        Ok(config) => Config::install(config),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Ensure the directory exists.
     fs::create_dir_all(&default_root)
         .unwrap_or_else(|_| panic!("Could not create directory {:?}", default_root));
 
     // Establish DB connection and run migrations.
-    let db_url = db_path.to_string_lossy().to_string();
-    let mut conn = db::establish_connection(&db_url);
-    db::run_migrations(&mut conn);
+    let db_url = db_path(None).to_string_lossy().to_string();
+    let mut conn = match db::establish_connection(&db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Error connecting to {}: {}", db_url, e);
+            std::process::exit(1);
+        }
+    };
+    migrate_or_exit(&mut conn, &db_url);
+
+    // A database given with --db is checked the same way, unless it doesn't exist yet.
+    if let Some(other) = args.command.db().filter(|path| Path::new(path).exists()) {
+        match db::establish_connection(other) {
+            Ok(mut conn) => migrate_or_exit(&mut conn, other),
+            Err(e) => {
+                eprintln!("Error connecting to {}: {}", other, e);
+                std::process::exit(1);
+            }
+        }
+    }
 
     // Dispatch command.
     match args.command {
-        Commands::Init => handle_init(),
+        Commands::Init {
+            yes,
+            output_path,
+            model,
+            context,
+            deployment,
+            languages,
+            no_compliance,
+            iso,
+            bsi,
+            no_toml,
+            force_new,
+            no_gitignore,
+            skip_benchmark,
+        } => handle_init(InitOptions {
+            yes,
+            output_path,
+            model,
+            context,
+            deployment,
+            languages,
+            no_compliance,
+            iso,
+            bsi,
+            no_toml,
+            force_new,
+            no_gitignore,
+            skip_benchmark,
+        }),
         Commands::Tangle {
             file,
             folder,
@@ -52,45 +131,300 @@ fn main() {
             file,
             folder,
             output,
-        } => handle_weave(file, folder, output, &default_root),
-        Commands::Edit { file, folder } => handle_edit(file, folder),
-        Commands::Save { db, input } => handle_save(db, &default_root, input),
+            save,
+            db,
+        } => handle_weave(file, folder, output, save, db, &default_root),
+        Commands::Edit {
+            file,
+            folder,
+            require_formatters,
+            jobs,
+            verbose,
+            lang,
+            front_matter,
+            annotate_failures,
+            watch,
+            check,
+            report,
+            report_file,
+            backup,
+        } => handle_edit(
+            file,
+            folder,
+            watch,
+            EditOptions {
+                config,
+                require_formatters,
+                jobs,
+                verbose,
+                check,
+                languages: lang,
+                front_matter,
+                annotate_failures,
+                backup: backup_target(&backup, &default_root),
+                report,
+                report_file,
+            },
+        ),
+        Commands::Save(args) => handle_save(args, &default_root),
+        Commands::Index {
+            query,
+            top,
+            force,
+            project,
+            db,
+        } => handle_index(query, top, force, project_scope(project), db, &default_root),
+        Commands::Verify {
+            db,
+            input,
+            project,
+            warn_only,
+        } => handle_verify(
+            db,
+            input,
+            ProjectScope::project_or_current(project),
+            warn_only,
+            &default_root,
+        ),
+        Commands::History { file, db } => handle_history(&file, None, db, &default_root),
+        Commands::Diff { file, rev, db } => handle_history(&file, Some(rev), db, &default_root),
+        Commands::List {
+            filter,
+            tags,
+            sort,
+            json,
+            project,
+            db,
+        } => handle_list(filter, &tags, sort, json, project_scope(project), db),
+        Commands::Stats { json, project, db } => {
+            handle_stats(json, project_scope(project), db, config)
+        }
+        Commands::Tag { command } => handle_tag(command),
+        Commands::Show {
+            file,
+            html,
+            asset,
+            db,
+        } => handle_show(&file, html, asset, db),
+        Commands::Search {
+            query,
+            limit,
+            json,
+            path,
+            tags,
+            project,
+            db,
+        } => handle_search(
+            &query,
+            SearchOptions {
+                limit,
+                json,
+                path_prefix: path,
+                tags,
+                project: project_scope(project),
+            },
+            db,
+        ),
+        Commands::Doctor => unreachable!("the doctor runs before the database is opened"),
+        Commands::Config { command } => handle_config(command, config),
+        Commands::Db { command } => handle_db(command, &default_root),
         Commands::Rm { all, output } => handle_rm(all, output, &default_root),
-        Commands::Server => {
-            let rt = tokio::runtime::Builder::new_multi_thread()
-                .worker_threads(4)
-                .enable_all()
-                .build()
-                .expect("Failed to create Tokio runtime");
-            rt.block_on(async {
-                if let Err(e) = server_start::start_server().await {
+        Commands::Server {
+            bind,
+            socket,
+            tls_cert,
+            tls_key,
+            model_path,
+            isq,
+            offline,
+        } => {
+            if offline {
+                hub::go_offline();
+            }
+            let rt = runtime();
+            let db_path = db_path(None);
+            let flags = ServerFlags {
+                bind,
+                socket,
+                tls_cert,
+                tls_key,
+                model_path,
+                isq,
+            };
+            let served = match ServerConfig::load(config, flags) {
+                Ok(config) => rt.block_on(server_start::start_server(
+                    db_path,
+                    models_dir(&default_root),
+                    config,
+                )),
+                Err(e) => Err(io::Error::other(e)),
+            };
+            // Don't wait for chat answers that were cut off and are still generating.
+            rt.shutdown_background();
+            let code = match served {
+                Ok(()) => {
+                    println!("Server stopped");
+                    0
+                }
+                Err(e) => {
                     eprintln!("Server failed: {}", e);
+                    1
                 }
-            });
-            return;
+            };
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            std::process::exit(code);
+        }
+        Commands::Chat {
+            prompt,
+            root,
+            files,
+            model,
+            model_path,
+            isq,
+            offline,
+            no_db,
+            db,
+            scope,
+            grep,
+            grep_any,
+            context_tokens,
+            temperature,
+            top_p,
+            max_tokens,
+            repeat_penalty,
+            seed,
+            system,
+            verbose,
+            quiet,
+            output,
+            interactive,
+            edit,
+            block,
+            yes,
+            count_only,
+            daemon,
+            idle_minutes,
+            no_daemon,
+        } => {
+            if offline {
+                hub::go_offline();
+            }
+            let options = ChatOptions {
+                prompt,
+                root: root.unwrap_or_else(|| PathBuf::from(".")),
+                files,
+                model: model.or(model_path.map(|path| path.to_string_lossy().into_owned())),
+                isq,
+                generation: GenerationParams {
+                    temperature,
+                    top_p,
+                    max_tokens,
+                    stop: Vec::new(),
+                    repeat_penalty,
+                    seed,
+                },
+                system,
+                db_path: (!no_db).then(|| db_path(db.as_deref())),
+                grep,
+                grep_any,
+                scope,
+                model_dir: models_dir(&default_root),
+                context_tokens: context_tokens.map(|tokens| tokens as usize),
+                verbose,
+                quiet,
+                output,
+                interactive,
+                use_daemon: !no_daemon,
+                history_file: default_root
+                    .parent()
+                    .unwrap_or(&default_root)
+                    .join("chat_history"),
+                edit,
+                block,
+                yes,
+                count_only,
+            };
+            if let Some(action) = daemon {
+                let idle_minutes = idle_minutes.unwrap_or(DEFAULT_IDLE_MINUTES);
+                let ran = commands::chat::run_daemon(action, options, idle_minutes, config);
+                if let Err(e) = runtime().block_on(ran) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            if let Err(e) = runtime().block_on(commands::chat::run_chat(options, config)) {
+                match output {
+                    ChatOutput::Text => eprintln!("Error: {}", e),
+                    ChatOutput::Json => {
+                        println!("{}", serde_json::json!({ "error": { "message": e } }))
+                    }
+                }
+                std::process::exit(1);
+            }
+        }
+        Commands::Prepare { folder, backup } => {
+            handle_prepare(folder, backup_target(&backup, &default_root))
         }
-        Commands::Prepare { folder } => handle_prepare(folder),
-        Commands::Bookbinding { folder, output } => handle_bookbinding(&folder, &output),
+        Commands::Bookbinding {
+            folder,
+            output,
+            backup,
+        } => handle_bookbinding(&folder, &output, backup_target(&backup, &default_root)),
+        Commands::Restore {
+            output,
+            rev,
+            force,
+            project,
+            db,
+        } => handle_restore(
+            Path::new(&output),
+            rev,
+            force,
+            ProjectScope::project_or_current(project),
+            db,
+        ),
+        Commands::RestoreBackup { folder, timestamp } => {
+            handle_restore_backup(folder, timestamp, &default_root)
+        }
+    }
+}
+
+/// The runtime the server and chat run on.
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .expect("Failed to create Tokio runtime")
+}
+
+/// Turns the `--project`/`--all-projects` flags into the projects to work on.
+fn project_scope(args: ProjectArgs) -> ProjectScope {
+    if args.all_projects {
+        ProjectScope::All
+    } else {
+        ProjectScope::project_or_current(args.project)
     }
 }
 
-/// Returns the default project root as `<HOME>/.lila/<current_directory>`.
-fn get_default_root() -> PathBuf {
-    let home = home_dir().expect("Could not determine the home directory");
-    let lila_root = home.join(".lila");
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-    let project_name = current_dir
-        .file_name()
-        .unwrap_or_else(|| OsStr::new("default"))
-        .to_string_lossy()
-        .to_string();
-    lila_root.join(&project_name)
+/// Turns the `--backup`/`--backup-dir` flags into a backup location.
+fn backup_target(args: &BackupArgs, default_root: &Path) -> Option<Backup> {
+    if args.backup_dir {
+        Some(Backup::timestamped(&default_root.join("backups")))
+    } else if args.backup {
+        Some(Backup::Beside)
+    } else {
+        None
+    }
 }
 
 /// Initializes the lila environment.
-fn handle_init() {
-    if let Err(e) = commands::init::init() {
+fn handle_init(options: InitOptions) {
+    if let Err(e) = commands::init::init(options) {
         eprintln!("Error during init: {}", e);
+        std::process::exit(1);
     }
 }
 
@@ -161,10 +495,15 @@ fn handle_tangle(
 
 /// Handles the Weave command: converts source code back into Markdown
 /// (without inlining placeholders) and writes out a list of generated files.
+///
+/// With `save`, the generated files are then saved to the DB directly; a failing save
+/// leaves the written Markdown in place.
 fn handle_weave(
     file: Option<String>,
     folder: Option<String>,
     output: Option<String>,
+    save: bool,
+    db: Option<String>,
     default_root: &Path,
 ) {
     // For the weave command, we now simply convert files without creating a book.
@@ -224,12 +563,35 @@ fn handle_weave(
         "✔".green(),
         created_files_list_path.display()
     );
+
+    if save {
+        let db_path = db_path(db.as_deref());
+        let files: Vec<String> = all_markdown_paths
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        let result = open_pool(&db_path.to_string_lossy())
+            .and_then(|pool| pool.get().map_err(|e| e.to_string()))
+            .and_then(|mut conn| save_markdown_files(&mut conn, &files, &root_folder, None, false));
+        match result {
+            Ok(()) => println!("Successfully saved md files to {}", db_path.display()),
+            Err(e) => {
+                eprintln!(
+                    "Error saving to {}: {} (the woven Markdown in {} is kept)",
+                    db_path.display(),
+                    e,
+                    root_folder.display()
+                );
+                std::process::exit(1);
+            }
+        }
+    }
 }
 
 /// Handles the Prepare command.
-fn handle_prepare(folder: String) {
+fn handle_prepare(folder: String, backup: Option<Backup>) {
     let folder_path = PathBuf::from(folder);
-    match prepare_readme_in_folder(&folder_path) {
+    match prepare_readme_in_folder(&folder_path, backup.as_ref()) {
         Ok(()) => println!(
             "Successfully updated README.md files in {}",
             folder_path.display()
@@ -239,35 +601,352 @@ fn handle_prepare(folder: String) {
 }
 
 /// Handles the Bookbinding command.
-fn handle_bookbinding(input_folder: &str, output_folder: &str) {
-    if let Err(e) = bookbinding::process_bookbinding(input_folder, output_folder) {
+fn handle_bookbinding(input_folder: &str, output_folder: &str, backup: Option<Backup>) {
+    if let Err(e) = bookbinding::process_bookbinding(input_folder, output_folder, backup.as_ref()) {
         eprintln!("Error during book binding: {}", e);
     }
 }
 
-/// Auto-formats code blocks in a Markdown file or folder.
-fn handle_edit(file: Option<String>, folder: Option<String>) {
-    if let Some(file) = file {
-        if let Err(e) = edit_format_code_in_markdown(&file) {
-            eprintln!("Error auto-formatting file {}: {}", file, e);
+/// Writes the saved files back to `output`; exits with 1 if some were left alone because
+/// they differ.
+fn handle_restore(
+    output: &Path,
+    rev: Option<i32>,
+    force: bool,
+    project: ProjectScope,
+    db: Option<String>,
+) {
+    let db_path = db_path(db.as_deref());
+    match commands::restore::restore_saved_files(&db_path, output, &project, rev, force) {
+        Ok(report) if report.conflicts.is_empty() => {}
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Error restoring from {}: {}", db_path.display(), e);
+            std::process::exit(1);
         }
-    } else if let Some(folder) = folder {
-        if let Err(e) = edit_format_code_in_folder(&folder) {
-            eprintln!("Error auto-formatting folder {}: {}", folder, e);
+    }
+}
+
+/// Copies backups back over the files they were taken from.
+fn handle_restore_backup(folder: Option<String>, timestamp: Option<String>, default_root: &Path) {
+    let result = if let Some(folder) = folder {
+        backup::restore_beside(Path::new(&folder))
+    } else {
+        let backups_root = default_root.join("backups");
+        let backup_dir = match timestamp {
+            Some(timestamp) => Some(backups_root.join(timestamp)).filter(|dir| dir.is_dir()),
+            None => backup::latest_backup(&backups_root).unwrap_or(None),
+        };
+        let Some(backup_dir) = backup_dir else {
+            eprintln!("No backup found in {}", backups_root.display());
+            std::process::exit(1);
+        };
+        println!("Restoring backup {}", backup_dir.display());
+        backup::restore_dir(&backup_dir)
+    };
+
+    match result {
+        Ok(restored) if restored.is_empty() => println!("No backups to restore."),
+        Ok(restored) => {
+            for path in &restored {
+                println!("{} Restored {}", "✔".green(), path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("Error restoring backups: {}", e);
+            std::process::exit(1);
         }
+    }
+}
+
+/// Auto-formats code blocks in a Markdown file or folder.
+///
+/// Exits with 0 if nothing changed, 1 if files were (or, with `--check`, would be)
+/// reformatted and 2 if a formatter or file failed.
+fn handle_edit(file: Option<String>, folder: Option<String>, watch: bool, options: EditOptions) {
+    if let (true, Some(folder)) = (watch, &folder) {
+        if let Err(e) = watch_folder(folder, &options) {
+            eprintln!("Error watching folder {}: {}", folder, e);
+            std::process::exit(EXIT_FAILED);
+        }
+        return;
+    }
+
+    let result = if let Some(file) = &file {
+        edit_format_code_in_markdown(file, &options)
+            .map_err(|e| eprintln!("Error auto-formatting file {}: {}", file, e))
+    } else if let Some(folder) = &folder {
+        edit_format_code_in_folder(folder, &options)
+            .map_err(|e| eprintln!("Error auto-formatting folder {}: {}", folder, e))
     } else {
         eprintln!("No file or folder provided for auto-formatting.");
+        return;
+    };
+
+    match result {
+        Ok(report) => std::process::exit(report.exit_code()),
+        Err(()) => std::process::exit(EXIT_FAILED),
     }
 }
 
-/// Saves Markdown file metadata to the DB.
-fn handle_save(db: Option<String>, default_root: &Path, input: Option<String>) {
-    let db_path = db
-        .as_ref()
+/// Searches the saved Markdown files.
+fn handle_search(query: &str, options: SearchOptions, db: Option<String>) {
+    let db_path = db_path(db.as_deref());
+    if let Err(e) = commands::search::search_saved_files(&db_path, query, &options) {
+        eprintln!("Error searching {}: {}", db_path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Lists the saved versions of `file`, or diffs revision `rev` against its current content.
+fn handle_history(file: &str, rev: Option<i32>, db: Option<String>, default_root: &Path) {
+    let db_path = db_path(db.as_deref());
+    let doc_root = default_root.join("doc");
+    let result = match rev {
+        Some(rev) => commands::history::diff_revision(&db_path, file, rev, &doc_root),
+        None => commands::history::list_history(&db_path, file, &doc_root),
+    };
+    if let Err(e) = result {
+        eprintln!("Error reading history from {}: {}", db_path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Exports the DB to a JSON bundle or imports one.
+fn handle_db(command: DbCommand, default_root: &Path) {
+    let db_path = |db: Option<String>| db_path(db.as_deref());
+    let result = match command {
+        DbCommand::Export { output, db } => {
+            commands::bundle::export_bundle(&db_path(db), output.as_deref().map(Path::new))
+        }
+        DbCommand::Import {
+            input,
+            on_conflict,
+            db,
+        } => commands::bundle::import_bundle(&db_path(db), Path::new(&input), on_conflict),
+        DbCommand::MigratePaths { prefix, db } => commands::migrate_paths::migrate_paths(
+            &db_path(db),
+            &default_root.join("doc"),
+            &default_root.join("html"),
+            prefix.as_deref(),
+        ),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Embeds the saved Markdown, or with `query`, prints the `top` chunks closest to it.
+fn handle_index(
+    query: Option<String>,
+    top: usize,
+    force: bool,
+    project: ProjectScope,
+    db: Option<String>,
+    default_root: &Path,
+) {
+    let db_path = db_path(db.as_deref());
+    let model_dir = models_dir(default_root);
+    let result = match query {
+        Some(query) => {
+            commands::index::print_retrieved(&db_path, &model_dir, &project, &query, top)
+        }
+        None => commands::index::index_saved_files(&db_path, &model_dir, &project, force),
+    };
+    if let Err(e) = result {
+        eprintln!("Error indexing {}: {}", db_path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Where embedding models are cached: shared by all projects, next to their folders in
+/// ~/.lila.
+fn models_dir(default_root: &Path) -> PathBuf {
+    default_root.parent().unwrap_or(default_root).join("models")
+}
+
+/// Checks the files saved in the DB against the doc folder.
+fn handle_verify(
+    db: Option<String>,
+    input: Option<String>,
+    project: ProjectScope,
+    warn_only: bool,
+    default_root: &Path,
+) {
+    let db_path = db_path(db.as_deref());
+    let doc_root = input
         .map(PathBuf::from)
-        .unwrap_or_else(|| default_root.join("lila.db"));
+        .unwrap_or_else(|| default_root.join("doc"));
+    match commands::verify::verify_saved_files(&db_path, &doc_root, &project) {
+        Ok(report) if report.is_in_sync() || warn_only => {}
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Error verifying {}: {}", db_path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Lists the files saved in the DB.
+fn handle_list(
+    filter: Option<String>,
+    tags: &[String],
+    sort: commands::list::SortKey,
+    json: bool,
+    project: ProjectScope,
+    db: Option<String>,
+) {
+    let db_path = db_path(db.as_deref());
+    if let Err(e) =
+        commands::list::list_saved_files(&db_path, filter.as_deref(), tags, sort, json, &project)
+    {
+        eprintln!("Error listing {}: {}", db_path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Prints statistics about the saved files and the database.
+fn handle_stats(json: bool, project: ProjectScope, db: Option<String>, config: &Config) {
+    let db_path = db_path(db.as_deref());
+    if let Err(e) = commands::stats::print_stats(&db_path, &project, json, config) {
+        eprintln!("Error reading statistics of {}: {}", db_path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Prints or changes a setting of `config`, or lists them all.
+fn handle_config(command: ConfigCommand, config: &Config) {
+    let result = match command {
+        ConfigCommand::Get { key } => commands::config::get(config, &key),
+        ConfigCommand::Set { key, value } => commands::config::set(config, &key, &value),
+        ConfigCommand::List => commands::config::list(config),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Adds or removes tags of a saved file.
+fn handle_tag(command: TagCommand) {
+    let db_path = |db: Option<String>| db_path(db.as_deref());
+    let result = match command {
+        TagCommand::Add { file, tags, db } => commands::tag::add_tags(&db_path(db), &file, &tags),
+        TagCommand::Remove { file, tags, db } => {
+            commands::tag::remove_tags(&db_path(db), &file, &tags)
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Prints the stored content of a saved file.
+fn handle_show(file: &str, html: bool, asset: bool, db: Option<String>) {
+    let db_path = db_path(db.as_deref());
+    let result = if asset {
+        commands::show::show_saved_asset(&db_path, file)
+    } else {
+        commands::show::show_saved_file(&db_path, file, html)
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the pending migrations on the database at `db_url`, exiting with
+/// `EXIT_NEWER_SCHEMA` if a newer lila created it, and with 1 on any other failure.
+fn migrate_or_exit(conn: &mut SqliteConnection, db_url: &str) {
+    match db::run_migrations(conn) {
+        Ok(()) => {}
+        Err(e @ MigrationError::NewerSchema { .. }) => {
+            eprintln!("Error opening database {}: {}", db_url, e);
+            std::process::exit(db::EXIT_NEWER_SCHEMA);
+        }
+        Err(e) => {
+            eprintln!("Error migrating database {}: {}", db_url, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Saves the Markdown `files` of the doc folder `doc_root` to `project`, or the current
+/// project, of the database `conn` is open on, which creates the project if it is new,
+/// with the history retention and image size limit from `Lila.toml`. With `quiet`,
+/// progress is printed as text instead of a bar.
+fn save_markdown_files(
+    conn: &mut SqliteConnection,
+    files: &[String],
+    doc_root: &Path,
+    project: Option<String>,
+    quiet: bool,
+) -> Result<(), String> {
+    let config = Config::current();
+    let max_versions = commands::history::load_max_versions(config).map_err(|e| e.to_string())?;
+    db::run_migrations(conn).map_err(|e| e.to_string())?;
+    let project = project.unwrap_or_else(current_project_name);
+    let project_id = projects::project_id(conn, &project).map_err(|e| e.to_string())?;
+    let options = SaveOptions {
+        max_versions,
+        max_asset_bytes: assets::max_asset_bytes(config)?,
+        quiet,
+    };
+    commands::save::save_files_to_db(files, doc_root, project_id, conn, &options)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// The existing files listed in `list`, one path per line, as absolute paths. Blank
+/// lines are ignored and missing files are reported and left out.
+fn read_file_list(list: impl std::io::BufRead) -> std::io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for line in list.lines() {
+        let line = line?;
+        let path = line.trim();
+        if path.is_empty() {
+            continue;
+        }
+        match fs::canonicalize(path) {
+            Ok(absolute) if absolute.is_file() => {
+                files.push(absolute.to_string_lossy().into_owned())
+            }
+            Ok(_) => eprintln!("{} {} is not a file; skipped", "Warning:".yellow(), path),
+            Err(e) => eprintln!("{} {}: {}; skipped", "Warning:".yellow(), path, e),
+        }
+    }
+    Ok(files)
+}
+
+/// Saves Markdown file metadata to the DB.
+fn handle_save(args: SaveArgs, default_root: &Path) {
+    let SaveArgs {
+        db,
+        input,
+        stdin,
+        html,
+        scan,
+        project,
+        quiet,
+    } = args;
+    let db_path = db_path(db.as_deref());
 
-    let mut conn = commands::save::establish_connection(&db_path.to_string_lossy());
+    let pool = match open_pool(&db_path.to_string_lossy()) {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Error connecting to {}: {}", db_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let mut conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Error connecting to {}: {}", db_path.display(), e);
+            std::process::exit(1);
+        }
+    };
 
     let doc_folder = input
         .as_ref()
@@ -276,25 +955,73 @@ fn handle_save(db: Option<String>, default_root: &Path, input: Option<String>) {
 
     let file_path = doc_folder.join("created_markdown_files.txt");
 
-    if !file_path.exists() {
-        eprintln!(
-            "Error: '{}' does not exist. Did you run the 'weave' step yet?",
-            file_path.display()
+    let files_to_save: Option<Vec<String>> = if stdin {
+        let files = match read_file_list(std::io::stdin().lock()) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("Error reading the file list from stdin: {e}");
+                std::process::exit(1);
+            }
+        };
+        if files.is_empty() {
+            println!("No files listed on stdin; nothing to save.");
+            return;
+        }
+        Some(files)
+    } else if file_path.exists() && !scan {
+        let created_files =
+            std::fs::read_to_string(&file_path).expect("Unable to read created_markdown_files.txt");
+        Some(created_files.lines().map(|s| s.to_owned()).collect())
+    } else if doc_folder.is_dir() {
+        let found = commands::save::collect_markdown_files(&doc_folder);
+        println!(
+            "Discovered {} Markdown files in {}",
+            found.len(),
+            doc_folder.display()
         );
-        std::process::exit(1);
-    }
-
-    let created_files =
-        std::fs::read_to_string(&file_path).expect("Unable to read created_markdown_files.txt");
-    let files_to_save: Vec<String> = created_files.lines().map(|s| s.to_owned()).collect();
+        Some(found)
+    } else {
+        None
+    };
 
-    if let Err(e) =
-        commands::save::save_files_to_db(&files_to_save, &mut conn, &db_path.to_string_lossy())
-    {
-        eprintln!("Error saving Markdown files to DB: {e}");
+    match files_to_save {
+        Some(files_to_save) => {
+            if let Err(e) =
+                save_markdown_files(&mut conn, &files_to_save, &doc_folder, project, quiet)
+            {
+                eprintln!("Error saving Markdown files to DB: {e}");
+                std::process::exit(1);
+            }
+            println!("Successfully saved md files to {}", db_path.display());
+        }
+        None if html.is_some() => println!(
+            "No '{}' found, saving only the HTML files.",
+            doc_folder.display()
+        ),
+        None => {
+            eprintln!(
+                "Error: neither '{}' nor the folder '{}' exists. Did you run the 'weave' step yet?",
+                file_path.display(),
+                doc_folder.display()
+            );
+            std::process::exit(1);
+        }
     }
 
-    println!("Successfully saved md files to {}", db_path.display());
+    if let Some(html_folder) = html {
+        let html_files = commands::save::collect_html_files(Path::new(&html_folder));
+        if html_files.is_empty() {
+            println!("No .html files found in {}", html_folder);
+            return;
+        }
+        if let Err(e) =
+            commands::save::save_html_files_to_db(&html_files, Path::new(&html_folder), &mut conn)
+        {
+            eprintln!("Error saving HTML files to DB: {e}");
+            std::process::exit(1);
+        }
+        println!("Successfully saved html files to {}", db_path.display());
+    }
 }
 
 /// Removes generated project files.
@@ -1,23 +1,135 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
-    file_content (rowid) {
-        rowid -> Integer,
+    assets (id) {
+        id -> Integer,
+        project_id -> Integer,
+        file_path -> Text,
+        mime_type -> Text,
+        content_hash -> Text,
+        content -> Binary,
+    }
+}
+
+diesel::table! {
+    blobs (hash) {
+        hash -> Text,
+        content -> Text,
+    }
+}
+
+diesel::table! {
+    chat_messages (id) {
+        id -> Integer,
+        session_id -> Text,
+        role -> Text,
+        content -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    chat_sessions (id) {
+        id -> Text,
+        created_at -> BigInt,
+        updated_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    content_history (id) {
+        id -> Integer,
+        metadata_id -> Integer,
+        content -> Text,
+        content_hash -> Text,
+        saved_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    embeddings (id) {
+        id -> Integer,
+        metadata_id -> Integer,
+        chunk_start -> Integer,
+        chunk_end -> Integer,
+        heading -> Nullable<Text>,
+        content_hash -> Text,
+        model -> Text,
+        vector -> Binary,
+    }
+}
+
+diesel::table! {
+    file_tags (metadata_id, tag_id) {
+        metadata_id -> Integer,
+        tag_id -> Integer,
+        source -> Text,
+    }
+}
+
+diesel::table! {
+    html_content (id) {
         id -> Integer,
         content -> Text,
     }
 }
 
+diesel::table! {
+    html_metadata (id) {
+        id -> Integer,
+        file_path -> Text,
+        content_hash -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     metadata (id) {
         id -> Integer,
         file_path -> Text,
+        content_hash -> Nullable<Text>,
+        language -> Nullable<Text>,
+        size_bytes -> Nullable<BigInt>,
+        modified_at -> Nullable<BigInt>,
+        title -> Nullable<Text>,
+        project_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    projects (id) {
+        id -> Integer,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Integer,
+        name -> Text,
     }
 }
 
-diesel::joinable!(file_content -> metadata (id));
+diesel::joinable!(assets -> projects (project_id));
+diesel::joinable!(chat_messages -> chat_sessions (session_id));
+diesel::joinable!(content_history -> metadata (metadata_id));
+diesel::joinable!(embeddings -> metadata (metadata_id));
+diesel::joinable!(file_tags -> metadata (metadata_id));
+diesel::joinable!(file_tags -> tags (tag_id));
+diesel::joinable!(html_content -> html_metadata (id));
+diesel::joinable!(metadata -> blobs (content_hash));
+diesel::joinable!(metadata -> projects (project_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
-    file_content,
+    assets,
+    blobs,
+    chat_messages,
+    chat_sessions,
+    content_history,
+    embeddings,
+    file_tags,
+    html_content,
+    html_metadata,
     metadata,
+    projects,
+    tags,
 );
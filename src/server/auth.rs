@@ -0,0 +1,95 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::server::chat::{ApiError, ServerError};
+use crate::utils::config::Config;
+
+/// Environment variable holding the token clients must send to the server.
+pub const SERVER_TOKEN_VAR: &str = "LILA_SERVER_TOKEN";
+
+/// Routes answered without a token, so a client can check the server is up.
+const PUBLIC_PATHS: &[&str] = &["/ping"];
+
+/// The bearer token the server requires; only its SHA-256 is kept, and it is kept out of
+/// `Debug` output.
+#[derive(Clone)]
+pub struct ServerToken([u8; 32]);
+
+impl fmt::Debug for ServerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ServerToken(..)")
+    }
+}
+
+impl ServerToken {
+    /// The token from `LILA_SERVER_TOKEN`, else the `token` in the `[server]` section of
+    /// `config` (a `Lila.toml`). `None` means the server is open.
+    pub fn load(config: &Config) -> Result<Option<ServerToken>, String> {
+        if let Ok(token) = std::env::var(SERVER_TOKEN_VAR) {
+            if !token.is_empty() {
+                return Ok(Some(ServerToken::new(&token)));
+            }
+        }
+        match &config.server.token {
+            None => Ok(None),
+            Some(token) if !token.is_empty() => Ok(Some(ServerToken::new(token))),
+            Some(_) => Err(format!(
+                "invalid {}: server.token must be a non-empty string",
+                config.path().display()
+            )),
+        }
+    }
+
+    fn new(token: &str) -> ServerToken {
+        ServerToken(Sha256::digest(token.as_bytes()).into())
+    }
+
+    /// Whether `candidate` is the token. Hashes are compared so that the time taken
+    /// depends on neither the token's content nor its length.
+    pub fn matches(&self, candidate: &str) -> bool {
+        let candidate: [u8; 32] = Sha256::digest(candidate.as_bytes()).into();
+        self.0
+            .iter()
+            .zip(candidate.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+/// Middleware answering 401 to requests without `Authorization: Bearer <token>` when the
+/// app has a `ServerToken`, except for `PUBLIC_PATHS`.
+pub async fn require_token<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let authorized = match req.app_data::<web::Data<Option<ServerToken>>>() {
+        Some(token) => match token.get_ref() {
+            Some(token) => {
+                PUBLIC_PATHS.contains(&req.path())
+                    || req
+                        .headers()
+                        .get(header::AUTHORIZATION)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.strip_prefix("Bearer "))
+                        .is_some_and(|candidate| token.matches(candidate.trim()))
+            }
+            None => true,
+        },
+        None => true,
+    };
+    if authorized {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let error = ServerError::Unauthorized(ApiError::new(
+        "unauthorized",
+        "missing or invalid bearer token",
+    ));
+    Ok(req
+        .into_response(error.error_response())
+        .map_into_right_body())
+}
@@ -0,0 +1,452 @@
+use clap::ValueEnum;
+use colored::Colorize;
+use mistralrs::{IsqType, Model, RequestBuilder, TextMessageRole};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::server::chat::{build_model, Answer, ApiError};
+use crate::server::daemon::DaemonClient;
+use crate::server::generation::GenerationParams;
+use crate::server::gguf::is_gguf_path;
+use crate::utils::config::Config;
+
+/// Environment variable choosing the backend, `local` or `openai`, over `[ai] backend`.
+pub const BACKEND_VAR: &str = "LILA_AI_BACKEND";
+/// Environment variable holding the API key unless `[ai] api_key_env` names another.
+pub const DEFAULT_API_KEY_VAR: &str = "OPENAI_API_KEY";
+/// Environment variable choosing the quantization of local models over `[ai] isq`.
+pub const ISQ_VAR: &str = "LILA_AI_ISQ";
+/// How long connecting to a remote backend may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the weights of a local model are quantized in place when it loads. GGUF files are
+/// quantized already and load as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Isq {
+    /// 4-bit, the smallest; fits a 3B model in 8 GB of RAM.
+    #[value(name = "q4k")]
+    Q4K,
+    /// 5-bit.
+    #[value(name = "q5k")]
+    Q5K,
+    /// 8-bit, close to the published weights.
+    #[default]
+    #[value(name = "q8_0")]
+    Q8_0,
+    /// Unquantized: the weights as published, the most accurate and the largest.
+    #[value(name = "none")]
+    None,
+}
+
+impl Isq {
+    /// The level named `name`, as `--isq` takes it.
+    pub fn parse(name: &str) -> Option<Isq> {
+        <Isq as ValueEnum>::from_str(name, true).ok()
+    }
+
+    /// The mistral.rs quantization, `None` to load the weights unquantized.
+    pub fn isq_type(self) -> Option<IsqType> {
+        match self {
+            Isq::Q4K => Some(IsqType::Q4K),
+            Isq::Q5K => Some(IsqType::Q5K),
+            Isq::Q8_0 => Some(IsqType::Q8_0),
+            Isq::None => None,
+        }
+    }
+
+    /// About how many bits a weight takes at this level, scales included.
+    pub fn bits_per_weight(self) -> f64 {
+        match self {
+            Isq::Q4K => 4.5,
+            Isq::Q5K => 5.5,
+            Isq::Q8_0 => 8.5,
+            Isq::None => 16.0,
+        }
+    }
+
+    /// The names of every level, as listed in errors.
+    fn accepted() -> String {
+        Isq::value_variants()
+            .iter()
+            .filter_map(|isq| isq.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for Isq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Where chat answers are generated, from `LILA_AI_BACKEND` and the `[ai]` section of a
+/// `Lila.toml`.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// mistral.rs, running the models in this process.
+    Local {
+        /// Quantization of the models loaded from the Hugging Face hub.
+        isq: Isq,
+    },
+    /// A server speaking the OpenAI chat completions API, such as vLLM.
+    OpenAi {
+        /// The API's root, like `http://gpu-box:8000/v1`.
+        base_url: String,
+        /// Environment variable holding the API key, sent as a bearer token if set.
+        api_key_env: String,
+    },
+}
+
+impl Backend {
+    /// The `backend`, `base_url`, `api_key_env` and `isq` in the `[ai]` section of
+    /// `config`, with `LILA_AI_BACKEND` and `LILA_AI_ISQ` taking precedence over `backend`
+    /// and `isq`. `base_url` is required for the `openai` backend.
+    pub fn load(config: &Config) -> Result<Backend, String> {
+        let from_env = std::env::var(BACKEND_VAR)
+            .ok()
+            .filter(|name| !name.is_empty());
+        let entry = |key: &str, value: &Option<String>| -> Result<Option<String>, String> {
+            match value {
+                None => Ok(None),
+                Some(text) if !text.is_empty() => Ok(Some(text.clone())),
+                Some(_) => Err(format!(
+                    "invalid {}: ai.{} must be a non-empty string",
+                    config.path().display(),
+                    key
+                )),
+            }
+        };
+        let ai = &config.ai;
+        let name = match from_env {
+            Some(name) => name,
+            None => entry("backend", &ai.backend)?.unwrap_or_else(|| "local".to_string()),
+        };
+        match name.as_str() {
+            "local" => {
+                let isq = match std::env::var(ISQ_VAR).ok().filter(|name| !name.is_empty()) {
+                    Some(name) => Isq::parse(&name).ok_or_else(|| {
+                        format!(
+                            "invalid {} {:?}; use one of {}",
+                            ISQ_VAR,
+                            name,
+                            Isq::accepted()
+                        )
+                    })?,
+                    None => {
+                        let invalid = || {
+                            format!(
+                                "invalid {}: ai.isq must be one of {}",
+                                config.path().display(),
+                                Isq::accepted()
+                            )
+                        };
+                        match entry("isq", &ai.isq).map_err(|_| invalid())? {
+                            Some(name) => Isq::parse(&name).ok_or_else(invalid)?,
+                            None => Isq::default(),
+                        }
+                    }
+                };
+                Ok(Backend::Local { isq })
+            }
+            "openai" => {
+                let base_url = entry("base_url", &ai.base_url)?
+                    .filter(|url| url.starts_with("http://") || url.starts_with("https://"))
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid {}: ai.base_url must be the URL of the OpenAI-compatible API, such as \"http://localhost:8000/v1\"",
+                            config.path().display()
+                        )
+                    })?;
+                Ok(Backend::OpenAi {
+                    base_url: base_url.trim_end_matches('/').to_string(),
+                    api_key_env: entry("api_key_env", &ai.api_key_env)?
+                        .unwrap_or_else(|| DEFAULT_API_KEY_VAR.to_string()),
+                })
+            }
+            other => Err(format!(
+                "unknown AI backend {:?} (in {} or ai.backend); use \"local\" or \"openai\"",
+                other, BACKEND_VAR
+            )),
+        }
+    }
+
+    /// Quantizes local models to `isq` instead, as `--isq` asks; remote models are
+    /// quantized by their server, so it is ignored with a warning for them.
+    pub fn override_isq(&mut self, isq: Isq) {
+        match self {
+            Backend::Local { isq: current } => *current = isq,
+            Backend::OpenAi { .. } => eprintln!(
+                "{} --isq only applies to the local backend; ignoring it",
+                "Warning:".yellow()
+            ),
+        }
+    }
+}
+
+/// Who says a message to the model.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// What the model is asked: the messages in order, and how to sample the answer.
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub messages: Vec<(Role, String)>,
+    pub generation: GenerationParams,
+}
+
+impl Prompt {
+    /// The mistral.rs request asking these messages.
+    pub fn request_builder(&self) -> RequestBuilder {
+        let mut request = RequestBuilder::new();
+        for (role, content) in &self.messages {
+            let role = match role {
+                Role::System => TextMessageRole::System,
+                Role::User => TextMessageRole::User,
+                Role::Assistant => TextMessageRole::Assistant,
+            };
+            request = request.add_message(role, content);
+        }
+        self.generation.apply(request)
+    }
+}
+
+/// A chat model ready to answer, in this process, behind a remote API, or in the chat
+/// daemon of `lila chat`.
+#[derive(Clone)]
+pub enum ChatModel {
+    Local(Arc<Model>),
+    Remote(Arc<RemoteModel>),
+    Daemon(Arc<DaemonClient>),
+}
+
+impl ChatModel {
+    /// Model `model_id` of `backend`: loaded, downloading it on first use, for the local
+    /// backend; only a client for a remote one.
+    pub async fn load(backend: &Backend, model_id: &str) -> Result<ChatModel, String> {
+        match backend {
+            Backend::Local { isq } => Ok(ChatModel::Local(Arc::new(
+                build_model(model_id, *isq).await?,
+            ))),
+            Backend::OpenAi { .. } if is_gguf_path(model_id) => Err(format!(
+                "{} is a GGUF file, which only the local backend can load",
+                model_id
+            )),
+            Backend::OpenAi {
+                base_url,
+                api_key_env,
+            } => Ok(ChatModel::Remote(Arc::new(RemoteModel::new(
+                base_url,
+                api_key_env,
+                model_id,
+            )?))),
+        }
+    }
+}
+
+/// A model served by an OpenAI-compatible API.
+pub struct RemoteModel {
+    model_id: String,
+    /// The chat completions endpoint.
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamEvent {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    usage: Option<StreamUsage>,
+    error: Option<StreamError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamDelta {
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamUsage {
+    completion_tokens: usize,
+    prompt_tokens: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamError {
+    message: String,
+}
+
+impl RemoteModel {
+    /// A client asking model `model_id` of the API at `base_url`, with the key in the
+    /// environment variable `api_key_env` if it is set.
+    fn new(base_url: &str, api_key_env: &str, model_id: &str) -> Result<RemoteModel, String> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .map_err(|e| format!("cannot create the HTTP client: {}", e))?;
+        Ok(RemoteModel {
+            model_id: model_id.to_string(),
+            url: format!("{}/chat/completions", base_url),
+            api_key: std::env::var(api_key_env)
+                .ok()
+                .filter(|key| !key.is_empty()),
+            client,
+        })
+    }
+
+    /// Streams the answer to `prompt`, as server-sent events, passing each piece of text
+    /// to `on_delta` as it comes, until the model is done or `stop` is set.
+    pub async fn generate(
+        &self,
+        prompt: Prompt,
+        stop: &AtomicBool,
+        mut on_delta: impl FnMut(&str),
+    ) -> Result<Answer, ApiError> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .json(&self.request_body(&prompt));
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let mut response = request.send().await.map_err(|e| {
+            eprintln!("Error during stream: {}", e);
+            ApiError::new(
+                "model_failed",
+                format!(
+                    "the model could not answer: cannot reach {}: {}",
+                    self.url, e
+                ),
+            )
+        })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            eprintln!("Error during stream: {} answered {}", self.url, status);
+            return Err(ApiError::new(
+                "model_failed",
+                format!(
+                    "the model could not answer: {} answered {}: {}",
+                    self.url,
+                    status,
+                    body.trim()
+                ),
+            ));
+        }
+
+        let mut answer = Answer {
+            text: String::new(),
+            tokens: None,
+            prompt_tokens: None,
+            stopped: false,
+        };
+        let failed = |error: String| {
+            eprintln!("Error during stream: {}", error);
+            ApiError::new(
+                "model_failed",
+                format!("the model failed while answering: {}", error),
+            )
+        };
+        // Events end with a line break; a chunk may end in the middle of one.
+        let mut pending: Vec<u8> = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| failed(e.to_string()))? {
+            if stop.load(Ordering::SeqCst) {
+                // Dropping the response closes the connection, which cancels the request.
+                answer.stopped = true;
+                break;
+            }
+            pending.extend_from_slice(&chunk);
+            while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    return Ok(answer);
+                }
+                let event: StreamEvent = serde_json::from_str(data)
+                    .map_err(|e| failed(format!("unexpected event {:?}: {}", data, e)))?;
+                if let Some(error) = event.error {
+                    return Err(failed(error.message));
+                }
+                if let Some(content) = event.choices.first().and_then(|c| c.delta.content.as_ref())
+                {
+                    on_delta(content);
+                    answer.text.push_str(content);
+                }
+                if let Some(usage) = event.usage {
+                    answer.tokens = Some(usage.completion_tokens);
+                    answer.prompt_tokens = usage.prompt_tokens;
+                }
+            }
+        }
+        Ok(answer)
+    }
+
+    /// The streaming chat completions request for `prompt`.
+    fn request_body(&self, prompt: &Prompt) -> Value {
+        let messages: Vec<Value> = prompt
+            .messages
+            .iter()
+            .map(|(role, content)| json!({"role": role.as_str(), "content": content}))
+            .collect();
+        let mut body = json!({
+            "model": self.model_id,
+            "messages": messages,
+            "stream": true,
+            "stream_options": {"include_usage": true},
+        });
+        let generation = &prompt.generation;
+        if let Some(temperature) = generation.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = generation.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = generation.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if !generation.stop.is_empty() {
+            body["stop"] = json!(generation.stop);
+        }
+        if let Some(penalty) = generation.repeat_penalty {
+            body["frequency_penalty"] = json!(penalty);
+        }
+        if let Some(seed) = generation.seed {
+            body["seed"] = json!(seed);
+        }
+        body
+    }
+}
@@ -1,184 +1,954 @@
-use actix_web::HttpResponse;
+//! Chat requests and their answers, and the errors every route of the server answers
+//! with, as `{"error": {"code": "...", "message": "..."}}` and the status of its kind:
+//!
+//! - 400 `invalid_request` (a body or query that cannot be read), `empty_prompt`,
+//!   `prompt_too_long`, `context_too_large`, `invalid_parameter`, `unknown_model` and
+//!   `invalid_query` (a `/search` query FTS5 cannot parse), and `missing_front_matter`
+//!   and `invalid_front_matter` for `/tangle`.
+//! - 401 `unauthorized`: the bearer token is missing or wrong.
+//! - 404 `unknown_session`, `unknown_file` and `unknown_asset`.
+//! - 413 `payload_too_large`, and `markdown_too_large` for `/render`.
+//! - 429 `rate_limited`, with `Retry-After`.
+//! - 500 `context_failed`, `model_failed` and `internal_error`.
+//! - 503 `model_unavailable` while the model loads or after it failed to, and
+//!   `server_busy`, with `Retry-After`.
+//!
+//! `/ws/chat` sends the same codes in `error` frames.
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
 use mistralrs::{
-    IsqType, PagedAttentionMetaBuilder, Response, TextMessageRole, TextMessages, TextModelBuilder,
+    GgufModelBuilder, Model, PagedAttentionConfig, PagedAttentionMetaBuilder, RequestBuilder,
+    Response, TextModelBuilder,
 };
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::{Duration, Instant};
 use tokio::task;
-use toml::Value as TomlValue;
+
+use crate::server::backend::{Backend, ChatModel, Isq, Prompt, Role};
+use crate::server::context::{
+    documents_context, source_spans, Retriever, Source, CONTEXT_HEADINGS, DEFAULT_CONTEXT_TOKENS,
+};
+use crate::server::generation::GenerationParams;
+use crate::server::gguf::{adjacent_tokenizer, check_gguf, gguf_folder, is_gguf_path};
+use crate::server::hub::{check_offline, explain_load_error};
+use crate::server::models::{context_length, token_counter};
+use crate::utils::config::Config;
+use crate::utils::database::documents::{document_content, document_ids_under, documents_matching};
+use crate::utils::database::pool::{open_pool, DbPool};
+use crate::utils::database::projects::ProjectScope;
+use crate::utils::database::tags::tagged_content;
+use crate::utils::tokens::TokenCounter;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatResponse {
     pub response: String,
+    /// The parts of the book retrieved as context, by the number they are cited with.
+    pub sources: Vec<Source>,
+    /// How the answer was sampled, for asking again the same way.
+    #[serde(default)]
+    pub generation: GenerationParams,
+    /// Session the prompt and the response were added to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Tokens of each part of the request, for a `verbose` one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<TokenBreakdown>,
+}
+
+/// What went wrong with a request, as clients see it.
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    /// What went wrong, for clients to match on, like `empty_prompt` or `model_failed`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// A request the server could not answer, by the status it is answered with.
+#[derive(Debug)]
+pub enum ServerError {
+    /// 400: the request cannot be read or asks for something invalid.
+    BadRequest(ApiError),
+    /// 401: the bearer token is missing or wrong.
+    Unauthorized(ApiError),
+    /// 404: what the request names does not exist.
+    NotFound(ApiError),
+    /// 413: the body is larger than the server reads.
+    PayloadTooLarge(ApiError),
+    /// 429: the client made too many requests, and may retry after these seconds.
+    RateLimited(ApiError, u64),
+    /// 503: no model can answer now; retry after these seconds, when known.
+    Unavailable(ApiError, Option<u64>),
+    /// 500: the server, the context or the model failed.
+    Internal(ApiError),
+}
+
+/// The body of error responses.
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a ApiError,
+}
+
+impl ServerError {
+    /// An `internal_error` saying `e`.
+    pub fn internal(e: impl std::fmt::Display) -> ServerError {
+        ServerError::Internal(ApiError::new("internal_error", e.to_string()))
+    }
+
+    pub fn error(&self) -> &ApiError {
+        match self {
+            ServerError::BadRequest(error)
+            | ServerError::Unauthorized(error)
+            | ServerError::NotFound(error)
+            | ServerError::PayloadTooLarge(error)
+            | ServerError::RateLimited(error, _)
+            | ServerError::Unavailable(error, _)
+            | ServerError::Internal(error) => error,
+        }
+    }
 }
 
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.error().code, self.error().message)
+    }
+}
+
+impl ResponseError for ServerError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ServerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ServerError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServerError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ServerError::RateLimited(..) => StatusCode::TOO_MANY_REQUESTS,
+            ServerError::Unavailable(..) => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        let retry_after = match self {
+            ServerError::RateLimited(_, seconds) => Some(*seconds),
+            ServerError::Unavailable(_, seconds) => *seconds,
+            _ => None,
+        };
+        if let Some(seconds) = retry_after {
+            response.insert_header((header::RETRY_AFTER, seconds));
+        }
+        if let ServerError::Unauthorized(_) = self {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(ErrorBody {
+            error: self.error(),
+        })
+    }
+}
+
+/// Where loading the chat model stands.
+pub enum ModelState {
+    Loading,
+    /// Loaded, in `load_time`.
+    Ready {
+        model: ChatModel,
+        load_time: Duration,
+    },
+    Failed(String),
+}
+
+/// The chat model, loaded once when the server starts and shared by every request.
+/// mistral.rs queues concurrent requests to a model itself, so no lock is held while a
+/// request runs.
+#[derive(Clone)]
+pub struct SharedModel {
+    pub model_id: String,
+    state: Arc<RwLock<ModelState>>,
+}
+
+impl SharedModel {
+    /// Starts loading `model_id` of `backend` on a thread of its own, so the server can
+    /// answer, and report that the model is loading, in the meantime.
+    pub fn load_in_background(model_id: String, backend: Backend) -> SharedModel {
+        let shared = SharedModel {
+            model_id,
+            state: Arc::new(RwLock::new(ModelState::Loading)),
+        };
+        let loader = shared.clone();
+        thread::spawn(move || {
+            let started = Instant::now();
+            let loaded = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| e.to_string())
+                .and_then(|rt| rt.block_on(ChatModel::load(&backend, &loader.model_id)));
+            let state = match loaded {
+                Ok(model) => {
+                    let load_time = started.elapsed();
+                    println!(
+                        "Model {} loaded in {:.1}s",
+                        loader.model_id,
+                        load_time.as_secs_f64()
+                    );
+                    ModelState::Ready { model, load_time }
+                }
+                Err(e) => {
+                    eprintln!("Error: could not load model {}: {}", loader.model_id, e);
+                    ModelState::Failed(e)
+                }
+            };
+            *loader.state.write().unwrap_or_else(|e| e.into_inner()) = state;
+        });
+        shared
+    }
+
+    /// Where loading the model stands.
+    pub fn state(&self) -> RwLockReadGuard<'_, ModelState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Drops the server's hold on the model, which is freed once the requests still using
+    /// it end.
+    pub fn release(&self) {
+        *self.state.write().unwrap_or_else(|e| e.into_inner()) =
+            ModelState::Failed("the server has stopped".to_string());
+    }
+
+    /// The model once it is loaded, else why it cannot answer yet.
+    pub fn get(&self) -> Result<ChatModel, String> {
+        match &*self.state() {
+            ModelState::Ready { model, .. } => Ok(model.clone()),
+            ModelState::Loading => Err(format!("model {} is still loading", self.model_id)),
+            ModelState::Failed(e) => Err(format!("model {} failed to load: {}", self.model_id, e)),
+        }
+    }
+}
+
+/// Downloads (on first use) and loads `model_id`, quantized in place to `isq`, or the GGUF
+/// file it names. Offline, only a model in the Hugging Face cache loads; the failures users
+/// can fix, like no network, are explained.
+pub async fn build_model(model_id: &str, isq: Isq) -> Result<Model, String> {
+    if is_gguf_path(model_id) {
+        return build_gguf_model(Path::new(model_id)).await;
+    }
+    check_offline(model_id)?;
+    let mut builder = TextModelBuilder::new(model_id);
+    if let Some(isq_type) = isq.isq_type() {
+        builder = builder.with_isq(isq_type);
+    }
+    builder
+        .with_logging()
+        .with_paged_attn(paged_attention)
+        .map_err(|e| format!("cannot create the model builder: {:#}", e))?
+        .build()
+        .await
+        .map_err(|e| explain_load_error(model_id, &format!("{:#}", e)))
+}
+
+/// How every model pages its attention cache.
+fn paged_attention() -> anyhow::Result<PagedAttentionConfig> {
+    PagedAttentionMetaBuilder::default().build()
+}
+
+/// Loads the GGUF file at `path`, quantized already, with the `tokenizer.json` next to it,
+/// else the tokenizer embedded in it. The file is checked first, so a wrong path fails
+/// before the long load does.
+async fn build_gguf_model(path: &Path) -> Result<Model, String> {
+    check_gguf(path)?;
+    let file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| format!("{} is not a file", path.display()))?;
+    let mut builder =
+        GgufModelBuilder::new(gguf_folder(path).to_string_lossy(), vec![file]).with_logging();
+    if let Some(folder) = adjacent_tokenizer(path) {
+        builder = builder.with_tok_model_id(folder.to_string_lossy());
+    }
+    builder
+        .with_paged_attn(paged_attention)
+        .map_err(|e| format!("cannot create the model builder: {:#}", e))?
+        .build()
+        .await
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// Who the model is told it is, unless `system_prompt` in `[ai_guidance]` says otherwise.
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are an AI agent with a specialty in programming.
+You do not provide information outside of this scope.
+If a question is not about programming, respond with, 'I can't assist you with that, sorry!'.";
+
 /// CLI arguments for the chat command.
 #[derive(Debug, Deserialize)]
 pub struct ChatArgs {
     pub prompt: Option<String>,
-    pub no_db: bool,
     pub file_content: Option<String>,
+    /// Database to load context from when `file_content` is missing or `use_retrieval`
+    /// is set.
+    pub db_path: Option<PathBuf>,
+    /// Pool of connections to `db_path`; one is opened for the request if not given.
+    #[serde(skip)]
+    pub pool: Option<DbPool>,
+    /// Only saved files carrying all of these tags are loaded as context.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Project the tagged files are loaded from; the current one if not given.
+    #[serde(default)]
+    pub project: Option<String>,
+    /// Load the tagged files of every project instead.
+    #[serde(default)]
+    pub all_projects: bool,
+    /// Add the parts of the book relevant to the prompt to the context, even with
+    /// `file_content` or `tags`. Without either, they are added anyway.
+    #[serde(default)]
+    pub use_retrieval: bool,
+    /// Use the saved files under `paths` as the context, whole while they fit in the
+    /// retriever's budget, instead of `tags` and retrieved parts.
+    #[serde(default)]
+    pub use_db: bool,
+    /// Path prefixes of the saved files `use_db` loads; all files of the project if empty.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// With `use_db`, only the saved files whose content matches all of these.
+    #[serde(skip)]
+    pub grep: Vec<Regex>,
+    /// Load the saved files matching any of `grep` instead.
+    #[serde(skip)]
+    pub grep_any: bool,
+    /// Finds the relevant parts; without it, nothing is retrieved.
+    #[serde(skip)]
+    pub retriever: Option<Retriever>,
+    /// How to sample the answer.
+    #[serde(default)]
+    pub generation: GenerationParams,
+    /// The whole system message, replacing the one built from `Lila.toml`.
+    #[serde(skip)]
+    pub system_prompt: Option<String>,
 }
 
-/// Runs the chat command and returns an HttpResponse with the AI response in JSON.
-pub async fn run_chat_response(args: ChatArgs) -> HttpResponse {
-    // We'll spawn a blocking task so we don't tie up the async threads.
-    let response_text = task::spawn_blocking(move || {
-        // Log the received prompt and file.
-        println!(
-            "Processing chat request: prompt = {:?}, file_content is {} bytes",
-            args.prompt,
-            args.file_content.as_ref().map_or(0, |c| c.len())
-        );
+/// A prompt and the answer it got earlier in a chat session.
+#[derive(Debug, Clone)]
+pub struct Turn {
+    pub prompt: String,
+    pub answer: String,
+}
 
-        // Build an inner runtime for blocking I/O
-        let rt_inner = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(4)
-            .enable_all()
-            .build()
-            .unwrap();
-
-        rt_inner.block_on(async {
-            // -------------------------------------------------------------
-            // 1. Get the "file_content" if provided.
-            // -------------------------------------------------------------
-            let context_content = match &args.file_content {
-                Some(s) => s.clone(),
-                None => String::new(),
-            };
+/// What `generate` got from the model.
+#[derive(Debug)]
+pub struct Answer {
+    pub text: String,
+    /// Tokens generated, when the model reports them.
+    pub tokens: Option<usize>,
+    /// Tokens of the prompt, when the model reports them.
+    pub prompt_tokens: Option<usize>,
+    /// Whether generation was stopped before the model was done.
+    pub stopped: bool,
+}
 
-            // -------------------------------------------------------------
-            // 2. Parse Lila.toml from the project root (optional).
-            // -------------------------------------------------------------
-            let lila_toml_path = "Lila.toml";
-            let mut project_info = String::from("No [project] info found.");
-            let mut development_info = String::from("No [development] info found.");
-            let mut dependencies_info = String::from("No [dependencies] info found.");
-            let mut compliance_info = String::from("No [compliance] info found.");
-            let mut code_of_conduct = String::from("No code_of_conduct found.");
-
-            if let Ok(lila_content) = fs::read_to_string(lila_toml_path) {
-                if let Ok(toml_value) = toml::from_str::<TomlValue>(&lila_content) {
-                    if let Some(val) = toml_value.get("project") {
-                        project_info = format!("{:#?}", val);
-                    }
-                    if let Some(val) = toml_value.get("development") {
-                        development_info = format!("{:#?}", val);
-                    }
-                    if let Some(val) = toml_value.get("dependencies") {
-                        dependencies_info = format!("{:#?}", val);
-                    }
-                    if let Some(val) = toml_value.get("compliance") {
-                        compliance_info = format!("{:#?}", val);
-                    }
-                    if let Some(ai_guidance) = toml_value.get("ai_guidance") {
-                        if let Some(coc) = ai_guidance.get("code_of_conduct") {
-                            if let Some(coc_str) = coc.as_str() {
-                                code_of_conduct = coc_str.to_string();
-                            }
-                        }
-                    }
-                }
+/// Runs the chat command with `model`, asking the prompt after the turns of `history`,
+/// and returns the AI response, or why the context or the model failed. Given the id of
+/// `model` as `count_for`, the response counts the tokens of each part of the request.
+pub async fn run_chat_response(
+    args: ChatArgs,
+    model: ChatModel,
+    history: Vec<Turn>,
+    count_for: Option<&str>,
+) -> Result<ChatResponse, ApiError> {
+    println!(
+        "Processing chat request: prompt = {:?}, file_content is {} bytes",
+        args.prompt,
+        args.file_content.as_ref().map_or(0, |c| c.len())
+    );
+    let started = Instant::now();
+    let generation = args.generation.clone();
+    let counting = count_for.map(|id| (token_counter(id), context_length(id)));
+    // Loading the context reads the database, and counting tokens takes a while too, so
+    // keep them off the async threads.
+    let (prompt, sources, tokens) = task::spawn_blocking(move || {
+        let conversation = assemble_conversation(&args, &history)?;
+        let tokens = counting.map(|(counter, length)| conversation.tokens(&counter, length));
+        let (prompt, sources) = conversation.into_prompt();
+        Ok((prompt, sources, tokens))
+    })
+    .await
+    .unwrap_or_else(|e| Err(task_failed(e)))?;
+    let answer = generate(&model, prompt, &AtomicBool::new(false), |_| {}).await?;
+    println!("Answered in {:.1}s", started.elapsed().as_secs_f64());
+    Ok(ChatResponse {
+        response: answer.text,
+        sources,
+        generation,
+        session_id: None,
+        tokens,
+    })
+}
+
+/// The error for a blocking chat task that panicked or was cancelled.
+pub fn task_failed(e: task::JoinError) -> ApiError {
+    ApiError::new("internal_error", format!("the chat task failed: {}", e))
+}
+
+/// The request asking the model the prompt of `args` after the turns of `history`, sampled
+/// as `args` says, and the sources retrieved for them. The context is read from the database, so this blocks.
+pub fn conversation(args: &ChatArgs, history: &[Turn]) -> Result<(Prompt, Vec<Source>), ApiError> {
+    Ok(assemble_conversation(args, history)?.into_prompt())
+}
+
+/// The parts of the request asking a prompt, before they are joined into messages, so
+/// their tokens can be counted and the context cut to fit.
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    /// The system message up to the `Lila.toml` sections, or the whole one given for the
+    /// chat.
+    pub system: String,
+    /// The `Lila.toml` sections appended to the system message, by name, as appended.
+    pub sections: Vec<(&'static str, String)>,
+    pub context: String,
+    /// The parts of the book in `context`, by the number they are cited with.
+    pub sources: Vec<Source>,
+    pub history: Vec<Turn>,
+    pub prompt: String,
+    pub generation: GenerationParams,
+}
+
+/// How the tokens of a request were counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Counting {
+    /// With the model's tokenizer.
+    Tokenizer,
+    /// From the length of the text, about four bytes a token.
+    Estimate,
+}
+
+/// Tokens of each part of a request, as `lila chat --count-only` prints them and verbose
+/// `/chat` responses include them. The parts add up to `total`; the few tokens marking
+/// where each message starts are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenBreakdown {
+    pub counted_with: Counting,
+    /// The system message before the `Lila.toml` sections.
+    pub system: usize,
+    /// Each `Lila.toml` section of the system message.
+    pub sections: Vec<SectionTokens>,
+    /// The context outside its parts of the book: its heading, and content given as is.
+    pub context: usize,
+    /// Each part of the book in the context.
+    pub documents: Vec<DocumentTokens>,
+    /// The earlier turns of the session.
+    pub history: usize,
+    pub prompt: usize,
+    pub total: usize,
+    /// The model's context window, when known.
+    pub context_length: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectionTokens {
+    pub name: String,
+    pub tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentTokens {
+    #[serde(flatten)]
+    pub source: Source,
+    pub tokens: usize,
+}
+
+impl Conversation {
+    /// The request asking the model, and the sources of its context.
+    pub fn into_prompt(self) -> (Prompt, Vec<Source>) {
+        let mut system = self.system;
+        for (_, section) in &self.sections {
+            system.push_str(section);
+        }
+        let mut messages = vec![(Role::System, system), (Role::System, self.context)];
+        for turn in self.history {
+            messages.push((Role::User, turn.prompt));
+            messages.push((Role::Assistant, turn.answer));
+        }
+        messages.push((Role::User, self.prompt));
+        let prompt = Prompt {
+            messages,
+            generation: self.generation,
+        };
+        (prompt, self.sources)
+    }
+
+    /// Tokens of each part, counted with `counter`, for a model whose context window is
+    /// `context_length` tokens.
+    pub fn tokens(&self, counter: &TokenCounter, context_length: Option<usize>) -> TokenBreakdown {
+        let sections: Vec<SectionTokens> = self
+            .sections
+            .iter()
+            .map(|(name, section)| SectionTokens {
+                name: name.to_string(),
+                tokens: counter.count(section),
+            })
+            .collect();
+        let spans = source_spans(&self.context, &self.sources);
+        let documents: Vec<DocumentTokens> = spans
+            .iter()
+            .zip(&self.sources)
+            .map(|(span, source)| DocumentTokens {
+                source: source.clone(),
+                tokens: counter.count(&self.context[span.clone()]),
+            })
+            .collect();
+        let outside = match spans.first() {
+            Some(first) => {
+                let last = spans.last().map_or(first.end, |span| span.end);
+                format!("{}{}", &self.context[..first.start], &self.context[last..])
             }
+            None => self.context.clone(),
+        };
+        let mut tokens = TokenBreakdown {
+            counted_with: match counter.exact() {
+                true => Counting::Tokenizer,
+                false => Counting::Estimate,
+            },
+            system: counter.count(&self.system),
+            context: counter.count(&outside),
+            history: self
+                .history
+                .iter()
+                .map(|turn| counter.count(&turn.prompt) + counter.count(&turn.answer))
+                .sum(),
+            prompt: counter.count(&self.prompt),
+            total: 0,
+            context_length,
+            sections,
+            documents,
+        };
+        tokens.total = tokens.system
+            + tokens.sections.iter().map(|s| s.tokens).sum::<usize>()
+            + tokens.context
+            + tokens.documents.iter().map(|d| d.tokens).sum::<usize>()
+            + tokens.history
+            + tokens.prompt;
+        tokens
+    }
 
-            // -------------------------------------------------------------
-            // 3. Extract prompt or bail if missing.
-            // -------------------------------------------------------------
-            let prompt = match &args.prompt {
-                Some(p) => p.clone(),
-                None => {
-                    return format!("No prompt provided");
-                }
+    /// Leaves the last parts of the book out of the context until the request is at most
+    /// `limit` tokens, as counted with `counter`. Returns those left out, in order, and
+    /// the tokens of the request once they are.
+    pub fn trim_to(
+        &mut self,
+        limit: usize,
+        counter: &TokenCounter,
+    ) -> (Vec<Source>, TokenBreakdown) {
+        let mut left_out = Vec::new();
+        loop {
+            let tokens = self.tokens(counter, Some(limit));
+            let spans = source_spans(&self.context, &self.sources);
+            let Some(last) = spans.last().filter(|_| tokens.total > limit) else {
+                left_out.reverse();
+                return (left_out, tokens);
             };
-
-            // -------------------------------------------------------------
-            // 4. Build/select your Mistral model.
-            // -------------------------------------------------------------
-            let model_id = std::env::var("LILA_AI_MODEL")
-                .unwrap_or_else(|_| "microsoft/Phi-3.5-mini-instruct".to_string());
-            println!("Using model={}", model_id);
-
-            let model = match TextModelBuilder::new(model_id)
-                .with_isq(IsqType::Q8_0)
-                .with_logging()
-                .with_paged_attn(|| PagedAttentionMetaBuilder::default().build())
-            {
-                Ok(builder) => match builder.build().await {
-                    Ok(m) => m,
-                    Err(e) => {
-                        println!("Error building model: {:?}", e);
-                        return format!("Error building model: {:?}", e);
+            self.context.truncate(last.start);
+            left_out.extend(self.sources.pop());
+            if self.sources.is_empty() {
+                // Without its parts, their heading goes too.
+                for heading in CONTEXT_HEADINGS {
+                    if let Some(rest) = self.context.strip_suffix(heading) {
+                        let kept = rest.trim_end().len();
+                        self.context.truncate(kept);
                     }
-                },
-                Err(e) => {
-                    println!("Error creating model builder: {:?}", e);
-                    return format!("Error creating model builder: {:?}", e);
                 }
-            };
+            }
+        }
+    }
+}
 
-            // -------------------------------------------------------------
-            // 5. Construct the system message + the context
-            // -------------------------------------------------------------
-            let mut system_msg = if !context_content.is_empty() {
-                "You are an AI agent with a specialty in programming.
-                 You do not provide information outside of this scope.
-                 If a question is not about programming, respond with, 'I can't assist you with that, sorry!'.
-                 Below is some Markdown file content. Use it to answer the user's question."
-                    .to_string()
-            } else {
-                "You are an AI agent with a specialty in programming.
-                 You do not provide information outside of this scope.
-                 If a question is not about programming, respond with, 'I can't assist you with that, sorry!'.
-                 No additional context was provided."
-                    .to_string()
+impl std::fmt::Display for TokenBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut rows = vec![("system prompt".to_string(), self.system)];
+        for section in &self.sections {
+            rows.push((format!("Lila.toml {}", section.name), section.tokens));
+        }
+        if self.context > 0 || self.documents.is_empty() {
+            rows.push(("context".to_string(), self.context));
+        }
+        for document in &self.documents {
+            let source = &document.source;
+            let name = match &source.heading {
+                Some(heading) => format!("[{}] {} > {}", source.number, source.path, heading),
+                None => format!("[{}] {}", source.number, source.path),
             };
+            rows.push((name, document.tokens));
+        }
+        if self.history > 0 {
+            rows.push(("history".to_string(), self.history));
+        }
+        rows.push(("prompt".to_string(), self.prompt));
+        rows.push(("total".to_string(), self.total));
+        let width = rows
+            .iter()
+            .map(|(name, _)| name.chars().count())
+            .max()
+            .unwrap_or(0);
+        match self.counted_with {
+            Counting::Tokenizer => writeln!(f, "Tokens, counted with the model's tokenizer:")?,
+            Counting::Estimate => writeln!(f, "Tokens, estimated at four bytes each:")?,
+        }
+        for (name, tokens) in &rows {
+            writeln!(f, "  {:<width$}  {:>7}", name, tokens, width = width)?;
+        }
+        match self.context_length {
+            Some(length) if self.total > length => write!(
+                f,
+                "{} more than the model's context window of {}",
+                self.total - length,
+                length
+            ),
+            Some(length) => write!(
+                f,
+                "{} left of the model's context window of {}",
+                length - self.total,
+                length
+            ),
+            None => write!(f, "The model's context window is unknown"),
+        }
+    }
+}
 
-            // Append Lila.toml sections
-            system_msg.push_str("\n---\n**Project**:\n");
-            system_msg.push_str(&project_info);
-            system_msg.push_str("\n\n**Development**:\n");
-            system_msg.push_str(&development_info);
-            system_msg.push_str("\n\n**Dependencies**:\n");
-            system_msg.push_str(&dependencies_info);
-            system_msg.push_str("\n\n**Compliance**:\n");
-            system_msg.push_str(&compliance_info);
-            system_msg.push_str("\n\n**AI Guidance Code of Conduct**:\n");
-            system_msg.push_str(&code_of_conduct);
-            system_msg.push_str("\n---\n");
-
-            // -------------------------------------------------------------
-            // 6. Build conversation (system + user).
-            // -------------------------------------------------------------
-            let messages = TextMessages::new()
-                .add_message(TextMessageRole::System, &system_msg)
-                .add_message(TextMessageRole::System, &context_content)
-                .add_message(TextMessageRole::User, &prompt);
-
-            // -------------------------------------------------------------
-            // 7. Stream the AI response
-            // -------------------------------------------------------------
-            let mut stream = match model.stream_chat_request(messages).await {
-                Ok(s) => s,
-                Err(e) => {
-                    println!("Error during stream: {:?}", e);
-                    return format!("Error during stream: {:?}", e);
-                }
-            };
+/// The parts of the request asking the prompt of `args` after the turns of `history`,
+/// sampled as `args` says. The context is read from the database, so this blocks.
+pub fn assemble_conversation(args: &ChatArgs, history: &[Turn]) -> Result<Conversation, ApiError> {
+    let Some(prompt) = &args.prompt else {
+        return Err(ApiError::new("empty_prompt", "no prompt provided"));
+    };
 
-            let mut accumulated_response = String::new();
-            while let Some(chunk) = stream.next().await {
-                if let Response::Chunk(chunk) = chunk {
-                    accumulated_response.push_str(&chunk.choices[0].delta.content);
+    // -------------------------------------------------------------
+    // 1. Get the "file_content" if provided.
+    // -------------------------------------------------------------
+    let (context_content, sources) = load_context(args).map_err(|e| {
+        eprintln!("Error loading context: {}", e);
+        ApiError::new(
+            "context_failed",
+            format!("could not load the context: {}", e),
+        )
+    })?;
+
+    // -------------------------------------------------------------
+    // 2. Take the project's sections of Lila.toml (optional).
+    // -------------------------------------------------------------
+    let config = Config::current();
+    let project_info = section_info(config.project.as_ref(), "No [project] info found.");
+    let development_info =
+        section_info(config.development.as_ref(), "No [development] info found.");
+    let dependencies_info = section_info(
+        config.dependencies.as_ref(),
+        "No [dependencies] info found.",
+    );
+    let compliance_info = section_info(config.compliance.as_ref(), "No [compliance] info found.");
+    let code_of_conduct = config
+        .ai_guidance
+        .code_of_conduct
+        .clone()
+        .unwrap_or_else(|| "No code_of_conduct found.".to_string());
+    let base_prompt = config
+        .ai_guidance
+        .system_prompt
+        .as_deref()
+        .map(str::trim)
+        .filter(|prompt| !prompt.is_empty())
+        .unwrap_or(DEFAULT_SYSTEM_PROMPT)
+        .to_string();
+
+    // -------------------------------------------------------------
+    // 3. Construct the system message + the context
+    // -------------------------------------------------------------
+    let mut system_msg = base_prompt;
+    if !context_content.is_empty() {
+        system_msg.push_str(
+            "\nBelow is some Markdown file content. Use it to answer the user's question.",
+        );
+    } else {
+        system_msg.push_str("\nNo additional context was provided.");
+    }
+
+    // Append Lila.toml sections
+    let mut sections = vec![
+        (
+            "[project]",
+            format!("\n---\n**Project**:\n{}", project_info),
+        ),
+        (
+            "[development]",
+            format!("\n\n**Development**:\n{}", development_info),
+        ),
+        (
+            "[dependencies]",
+            format!("\n\n**Dependencies**:\n{}", dependencies_info),
+        ),
+        (
+            "[compliance]",
+            format!("\n\n**Compliance**:\n{}", compliance_info),
+        ),
+        (
+            "[ai_guidance] code_of_conduct",
+            format!(
+                "\n\n**AI Guidance Code of Conduct**:\n{}\n---\n",
+                code_of_conduct
+            ),
+        ),
+    ];
+    // A system prompt given for this chat replaces all of it.
+    if let Some(system_prompt) = &args.system_prompt {
+        system_msg = system_prompt.clone();
+        sections.clear();
+    }
+
+    // -------------------------------------------------------------
+    // 4. Build conversation (system + earlier turns + user).
+    // -------------------------------------------------------------
+    Ok(Conversation {
+        system: system_msg,
+        sections,
+        context: context_content,
+        sources,
+        history: history.to_vec(),
+        prompt: prompt.clone(),
+        generation: args.generation.clone(),
+    })
+}
+
+/// A section of `Lila.toml` as TOML for the model, else `missing`.
+fn section_info<T: Serialize>(section: Option<&T>, missing: &str) -> String {
+    section
+        .and_then(|section| toml::to_string(section).ok())
+        .filter(|info| !info.trim().is_empty())
+        .unwrap_or_else(|| missing.to_string())
+}
+
+/// Streams the answer of `model` to `prompt`, passing each piece of text to `on_delta` as
+/// it comes, until the model is done or `stop` is set.
+pub async fn generate(
+    model: &ChatModel,
+    prompt: Prompt,
+    stop: &AtomicBool,
+    on_delta: impl FnMut(&str),
+) -> Result<Answer, ApiError> {
+    match model {
+        ChatModel::Local(model) => {
+            generate_locally(model, prompt.request_builder(), stop, on_delta).await
+        }
+        ChatModel::Remote(model) => model.generate(prompt, stop, on_delta).await,
+        ChatModel::Daemon(daemon) => daemon.generate(prompt, stop, on_delta).await,
+    }
+}
+
+/// `generate` with mistral.rs.
+async fn generate_locally(
+    model: &Model,
+    messages: RequestBuilder,
+    stop: &AtomicBool,
+    mut on_delta: impl FnMut(&str),
+) -> Result<Answer, ApiError> {
+    let mut stream = match model.stream_chat_request(messages).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error during stream: {:#}", e);
+            return Err(ApiError::new(
+                "model_failed",
+                format!("the model could not answer: {:#}", e),
+            ));
+        }
+    };
+
+    let mut answer = Answer {
+        text: String::new(),
+        tokens: None,
+        prompt_tokens: None,
+        stopped: false,
+    };
+    while let Some(chunk) = stream.next().await {
+        if stop.load(Ordering::SeqCst) {
+            // Dropping the stream cancels the request.
+            answer.stopped = true;
+            break;
+        }
+        let error = match chunk {
+            Response::Chunk(chunk) => {
+                if let Some(choice) = chunk.choices.first() {
+                    on_delta(&choice.delta.content);
+                    answer.text.push_str(&choice.delta.content);
                 }
+                if let Some(usage) = chunk.usage {
+                    answer.tokens = Some(usage.completion_tokens);
+                    answer.prompt_tokens = Some(usage.prompt_tokens);
+                }
+                continue;
             }
+            Response::ModelError(e, _) => e,
+            Response::InternalError(e) | Response::ValidationError(e) => e.to_string(),
+            _ => continue,
+        };
+        eprintln!("Error during stream: {}", error);
+        return Err(ApiError::new(
+            "model_failed",
+            format!("the model failed while answering: {}", error),
+        ));
+    }
+    Ok(answer)
+}
+
+/// The context for the request `args` and its sources: the saved files under `paths`
+/// with `use_db`; else `file_content`, else the files with `tags`, and the parts of the
+/// book relevant to the prompt when `use_retrieval` is set or neither is given.
+fn load_context(args: &ChatArgs) -> Result<(String, Vec<Source>), String> {
+    let given = args.file_content.clone().unwrap_or_default();
+    let Some(db_path) = &args.db_path else {
+        return Ok((given, Vec::new()));
+    };
+    if args.use_db {
+        return load_documents_context(args, db_path);
+    }
+    let tagged = args.file_content.is_none() && !args.tags.is_empty();
+    let retrieval = args.use_retrieval || (args.file_content.is_none() && args.tags.is_empty());
+    if !tagged && !retrieval {
+        return Ok((given, Vec::new()));
+    }
+
+    let scope = if args.all_projects {
+        ProjectScope::All
+    } else {
+        ProjectScope::project_or_current(args.project.clone())
+    };
+    let pool = match &args.pool {
+        Some(pool) => pool.clone(),
+        None => open_pool(&db_path.to_string_lossy())?,
+    };
+    let mut context = if tagged {
+        load_tagged_context(&pool, &args.tags, &scope)?
+    } else {
+        given
+    };
+    let (Some(retriever), Some(prompt), true) = (&args.retriever, &args.prompt, retrieval) else {
+        return Ok((context, Vec::new()));
+    };
 
-            accumulated_response
+    let mut conn = pool.get().map_err(|e| e.to_string())?;
+    let Some(project_id) = scope.resolve(&mut conn).map_err(|e| e.to_string())? else {
+        eprintln!("No files of {} are saved to retrieve from", scope);
+        return Ok((context, Vec::new()));
+    };
+    let (retrieved, sources) = retriever.context(&mut conn, prompt, project_id)?;
+    eprintln!("Retrieved {} part(s) of the book as context", sources.len());
+    if !context.is_empty() && !retrieved.is_empty() {
+        context.push_str("\n\n");
+    }
+    context.push_str(&retrieved);
+    Ok((context, sources))
+}
+
+/// The saved files of the request `args` under its `paths`, and matching its `grep`, read
+/// from the database at `db_path`, cut to the retriever's budget, and their sources.
+fn load_documents_context(
+    args: &ChatArgs,
+    db_path: &Path,
+) -> Result<(String, Vec<Source>), String> {
+    let scope = if args.all_projects {
+        ProjectScope::All
+    } else {
+        ProjectScope::project_or_current(args.project.clone())
+    };
+    let pool = match &args.pool {
+        Some(pool) => pool.clone(),
+        None => open_pool(&db_path.to_string_lossy())?,
+    };
+    let mut conn = pool.get().map_err(|e| e.to_string())?;
+    let Some(project_id) = scope.resolve(&mut conn).map_err(|e| e.to_string())? else {
+        eprintln!("No files of {} are saved to load as context", scope);
+        return Ok((String::new(), Vec::new()));
+    };
+    let mut ids =
+        document_ids_under(&mut conn, &args.paths, project_id).map_err(|e| e.to_string())?;
+    let saved = ids.len();
+    if !args.grep.is_empty() {
+        ids = documents_matching(&mut conn, &ids, &args.grep, args.grep_any)
+            .map_err(|e| e.to_string())?;
+    }
+    let budget = args
+        .retriever
+        .as_ref()
+        .map_or(DEFAULT_CONTEXT_TOKENS, |retriever| retriever.budget_tokens);
+    // Files are read one at a time, only until the budget is used up.
+    let mut failed = None;
+    let files = ids
+        .iter()
+        .map_while(|id| match document_content(&mut conn, *id) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                failed = Some(e);
+                None
+            }
         })
-    })
-    .await
-    .unwrap_or_else(|e| format!("Error during spawn_blocking: {:?}", e));
+        .flatten();
+    let (context, sources) = documents_context(files, budget);
+    if let Some(e) = failed {
+        return Err(e.to_string());
+    }
+    let under = match args.paths.is_empty() {
+        true => String::new(),
+        false => format!(" under {}", args.paths.join(", ")),
+    };
+    if args.grep.is_empty() {
+        eprintln!(
+            "Loaded {} of {} saved file(s){} as context",
+            sources.len(),
+            ids.len(),
+            under
+        );
+    } else {
+        eprintln!(
+            "{} of {} saved file(s){} matched, {} of them loaded as context",
+            ids.len(),
+            saved,
+            under,
+            sources.len()
+        );
+    }
+    Ok((context, sources))
+}
 
-    HttpResponse::Ok().json(ChatResponse {
-        response: response_text,
-    })
+/// The saved Markdown of the files of `project` tagged with all of `tags`, each under a
+/// heading with its path, read through `pool`.
+fn load_tagged_context(
+    pool: &DbPool,
+    tags: &[String],
+    project: &ProjectScope,
+) -> Result<String, String> {
+    let mut conn = pool.get().map_err(|e| e.to_string())?;
+    let files = match project.resolve(&mut conn).map_err(|e| e.to_string())? {
+        Some(project_id) => {
+            tagged_content(&mut conn, tags, project_id).map_err(|e| e.to_string())?
+        }
+        None => Vec::new(),
+    };
+    eprintln!(
+        "Loaded {} file(s) tagged {} as context",
+        files.len(),
+        tags.join(", ")
+    );
+    Ok(files
+        .iter()
+        .map(|(path, content)| format!("## {}\n\n{}", path, content))
+        .collect::<Vec<_>>()
+        .join("\n\n"))
 }
@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::server::auth::ServerToken;
+use crate::server::backend::{Backend, Isq};
+use crate::server::context::context_tokens;
+use crate::server::generation::GenerationParams;
+use crate::server::gguf::{check_gguf, is_gguf_path};
+use crate::server::hub::check_offline;
+use crate::server::listen::Listen;
+use crate::server::models::ModelConfig;
+use crate::server::rate_limit::RateLimits;
+use crate::server::render::RenderConfig;
+use crate::server::sessions::history_tokens;
+use crate::server::shutdown::shutdown_grace;
+use crate::server::start::{max_context_bytes, ChatLimits};
+use crate::server::tls::TlsFiles;
+use crate::utils::config::Config;
+
+/// Host the server listens on unless `[server] host` or `--bind` says otherwise.
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+/// Port the server listens on unless `[server] port` or `--bind` says otherwise.
+pub const DEFAULT_PORT: u16 = 8080;
+/// Workers serving requests unless `[server] workers` says otherwise.
+pub const DEFAULT_WORKERS: usize = 4;
+
+/// What `lila server` was given on the command line, taking precedence over `Lila.toml`.
+#[derive(Debug, Clone, Default)]
+pub struct ServerFlags {
+    /// `--bind`, a TCP address replacing `host` and `port`.
+    pub bind: Option<String>,
+    /// `--socket`, a Unix socket to listen on instead of TCP.
+    pub socket: Option<PathBuf>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// `--model-path`, a GGUF file to answer with by default.
+    pub model_path: Option<PathBuf>,
+    /// `--isq`, how local models are quantized.
+    pub isq: Option<Isq>,
+}
+
+/// How the server is set up: the `[server]` and `[ai]` sections of a `Lila.toml`, under
+/// the environment variables (`LILA_SERVER_TOKEN`, `LILA_AI_MODEL`, `LILA_AI_MODEL_PATH`,
+/// `LILA_AI_BACKEND`, `LILA_AI_ISQ`), under the command line flags. What none of them sets keeps its default.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub listen: Listen,
+    pub workers: usize,
+    /// Origins browsers may call the server from; any when empty.
+    pub cors_origins: Vec<String>,
+    pub token: Option<ServerToken>,
+    pub tls: Option<TlsFiles>,
+    pub models: ModelConfig,
+    /// Where the models run.
+    pub backend: Backend,
+    /// Sampling of chat answers where a request does not say.
+    pub generation: GenerationParams,
+    pub rate_limits: RateLimits,
+    pub chat_limits: ChatLimits,
+    pub render: RenderConfig,
+    /// Tokens of the book given to the model as context.
+    pub context_tokens: usize,
+    /// Tokens of a session's earlier turns given to the model.
+    pub history_tokens: usize,
+    pub shutdown_grace: Duration,
+}
+
+impl ServerConfig {
+    /// The configuration from `config` and the environment, overridden by `flags`. A
+    /// missing file means the defaults; an invalid entry is an error naming its key.
+    pub fn load(config: &Config, flags: ServerFlags) -> Result<ServerConfig, String> {
+        let listen = match (flags.socket, flags.bind) {
+            (Some(socket), _) => Listen::Unix(socket),
+            (None, Some(address)) => Listen::Tcp(address),
+            (None, None) => {
+                let (host, port) = address(config)?;
+                Listen::Tcp(format!("{}:{}", host, port))
+            }
+        };
+        let tls = TlsFiles::load(flags.tls_cert, flags.tls_key, config)?;
+        if let (Listen::Unix(path), Some(_)) = (&listen, &tls) {
+            return Err(format!(
+                "cannot serve HTTPS on the Unix socket {}; leave out the TLS certificate and key",
+                path.display()
+            ));
+        }
+        let (workers, cors_origins) = http(config)?;
+        let mut models = ModelConfig::load(config)?;
+        if let Some(path) = flags.model_path {
+            models.set_default(path.to_string_lossy().into_owned());
+        }
+        let mut backend = Backend::load(config)?;
+        if let Some(isq) = flags.isq {
+            backend.override_isq(isq);
+        }
+        // Check GGUF files, and offline the cache, now rather than when a request first
+        // asks for a model.
+        for id in &models.allowed {
+            match backend {
+                Backend::OpenAi { .. } if is_gguf_path(id) => {
+                    return Err(format!(
+                        "{} is a GGUF file, which only the local backend can load",
+                        id
+                    ))
+                }
+                Backend::OpenAi { .. } => {}
+                Backend::Local { .. } if is_gguf_path(id) => {
+                    check_gguf(Path::new(id))?;
+                }
+                Backend::Local { .. } => check_offline(id)?,
+            }
+        }
+        Ok(ServerConfig {
+            listen,
+            workers,
+            cors_origins,
+            token: ServerToken::load(config)?,
+            tls,
+            models,
+            backend,
+            generation: GenerationParams::load(config)?,
+            rate_limits: RateLimits::load(config)?,
+            chat_limits: ChatLimits {
+                max_context_bytes: max_context_bytes(config)?,
+            },
+            render: RenderConfig::load(config)?,
+            context_tokens: context_tokens(config)?,
+            history_tokens: history_tokens(config)?,
+            shutdown_grace: shutdown_grace(config)?,
+        })
+    }
+}
+
+/// The `host` and `port` in the `[server]` section of `config`, each defaulting when
+/// missing.
+fn address(config: &Config) -> Result<(String, u16), String> {
+    let mut address = (DEFAULT_HOST.to_string(), DEFAULT_PORT);
+    if let Some(host) = &config.server.host {
+        if host.is_empty() {
+            return Err(format!(
+                "invalid {}: server.host must be a non-empty host name or IP address",
+                config.path().display()
+            ));
+        }
+        address.0 = host.clone();
+    }
+    if let Some(port) = config.server.port {
+        match u16::try_from(port) {
+            Ok(port) if port > 0 => address.1 = port,
+            _ => {
+                return Err(format!(
+                    "invalid {}: server.port must be an integer from 1 to 65535",
+                    config.path().display()
+                ))
+            }
+        }
+    }
+    Ok(address)
+}
+
+/// The `workers` and `cors_origins` in the `[server]` section of `config`, each
+/// defaulting when missing.
+fn http(config: &Config) -> Result<(usize, Vec<String>), String> {
+    let mut http = (DEFAULT_WORKERS, Vec::new());
+    if let Some(workers) = config.server.workers {
+        if workers <= 0 {
+            return Err(format!(
+                "invalid {}: server.workers must be a positive integer",
+                config.path().display()
+            ));
+        }
+        http.0 = workers as usize;
+    }
+    if let Some(origins) = &config.server.cors_origins {
+        let is_origin =
+            |origin: &String| origin.starts_with("http://") || origin.starts_with("https://");
+        if !origins.iter().all(is_origin) {
+            return Err(format!(
+                "invalid {}: server.cors_origins must be a list of origins such as \"http://localhost:5173\"",
+                config.path().display()
+            ));
+        }
+        http.1 = origins
+            .iter()
+            .map(|origin| origin.trim_end_matches('/').to_string())
+            .collect();
+    }
+    Ok(http)
+}
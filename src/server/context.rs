@@ -0,0 +1,316 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::schema::{embeddings, metadata};
+use crate::utils::config::Config;
+use crate::utils::database::documents::document_content;
+use crate::utils::database::embeddings::{chunk_markdown, retrieve};
+use crate::utils::database::search::{query_words, search_snippets, SearchError};
+use crate::utils::embedding::{LocalEmbedder, LOCAL_MODEL_NAME};
+use crate::utils::tokens::estimated_tokens;
+
+/// Tokens of retrieved context given to the model unless `[server] context_tokens` says
+/// otherwise.
+pub const DEFAULT_CONTEXT_TOKENS: usize = 3000;
+
+/// Headings of the contexts `Retriever::context` and `documents_context` build, above
+/// their numbered parts.
+pub const CONTEXT_HEADINGS: [&str; 2] = [
+    "Excerpts of the book, each under its source number. Cite the sources you use, like [1].\n\n",
+    "Files of the book, each under its source number. Cite the sources you use, like [1].\n\n",
+];
+
+/// Chunks considered, most relevant first, before the token budget is applied.
+const CANDIDATE_CHUNKS: usize = 20;
+/// Files searched by keyword when nothing is embedded, and chunks taken from each.
+const KEYWORD_FILES: usize = 10;
+const CHUNKS_PER_FILE: usize = 2;
+
+/// The `context_tokens` in the `[server]` section of `config`, else
+/// `DEFAULT_CONTEXT_TOKENS`.
+pub fn context_tokens(config: &Config) -> Result<usize, String> {
+    Ok(configured_context_tokens(config)?.unwrap_or(DEFAULT_CONTEXT_TOKENS))
+}
+
+/// The `context_tokens` in the `[server]` section of `config`, if it is set.
+pub fn configured_context_tokens(config: &Config) -> Result<Option<usize>, String> {
+    match config.server.context_tokens {
+        None => Ok(None),
+        Some(tokens) if tokens > 0 => Ok(Some(tokens as usize)),
+        Some(_) => Err(format!(
+            "invalid {}: server.context_tokens must be a positive integer",
+            config.path().display()
+        )),
+    }
+}
+
+/// A part of the book given to the model as context, cited by `number`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Source {
+    pub number: usize,
+    pub path: String,
+    /// Heading the part is under.
+    pub heading: Option<String>,
+}
+
+/// Picks the parts of the saved book relevant to a question. The embedding model is
+/// loaded on first use and shared by every request.
+#[derive(Clone)]
+pub struct Retriever {
+    model_dir: PathBuf,
+    embedder: Arc<Mutex<Option<LocalEmbedder>>>,
+    /// Estimated tokens of context at most.
+    pub budget_tokens: usize,
+}
+
+impl fmt::Debug for Retriever {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retriever")
+            .field("model_dir", &self.model_dir)
+            .field("budget_tokens", &self.budget_tokens)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A chunk of a saved file, before the budget is applied.
+struct Passage {
+    path: String,
+    heading: Option<String>,
+    text: String,
+}
+
+impl Retriever {
+    /// A retriever using the embedding model cached in `model_dir`.
+    pub fn new(model_dir: PathBuf, budget_tokens: usize) -> Retriever {
+        Retriever {
+            model_dir,
+            embedder: Arc::new(Mutex::new(None)),
+            budget_tokens,
+        }
+    }
+
+    /// The chunks of the files of `project_id` (all projects if `None`) closest to
+    /// `question`, each under its source number, and the sources.
+    ///
+    /// Chunks come from the embeddings written by `lila index`; when the files are not
+    /// indexed, they are found by full-text search instead. The most relevant ones are
+    /// taken while they fit in `budget_tokens`.
+    pub fn context(
+        &self,
+        conn: &mut SqliteConnection,
+        question: &str,
+        project_id: Option<i32>,
+    ) -> Result<(String, Vec<Source>), String> {
+        let passages = if has_embeddings(conn, project_id).map_err(|e| e.to_string())? {
+            match self.embedded_passages(conn, question, project_id) {
+                Ok(passages) => passages,
+                Err(e) => {
+                    eprintln!(
+                        "Retrieval by embeddings failed ({}); searching by keyword",
+                        e
+                    );
+                    keyword_passages(conn, question, project_id)?
+                }
+            }
+        } else {
+            keyword_passages(conn, question, project_id)?
+        };
+        Ok(assemble(passages, self.budget_tokens))
+    }
+
+    fn embedded_passages(
+        &self,
+        conn: &mut SqliteConnection,
+        question: &str,
+        project_id: Option<i32>,
+    ) -> Result<Vec<Passage>, String> {
+        let mut embedder = self.embedder.lock().unwrap_or_else(|e| e.into_inner());
+        if embedder.is_none() {
+            *embedder = Some(LocalEmbedder::load(&self.model_dir).map_err(|e| e.to_string())?);
+        }
+        let embedder = embedder.as_mut().expect("the embedder was just loaded");
+        let chunks = retrieve(conn, embedder, question, CANDIDATE_CHUNKS, project_id)
+            .map_err(|e| e.to_string())?;
+        Ok(chunks
+            .into_iter()
+            .map(|chunk| Passage {
+                path: chunk.file_path,
+                heading: chunk.heading,
+                text: chunk.text,
+            })
+            .collect())
+    }
+}
+
+/// Whether any file of `project_id` has vectors of the local embedding model.
+fn has_embeddings(conn: &mut SqliteConnection, project_id: Option<i32>) -> QueryResult<bool> {
+    let mut query = embeddings::table
+        .inner_join(metadata::table)
+        .filter(embeddings::model.eq(LOCAL_MODEL_NAME))
+        .select(embeddings::id)
+        .into_boxed();
+    if let Some(id) = project_id {
+        query = query.filter(metadata::project_id.eq(id));
+    }
+    Ok(query.first::<i32>(conn).optional()?.is_some())
+}
+
+/// The chunks mentioning the most words of `question`, from the files full-text search
+/// ranks best for them.
+fn keyword_passages(
+    conn: &mut SqliteConnection,
+    question: &str,
+    project_id: Option<i32>,
+) -> Result<Vec<Passage>, String> {
+    let words: Vec<String> = query_words(question)
+        .iter()
+        .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()).to_string())
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+    // Any of the words; bm25 ranks the files with more, and rarer, ones first.
+    let fts_query = words
+        .iter()
+        .map(|w| format!("\"{}\"", w))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let hits = match search_snippets(conn, &fts_query, KEYWORD_FILES, project_id) {
+        Ok(hits) => hits,
+        Err(SearchError::FtsUnavailable) => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut passages = Vec::new();
+    for hit in hits {
+        let Some((path, content)) = document_content(conn, hit.id).map_err(|e| e.to_string())?
+        else {
+            continue;
+        };
+        let mut chunks: Vec<(usize, Passage)> = chunk_markdown(&content)
+            .into_iter()
+            .map(|chunk| {
+                let text = &content[chunk.start..chunk.end];
+                let lower = text.to_lowercase();
+                let mentions = words
+                    .iter()
+                    .map(|w| lower.matches(w.as_str()).count())
+                    .sum();
+                (
+                    mentions,
+                    Passage {
+                        path: path.clone(),
+                        heading: chunk.heading,
+                        text: text.to_string(),
+                    },
+                )
+            })
+            .filter(|(mentions, _)| *mentions > 0)
+            .collect();
+        chunks.sort_by_key(|(mentions, _)| std::cmp::Reverse(*mentions));
+        passages.extend(chunks.into_iter().take(CHUNKS_PER_FILE).map(|(_, p)| p));
+    }
+    Ok(passages)
+}
+
+/// `passages`, in order, each under its source number, skipping those that no longer fit
+/// in `budget_tokens`.
+fn assemble(passages: Vec<Passage>, budget_tokens: usize) -> (String, Vec<Source>) {
+    let mut context = String::new();
+    let mut sources = Vec::new();
+    let mut used = 0;
+    for passage in passages {
+        let label = match &passage.heading {
+            Some(heading) => format!("{} > {}", passage.path, heading),
+            None => passage.path.clone(),
+        };
+        let section = format!(
+            "[{}] {}\n{}\n\n",
+            sources.len() + 1,
+            label,
+            passage.text.trim()
+        );
+        let tokens = estimated_tokens(&section);
+        if used + tokens > budget_tokens {
+            continue;
+        }
+        used += tokens;
+        context.push_str(&section);
+        sources.push(Source {
+            number: sources.len() + 1,
+            path: passage.path,
+            heading: passage.heading,
+        });
+    }
+    if !context.is_empty() {
+        context.insert_str(0, CONTEXT_HEADINGS[0]);
+    }
+    (context, sources)
+}
+
+/// `files` (path and content) in order, each under its source number, while they fit in
+/// `budget_tokens`; the first one that does not fit is cut short, and no more are read.
+/// Returns the context and the files it holds as sources.
+pub fn documents_context(
+    files: impl IntoIterator<Item = (String, String)>,
+    budget_tokens: usize,
+) -> (String, Vec<Source>) {
+    let mut context = String::new();
+    let mut sources = Vec::new();
+    let mut used = 0;
+    for (path, content) in files {
+        let header = format!("[{}] {}\n", sources.len() + 1, path);
+        let room = budget_tokens
+            .saturating_sub(used + estimated_tokens(&header))
+            .saturating_mul(4);
+        if room == 0 {
+            break;
+        }
+        let content = content.trim();
+        let mut end = content.len().min(room);
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        let section = format!("{}{}\n\n", header, &content[..end]);
+        used += estimated_tokens(&section);
+        context.push_str(&section);
+        sources.push(Source {
+            number: sources.len() + 1,
+            path,
+            heading: None,
+        });
+        if end < content.len() {
+            break;
+        }
+    }
+    if !context.is_empty() {
+        context.insert_str(0, CONTEXT_HEADINGS[1]);
+    }
+    (context, sources)
+}
+
+/// Where the part of each of `sources` is in `context`, a context they were numbered in:
+/// from the line starting with its number to the next such line, or the end of the context.
+pub fn source_spans(context: &str, sources: &[Source]) -> Vec<Range<usize>> {
+    let mut starts = Vec::new();
+    let mut from = 0;
+    for source in sources {
+        let header = format!("\n[{}] ", source.number);
+        let Some(found) = context[from..].find(&header) else {
+            break;
+        };
+        from += found + 1;
+        starts.push(from);
+    }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| *start..starts.get(i + 1).copied().unwrap_or(context.len()))
+        .collect()
+}
@@ -0,0 +1,684 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::sync::{mpsc, Notify};
+
+use crate::server::backend::{Backend, ChatModel, Prompt, Role};
+use crate::server::chat::{generate, Answer, ApiError};
+use crate::server::generation::GenerationParams;
+use crate::utils::utils::lila_home;
+
+/// Version of the protocol `lila chat` and its daemon speak. A daemon refuses the requests
+/// of another version, sent by a lila built from other sources than its own.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Minutes without a question after which the daemon unloads its model, unless
+/// `--idle-minutes` says otherwise.
+pub const DEFAULT_IDLE_MINUTES: u64 = 30;
+/// How often the daemon checks whether it has been idle for too long.
+const IDLE_CHECK: Duration = Duration::from_secs(10);
+/// How often a client waiting for the next part of an answer checks whether it was stopped.
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// Where the daemon listens, for its user only: the Unix socket `~/.lila/chat.sock`, or on
+/// Windows the named pipe `\\.\pipe\lila-chat-<user>`.
+pub fn socket_path() -> PathBuf {
+    if cfg!(windows) {
+        let user = std::env::var("USERNAME").unwrap_or_else(|_| "default".to_string());
+        PathBuf::from(format!(r"\\.\pipe\lila-chat-{}", user))
+    } else {
+        lila_home().join("chat.sock")
+    }
+}
+
+/// Where the daemon started by `lila chat --daemon start` writes what it prints.
+pub fn log_path() -> PathBuf {
+    lila_home().join("chat-daemon.log")
+}
+
+/// A request to the daemon, on one line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    version: u32,
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    Status,
+    Stop,
+    /// Answer these messages, replying with each part of the answer, then `Done`.
+    Generate {
+        messages: Vec<(Role, String)>,
+        generation: GenerationParams,
+    },
+}
+
+/// A reply of the daemon, on one line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+enum Reply {
+    Status(DaemonStatus),
+    Stopping,
+    Delta {
+        text: String,
+    },
+    Done {
+        tokens: Option<usize>,
+        prompt_tokens: Option<usize>,
+    },
+    Error {
+        message: String,
+    },
+    /// The request was of another protocol version than `version`, the daemon's.
+    Refused {
+        version: u32,
+    },
+}
+
+/// What a running daemon says about itself.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub version: u32,
+    pub pid: u32,
+    pub model: String,
+    /// Whether the model is in memory; once the idle timeout unloaded it, it is loaded
+    /// again for the next question.
+    pub loaded: bool,
+    /// Whether it is answering a question.
+    pub busy: bool,
+    /// Minutes without a question after which the model is unloaded; never if 0.
+    pub idle_minutes: u64,
+    /// Seconds since the last question, or since the daemon started.
+    pub idle_secs: u64,
+}
+
+impl fmt::Display for DaemonStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.busy {
+            "answering"
+        } else if self.loaded {
+            "loaded"
+        } else {
+            "unloaded while idle"
+        };
+        write!(
+            f,
+            "Chat daemon running (pid {}) with {} ({}), idle for {}m{:02}s",
+            self.pid,
+            self.model,
+            state,
+            self.idle_secs / 60,
+            self.idle_secs % 60
+        )?;
+        match self.idle_minutes {
+            0 => write!(f, "; it keeps the model loaded"),
+            minutes => write!(f, "; it unloads the model after {} idle minute(s)", minutes),
+        }
+    }
+}
+
+/// The daemon: model `model_id` of `backend`, loaded once, answering the questions of
+/// `lila chat` runs one at a time.
+struct Daemon {
+    backend: Backend,
+    model_id: String,
+    idle: Option<Duration>,
+    /// The model, `None` once unloaded after the idle timeout; locked while answering.
+    model: tokio::sync::Mutex<Option<ChatModel>>,
+    last_used: Mutex<Instant>,
+    stopping: Notify,
+}
+
+/// Answers with `model`, model `model_id` of `backend`, on `socket` until asked to stop
+/// or interrupted. The model is unloaded after `idle` without questions, and loaded again
+/// for the next one.
+pub async fn serve(
+    socket: &Path,
+    backend: Backend,
+    model_id: String,
+    model: ChatModel,
+    idle: Option<Duration>,
+) -> Result<(), String> {
+    use crate::server::shutdown::stop_signal;
+
+    let cannot_listen = |e: io::Error| format!("cannot listen on {}: {}", socket.display(), e);
+    let mut listener = Listener::bind(socket).map_err(cannot_listen)?;
+
+    let daemon = Arc::new(Daemon {
+        backend,
+        model_id,
+        idle,
+        model: tokio::sync::Mutex::new(Some(model)),
+        last_used: Mutex::new(Instant::now()),
+        stopping: Notify::new(),
+    });
+    let mut idle_check = tokio::time::interval(IDLE_CHECK);
+    let signal = stop_signal();
+    tokio::pin!(signal);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(stream) => {
+                    tokio::spawn(daemon.clone().answer(stream));
+                }
+                Err(e) => eprintln!("Cannot accept a connection: {}", e),
+            },
+            _ = idle_check.tick() => daemon.unload_if_idle(),
+            _ = daemon.stopping.notified() => break,
+            signal = &mut signal => {
+                eprintln!("Received {}", signal);
+                break;
+            }
+        }
+    }
+    listener.close(socket);
+    eprintln!("Chat daemon stopped");
+    Ok(())
+}
+
+/// The Unix socket the daemon takes connections on.
+#[cfg(unix)]
+struct Listener(tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl Listener {
+    /// Listens on `socket`, readable by the daemon's user only.
+    fn bind(socket: &Path) -> io::Result<Listener> {
+        use crate::server::listen::{check_socket_path, remove_socket, restrict_socket};
+
+        check_socket_path(socket)?;
+        // Only a socket left by a daemon that did not stop cleanly may be there.
+        let _ = std::fs::remove_file(socket);
+        let listener = tokio::net::UnixListener::bind(socket)?;
+        restrict_socket(socket).inspect_err(|_| remove_socket(socket))?;
+        Ok(Listener(listener))
+    }
+
+    async fn accept(&mut self) -> io::Result<tokio::net::UnixStream> {
+        let (stream, _) = self.0.accept().await?;
+        Ok(stream)
+    }
+
+    /// Stops listening and removes the socket.
+    fn close(self, socket: &Path) {
+        drop(self.0);
+        crate::server::listen::remove_socket(socket);
+    }
+}
+
+/// The named pipe the daemon takes connections on: an instance of it waits for the next
+/// client at all times. The default security of a pipe lets only its user and the
+/// administrators write to it, and clients of other machines are refused.
+#[cfg(windows)]
+struct Listener {
+    name: PathBuf,
+    next: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+#[cfg(windows)]
+impl Listener {
+    /// Creates the pipe `name`; fails when another daemon has it.
+    fn bind(name: &Path) -> io::Result<Listener> {
+        let next = Listener::options().first_pipe_instance(true).create(name)?;
+        Ok(Listener {
+            name: name.to_path_buf(),
+            next,
+        })
+    }
+
+    fn options() -> tokio::net::windows::named_pipe::ServerOptions {
+        let mut options = tokio::net::windows::named_pipe::ServerOptions::new();
+        options.reject_remote_clients(true);
+        options
+    }
+
+    /// Waits for a client on the waiting instance, and makes another for the next one.
+    async fn accept(&mut self) -> io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+        let connected = self.next.connect().await;
+        let next = Listener::options().create(&self.name)?;
+        let client = std::mem::replace(&mut self.next, next);
+        connected.map(|()| client)
+    }
+
+    /// Stops listening; the pipe goes away with its last instance.
+    fn close(self, _name: &Path) {}
+}
+
+impl Daemon {
+    /// Answers the request read from `stream`.
+    async fn answer(self: Arc<Self>, stream: impl AsyncRead + AsyncWrite) {
+        let (read, mut write) = tokio::io::split(stream);
+        let Ok(Some(line)) = BufReader::new(read).lines().next_line().await else {
+            return;
+        };
+        let reply = match read_request(&line) {
+            Ok(Command::Status) => Reply::Status(self.status()),
+            Ok(Command::Stop) => {
+                let _ = send(&mut write, &Reply::Stopping).await;
+                self.stopping.notify_one();
+                return;
+            }
+            Ok(Command::Generate {
+                messages,
+                generation,
+            }) => {
+                let prompt = Prompt {
+                    messages,
+                    generation,
+                };
+                return self.generate(prompt, &mut write).await;
+            }
+            Err(reply) => reply,
+        };
+        let _ = send(&mut write, &reply).await;
+    }
+
+    /// Answers `prompt`, loading the model again if it was unloaded, and writes each part
+    /// of the answer to `write` as it comes. Stops when the client goes away.
+    async fn generate(&self, prompt: Prompt, write: &mut (impl AsyncWrite + Unpin)) {
+        let mut model = self.model.lock().await;
+        self.touch();
+        let model = match &mut *model {
+            Some(model) => model,
+            empty => {
+                eprintln!("Loading model {} again...", self.model_id);
+                match ChatModel::load(&self.backend, &self.model_id).await {
+                    Ok(loaded) => empty.insert(loaded),
+                    Err(e) => {
+                        let message = format!("could not load model {}: {}", self.model_id, e);
+                        let _ = send(write, &Reply::Error { message }).await;
+                        return;
+                    }
+                }
+            }
+        };
+
+        let stop = AtomicBool::new(false);
+        let (deltas, mut received) = mpsc::unbounded_channel();
+        let answering = generate(model, prompt, &stop, move |delta| {
+            let _ = deltas.send(delta.to_string());
+        });
+        let forwarding = async {
+            while let Some(text) = received.recv().await {
+                if send(write, &Reply::Delta { text }).await.is_err() {
+                    // The client stopped the answer or went away.
+                    stop.store(true, Ordering::SeqCst);
+                }
+            }
+        };
+        let (answer, ()) = tokio::join!(answering, forwarding);
+        self.touch();
+        let reply = match answer {
+            Ok(answer) => Reply::Done {
+                tokens: answer.tokens,
+                prompt_tokens: answer.prompt_tokens,
+            },
+            Err(e) => Reply::Error { message: e.message },
+        };
+        let _ = send(write, &reply).await;
+    }
+
+    fn status(&self) -> DaemonStatus {
+        let (loaded, busy) = match self.model.try_lock() {
+            Ok(model) => (model.is_some(), false),
+            Err(_) => (true, true),
+        };
+        DaemonStatus {
+            version: PROTOCOL_VERSION,
+            pid: std::process::id(),
+            model: self.model_id.clone(),
+            loaded,
+            busy,
+            idle_minutes: self.idle.map_or(0, |idle| idle.as_secs() / 60),
+            idle_secs: self.idle_for().as_secs(),
+        }
+    }
+
+    /// Unloads the model if no question came for the idle timeout.
+    fn unload_if_idle(&self) {
+        let Some(idle) = self.idle else {
+            return;
+        };
+        // Locked while answering, which is not idle.
+        let Ok(mut model) = self.model.try_lock() else {
+            return;
+        };
+        if model.is_some() && self.idle_for() >= idle {
+            *model = None;
+            eprintln!(
+                "Unloaded model {} after {} idle minute(s)",
+                self.model_id,
+                idle.as_secs() / 60
+            );
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_used.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used.lock().unwrap().elapsed()
+    }
+}
+
+/// The command of the request `line`, or the reply refusing it.
+fn read_request(line: &str) -> Result<Command, Reply> {
+    let invalid = |e: serde_json::Error| Reply::Error {
+        message: format!("invalid request: {}", e),
+    };
+    let request: serde_json::Value = serde_json::from_str(line).map_err(invalid)?;
+    // Any version may stop the daemon, for `--daemon stop` to replace one of another.
+    if request.get("command").and_then(|c| c.as_str()) == Some("stop") {
+        return Ok(Command::Stop);
+    }
+    // Check the version first: a request of another version may not parse.
+    if request.get("version").and_then(|v| v.as_u64()) != Some(PROTOCOL_VERSION.into()) {
+        return Err(Reply::Refused {
+            version: PROTOCOL_VERSION,
+        });
+    }
+    serde_json::from_value::<Request>(request)
+        .map(|request| request.command)
+        .map_err(invalid)
+}
+
+/// Writes `message` to `write` as a line of JSON.
+async fn send(write: &mut (impl AsyncWrite + Unpin), message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    write.write_all(line.as_bytes()).await?;
+    write.flush().await
+}
+
+/// A model of the daemon: `generate` asks it through its socket.
+pub struct DaemonClient {
+    socket: PathBuf,
+}
+
+impl DaemonClient {
+    pub fn new(socket: PathBuf) -> DaemonClient {
+        DaemonClient { socket }
+    }
+
+    /// `generate` with the model of the daemon. Setting `stop` closes the connection,
+    /// which stops the daemon's answer too.
+    pub async fn generate(
+        &self,
+        prompt: Prompt,
+        stop: &AtomicBool,
+        mut on_delta: impl FnMut(&str),
+    ) -> Result<Answer, ApiError> {
+        let failed = |error: String| {
+            ApiError::new(
+                "model_failed",
+                format!("the chat daemon could not answer: {}", error),
+            )
+        };
+        let command = Command::Generate {
+            messages: prompt.messages,
+            generation: prompt.generation,
+        };
+        let mut connection = request(&self.socket, command)
+            .await
+            .map_err(|e| failed(e.to_string()))?
+            .ok_or_else(|| failed("it is no longer running".to_string()))?;
+
+        let mut answer = Answer {
+            text: String::new(),
+            tokens: None,
+            prompt_tokens: None,
+            stopped: false,
+        };
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                answer.stopped = true;
+                return Ok(answer);
+            }
+            let Ok(reply) = tokio::time::timeout(STOP_CHECK, connection.reply()).await else {
+                continue;
+            };
+            match reply.map_err(failed)? {
+                Reply::Delta { text } => {
+                    on_delta(&text);
+                    answer.text.push_str(&text);
+                }
+                Reply::Done {
+                    tokens,
+                    prompt_tokens,
+                } => {
+                    answer.tokens = tokens;
+                    answer.prompt_tokens = prompt_tokens;
+                    return Ok(answer);
+                }
+                Reply::Error { message } => return Err(ApiError::new("model_failed", message)),
+                other => return Err(failed(unexpected(&other))),
+            }
+        }
+    }
+}
+
+/// What the daemon listening on `socket` says about itself; `None` if none listens there.
+pub async fn status(socket: &Path) -> Result<Option<DaemonStatus>, String> {
+    let Some(mut connection) = request(socket, Command::Status)
+        .await
+        .map_err(|e| cannot_reach(socket, e))?
+    else {
+        return Ok(None);
+    };
+    match connection.reply().await? {
+        Reply::Status(status) if status.version == PROTOCOL_VERSION => Ok(Some(status)),
+        Reply::Status(status) => Err(mismatch(Some(status.version))),
+        other => Err(unexpected(&other)),
+    }
+}
+
+/// Asks the daemon listening on `socket`, of any version, to stop, and waits until it
+/// did; `false` if none listens there.
+pub async fn stop(socket: &Path) -> Result<bool, String> {
+    let Some(mut connection) = request(socket, Command::Stop)
+        .await
+        .map_err(|e| cannot_reach(socket, e))?
+    else {
+        return Ok(false);
+    };
+    match connection.reply().await {
+        Ok(Reply::Stopping) => {}
+        Ok(other) => return Err(unexpected(&other)),
+        // A daemon of another version may say it is stopping in its own words.
+        Err(_) => {}
+    }
+    for _ in 0..50 {
+        if !still_listening(socket).await {
+            return Ok(true);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Err(format!(
+        "the chat daemon did not stop; {} is still there",
+        socket.display()
+    ))
+}
+
+/// A connection to the daemon, its replies read line by line.
+struct Connection {
+    lines: Lines<BufReader<Box<dyn AsyncRead + Send + Unpin>>>,
+}
+
+impl Connection {
+    /// The next reply; an error when the daemon hung up or speaks another protocol.
+    async fn reply(&mut self) -> Result<Reply, String> {
+        let line = self
+            .lines
+            .next_line()
+            .await
+            .map_err(|e| format!("cannot read from the chat daemon: {}", e))?
+            .ok_or("the chat daemon hung up")?;
+        match serde_json::from_str(&line) {
+            Ok(Reply::Refused { version }) => Err(mismatch(Some(version))),
+            Ok(reply) => Ok(reply),
+            Err(_) => Err(mismatch(None)),
+        }
+    }
+}
+
+/// Connects to the daemon on `socket` and sends it `command`; `None` when no daemon
+/// listens there.
+async fn request(socket: &Path, command: Command) -> io::Result<Option<Connection>> {
+    let stream = match open(socket).await {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    };
+    let (read, mut write) = tokio::io::split(stream);
+    let request = Request {
+        version: PROTOCOL_VERSION,
+        command,
+    };
+    send(&mut write, &request).await?;
+    let read: Box<dyn AsyncRead + Send + Unpin> = Box::new(read);
+    Ok(Some(Connection {
+        lines: BufReader::new(read).lines(),
+    }))
+}
+
+#[cfg(unix)]
+async fn open(socket: &Path) -> io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(socket).await
+}
+
+/// Opens the pipe `name`, waiting while the daemon makes an instance for the next client.
+#[cfg(windows)]
+async fn open(name: &Path) -> io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    /// Error of opening a pipe whose instances are all taken.
+    const ERROR_PIPE_BUSY: i32 = 231;
+    loop {
+        match tokio::net::windows::named_pipe::ClientOptions::new().open(name) {
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            opened => return opened,
+        }
+    }
+}
+
+/// Whether the socket of a daemon is still there.
+#[cfg(unix)]
+async fn still_listening(socket: &Path) -> bool {
+    socket.exists()
+}
+
+/// Whether the pipe of a daemon is still there. Looking at a pipe's file opens it, so it
+/// is opened as a client that sends nothing.
+#[cfg(windows)]
+async fn still_listening(name: &Path) -> bool {
+    !matches!(open(name).await, Err(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+fn cannot_reach(socket: &Path, error: io::Error) -> String {
+    format!(
+        "cannot reach the chat daemon on {}: {}",
+        socket.display(),
+        error
+    )
+}
+
+/// Why the daemon, which speaks protocol `version` if known, cannot be used.
+fn mismatch(version: Option<u32>) -> String {
+    let speaks = match version {
+        Some(version) => format!("protocol {}", version),
+        None => "another protocol".to_string(),
+    };
+    format!(
+        "the chat daemon was started by another version of lila and speaks {}, this one {}; restart it with `lila chat --daemon stop` and `lila chat --daemon start`",
+        speaks, PROTOCOL_VERSION
+    )
+}
+
+fn unexpected(reply: &Reply) -> String {
+    format!("the chat daemon answered unexpectedly: {:?}", reply)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::server::testing::{FakeModel, FAKE_MODEL};
+
+    #[actix_web::test]
+    async fn a_daemon_answers_reports_its_status_and_stops() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("chat.sock");
+        let backend = Backend::OpenAi {
+            base_url: FakeModel::answering(&["Hello", " there"]).serve(),
+            api_key_env: "LILA_TEST_API_KEY".to_string(),
+        };
+        let model = ChatModel::load(&backend, FAKE_MODEL).await.unwrap();
+        let serving = {
+            let socket = socket.clone();
+            actix_web::rt::spawn(async move {
+                serve(&socket, backend, FAKE_MODEL.to_string(), model, None).await
+            })
+        };
+        while !socket.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let running = status(&socket).await.unwrap().unwrap();
+        assert_eq!(running.model, FAKE_MODEL);
+        assert!(running.loaded && !running.busy);
+
+        let prompt = Prompt {
+            messages: vec![(Role::User, "Hi".to_string())],
+            generation: GenerationParams::default(),
+        };
+        let mut deltas = Vec::new();
+        let answer = DaemonClient::new(socket.clone())
+            .generate(prompt, &AtomicBool::new(false), |delta| {
+                deltas.push(delta.to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(answer.text, "Hello there");
+        assert_eq!(deltas, ["Hello", " there"]);
+
+        assert!(stop(&socket).await.unwrap());
+        serving.await.unwrap().unwrap();
+        assert!(!socket.exists());
+        assert!(status(&socket).await.unwrap().is_none());
+        assert!(!stop(&socket).await.unwrap());
+    }
+
+    #[test]
+    fn requests_of_another_version_are_refused_but_may_stop() {
+        let refused = read_request(r#"{"version": 99, "command": "status"}"#).unwrap_err();
+        assert!(matches!(
+            refused,
+            Reply::Refused {
+                version: PROTOCOL_VERSION
+            }
+        ));
+        assert!(matches!(
+            read_request(r#"{"version": 99, "command": "stop"}"#),
+            Ok(Command::Stop)
+        ));
+        assert!(matches!(
+            read_request(r#"{"version": 1, "command": "status"}"#),
+            Ok(Command::Status)
+        ));
+        assert!(matches!(read_request("not json"), Err(Reply::Error { .. })));
+    }
+}
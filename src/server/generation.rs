@@ -0,0 +1,206 @@
+use mistralrs::{RequestBuilder, StopTokens};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::server::chat::ApiError;
+use crate::utils::config::Config;
+
+/// Highest `temperature` accepted.
+const MAX_TEMPERATURE: f64 = 2.0;
+/// Most tokens an answer may be given with `max_tokens`.
+const MAX_TOKENS_LIMIT: usize = 32 * 1024;
+/// Most `stop` sequences accepted.
+const MAX_STOP_SEQUENCES: usize = 8;
+/// Longest `stop` sequence accepted.
+const MAX_STOP_BYTES: usize = 256;
+/// Largest `repeat_penalty`, either way.
+const MAX_REPEAT_PENALTY: f64 = 2.0;
+
+/// How the model samples an answer. Unset fields keep the model's defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationParams {
+    /// Randomness, from 0 (always the likeliest token) to 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Only sample from the likeliest tokens making up this share of the probability.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// Most tokens to generate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    /// Stop the answer at any of these, which are left out of it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Penalty on tokens already in the answer, growing with how often they appear, from
+    /// -2 to 2 (0 for none), like the `frequency_penalty` of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+    /// Seed of the sampling, so an answer can be generated again. Only OpenAI-compatible
+    /// backends take it; mistral.rs has no seed per request, so the local backend needs a
+    /// `temperature` of 0 for that.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl GenerationParams {
+    /// The `[ai.generation]` section of `config` (a `Lila.toml`), under what
+    /// `[server.generation]` sets, used for what a chat request leaves out; the model's
+    /// defaults without either.
+    pub fn load(config: &Config) -> Result<GenerationParams, String> {
+        let ai = GenerationParams::checked(config, config.ai.generation.as_ref(), "ai")?;
+        let server =
+            GenerationParams::checked(config, config.server.generation.as_ref(), "server")?;
+        Ok(server.or(&ai))
+    }
+
+    /// `params`, the `generation` table of the `table` section of `config`, if they are sane.
+    fn checked(
+        config: &Config,
+        params: Option<&GenerationParams>,
+        table: &str,
+    ) -> Result<GenerationParams, String> {
+        let Some(params) = params else {
+            return Ok(GenerationParams::default());
+        };
+        params.check().map_err(|(field, requirement)| {
+            format!(
+                "invalid {}: {}.generation.{} must be {}",
+                config.path().display(),
+                table,
+                field,
+                requirement
+            )
+        })?;
+        Ok(params.clone())
+    }
+
+    /// Why these are not sane, if they are not: the field and what it must be.
+    fn check(&self) -> Result<(), (&'static str, String)> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=MAX_TEMPERATURE).contains(&temperature) {
+                return Err((
+                    "temperature",
+                    format!("a number from 0 to {}", MAX_TEMPERATURE),
+                ));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(top_p > 0.0 && top_p <= 1.0) {
+                return Err(("top_p", "a number above 0, up to 1".to_string()));
+            }
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            if !(1..=MAX_TOKENS_LIMIT).contains(&max_tokens) {
+                return Err(("max_tokens", format!("from 1 to {}", MAX_TOKENS_LIMIT)));
+            }
+        }
+        if let Some(penalty) = self.repeat_penalty {
+            if !(-MAX_REPEAT_PENALTY..=MAX_REPEAT_PENALTY).contains(&penalty) {
+                return Err((
+                    "repeat_penalty",
+                    format!(
+                        "a number from -{} to {}",
+                        MAX_REPEAT_PENALTY, MAX_REPEAT_PENALTY
+                    ),
+                ));
+            }
+        }
+        if self.stop.len() > MAX_STOP_SEQUENCES {
+            return Err(("stop", format!("at most {} sequences", MAX_STOP_SEQUENCES)));
+        }
+        if self
+            .stop
+            .iter()
+            .any(|seq| seq.is_empty() || seq.len() > MAX_STOP_BYTES)
+        {
+            return Err((
+                "stop",
+                format!("sequences of 1 to {} bytes", MAX_STOP_BYTES),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Why a chat request asking for these cannot be answered, if they are not sane.
+    pub fn validate(&self) -> Result<(), ApiError> {
+        self.check().map_err(|(field, requirement)| {
+            ApiError::new(
+                "invalid_parameter",
+                format!("{} must be {}", field, requirement),
+            )
+        })
+    }
+
+    /// Why these flags of `lila chat` are not sane, if they are not.
+    pub fn validate_flags(&self) -> Result<(), String> {
+        self.check().map_err(|(field, requirement)| {
+            format!("--{} must be {}", field.replace('_', "-"), requirement)
+        })
+    }
+
+    /// These, with what they leave out taken from `defaults`.
+    pub fn or(&self, defaults: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            stop: if self.stop.is_empty() {
+                defaults.stop.clone()
+            } else {
+                self.stop.clone()
+            },
+            repeat_penalty: self.repeat_penalty.or(defaults.repeat_penalty),
+            seed: self.seed.or(defaults.seed),
+        }
+    }
+
+    /// `request` sampling with these.
+    pub fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(temperature) = self.temperature {
+            request = request.set_sampler_temperature(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            request = request.set_sampler_topp(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            request = request.set_sampler_max_len(max_tokens);
+        }
+        if !self.stop.is_empty() {
+            request = request.set_sampler_stop_toks(StopTokens::Seqs(self.stop.clone()));
+        }
+        if let Some(penalty) = self.repeat_penalty {
+            request = request.set_sampler_frequency_penalty(penalty as f32);
+        }
+        request
+    }
+}
+
+/// The values set, like `temperature 0, seed 42`, else that the model's defaults are used.
+impl fmt::Display for GenerationParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut values = Vec::new();
+        if let Some(temperature) = self.temperature {
+            values.push(format!("temperature {}", temperature));
+        }
+        if let Some(top_p) = self.top_p {
+            values.push(format!("top_p {}", top_p));
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            values.push(format!("max_tokens {}", max_tokens));
+        }
+        if let Some(penalty) = self.repeat_penalty {
+            values.push(format!("repeat_penalty {}", penalty));
+        }
+        if let Some(seed) = self.seed {
+            values.push(format!("seed {}", seed));
+        }
+        if !self.stop.is_empty() {
+            values.push(format!("stop {:?}", self.stop));
+        }
+        if values.is_empty() {
+            write!(f, "the model's defaults")
+        } else {
+            write!(f, "{}", values.join(", "))
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Bytes every GGUF file starts with.
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+/// Longest metadata key or string accepted; a longer one means the file is corrupt.
+const MAX_STRING_BYTES: u64 = 16 * 1024 * 1024;
+/// Most metadata entries accepted.
+const MAX_ENTRIES: u64 = 1024 * 1024;
+
+/// What lila needs from the metadata of a GGUF file.
+#[derive(Debug, Default)]
+pub struct GgufInfo {
+    pub architecture: Option<String>,
+    /// Tokens the model was trained to attend to.
+    pub context_length: Option<u64>,
+    /// Whether the tokenizer is embedded in the file.
+    pub has_tokenizer: bool,
+}
+
+/// Whether `model` names a GGUF file on disk rather than a model of the Hugging Face hub.
+pub fn is_gguf_path(model: &str) -> bool {
+    model.to_ascii_lowercase().ends_with(".gguf")
+}
+
+/// The folder of the GGUF file at `path`, if a `tokenizer.json` lies next to it there;
+/// it is used instead of the tokenizer embedded in the file.
+pub fn adjacent_tokenizer(path: &Path) -> Option<PathBuf> {
+    let folder = gguf_folder(path);
+    folder.join("tokenizer.json").is_file().then_some(folder)
+}
+
+/// The folder holding the GGUF file at `path`.
+pub fn gguf_folder(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// The metadata of the GGUF file at `path`, or why lila cannot load it: it is missing, is
+/// not a GGUF file, is cut short, or has no tokenizer, embedded or next to it.
+pub fn check_gguf(path: &Path) -> Result<GgufInfo, String> {
+    if !path.is_file() {
+        return Err(format!("model file {} does not exist", path.display()));
+    }
+    let info = read_gguf_info(path).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            format!("model file {} is truncated or corrupt", path.display())
+        }
+        _ => format!("cannot read model file {}: {}", path.display(), e),
+    })?;
+    if !info.has_tokenizer && adjacent_tokenizer(path).is_none() {
+        return Err(format!(
+            "model file {} has no embedded tokenizer; put its tokenizer.json next to it",
+            path.display()
+        ));
+    }
+    Ok(info)
+}
+
+/// Reads the metadata at the start of the GGUF file at `path`, skipping the values lila
+/// does not need.
+pub fn read_gguf_info(path: &Path) -> io::Result<GgufInfo> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != GGUF_MAGIC {
+        return Err(invalid("not a GGUF file"));
+    }
+    let version = read_u32(&mut reader)?;
+    if !(2..=3).contains(&version) {
+        return Err(invalid(&format!(
+            "GGUF version {} is not supported; convert the model again with a recent llama.cpp",
+            version
+        )));
+    }
+    let _tensors = read_u64(&mut reader)?;
+    let entries = read_u64(&mut reader)?;
+    if entries > MAX_ENTRIES {
+        return Err(invalid("the metadata is corrupt"));
+    }
+
+    let mut info = GgufInfo::default();
+    for _ in 0..entries {
+        let key = read_string(&mut reader)?;
+        let value_type = read_u32(&mut reader)?;
+        match key.as_str() {
+            "general.architecture" if value_type == STRING => {
+                info.architecture = Some(read_string(&mut reader)?);
+            }
+            "tokenizer.ggml.model" => {
+                info.has_tokenizer = true;
+                skip_value(&mut reader, value_type)?;
+            }
+            key if key.ends_with(".context_length")
+                && info
+                    .architecture
+                    .as_ref()
+                    .is_none_or(|arch| key == format!("{}.context_length", arch)) =>
+            {
+                info.context_length = read_integer(&mut reader, value_type)?;
+            }
+            _ => skip_value(&mut reader, value_type)?,
+        }
+    }
+    Ok(info)
+}
+
+// Metadata value types of the GGUF format.
+const UINT32: u32 = 4;
+const INT32: u32 = 5;
+const STRING: u32 = 8;
+const ARRAY: u32 = 9;
+const UINT64: u32 = 10;
+const INT64: u32 = 11;
+
+/// Bytes taken by a value of fixed-size `value_type`.
+fn fixed_size(value_type: u32) -> Option<u64> {
+    match value_type {
+        0 | 1 | 7 => Some(1),
+        2 | 3 => Some(2),
+        UINT32 | INT32 | 6 => Some(4),
+        UINT64 | INT64 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+fn skip_value(reader: &mut impl Read, value_type: u32) -> io::Result<()> {
+    match value_type {
+        STRING => {
+            let len = read_u64(reader)?;
+            skip(reader, checked_len(len)?)
+        }
+        ARRAY => {
+            let item_type = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            match fixed_size(item_type) {
+                Some(size) => skip(reader, len.saturating_mul(size)),
+                None => (0..len).try_for_each(|_| skip_value(reader, item_type)),
+            }
+        }
+        other => match fixed_size(other) {
+            Some(size) => skip(reader, size),
+            None => Err(invalid("the metadata is corrupt")),
+        },
+    }
+}
+
+/// An integer value of `value_type`, else `None` after skipping it.
+fn read_integer(reader: &mut impl Read, value_type: u32) -> io::Result<Option<u64>> {
+    Ok(match value_type {
+        UINT32 | INT32 => Some(read_u32(reader)? as u64),
+        UINT64 | INT64 => Some(read_u64(reader)?),
+        _ => {
+            skip_value(reader, value_type)?;
+            None
+        }
+    })
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let len = checked_len(read_u64(reader)?)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn checked_len(len: u64) -> io::Result<u64> {
+    if len > MAX_STRING_BYTES {
+        return Err(invalid("the metadata is corrupt"));
+    }
+    Ok(len)
+}
+
+fn skip(reader: &mut impl Read, bytes: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(bytes), &mut io::sink())?;
+    if skipped != bytes {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,179 @@
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use sysinfo::Disks;
+
+use crate::server::models::MODEL_PATH_VAR;
+
+/// Environment variable keeping mistral.rs off the network, loading models from the
+/// Hugging Face cache only; `--offline` sets it.
+pub const OFFLINE_VAR: &str = "HF_HUB_OFFLINE";
+/// Bytes a downloaded model takes per parameter: the hub serves 16-bit weights.
+const BYTES_PER_PARAMETER: f64 = 2.0;
+/// Parameters, in billions, of the models lila is known to run with whose ids do not say.
+const PARAMETERS: &[(&str, f64)] = &[
+    ("microsoft/Phi-3.5-mini-instruct", 3.8),
+    ("microsoft/Phi-4-mini-instruct", 3.8),
+    ("microsoft/Phi-3-mini-4k-instruct", 3.8),
+    ("microsoft/Phi-3-mini-128k-instruct", 3.8),
+];
+
+/// Parts of the error chain when the hub cannot be reached.
+const UNREACHABLE: &[&str] = &[
+    "dns error",
+    "failed to lookup address",
+    "connection refused",
+    "network is unreachable",
+    "no route to host",
+    "tcp connect error",
+    "error sending request",
+    "connection reset",
+    "operation timed out",
+];
+/// Parts of the error chain when the hub refuses the download.
+const REFUSED: &[&str] = &[
+    "status code 401",
+    "status code 403",
+    "401 unauthorized",
+    "403 forbidden",
+    "gated repo",
+    "access to model",
+];
+/// Parts of the error chain when the disk filled up.
+const DISK_FULL: &[&str] = &["no space left on device", "os error 28", "disk full"];
+
+/// Keeps mistral.rs off the network for the rest of the process, as `--offline` asks. Call
+/// it before any thread starts.
+pub fn go_offline() {
+    std::env::set_var(OFFLINE_VAR, "1");
+}
+
+/// Whether models may only come from the Hugging Face cache.
+pub fn offline() -> bool {
+    std::env::var(OFFLINE_VAR)
+        .is_ok_and(|value| matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+/// Where the Hugging Face hub keeps downloaded models: `HF_HUB_CACHE`, else `hub` in
+/// `HF_HOME`, else `~/.cache/huggingface/hub`.
+pub fn cache_dir() -> PathBuf {
+    if let Some(cache) = std::env::var_os("HF_HUB_CACHE").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(cache);
+    }
+    match std::env::var_os("HF_HOME").filter(|dir| !dir.is_empty()) {
+        Some(home) => PathBuf::from(home).join("hub"),
+        None => dirs::home_dir()
+            .unwrap_or_default()
+            .join(".cache")
+            .join("huggingface")
+            .join("hub"),
+    }
+}
+
+/// Whether a snapshot of `model_id` was downloaded to the cache.
+pub fn is_cached(model_id: &str) -> bool {
+    let folder = format!("models--{}", model_id.replace('/', "--"));
+    cache_dir()
+        .join(folder)
+        .join("snapshots")
+        .read_dir()
+        .is_ok_and(|mut snapshots| snapshots.next().is_some())
+}
+
+/// The file `name` of a snapshot of `model_id` in the cache, if one was downloaded with it.
+pub fn cached_file(model_id: &str, name: &str) -> Option<PathBuf> {
+    let folder = format!("models--{}", model_id.replace('/', "--"));
+    cache_dir()
+        .join(folder)
+        .join("snapshots")
+        .read_dir()
+        .ok()?
+        .filter_map(|snapshot| snapshot.ok())
+        .map(|snapshot| snapshot.path().join(name))
+        .find(|file| file.is_file())
+}
+
+/// Why `model_id` cannot be loaded without the network, if it is not in the cache.
+pub fn check_offline(model_id: &str) -> Result<(), String> {
+    if !offline() || is_cached(model_id) {
+        return Ok(());
+    }
+    Err(format!(
+        "{} was never downloaded to {} and the hub is offline ({}); load it once online, or set {} to a local GGUF file",
+        model_id,
+        cache_dir().display(),
+        OFFLINE_VAR,
+        MODEL_PATH_VAR
+    ))
+}
+
+/// What to do about `error`, the failure to load `model_id` from the hub: the error itself
+/// unless it is one of the failures users can fix.
+pub fn explain_load_error(model_id: &str, error: &str) -> String {
+    let lowercase = error.to_lowercase();
+    let says = |parts: &[&str]| parts.iter().any(|part| lowercase.contains(part));
+    if says(UNREACHABLE) {
+        return format!(
+            "cannot reach the Hugging Face hub to download {}; check the network connection, or set {} to a local GGUF file (a model downloaded before loads with --offline)",
+            model_id, MODEL_PATH_VAR
+        );
+    }
+    if says(REFUSED) {
+        return format!(
+            "the Hugging Face hub refused to let {} be downloaded; accept the model license on https://huggingface.co/{}, then log in with `huggingface-cli login`",
+            model_id, model_id
+        );
+    }
+    if !says(DISK_FULL) {
+        return error.to_string();
+    }
+    let cache = cache_dir();
+    let cause = format!(
+        "the disk filled up while downloading {} to {}",
+        model_id,
+        cache.display()
+    );
+    match (download_gigabytes(model_id), available_gigabytes(&cache)) {
+        (Some(needed), Some(free)) => format!(
+            "{}; free {:.0} GB and try again (the model takes about {:.1} GB, {:.1} GB is free)",
+            cause,
+            (needed - free).max(1.0).ceil(),
+            needed,
+            free
+        ),
+        (Some(needed), None) => format!(
+            "{}; free about {:.0} GB and try again",
+            cause,
+            needed.ceil()
+        ),
+        (None, _) => format!(
+            "{}; free about {} GB per billion parameters of the model and try again",
+            cause, BYTES_PER_PARAMETER
+        ),
+    }
+}
+
+/// About how many GB downloading `model_id` takes, from its parameters: those lila knows
+/// of, else the size in its id, like `Llama-3.2-3B-Instruct`.
+fn download_gigabytes(model_id: &str) -> Option<f64> {
+    let billions = match PARAMETERS.iter().find(|(id, _)| *id == model_id) {
+        Some((_, billions)) => *billions,
+        None => Regex::new(r"(?i)\b(\d+(?:\.\d+)?)b\b")
+            .ok()?
+            .captures(model_id)?[1]
+            .parse()
+            .ok()?,
+    };
+    Some(billions * BYTES_PER_PARAMETER)
+}
+
+/// GB free on the disk holding `path`, or the nearest of its folders that exists.
+fn available_gigabytes(path: &Path) -> Option<f64> {
+    let existing = path.ancestors().find(|folder| folder.exists())?;
+    let existing = existing.canonicalize().ok()?;
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| existing.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space() as f64 / 1_000_000_000.0)
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the server listens.
+#[derive(Debug, Clone)]
+pub enum Listen {
+    /// A TCP address, such as `127.0.0.1:8080`.
+    Tcp(String),
+    /// A Unix domain socket, created for the server, readable by its user only and removed
+    /// when it stops.
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listen::Tcp(address) => write!(f, "{}", address),
+            Listen::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Why a socket cannot be bound at `path`: a file that is not a socket is there, or
+/// another server listens on the socket. actix replaces what is at the path when binding,
+/// so only a socket left by a server that did not stop cleanly may be.
+#[cfg(unix)]
+pub fn check_socket_path(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    let Ok(metadata) = path.symlink_metadata() else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another server listens on {}", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Lets only the server's user connect to the socket at `path`.
+#[cfg(unix)]
+pub fn restrict_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+}
+
+/// Removes the socket at `path` once the server stopped.
+pub fn remove_socket(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            eprintln!("Cannot remove socket {}: {}", path.display(), e);
+        }
+    }
+}
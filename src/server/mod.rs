@@ -1,2 +1,21 @@
+pub mod auth;
+pub mod backend;
 pub mod chat;
+pub mod config;
+pub mod context;
+pub mod daemon;
+pub mod generation;
+pub mod gguf;
+pub mod hub;
+pub mod listen;
+pub mod models;
+pub mod rate_limit;
+pub mod render;
+pub mod sessions;
+pub mod shutdown;
 pub mod start;
+pub mod tangle;
+#[cfg(test)]
+pub mod testing;
+pub mod tls;
+pub mod ws;
@@ -0,0 +1,299 @@
+use colored::Colorize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::server::backend::{Backend, ChatModel, Isq};
+use crate::server::chat::{ApiError, SharedModel};
+use crate::server::gguf::{adjacent_tokenizer, is_gguf_path, read_gguf_info};
+use crate::server::hub::cached_file;
+use crate::utils::config::Config;
+use crate::utils::tokens::TokenCounter;
+
+/// Environment variable naming the default chat model.
+pub const MODEL_VAR: &str = "LILA_AI_MODEL";
+/// Environment variable naming a GGUF file to use as the default chat model instead.
+pub const MODEL_PATH_VAR: &str = "LILA_AI_MODEL_PATH";
+/// The default chat model unless `LILA_AI_MODEL` or `[server] default_model` names another.
+pub const DEFAULT_MODEL: &str = "microsoft/Phi-3.5-mini-instruct";
+/// Context windows, in tokens, of the models lila is known to run with.
+const CONTEXT_LENGTHS: &[(&str, usize)] = &[
+    ("microsoft/Phi-3.5-mini-instruct", 131_072),
+    ("microsoft/Phi-3-mini-4k-instruct", 4_096),
+    ("microsoft/Phi-3-mini-128k-instruct", 131_072),
+    ("meta-llama/Llama-3.2-1B-Instruct", 131_072),
+    ("meta-llama/Llama-3.2-3B-Instruct", 131_072),
+    ("meta-llama/Llama-3.1-8B-Instruct", 131_072),
+    ("mistralai/Mistral-7B-Instruct-v0.3", 32_768),
+    ("Qwen/Qwen2.5-7B-Instruct", 32_768),
+    ("google/gemma-2-2b-it", 8_192),
+];
+/// A model `lila init` can recommend, with what it needs to run well.
+#[derive(Debug, Clone, Copy)]
+pub struct CatalogModel {
+    pub id: &'static str,
+    /// Billions of parameters.
+    pub parameters_b: f64,
+    /// RAM, in GB, to run it on the CPU at `isq` beside the rest of the system.
+    pub min_ram_gb: f64,
+    /// GPU memory, in GB, to hold it at `isq` with its context.
+    pub min_vram_gb: f64,
+    /// The quantization it is recommended with.
+    pub isq: Isq,
+}
+
+impl CatalogModel {
+    /// The size of its weights at its `isq`, in GB.
+    pub fn weights_gb(&self) -> f64 {
+        self.parameters_b * self.isq.bits_per_weight() / 8.0
+    }
+}
+
+/// The models `lila init` recommends from, smallest first.
+pub const MODEL_CATALOG: &[CatalogModel] = &[
+    CatalogModel {
+        id: "meta-llama/Llama-3.2-1B-Instruct",
+        parameters_b: 1.24,
+        min_ram_gb: 4.0,
+        min_vram_gb: 2.0,
+        isq: Isq::Q8_0,
+    },
+    CatalogModel {
+        id: "Qwen/Qwen2.5-Coder-3B-Instruct",
+        parameters_b: 3.09,
+        min_ram_gb: 8.0,
+        min_vram_gb: 3.0,
+        isq: Isq::Q5K,
+    },
+    CatalogModel {
+        id: "microsoft/Phi-3.5-mini-instruct",
+        parameters_b: 3.82,
+        min_ram_gb: 8.0,
+        min_vram_gb: 4.0,
+        isq: Isq::Q4K,
+    },
+    CatalogModel {
+        id: "microsoft/Phi-4-mini-instruct",
+        parameters_b: 3.84,
+        min_ram_gb: 8.0,
+        min_vram_gb: 4.0,
+        isq: Isq::Q4K,
+    },
+    CatalogModel {
+        id: "Qwen/Qwen2.5-7B-Instruct",
+        parameters_b: 7.62,
+        min_ram_gb: 16.0,
+        min_vram_gb: 6.0,
+        isq: Isq::Q4K,
+    },
+];
+
+/// Models kept loaded at once unless `[server] max_loaded_models` says otherwise.
+pub const DEFAULT_MAX_LOADED_MODELS: usize = 1;
+
+/// Which models chat requests may ask for, from `[server]` in a `Lila.toml`.
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+    /// Ids a request may name in `model`.
+    pub allowed: Vec<String>,
+    /// The model of requests that name none, loaded when the server starts.
+    pub default: String,
+    pub max_loaded: usize,
+}
+
+impl ModelConfig {
+    /// The `default_model`, `models` and `max_loaded_models` in the `[server]` section of
+    /// `config`, with `LILA_AI_MODEL` taking precedence over `default_model`. Without
+    /// `models`, only the default model is allowed; with them, it stays the default if it
+    /// is one of them, else the first one is. A GGUF file in `LILA_AI_MODEL_PATH` is the
+    /// default over all of them.
+    pub fn load(config: &Config) -> Result<ModelConfig, String> {
+        let mut models = ModelConfig::listed(config)?;
+        if let Some(path) = std::env::var(MODEL_PATH_VAR).ok().filter(|p| !p.is_empty()) {
+            models.set_default(path);
+        }
+        Ok(models)
+    }
+
+    /// `load` without `LILA_AI_MODEL_PATH`.
+    fn listed(config: &Config) -> Result<ModelConfig, String> {
+        let from_env = std::env::var(MODEL_VAR).ok().filter(|id| !id.is_empty());
+        let default = from_env
+            .clone()
+            .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        let mut models = ModelConfig {
+            allowed: vec![default.clone()],
+            default,
+            max_loaded: DEFAULT_MAX_LOADED_MODELS,
+        };
+        let server = &config.server;
+        if let Some(id) = &server.default_model {
+            if id.is_empty() {
+                return Err(format!(
+                    "invalid {}: server.default_model must be a non-empty model id",
+                    config.path().display()
+                ));
+            }
+            if from_env.is_none() {
+                models.default = id.clone();
+                models.allowed = vec![id.clone()];
+            }
+        }
+        if let Some(allowed) = &server.models {
+            if allowed.is_empty() || allowed.iter().any(|id| id.is_empty()) {
+                return Err(format!(
+                    "invalid {}: server.models must be a non-empty list of model ids",
+                    config.path().display()
+                ));
+            }
+            if !allowed.contains(&models.default) {
+                models.default = allowed[0].clone();
+            }
+            models.allowed = allowed.clone();
+        }
+        if let Some(max) = server.max_loaded_models {
+            if max <= 0 {
+                return Err(format!(
+                    "invalid {}: server.max_loaded_models must be a positive integer",
+                    config.path().display()
+                ));
+            }
+            models.max_loaded = max as usize;
+        }
+        Ok(models)
+    }
+
+    /// Makes `id` the default model, allowing it if it is not yet.
+    pub fn set_default(&mut self, id: String) {
+        if !self.allowed.contains(&id) {
+            self.allowed.insert(0, id.clone());
+        }
+        self.default = id;
+    }
+}
+
+/// The context window of `model_id` in tokens, if it is a model lila knows or a GGUF file
+/// saying it.
+pub fn context_length(model_id: &str) -> Option<usize> {
+    if is_gguf_path(model_id) {
+        let info = read_gguf_info(Path::new(model_id)).ok()?;
+        return info.context_length.map(|tokens| tokens as usize);
+    }
+    CONTEXT_LENGTHS
+        .iter()
+        .find(|(id, _)| id.eq_ignore_ascii_case(model_id))
+        .map(|(_, tokens)| *tokens)
+}
+
+/// The `tokenizer.json` of `model_id`, if it is on disk: next to its GGUF file, or in the
+/// Hugging Face cache with the model.
+fn tokenizer_file(model_id: &str) -> Option<PathBuf> {
+    if is_gguf_path(model_id) {
+        return adjacent_tokenizer(Path::new(model_id)).map(|folder| folder.join("tokenizer.json"));
+    }
+    cached_file(model_id, "tokenizer.json")
+}
+
+/// Counts the tokens of requests to `model_id` with its tokenizer when it is on disk, else
+/// estimates them. The tokenizer is loaded once per model.
+pub fn token_counter(model_id: &str) -> Arc<TokenCounter> {
+    static COUNTERS: OnceLock<Mutex<HashMap<String, Arc<TokenCounter>>>> = OnceLock::new();
+    let mut counters = COUNTERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(counter) = counters.get(model_id) {
+        return counter.clone();
+    }
+    let counter = match tokenizer_file(model_id).map(|file| TokenCounter::load(&file)) {
+        Some(Ok(counter)) => counter,
+        Some(Err(e)) => {
+            eprintln!("{} {}; estimating tokens instead", "Warning:".yellow(), e);
+            TokenCounter::estimate()
+        }
+        None => TokenCounter::estimate(),
+    };
+    let counter = Arc::new(counter);
+    counters.insert(model_id.to_string(), counter.clone());
+    counter
+}
+
+/// The chat models loaded, each shared by every request asking for it. A model is
+/// loaded, in the background, when first asked for; past `max_loaded`, the model used
+/// least recently is let go.
+pub struct Models {
+    config: ModelConfig,
+    backend: Backend,
+    /// Least recently used first.
+    loaded: Mutex<Vec<SharedModel>>,
+}
+
+impl Models {
+    /// Starts loading the default model of `config` with `backend`.
+    pub fn new(config: ModelConfig, backend: Backend) -> Models {
+        let default = SharedModel::load_in_background(config.default.clone(), backend.clone());
+        Models {
+            config,
+            backend,
+            loaded: Mutex::new(vec![default]),
+        }
+    }
+
+    pub fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    /// The id of the model `requested`, else of the default one, if requests may use it.
+    pub fn resolve(&self, requested: Option<&str>) -> Result<String, ApiError> {
+        let Some(id) = requested else {
+            return Ok(self.config.default.clone());
+        };
+        if self.config.allowed.iter().any(|allowed| allowed == id) {
+            return Ok(id.to_string());
+        }
+        Err(ApiError::new(
+            "unknown_model",
+            format!(
+                "model {} is not allowed; use one of: {}",
+                id,
+                self.config.allowed.join(", ")
+            ),
+        ))
+    }
+
+    /// Model `id` once it is loaded, else why it cannot answer yet. A model not loaded
+    /// starts loading, in place of the one used least recently if `max_loaded` are.
+    pub fn get(&self, id: &str) -> Result<ChatModel, String> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        let model = match loaded.iter().position(|model| model.model_id == id) {
+            Some(i) => loaded.remove(i),
+            None => {
+                while loaded.len() >= self.config.max_loaded {
+                    let evicted = loaded.remove(0);
+                    println!("Unloading model {} to load {}", evicted.model_id, id);
+                    evicted.release();
+                }
+                println!("Loading model {}", id);
+                SharedModel::load_in_background(id.to_string(), self.backend.clone())
+            }
+        };
+        loaded.push(model.clone());
+        drop(loaded);
+        model.get()
+    }
+
+    /// The models loaded or loading, least recently used first.
+    pub fn resident(&self) -> Vec<SharedModel> {
+        self.loaded
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Lets go of every model, as `SharedModel::release` does.
+    pub fn release(&self) {
+        for model in self.resident() {
+            model.release();
+        }
+    }
+}
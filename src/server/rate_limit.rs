@@ -0,0 +1,331 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, ResponseError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::server::auth::ServerToken;
+use crate::server::chat::{ApiError, ServerError};
+use crate::utils::config::Config;
+
+/// Chat requests a client may make per minute unless `[server] requests_per_minute` says
+/// otherwise.
+pub const DEFAULT_REQUESTS_PER_MINUTE: u64 = 30;
+/// Chat requests a client may make at once after a quiet spell unless `[server] burst`
+/// says otherwise.
+pub const DEFAULT_BURST: u64 = 10;
+/// Chat answers generated at the same time, by all clients, unless
+/// `[server] max_concurrent_generations` says otherwise.
+pub const DEFAULT_MAX_GENERATIONS: u64 = 1;
+/// Chat answers that may wait for their turn unless `[server] max_queued_generations`
+/// says otherwise.
+pub const DEFAULT_MAX_QUEUED: u64 = 8;
+/// Clients remembered before those whose allowance has refilled are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// How many chat requests the server takes, from `[server]` in a `Lila.toml`.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    pub requests_per_minute: u64,
+    pub burst: u64,
+    pub max_generations: usize,
+    pub max_queued: usize,
+}
+
+impl RateLimits {
+    /// The `requests_per_minute`, `burst`, `max_concurrent_generations` and
+    /// `max_queued_generations` in the `[server]` section of `config`, each defaulting when
+    /// missing.
+    pub fn load(config: &Config) -> Result<RateLimits, String> {
+        let mut limits = RateLimits {
+            requests_per_minute: DEFAULT_REQUESTS_PER_MINUTE,
+            burst: DEFAULT_BURST,
+            max_generations: DEFAULT_MAX_GENERATIONS as usize,
+            max_queued: DEFAULT_MAX_QUEUED as usize,
+        };
+        let server = &config.server;
+        let positive = |key: &str, entry: Option<i64>, default: u64| match entry {
+            None => Ok(default),
+            Some(n) if n > 0 => Ok(n as u64),
+            Some(_) => Err(format!(
+                "invalid {}: server.{} must be a positive integer",
+                config.path().display(),
+                key
+            )),
+        };
+        limits.requests_per_minute = positive(
+            "requests_per_minute",
+            server.requests_per_minute,
+            limits.requests_per_minute,
+        )?;
+        limits.burst = positive("burst", server.burst, limits.burst)?;
+        limits.max_generations = positive(
+            "max_concurrent_generations",
+            server.max_concurrent_generations,
+            DEFAULT_MAX_GENERATIONS,
+        )? as usize;
+        if let Some(n) = server.max_queued_generations {
+            if n < 0 {
+                return Err(format!(
+                    "invalid {}: server.max_queued_generations must be a non-negative integer",
+                    config.path().display()
+                ));
+            }
+            limits.max_queued = n as usize;
+        }
+        Ok(limits)
+    }
+}
+
+/// What is left of one client's allowance.
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// A token bucket per client: each chat request takes a token, and tokens come back at
+/// `requests_per_minute` up to `burst`.
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+/// The rate limit and how clients stand against it, for `/health`.
+#[derive(Debug, Serialize)]
+pub struct RateLimitUsage {
+    pub requests_per_minute: u64,
+    pub burst: u64,
+    /// Clients that made a chat request recently.
+    pub clients: usize,
+    /// Of those, the ones out of requests right now.
+    pub limited_clients: usize,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> RateLimiter {
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a request from the allowance of `client`, or says how long until it has one.
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.limits.burst as f64);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.limits.burst as f64,
+            refilled_at: now,
+        });
+        let tokens = self.refill(bucket, now);
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - tokens) / self.per_second()))
+    }
+
+    pub fn usage(&self) -> RateLimitUsage {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets.retain(|_, bucket| self.refill(bucket, now) < self.limits.burst as f64);
+        RateLimitUsage {
+            requests_per_minute: self.limits.requests_per_minute,
+            burst: self.limits.burst,
+            clients: buckets.len(),
+            limited_clients: buckets
+                .values()
+                .filter(|bucket| bucket.tokens < 1.0)
+                .count(),
+        }
+    }
+
+    /// Adds the tokens earned since `bucket` was last refilled and returns its tokens.
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let earned = now.duration_since(bucket.refilled_at).as_secs_f64() * self.per_second();
+        bucket.tokens = (bucket.tokens + earned).min(self.limits.burst as f64);
+        bucket.refilled_at = now;
+        bucket.tokens
+    }
+
+    fn per_second(&self) -> f64 {
+        self.limits.requests_per_minute as f64 / 60.0
+    }
+}
+
+/// Who `req` comes from, for the rate limit: the address it came from and, when the server
+/// requires a token, the bearer token it sent, hashed so the limiter keeps no tokens. On an
+/// open server the token is left out, as a client could send a new one with each request to
+/// get a fresh allowance. Clients behind one proxy share an address.
+pub fn client_key(req: &HttpRequest) -> String {
+    let address = req
+        .peer_addr()
+        .map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string());
+    let requires_token = req
+        .app_data::<web::Data<Option<ServerToken>>>()
+        .is_some_and(|token| token.is_some());
+    if !requires_token {
+        return address;
+    }
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) => {
+            let digest = Sha256::digest(token.trim().as_bytes());
+            let hash: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{} {}", address, hash)
+        }
+        None => address,
+    }
+}
+
+/// The error for a client out of requests, to retry after `wait`.
+pub fn rate_limited(wait: Duration) -> ApiError {
+    ApiError::new(
+        "rate_limited",
+        format!(
+            "too many chat requests; retry in {} seconds",
+            retry_after_seconds(wait)
+        ),
+    )
+}
+
+/// Whole seconds to wait, rounded up so a client retrying then gets through.
+pub fn retry_after_seconds(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// Middleware answering 429 with `Retry-After` to clients out of requests when the app
+/// has a `RateLimiter`.
+pub async fn limit_requests<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let checked = match req.app_data::<web::Data<RateLimiter>>() {
+        Some(limiter) => limiter.check(&client_key(req.request())),
+        None => Ok(()),
+    };
+    let wait = match checked {
+        Ok(()) => return Ok(next.call(req).await?.map_into_left_body()),
+        Err(wait) => wait,
+    };
+    let error = ServerError::RateLimited(rate_limited(wait), retry_after_seconds(wait));
+    Ok(req
+        .into_response(error.error_response())
+        .map_into_right_body())
+}
+
+/// Seconds a client is told to wait when the queue of chat answers is full.
+pub const BUSY_RETRY_SECONDS: u64 = 5;
+
+/// The error for a chat request when `max` answers are being generated and `max_queued`
+/// more are waiting already.
+pub fn server_busy(max: usize, max_queued: usize) -> ApiError {
+    ApiError::new(
+        "server_busy",
+        format!(
+            "the server is generating {} answers with {} more waiting (server.max_concurrent_generations, server.max_queued_generations); retry in {} seconds",
+            max, max_queued, BUSY_RETRY_SECONDS
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{http::StatusCode, App, HttpResponse};
+    use std::net::SocketAddr;
+
+    fn limits(burst: u64) -> RateLimits {
+        RateLimits {
+            requests_per_minute: 1,
+            burst,
+            max_generations: 1,
+            max_queued: 0,
+        }
+    }
+
+    /// A request from `peer` sending the bearer `token`.
+    fn request(peer: &str, token: &str) -> TestRequest {
+        TestRequest::get()
+            .uri("/chat")
+            .peer_addr(peer.parse::<SocketAddr>().unwrap())
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+    }
+
+    #[actix_web::test]
+    async fn rotating_tokens_on_an_open_server_are_still_limited() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::new(limits(2))))
+                .app_data(web::Data::new(None::<ServerToken>))
+                .wrap(from_fn(limit_requests))
+                .route("/chat", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let mut statuses = Vec::new();
+        for token in ["a", "b", "c", "d"] {
+            let response = call_service(&app, request("10.0.0.1:4000", token).to_request()).await;
+            statuses.push(response.status());
+        }
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::TOO_MANY_REQUESTS
+            ]
+        );
+        // Another address has its own allowance.
+        let response = call_service(&app, request("10.0.0.2:4000", "a").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn the_token_is_part_of_the_key_only_when_the_server_requires_one() {
+        let open = request("10.0.0.1:4000", "secret")
+            .app_data(web::Data::new(None::<ServerToken>))
+            .to_http_request();
+        assert_eq!(client_key(&open), "10.0.0.1");
+
+        let config = Config::parse(
+            std::path::Path::new("/book/Lila.toml"),
+            "[server]\ntoken = \"secret\"\n",
+        )
+        .unwrap()
+        .0;
+        let token = ServerToken::load(&config).unwrap();
+        let guarded = request("10.0.0.1:4000", "secret")
+            .app_data(web::Data::new(token))
+            .to_http_request();
+        let key = client_key(&guarded);
+        assert!(key.starts_with("10.0.0.1 "), "{}", key);
+        assert!(!key.contains("secret"), "{}", key);
+    }
+
+    #[test]
+    fn a_client_gets_its_burst_then_waits() {
+        let limiter = RateLimiter::new(limits(2));
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_ok());
+        let wait = limiter.check("a").unwrap_err();
+        assert!(wait > Duration::from_secs(55), "{:?}", wait);
+        assert!(limiter.check("b").is_ok());
+        let usage = limiter.usage();
+        assert_eq!((usage.clients, usage.limited_clients), (2, 1));
+    }
+}
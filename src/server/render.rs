@@ -0,0 +1,140 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+use crate::server::chat::{ApiError, ServerError};
+use crate::utils::config::Config;
+use crate::utils::markdown::{
+    highlight_theme, highlight_themes, markdown_to_html, RenderOptions, DEFAULT_THEME,
+};
+
+/// Largest Markdown `/render` and `/tangle` accept unless `[server] max_render_bytes` says
+/// otherwise.
+pub const DEFAULT_MAX_RENDER_BYTES: usize = 1024 * 1024;
+/// Room in a `/render` body for the other fields and the JSON around them.
+const RENDER_BODY_OVERHEAD_BYTES: usize = 4 * 1024;
+
+/// How `/render` renders, and how much Markdown it and `/tangle` take, from `[server]` and
+/// `[render]` in a `Lila.toml`.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderConfig {
+    pub max_bytes: usize,
+    /// Leave raw HTML out of rendered Markdown.
+    pub sanitize: bool,
+    /// Theme code blocks are highlighted with.
+    pub theme: &'static str,
+}
+
+impl RenderConfig {
+    /// The `max_render_bytes` and `sanitize_html` in the `[server]` section of `config`,
+    /// and the `theme` of its `[render]` section, each defaulting when missing.
+    pub fn load(config: &Config) -> Result<RenderConfig, String> {
+        let theme = match &config.render.theme {
+            None => DEFAULT_THEME,
+            Some(name) => highlight_theme(name).ok_or_else(|| {
+                format!(
+                    "invalid {}: render.theme must be one of {}",
+                    config.path().display(),
+                    highlight_themes().collect::<Vec<_>>().join(", ")
+                )
+            })?,
+        };
+        let mut render = RenderConfig {
+            max_bytes: DEFAULT_MAX_RENDER_BYTES,
+            sanitize: config.server.sanitize_html.unwrap_or(false),
+            theme,
+        };
+        if let Some(bytes) = config.server.max_render_bytes {
+            if bytes <= 0 {
+                return Err(format!(
+                    "invalid {}: server.max_render_bytes must be a positive integer",
+                    config.path().display()
+                ));
+            }
+            render.max_bytes = bytes as usize;
+        }
+        Ok(render)
+    }
+
+    /// Largest `/render` body read; like `ChatLimits::body_bytes`, it leaves room for JSON
+    /// escaping so a `markdown` just over the limit is refused with a clear error.
+    pub fn body_bytes(&self) -> usize {
+        2 * self.max_bytes + RENDER_BODY_OVERHEAD_BYTES
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenderRequest {
+    pub markdown: String,
+    /// Render as a chapter of the book, with heading anchors and footnotes.
+    #[serde(default)]
+    pub book: bool,
+}
+
+/// Renders posted Markdown to an HTML body, for a live preview.
+pub async fn render_handler(
+    render_req: web::Json<RenderRequest>,
+    config: web::Data<RenderConfig>,
+) -> Result<HttpResponse, ServerError> {
+    let RenderRequest { markdown, book } = render_req.into_inner();
+    if markdown.len() > config.max_bytes {
+        return Err(ServerError::PayloadTooLarge(ApiError::new(
+            "markdown_too_large",
+            format!(
+                "markdown is {} bytes, more than the {} allowed (server.max_render_bytes)",
+                markdown.len(),
+                config.max_bytes
+            ),
+        )));
+    }
+    let options = RenderOptions {
+        book,
+        sanitize: config.sanitize,
+        theme: config.theme,
+    };
+    let html = web::block(move || markdown_to_html(&markdown, options))
+        .await
+        .map_err(ServerError::internal)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn load(content: &str) -> Result<RenderConfig, String> {
+        let (config, _) = Config::parse(Path::new("/book/Lila.toml"), content).unwrap();
+        RenderConfig::load(&config)
+    }
+
+    #[test]
+    fn the_theme_is_one_syntect_knows() {
+        assert_eq!(load("").unwrap().theme, DEFAULT_THEME);
+        let render = load("[render]\ntheme = \"InspiredGitHub\"\n").unwrap();
+        assert_eq!(render.theme, "InspiredGitHub");
+        let error = load("[render]\ntheme = \"dark\"\n").unwrap_err();
+        assert!(
+            error.starts_with("invalid /book/Lila.toml: render.theme must be one of "),
+            "{}",
+            error
+        );
+        assert!(error.contains("InspiredGitHub"), "{}", error);
+    }
+
+    #[test]
+    fn code_blocks_are_highlighted_with_the_theme() {
+        let markdown = "```rust\nfn main() {}\n```\n";
+        let dark = markdown_to_html(markdown, RenderOptions::default());
+        let light = markdown_to_html(
+            markdown,
+            RenderOptions {
+                theme: "InspiredGitHub",
+                ..RenderOptions::default()
+            },
+        );
+        assert!(dark.contains("background-color:#2b303b;"), "{}", dark);
+        assert!(light.contains("background-color:#ffffff;"), "{}", light);
+    }
+}
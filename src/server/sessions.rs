@@ -0,0 +1,142 @@
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+
+use crate::server::chat::{ApiError, ServerError, Turn};
+use crate::utils::config::Config;
+use crate::utils::database::pool::DbPool;
+use crate::utils::database::sessions::{
+    append_turn, create_session, delete_session, session_exists, session_turns,
+};
+use crate::utils::tokens::estimated_tokens;
+
+/// Tokens of a session's earlier turns given to the model with a new prompt unless
+/// `[server] history_tokens` says otherwise.
+pub const DEFAULT_HISTORY_TOKENS: usize = 2000;
+
+/// The `history_tokens` in the `[server]` section of `config` (a `Lila.toml`), else
+/// `DEFAULT_HISTORY_TOKENS`.
+pub fn history_tokens(config: &Config) -> Result<usize, String> {
+    match config.server.history_tokens {
+        None => Ok(DEFAULT_HISTORY_TOKENS),
+        Some(tokens) if tokens >= 0 => Ok(tokens as usize),
+        Some(_) => Err(format!(
+            "invalid {}: server.history_tokens must be a non-negative integer",
+            config.path().display()
+        )),
+    }
+}
+
+/// The chat sessions saved in the database, so they outlive the server. Every method
+/// reads or writes the database, so call them off the async threads.
+#[derive(Clone)]
+pub struct ChatSessions {
+    pool: DbPool,
+    /// Estimated tokens of earlier turns given to the model at most.
+    budget_tokens: usize,
+}
+
+impl ChatSessions {
+    pub fn new(pool: DbPool, budget_tokens: usize) -> ChatSessions {
+        ChatSessions {
+            pool,
+            budget_tokens,
+        }
+    }
+
+    /// Starts a session and returns its id.
+    pub fn create(&self) -> Result<String, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        create_session(&mut conn).map_err(|e| e.to_string())
+    }
+
+    /// Whether there is a session `id`.
+    pub fn exists(&self, id: &str) -> Result<bool, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        session_exists(&mut conn, id).map_err(|e| e.to_string())
+    }
+
+    /// The turns of session `id` to give the model with a new prompt: the latest ones that
+    /// fit in the budget, oldest first. `None` when there is no such session.
+    pub fn history(&self, id: &str) -> Result<Option<Vec<Turn>>, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        if !session_exists(&mut conn, id).map_err(|e| e.to_string())? {
+            return Ok(None);
+        }
+        let turns = session_turns(&mut conn, id).map_err(|e| e.to_string())?;
+        let turns = turns
+            .into_iter()
+            .map(|(prompt, answer)| Turn { prompt, answer })
+            .collect();
+        Ok(Some(trim_history(turns, self.budget_tokens)))
+    }
+
+    /// Adds `turn` to session `id`.
+    pub fn record(&self, id: &str, turn: &Turn) -> Result<(), String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        append_turn(&mut conn, id, &turn.prompt, &turn.answer).map_err(|e| e.to_string())
+    }
+
+    /// Deletes session `id`; `false` if there was no such session.
+    pub fn delete(&self, id: &str) -> Result<bool, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        delete_session(&mut conn, id).map_err(|e| e.to_string())
+    }
+}
+
+/// The latest of `turns` whose prompts and answers fit in `budget_tokens` together,
+/// oldest first.
+pub fn trim_history(mut turns: Vec<Turn>, budget_tokens: usize) -> Vec<Turn> {
+    let mut used = 0;
+    let mut kept = 0;
+    for turn in turns.iter().rev() {
+        let tokens = estimated_tokens(&turn.prompt) + estimated_tokens(&turn.answer);
+        if used + tokens > budget_tokens {
+            break;
+        }
+        used += tokens;
+        kept += 1;
+    }
+    turns.split_off(turns.len() - kept)
+}
+
+/// Answer of `POST /sessions`.
+#[derive(Debug, Serialize)]
+pub struct SessionCreated {
+    pub session_id: String,
+}
+
+/// Starts a chat session, for `/chat` requests to continue with `session_id`.
+pub async fn create_session_handler(
+    sessions: web::Data<ChatSessions>,
+) -> Result<HttpResponse, ServerError> {
+    let session_id = web::block(move || sessions.create())
+        .await
+        .map_err(ServerError::internal)?
+        .map_err(ServerError::internal)?;
+    Ok(HttpResponse::Created().json(SessionCreated { session_id }))
+}
+
+/// Deletes a chat session and its history.
+pub async fn delete_session_handler(
+    id: web::Path<String>,
+    sessions: web::Data<ChatSessions>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let lookup = id.clone();
+    let deleted = web::block(move || sessions.delete(&lookup))
+        .await
+        .map_err(ServerError::internal)?
+        .map_err(ServerError::internal)?;
+    if !deleted {
+        return Err(unknown_session(&id));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The error for a request naming session `id` when there is none.
+pub fn unknown_session(id: &str) -> ServerError {
+    ServerError::NotFound(ApiError::new(
+        "unknown_session",
+        format!("there is no session {}", id),
+    ))
+}
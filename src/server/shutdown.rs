@@ -0,0 +1,224 @@
+use crate::utils::config::Config;
+use actix_web::dev::ServerHandle;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{watch, Semaphore, SemaphorePermit};
+
+/// How long chat answers in progress may run after a stop signal unless
+/// `[server] shutdown_grace_seconds` says otherwise.
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// The `shutdown_grace_seconds` in the `[server]` section of `config` (a `Lila.toml`),
+/// else `DEFAULT_SHUTDOWN_GRACE`.
+pub fn shutdown_grace(config: &Config) -> Result<Duration, String> {
+    match config.server.shutdown_grace_seconds {
+        None => Ok(DEFAULT_SHUTDOWN_GRACE),
+        Some(seconds) if seconds >= 0 => Ok(Duration::from_secs(seconds as u64)),
+        Some(_) => Err(format!(
+            "invalid {}: server.shutdown_grace_seconds must be a non-negative integer",
+            config.path().display()
+        )),
+    }
+}
+
+/// Chat answers being generated, at most `max` at once to bound the memory they take,
+/// with at most `max_queued` more waiting their turn, first come first served. It also
+/// lets shutdown say how many answers it waits for and whether it cut any off, and tells
+/// open chat sockets when the server is stopping, so they can close.
+#[derive(Debug)]
+pub struct Generations {
+    slots: Semaphore,
+    running: AtomicUsize,
+    max: usize,
+    queued: AtomicUsize,
+    max_queued: usize,
+    cancelled: AtomicUsize,
+    stopping: watch::Sender<bool>,
+}
+
+/// A chat answer waiting for its turn; it leaves the queue when dropped.
+pub struct Queued<'a> {
+    generations: &'a Generations,
+    position: usize,
+}
+
+/// One chat answer in progress; counted as cancelled if dropped before `finish` once the
+/// server is stopping, as it is when shutdown cuts its request off. Before that, a drop is
+/// a client that went away, which is no concern of shutdown.
+pub struct Generation<'a> {
+    generations: &'a Generations,
+    finished: bool,
+    _slot: SemaphorePermit<'a>,
+}
+
+impl Generations {
+    pub fn new(max: usize, max_queued: usize) -> Generations {
+        Generations {
+            slots: Semaphore::new(max),
+            running: AtomicUsize::new(0),
+            max,
+            queued: AtomicUsize::new(0),
+            max_queued,
+            cancelled: AtomicUsize::new(0),
+            stopping: watch::Sender::new(false),
+        }
+    }
+
+    /// Queues an answer, unless `max` are running and `max_queued` are waiting already.
+    pub fn enqueue(&self) -> Option<Queued<'_>> {
+        let mut position = 0;
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                let free = self.slots.available_permits();
+                position = (queued + 1).saturating_sub(free);
+                (position <= self.max_queued).then_some(queued + 1)
+            })
+            .ok()?;
+        Some(Queued {
+            generations: self,
+            position,
+        })
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Answers waiting for one of the `max` running to finish.
+    pub fn queued(&self) -> usize {
+        self.queued
+            .load(Ordering::SeqCst)
+            .saturating_sub(self.slots.available_permits())
+    }
+
+    pub fn max_queued(&self) -> usize {
+        self.max_queued
+    }
+
+    /// Changes to `true` when the server starts stopping.
+    pub fn stopping(&self) -> watch::Receiver<bool> {
+        self.stopping.subscribe()
+    }
+
+    /// Answers that were cut off: still running, or dropped before they finished since the
+    /// server started stopping.
+    pub fn unfinished(&self) -> usize {
+        self.running() + self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl<'a> Queued<'a> {
+    /// Where the answer stood in line when queued: 0 when it could start at once, 1 when
+    /// it was next, and so on.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Waits for the answer's turn and starts it.
+    pub async fn start(self) -> Generation<'a> {
+        let generations = self.generations;
+        let slot = generations
+            .slots
+            .acquire()
+            .await
+            .expect("the generation slots are never closed");
+        generations.running.fetch_add(1, Ordering::SeqCst);
+        drop(self);
+        Generation {
+            generations,
+            finished: false,
+            _slot: slot,
+        }
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.generations.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Generation<'_> {
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Generation<'_> {
+    fn drop(&mut self) {
+        self.generations.running.fetch_sub(1, Ordering::SeqCst);
+        if !self.finished && *self.generations.stopping.borrow() {
+            self.generations.cancelled.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Waits for SIGINT (Ctrl-C) or SIGTERM and returns its name.
+pub async fn stop_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            },
+            Err(e) => {
+                eprintln!("Cannot listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
+
+/// On the first stop signal, stops `server` from accepting connections and gives the
+/// requests in progress `grace` to finish; the server cuts off those still running then.
+/// Returns on a second signal, for the caller to stop at once; otherwise never returns.
+pub async fn stop_on_signal(server: ServerHandle, grace: Duration, generations: &Generations) {
+    let signal = stop_signal().await;
+    println!(
+        "Received {}; no longer accepting requests, waiting up to {}s for {} chat answers in progress (send it again to stop now)",
+        signal,
+        grace.as_secs(),
+        generations.running()
+    );
+    generations.stopping.send_replace(true);
+    tokio::select! {
+        _ = server.stop(true) => std::future::pending().await,
+        signal = stop_signal() => println!("Received {} again; stopping now", signal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_dropped_before_stopping_are_not_cut_off() {
+        let generations = Generations::new(2, 0);
+        // A client that disconnected mid-answer while the server was running.
+        drop(generations.enqueue().unwrap().start().await);
+        generations.enqueue().unwrap().start().await.finish();
+        assert_eq!(generations.unfinished(), 0);
+
+        let running = generations.enqueue().unwrap().start().await;
+        let dropped = generations.enqueue().unwrap().start().await;
+        generations.stopping.send_replace(true);
+        assert_eq!(generations.unfinished(), 2);
+        drop(dropped);
+        assert_eq!(generations.unfinished(), 2);
+        running.finish();
+        assert_eq!(generations.unfinished(), 1);
+    }
+}
@@ -1,44 +1,995 @@
 use actix_cors::Cors;
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::error::JsonPayloadError;
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use colored::Colorize;
+use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use crate::server::chat::{run_chat_response, ChatArgs};
+use crate::schema::metadata;
+use crate::server::auth::require_token;
+use crate::server::backend::Backend;
+use crate::server::chat::{
+    run_chat_response, ApiError, ChatArgs, ModelState, ServerError, SharedModel, Turn,
+};
+use crate::server::config::ServerConfig;
+use crate::server::context::Retriever;
+use crate::server::generation::GenerationParams;
+#[cfg(unix)]
+use crate::server::listen::{check_socket_path, restrict_socket};
+use crate::server::listen::{remove_socket, Listen};
+use crate::server::models::Models;
+use crate::server::rate_limit::{
+    limit_requests, server_busy, RateLimitUsage, RateLimiter, BUSY_RETRY_SECONDS,
+};
+use crate::server::render::render_handler;
+use crate::server::sessions::{
+    create_session_handler, delete_session_handler, unknown_session, ChatSessions,
+};
+use crate::server::shutdown::{stop_on_signal, Generations};
+use crate::server::tangle::tangle_handler;
+use crate::server::ws::ws_chat_handler;
+use crate::utils::config::Config;
+use crate::utils::database::assets::find_asset;
+use crate::utils::database::documents::{document_content, list_documents, DocumentFilter};
+use crate::utils::database::pool::{open_pool, DbPool};
+use crate::utils::database::projects::ProjectScope;
+use crate::utils::database::search::{
+    search_snippets, SearchError, SnippetHit, SNIPPET_END, SNIPPET_START,
+};
+
+/// Files `/files` returns unless `limit` says otherwise.
+const DEFAULT_FILES_LIMIT: u32 = 100;
+/// Most files `/files` returns at once.
+const MAX_FILES_LIMIT: u32 = 1000;
+/// Matches `/search` returns unless `limit` says otherwise.
+const DEFAULT_SEARCH_LIMIT: u32 = 20;
+/// Most matches `/search` returns at once.
+const MAX_SEARCH_LIMIT: u32 = 100;
+/// How long `/health` waits for a database connection before reporting it unreachable.
+const HEALTH_DB_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest prompt `/chat` accepts.
+const MAX_PROMPT_BYTES: usize = 32 * 1024;
+/// Largest `file_content` `/chat` accepts unless `[server] max_context_bytes` says
+/// otherwise.
+pub const DEFAULT_MAX_CONTEXT_BYTES: usize = 1024 * 1024;
+/// Room in a `/chat` body for the tags, the other fields and the JSON around them.
+const CHAT_BODY_OVERHEAD_BYTES: usize = 64 * 1024;
+
+/// The `max_context_bytes` in the `[server]` section of `config` (a `Lila.toml`), else
+/// `DEFAULT_MAX_CONTEXT_BYTES`.
+pub fn max_context_bytes(config: &Config) -> Result<usize, String> {
+    match config.server.max_context_bytes {
+        None => Ok(DEFAULT_MAX_CONTEXT_BYTES),
+        Some(bytes) if bytes > 0 => Ok(bytes as usize),
+        Some(_) => Err(format!(
+            "invalid {}: server.max_context_bytes must be a positive integer",
+            config.path().display()
+        )),
+    }
+}
 
 #[derive(Debug, Deserialize)]
 pub struct ChatRequest {
     pub prompt: String,
     pub file_content: Option<String>,
+    /// Without `file_content`, use the saved files with all of these tags as context.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Project of the tagged files (default: the project the server was started in).
+    #[serde(default)]
+    pub project: Option<String>,
+    /// Use the tagged files of every project instead.
+    #[serde(default)]
+    pub all_projects: bool,
+    /// Also retrieve the parts of the book relevant to the prompt, as is done anyway
+    /// without `file_content` and `tags`.
+    #[serde(default)]
+    pub use_retrieval: bool,
+    /// Use the saved files as the context, whole while they fit, and list them as sources.
+    #[serde(default)]
+    pub use_db: bool,
+    /// With `use_db`, only the saved files whose paths start with one of these.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Session from `POST /sessions` to ask the prompt in, after its earlier turns; the
+    /// prompt and the response are added to it.
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Model to answer with, one of `[server] models` (default: the server's default
+    /// model).
+    #[serde(default)]
+    pub model: Option<String>,
+    /// How to sample the answer; what is left out comes from `[server.generation]`, then
+    /// `[ai.generation]`.
+    #[serde(flatten)]
+    pub generation: GenerationParams,
+    /// Add the tokens of each part of the request to the response, as `tokens`.
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+impl ChatRequest {
+    /// Why the request cannot be answered, if it has no prompt, is too large, or asks for
+    /// nonsense generation parameters.
+    pub fn validate(&self, limits: &ChatLimits) -> Result<(), ApiError> {
+        if self.prompt.trim().is_empty() {
+            return Err(ApiError::new("empty_prompt", "prompt must not be empty"));
+        }
+        self.generation.validate()?;
+        if self.use_db && (self.file_content.is_some() || !self.tags.is_empty()) {
+            return Err(ApiError::new(
+                "invalid_request",
+                "use_db cannot be combined with file_content or tags",
+            ));
+        }
+        if !self.use_db && !self.paths.is_empty() {
+            return Err(ApiError::new(
+                "invalid_request",
+                "paths only applies with use_db",
+            ));
+        }
+        if self.prompt.len() > MAX_PROMPT_BYTES {
+            return Err(ApiError::new(
+                "prompt_too_long",
+                format!(
+                    "prompt is {} bytes, more than the {} allowed",
+                    self.prompt.len(),
+                    MAX_PROMPT_BYTES
+                ),
+            ));
+        }
+        if let Some(content) = &self.file_content {
+            if content.len() > limits.max_context_bytes {
+                return Err(ApiError::new(
+                    "context_too_large",
+                    format!(
+                        "file_content is {} bytes, more than the {} allowed (server.max_context_bytes)",
+                        content.len(),
+                        limits.max_context_bytes
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The arguments answering this request with context from `book`, generating with
+    /// `defaults` where the request does not say.
+    pub fn to_args(&self, book: &SavedBook, defaults: &GenerationParams) -> ChatArgs {
+        ChatArgs {
+            prompt: Some(self.prompt.clone()),
+            file_content: self.file_content.clone(),
+            db_path: Some(book.db_path.clone()),
+            pool: Some(book.pool.clone()),
+            tags: self.tags.clone(),
+            project: self.project.clone(),
+            all_projects: self.all_projects,
+            use_retrieval: self.use_retrieval,
+            use_db: self.use_db,
+            paths: self.paths.clone(),
+            grep: Vec::new(),
+            grep_any: false,
+            retriever: Some(book.retriever.clone()),
+            generation: self.generation.or(defaults),
+            system_prompt: None,
+        }
+    }
+}
+
+/// The saved book chat requests take their context from.
+pub struct SavedBook {
+    pub db_path: PathBuf,
+    pub pool: DbPool,
+    pub retriever: Retriever,
+}
+
+/// Sizes `/chat` and `/ws/chat` accept.
+#[derive(Debug, Clone, Copy)]
+pub struct ChatLimits {
+    pub max_context_bytes: usize,
+}
+
+impl ChatLimits {
+    /// Largest chat request read. JSON escaping makes a request longer than the text it
+    /// holds, so this leaves room for a `file_content` of `max_context_bytes` to be read
+    /// and refused with a clear error rather than cut off.
+    pub fn body_bytes(&self) -> usize {
+        2 * (self.max_context_bytes + MAX_PROMPT_BYTES) + CHAT_BODY_OVERHEAD_BYTES
+    }
+}
+
+/// Answers a `/chat` or `/render` body that cannot be read as a `ChatRequest` with a `ServerError`: 413
+/// when it is over the limit, else 400.
+fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match &err {
+        JsonPayloadError::Overflow { limit }
+        | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+            ServerError::PayloadTooLarge(ApiError::new(
+                "payload_too_large",
+                format!("the request body is larger than {} bytes", limit),
+            ))
+        }
+        JsonPayloadError::ContentType => ServerError::BadRequest(ApiError::new(
+            "invalid_request",
+            "the request body must be JSON, with Content-Type: application/json",
+        )),
+        e => ServerError::BadRequest(ApiError::new(
+            "invalid_request",
+            format!("invalid request body: {}", e),
+        )),
+    }
+    .into()
+}
+
+/// Answers a query string or path that cannot be read with an `invalid_request`.
+fn extract_error(err: impl std::fmt::Display) -> actix_web::Error {
+    ServerError::BadRequest(ApiError::new(
+        "invalid_request",
+        format!("invalid request: {}", err),
+    ))
+    .into()
+}
+
+/// Query of `/files`.
+#[derive(Debug, Deserialize)]
+pub struct FilesQuery {
+    /// Only files whose saved path starts with this prefix.
+    pub path_prefix: Option<String>,
+    /// Files to skip, for the next page.
+    #[serde(default)]
+    pub offset: u32,
+    /// Files to return, at most `MAX_FILES_LIMIT` (default: `DEFAULT_FILES_LIMIT`).
+    pub limit: Option<u32>,
+    /// Project of the files (default: the project the server was started in).
+    pub project: Option<String>,
+    /// List the files of every project instead.
+    #[serde(default)]
+    pub all_projects: bool,
+}
+
+/// Query of `/search`.
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// FTS5 query, e.g. `borrow checker`, `"exact phrase"` or `serial*`.
+    pub q: String,
+    /// Matches to return, at most `MAX_SEARCH_LIMIT` (default: `DEFAULT_SEARCH_LIMIT`).
+    pub limit: Option<u32>,
+    /// Project to search (default: the project the server was started in).
+    pub project: Option<String>,
+    /// Search the files of every project instead.
+    #[serde(default)]
+    pub all_projects: bool,
 }
 
+/// Answer of `/health`.
 #[derive(Debug, Serialize)]
-pub struct ChatResponse {
-    pub response: String,
+pub struct HealthResponse {
+    /// "ready" while a model can answer, else "loading" while one loads; "failed" when
+    /// none could be loaded or the database cannot be read.
+    pub status: &'static str,
+    pub version: &'static str,
+    /// The model of requests that name none.
+    pub default_model: String,
+    /// The models loaded or loading, least recently used first.
+    pub models: Vec<ModelHealth>,
+    pub database: DatabaseHealth,
+    pub rate_limit: RateLimitUsage,
+    pub generations: GenerationsHealth,
+    pub uptime_seconds: u64,
 }
 
-async fn chat_handler(chat_req: web::Json<ChatRequest>) -> impl Responder {
-    let args = ChatArgs {
-        prompt: Some(chat_req.prompt.clone()),
-        no_db: true, // Always disable DB loading.
-        file_content: chat_req.file_content.clone(),
+#[derive(Debug, Serialize)]
+pub struct ModelHealth {
+    pub id: String,
+    pub loaded: bool,
+    /// How long loading took, once loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_seconds: Option<f64>,
+    /// Why the model failed to load.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseHealth {
+    pub reachable: bool,
+    /// Saved documents, of all projects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documents: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Chat answers being generated, of the most allowed at once, and those waiting.
+#[derive(Debug, Serialize)]
+pub struct GenerationsHealth {
+    pub running: usize,
+    pub max: usize,
+    pub queued: usize,
+    pub max_queued: usize,
+}
+
+/// When the server started, for `/health`.
+struct StartedAt(Instant);
+
+async fn chat_handler(
+    chat_req: web::Json<ChatRequest>,
+    book: web::Data<SavedBook>,
+    models: web::Data<Models>,
+    generations: web::Data<Generations>,
+    limits: web::Data<ChatLimits>,
+    sessions: web::Data<ChatSessions>,
+    generation_defaults: web::Data<GenerationParams>,
+) -> Result<HttpResponse, ServerError> {
+    chat_req
+        .validate(&limits)
+        .map_err(ServerError::BadRequest)?;
+    let model_id = models
+        .resolve(chat_req.model.as_deref())
+        .map_err(ServerError::BadRequest)?;
+    let model = models
+        .get(&model_id)
+        .map_err(|e| ServerError::Unavailable(ApiError::new("model_unavailable", e), None))?;
+    let history = match &chat_req.session_id {
+        None => Vec::new(),
+        Some(id) => {
+            let (sessions, lookup) = (sessions.clone(), id.clone());
+            web::block(move || sessions.history(&lookup))
+                .await
+                .map_err(ServerError::internal)?
+                .map_err(ServerError::internal)?
+                .ok_or_else(|| unknown_session(id))?
+        }
+    };
+    let Some(queued) = generations.enqueue() else {
+        return Err(ServerError::Unavailable(
+            server_busy(generations.max(), generations.max_queued()),
+            Some(BUSY_RETRY_SECONDS),
+        ));
     };
+    let args = chat_req.to_args(&book, &generation_defaults);
+    let generation = queued.start().await;
 
-    run_chat_response(args).await
+    let count_for = chat_req.verbose.then_some(model_id.as_str());
+    let answered = run_chat_response(args, model, history, count_for).await;
+    generation.finish();
+    let mut response = answered.map_err(ServerError::Internal)?;
+    if let Some(id) = &chat_req.session_id {
+        let turn = Turn {
+            prompt: chat_req.prompt.clone(),
+            answer: response.response.clone(),
+        };
+        let (sessions, session_id) = (sessions.clone(), id.clone());
+        let recorded = web::block(move || sessions.record(&session_id, &turn))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|recorded| recorded);
+        match recorded {
+            Ok(()) => response.session_id = Some(id.clone()),
+            Err(e) => eprintln!(
+                "{} could not add the answer to session {}: {}",
+                "Warning:".yellow(),
+                id,
+                e
+            ),
+        }
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Serves the image saved under `id` with its content type.
+async fn asset_handler(
+    id: web::Path<i32>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let asset = web::block(move || {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        find_asset(&mut conn, id).map_err(|e| e.to_string())
+    })
+    .await;
+    match asset {
+        Ok(Ok(Some(asset))) => Ok(HttpResponse::Ok()
+            .content_type(asset.mime_type)
+            .body(asset.content)),
+        Ok(Ok(None)) => Err(ServerError::NotFound(ApiError::new(
+            "unknown_asset",
+            format!("no asset {}", id),
+        ))),
+        Ok(Err(e)) => Err(ServerError::internal(e)),
+        Err(e) => Err(ServerError::internal(e)),
+    }
+}
+
+/// Lists the saved files, ordered by path, one page at a time.
+async fn files_handler(
+    query: web::Query<FilesQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ServerError> {
+    let query = query.into_inner();
+    let scope = if query.all_projects {
+        ProjectScope::All
+    } else {
+        ProjectScope::project_or_current(query.project)
+    };
+    let requested = scope.clone();
+    let files = web::block(move || {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        let Some(project_id) = scope.resolve(&mut conn).map_err(|e| e.to_string())? else {
+            return Ok(None);
+        };
+        let filter = DocumentFilter {
+            path_prefix: query.path_prefix.as_deref(),
+            project_id,
+            offset: i64::from(query.offset),
+            limit: i64::from(
+                query
+                    .limit
+                    .unwrap_or(DEFAULT_FILES_LIMIT)
+                    .min(MAX_FILES_LIMIT),
+            ),
+        };
+        list_documents(&mut conn, &filter)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    })
+    .await;
+    match files {
+        Ok(Ok(Some(files))) => Ok(HttpResponse::Ok().json(files)),
+        Ok(Ok(None)) => Err(unknown_project(&requested)),
+        Ok(Err(e)) => Err(ServerError::internal(e)),
+        Err(e) => Err(ServerError::internal(e)),
+    }
+}
+
+/// Serves the stored Markdown of the file saved under `id`.
+async fn file_handler(
+    id: web::Path<i32>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let document = web::block(move || {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        document_content(&mut conn, id).map_err(|e| e.to_string())
+    })
+    .await;
+    match document {
+        Ok(Ok(Some((_, content)))) => Ok(HttpResponse::Ok()
+            .content_type("text/markdown; charset=utf-8")
+            .body(content)),
+        Ok(Ok(None)) => Err(ServerError::NotFound(ApiError::new(
+            "unknown_file",
+            format!("no file {}", id),
+        ))),
+        Ok(Err(e)) => Err(ServerError::internal(e)),
+        Err(e) => Err(ServerError::internal(e)),
+    }
+}
+
+/// Full-text searches the saved files, best match first. The snippets are HTML, with the
+/// matched words in `<mark>`.
+async fn search_handler(
+    query: web::Query<SearchQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ServerError> {
+    let query = query.into_inner();
+    let scope = if query.all_projects {
+        ProjectScope::All
+    } else {
+        ProjectScope::project_or_current(query.project)
+    };
+    let requested = scope.clone();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .min(MAX_SEARCH_LIMIT) as usize;
+    let hits = web::block(move || {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        let Some(project_id) = scope.resolve(&mut conn).map_err(|e| e.to_string())? else {
+            return Ok(None);
+        };
+        Ok::<_, String>(Some(search_snippets(
+            &mut conn, &query.q, limit, project_id,
+        )))
+    })
+    .await;
+    match hits {
+        Ok(Ok(None)) => Err(unknown_project(&requested)),
+        Ok(Ok(Some(Ok(hits)))) => Ok(HttpResponse::Ok().json(
+            hits.into_iter()
+                .map(|hit| SnippetHit {
+                    snippet: highlight(&hit.snippet),
+                    ..hit
+                })
+                .collect::<Vec<_>>(),
+        )),
+        Ok(Ok(Some(Err(e @ (SearchError::EmptyQuery | SearchError::InvalidQuery(_)))))) => Err(
+            ServerError::BadRequest(ApiError::new("invalid_query", e.to_string())),
+        ),
+        Ok(Ok(Some(Err(e)))) => Err(ServerError::internal(e)),
+        Ok(Err(e)) => Err(ServerError::internal(e)),
+        Err(e) => Err(ServerError::internal(e)),
+    }
+}
+
+/// The 404 of a `project` the database has no files of.
+fn unknown_project(project: &ProjectScope) -> ServerError {
+    ServerError::NotFound(ApiError::new(
+        "unknown_project",
+        format!("no files of {} are saved", project),
+    ))
+}
+
+/// `snippet` with HTML escaped and the matched words in `<mark>`; the saved Markdown may
+/// contain HTML of its own.
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len() + 16);
+    for c in snippet.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            SNIPPET_START => html.push_str("<mark>"),
+            SNIPPET_END => html.push_str("</mark>"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// CORS letting browsers call the server from `origins`, or from anywhere when empty.
+fn cors(origins: &[String]) -> Cors {
+    origins.iter().fold(Cors::permissive(), |cors, origin| {
+        cors.allowed_origin(origin)
+    })
 }
 
 async fn ping_handler() -> impl Responder {
     HttpResponse::Ok().body("pong")
 }
 
-pub async fn start_server() -> std::io::Result<()> {
-    println!("Starting backend server on http://127.0.0.1:8080");
-    HttpServer::new(|| {
+/// How loading `model` stands.
+fn model_health(model: &SharedModel) -> ModelHealth {
+    let (loaded, load_seconds, error) = match &*model.state() {
+        ModelState::Loading => (false, None, None),
+        ModelState::Ready { load_time, .. } => (true, Some(load_time.as_secs_f64()), None),
+        ModelState::Failed(e) => (false, None, Some(e.clone())),
+    };
+    ModelHealth {
+        id: model.model_id.clone(),
+        loaded,
+        load_seconds,
+        error,
+    }
+}
+
+/// Reports the version, the models, the database and the uptime: 200 while the server
+/// works, including while a model loads, and 503 when the models failed to load or the
+/// database cannot be read. Nothing here waits for a chat request to finish.
+async fn health_handler(
+    models: web::Data<Models>,
+    pool: web::Data<DbPool>,
+    started_at: web::Data<StartedAt>,
+    limiter: web::Data<RateLimiter>,
+    generations: web::Data<Generations>,
+) -> impl Responder {
+    let resident: Vec<ModelHealth> = models.resident().iter().map(model_health).collect();
+    let status = if resident.iter().any(|model| model.loaded) {
+        "ready"
+    } else if resident.iter().any(|model| model.error.is_none()) {
+        "loading"
+    } else {
+        "failed"
+    };
+
+    let documents = web::block(move || {
+        let mut conn = pool
+            .get_timeout(HEALTH_DB_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        metadata::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    let database = match documents {
+        Ok(documents) => DatabaseHealth {
+            reachable: true,
+            documents: Some(documents),
+            error: None,
+        },
+        Err(e) => DatabaseHealth {
+            reachable: false,
+            documents: None,
+            error: Some(e),
+        },
+    };
+
+    let healthy = status != "failed" && database.reachable;
+    let health = HealthResponse {
+        status: if healthy { status } else { "failed" },
+        version: env!("CARGO_PKG_VERSION"),
+        default_model: models.config().default.clone(),
+        models: resident,
+        database,
+        rate_limit: limiter.usage(),
+        generations: GenerationsHealth {
+            running: generations.running(),
+            max: generations.max(),
+            queued: generations.queued(),
+            max_queued: generations.max_queued(),
+        },
+        uptime_seconds: started_at.0.elapsed().as_secs(),
+    };
+    if healthy {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+/// Serves the chat API, Markdown previews and code extraction, the saved files and the
+/// saved images, as `config` says; tag-filtered chat context, files and images are read
+/// from the database at `db_path`, through a connection pool shared by all workers.
+///
+/// The chat model is loaded once, in the background, and shared by all workers; until it
+/// is loaded, `/chat` answers 503 and `/health` says it is loading. Requests may ask for
+/// the other allowed models, loaded the same way when first asked for, keeping at most
+/// `max_loaded`. The embedding model used to retrieve chat context is cached in
+/// `model_dir`.
+///
+/// With a token, every route but `/ping` requires it as a bearer token.
+///
+/// Each client gets `requests_per_minute` chat requests, `burst` at once. At most
+/// `max_generations` answers are generated at a time, and `max_queued` more wait their
+/// turn; other chat requests are refused with `Retry-After`.
+///
+/// The server listens on a TCP address or a Unix socket. With a certificate and key, it
+/// speaks HTTPS only.
+///
+/// On SIGINT or SIGTERM the server stops accepting requests, gives chat answers in
+/// progress `shutdown_grace` to finish, then releases the model and returns. It fails if
+/// answers had to be cut off.
+pub async fn start_server(
+    db_path: PathBuf,
+    model_dir: PathBuf,
+    config: ServerConfig,
+) -> std::io::Result<()> {
+    let pool = open_pool(&db_path.to_string_lossy())
+        .map_err(|e| std::io::Error::other(format!("cannot open {}: {}", db_path.display(), e)))?;
+    let retriever = Retriever::new(model_dir, config.context_tokens);
+    let tls = config
+        .tls
+        .as_ref()
+        .map(|files| files.server_config())
+        .transpose()
+        .map_err(std::io::Error::other)?;
+    let grace = config.shutdown_grace;
+    let generation_defaults = web::Data::new(config.generation.clone());
+    let rate_limits = config.rate_limits;
+    let render_config = config.render;
+    let limits = config.chat_limits;
+    let authentication = if config.token.is_some() {
+        "on, a bearer token is required"
+    } else {
+        "off"
+    };
+    let token = web::Data::new(config.token.clone());
+    let started_at = web::Data::new(StartedAt(Instant::now()));
+    let model_config = config.models.clone();
+    println!("Loading model {}", model_config.default);
+    if model_config.allowed.len() > 1 {
+        println!(
+            "Requests may choose any of {}, {} loaded at a time",
+            model_config.allowed.join(", "),
+            model_config.max_loaded
+        );
+    }
+    match &config.backend {
+        Backend::Local { isq } => println!("Quantization of models from the hub: {}", isq),
+        Backend::OpenAi { base_url, .. } => {
+            println!("Answering with the OpenAI-compatible API at {}", base_url)
+        }
+    }
+    let models = web::Data::new(Models::new(model_config, config.backend.clone()));
+    let listen = config.listen.clone();
+    let location = match (&listen, &tls) {
+        (Listen::Tcp(address), Some(_)) => format!("https://{}", address),
+        (Listen::Tcp(address), None) => format!("http://{}", address),
+        (Listen::Unix(_), _) => listen.to_string(),
+    };
+    println!(
+        "Starting backend server on {} (authentication {})",
+        location, authentication
+    );
+    let cors_origins = config.cors_origins.clone();
+    let sessions = web::Data::new(ChatSessions::new(pool.clone(), config.history_tokens));
+    let book = web::Data::new(SavedBook {
+        db_path,
+        pool: pool.clone(),
+        retriever,
+    });
+    let pool = web::Data::new(pool);
+    let generations = web::Data::new(Generations::new(
+        rate_limits.max_generations,
+        rate_limits.max_queued,
+    ));
+    let limiter = web::Data::new(RateLimiter::new(rate_limits));
+    let shared_models = models.clone();
+    let shared_generations = generations.clone();
+    let server = HttpServer::new(move || {
         App::new()
-            .wrap(Cors::permissive())
+            .app_data(pool.clone())
+            .app_data(models.clone())
+            .app_data(book.clone())
+            .app_data(token.clone())
+            .app_data(started_at.clone())
+            .app_data(generations.clone())
+            .app_data(web::Data::new(limits))
+            .app_data(sessions.clone())
+            .app_data(limiter.clone())
+            .app_data(generation_defaults.clone())
+            .app_data(web::Data::new(render_config))
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(limits.body_bytes())
+                    .error_handler(json_error),
+            )
+            .app_data(web::QueryConfig::default().error_handler(|e, _| extract_error(e)))
+            .app_data(web::PathConfig::default().error_handler(|e, _| extract_error(e)))
+            .wrap(from_fn(require_token))
+            .wrap(cors(&cors_origins))
             .route("/ping", web::get().to(ping_handler))
-            .route("/chat", web::post().to(chat_handler))
+            .route("/health", web::get().to(health_handler))
+            .service(
+                web::resource("/chat")
+                    .wrap(from_fn(limit_requests))
+                    .route(web::post().to(chat_handler)),
+            )
+            .route("/ws/chat", web::get().to(ws_chat_handler))
+            .service(
+                web::resource("/render")
+                    .app_data(
+                        web::JsonConfig::default()
+                            .limit(render_config.body_bytes())
+                            .error_handler(json_error),
+                    )
+                    .route(web::post().to(render_handler)),
+            )
+            .route("/tangle", web::post().to(tangle_handler))
+            .route("/sessions", web::post().to(create_session_handler))
+            .route("/sessions/{id}", web::delete().to(delete_session_handler))
+            .route("/asset/{id}", web::get().to(asset_handler))
+            .route("/files", web::get().to(files_handler))
+            .route("/files/{id}", web::get().to(file_handler))
+            .route("/search", web::get().to(search_handler))
     })
-    .workers(4) // Ensure multi-threaded workers.
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    .workers(config.workers)
+    .disable_signals()
+    .shutdown_timeout(grace.as_secs());
+    let server = match (&listen, tls) {
+        (Listen::Tcp(address), Some(config)) => {
+            server.bind_rustls_0_23(address.as_str(), config)?
+        }
+        (Listen::Tcp(address), None) => server.bind(address.as_str())?,
+        #[cfg(unix)]
+        (Listen::Unix(path), _) => {
+            check_socket_path(path)?;
+            let server = server.bind_uds(path)?;
+            restrict_socket(path).inspect_err(|_| remove_socket(path))?;
+            server
+        }
+        #[cfg(not(unix))]
+        (Listen::Unix(_), _) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix sockets are not supported on this platform; use --bind",
+            ))
+        }
+    }
+    .run();
+
+    let handle = server.handle();
+    let served = tokio::select! {
+        served = server => served,
+        () = stop_on_signal(handle, grace, &shared_generations) => Ok(()),
+    };
+    shared_models.release();
+    if let Listen::Unix(path) = &listen {
+        remove_socket(path);
+    }
+    served?;
+    match shared_generations.unfinished() {
+        0 => Ok(()),
+        unfinished => Err(std::io::Error::other(format!(
+            "{} chat answers were cut off before they finished",
+            unfinished
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::save::{save_files_to_db, SaveOptions};
+    use crate::server::testing::{generous_limits, serve, ChatState, FakeModel};
+    use crate::utils::database::db::ConnectionCustomizer;
+    use crate::utils::database::pool::build_pool;
+    use crate::utils::database::projects::project_id;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::Value;
+    use std::fs;
+
+    /// The saved files of the fixture, `(path, content)`, in the project "book".
+    const FILES: [(&str, &str); 3] = [
+        (
+            "intro.md",
+            "# Intro\n\nLila tangles code out of Markdown.\n",
+        ),
+        (
+            "guide/tangle.md",
+            "# Tangle\n\nTangling writes each <code> block to its file.\n",
+        ),
+        (
+            "guide/weave.md",
+            "# Weave\n\nWeaving renders the book as HTML.\n",
+        ),
+    ];
+
+    /// A pool on an in-memory database named `name` holding `FILES`, and a connection that
+    /// keeps the database alive as long as it is.
+    fn fixture(name: &str) -> (DbPool, SqliteConnection) {
+        let url = format!("file:{}?mode=memory&cache=shared", name);
+        let mut keep_alive = SqliteConnection::establish(&url).unwrap();
+        ConnectionCustomizer::default()
+            .apply(&mut keep_alive)
+            .unwrap();
+        let pool = build_pool(&url, 2, None).unwrap();
+
+        let doc = tempfile::tempdir().unwrap();
+        let files: Vec<String> = FILES
+            .iter()
+            .map(|(path, content)| {
+                let path = doc.path().join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, content).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect();
+        let mut conn = pool.get().unwrap();
+        let project = project_id(&mut conn, "book").unwrap();
+        let options = SaveOptions {
+            max_versions: None,
+            max_asset_bytes: 0,
+            quiet: true,
+        };
+        save_files_to_db(&files, doc.path(), project, &mut conn, &options).unwrap();
+        drop(conn);
+        (pool, keep_alive)
+    }
+
+    /// Answers `uri` with the database routes of the server, on `pool`.
+    async fn get(pool: DbPool, uri: &str) -> (StatusCode, Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .route("/files", web::get().to(files_handler))
+                .route("/files/{id}", web::get().to(file_handler))
+                .route("/search", web::get().to(search_handler)),
+        )
+        .await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        let status = response.status();
+        let body = test::read_body(response).await;
+        let json = serde_json::from_slice(&body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+        (status, json)
+    }
+
+    #[actix_web::test]
+    async fn search_returns_highlighted_snippets() {
+        let (pool, _db) = fixture("search_returns_highlighted_snippets");
+        let (status, hits) = get(pool, "/search?q=tangling&project=book").await;
+        assert_eq!(status, StatusCode::OK);
+        let hits = hits.as_array().unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0]["path"], "guide/tangle.md");
+        assert!(hits[0]["id"].is_i64());
+        assert!(hits[0]["score"].is_f64());
+        let snippet = hits[0]["snippet"].as_str().unwrap();
+        assert!(snippet.contains("<mark>Tangling</mark>"), "{}", snippet);
+        assert!(snippet.contains("&lt;code&gt;"), "{}", snippet);
+    }
+
+    #[actix_web::test]
+    async fn search_limits_the_matches() {
+        let (pool, _db) = fixture("search_limits_the_matches");
+        let (status, hits) = get(pool, "/search?q=md*%20OR%20book&project=book&limit=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hits.as_array().unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn a_query_fts5_cannot_parse_is_a_bad_request() {
+        let (pool, _db) = fixture("a_query_fts5_cannot_parse_is_a_bad_request");
+        let (status, body) = get(pool, "/search?q=%22unclosed&project=book").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_query");
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_project_is_not_found() {
+        let (pool, _db) = fixture("an_unknown_project_is_not_found");
+        let (status, body) = get(pool.clone(), "/search?q=intro&project=other").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "unknown_project");
+        let (status, _) = get(pool, "/files?project=other").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn files_are_listed_and_served() {
+        let (pool, _db) = fixture("files_are_listed_and_served");
+        let (status, files) = get(pool.clone(), "/files?project=book&path_prefix=guide/").await;
+        assert_eq!(status, StatusCode::OK);
+        let paths: Vec<&str> = files
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| file["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["guide/tangle.md", "guide/weave.md"]);
+
+        let id = files[1]["id"].as_i64().unwrap();
+        let (status, content) = get(pool.clone(), &format!("/files/{}", id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content, FILES[2].1);
+        let (status, body) = get(pool, "/files/9999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "unknown_file");
+    }
+
+    #[actix_web::test]
+    async fn ten_concurrent_chat_requests_are_all_answered() {
+        let dir = tempfile::tempdir().unwrap();
+        let model = FakeModel {
+            delay: Duration::from_millis(20),
+            ..FakeModel::answering(&["Hello", ", world"])
+        };
+        let state = ChatState::new(dir.path(), model.serve(), generous_limits(2)).await;
+        let shared = state.clone();
+        let address = serve(move || {
+            let state = shared.clone();
+            App::new()
+                .configure(move |config| state.configure(config))
+                .route("/chat", web::post().to(chat_handler))
+        });
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/chat", address);
+        let requests = (0..10).map(|i| {
+            let request = client.post(&url).json(&serde_json::json!({
+                "prompt": format!("Question {}", i),
+                "file_content": "A book.",
+            }));
+            async move {
+                let response = request.send().await.unwrap();
+                (response.status(), response.json::<Value>().await.unwrap())
+            }
+        });
+        for (status, body) in futures::future::join_all(requests).await {
+            assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+            assert_eq!(body["response"], "Hello, world");
+        }
+        assert_eq!(state.generations.running(), 0);
+        assert_eq!(state.generations.queued(), 0);
+    }
 }
@@ -0,0 +1,51 @@
+use actix_web::{web, HttpResponse};
+use futures::StreamExt;
+use std::io;
+
+use crate::commands::tangle::extract_code;
+use crate::server::chat::{ApiError, ServerError};
+use crate::server::render::RenderConfig;
+
+/// Extracts the code of the posted Markdown as `lila tangle` does, answering the code of
+/// each output file by its name.
+pub async fn tangle_handler(
+    mut payload: web::Payload,
+    config: web::Data<RenderConfig>,
+) -> Result<HttpResponse, ServerError> {
+    let mut markdown = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| {
+            ServerError::BadRequest(ApiError::new(
+                "invalid_request",
+                format!("invalid request body: {}", e),
+            ))
+        })?;
+        if markdown.len() + chunk.len() > config.max_bytes {
+            return Err(ServerError::PayloadTooLarge(ApiError::new(
+                "payload_too_large",
+                format!(
+                    "the Markdown is larger than {} bytes (server.max_render_bytes)",
+                    config.max_bytes
+                ),
+            )));
+        }
+        markdown.extend_from_slice(&chunk);
+    }
+    let markdown = std::str::from_utf8(&markdown).map_err(|e| {
+        ServerError::BadRequest(ApiError::new(
+            "invalid_request",
+            format!("the Markdown is not UTF-8: {}", e),
+        ))
+    })?;
+    match extract_code(markdown.as_bytes()) {
+        Ok(Ok(files)) => Ok(HttpResponse::Ok().json(files)),
+        Ok(Err(_)) => Err(ServerError::BadRequest(ApiError::new(
+            "missing_front_matter",
+            "the Markdown has no front matter between --- lines to name the output files",
+        ))),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Err(ServerError::BadRequest(
+            ApiError::new("invalid_front_matter", e.to_string()),
+        )),
+        Err(e) => Err(ServerError::internal(e)),
+    }
+}
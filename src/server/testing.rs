@@ -0,0 +1,213 @@
+//! What the server's tests share: a fake OpenAI-compatible model server, and the app
+//! state the chat routes read, answering with it.
+
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use futures::stream;
+use rustls::ServerConfig;
+use serde_json::{json, Value};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::server::backend::Backend;
+use crate::server::context::Retriever;
+use crate::server::generation::GenerationParams;
+use crate::server::models::{ModelConfig, Models};
+use crate::server::rate_limit::{RateLimiter, RateLimits};
+use crate::server::sessions::ChatSessions;
+use crate::server::shutdown::Generations;
+use crate::server::start::{ChatLimits, SavedBook, DEFAULT_MAX_CONTEXT_BYTES};
+use crate::utils::database::pool::build_pool;
+
+/// Id of the model `FakeModel` serves.
+pub const FAKE_MODEL: &str = "fake-model";
+
+/// How a fake model answers every chat completion: `deltas`, one event each, `delay`
+/// apart.
+#[derive(Clone)]
+pub struct FakeModel {
+    pub deltas: Vec<String>,
+    pub delay: Duration,
+    /// The bodies of the requests it was sent, in order.
+    pub received: Arc<Mutex<Vec<Value>>>,
+}
+
+impl FakeModel {
+    /// A model answering at once with `deltas`.
+    pub fn answering(deltas: &[&str]) -> FakeModel {
+        FakeModel {
+            deltas: deltas.iter().map(|delta| delta.to_string()).collect(),
+            delay: Duration::ZERO,
+            received: Arc::default(),
+        }
+    }
+
+    /// Serves the model on a free local port, on the current actix system, and returns the
+    /// root of its API.
+    pub fn serve(self) -> String {
+        let address = serve(move || {
+            let model = self.clone();
+            App::new().route(
+                "/v1/chat/completions",
+                web::post().to(move |body| completion(model.clone(), body)),
+            )
+        });
+        format!("http://{}/v1", address)
+    }
+}
+
+/// The streamed chat completion of `model` for the request `body`.
+async fn completion(model: FakeModel, body: web::Json<Value>) -> HttpResponse {
+    model.received.lock().unwrap().push(body.into_inner());
+    let mut events: Vec<String> = model
+        .deltas
+        .iter()
+        .map(|delta| json!({"choices": [{"delta": {"content": delta}}]}).to_string())
+        .collect();
+    events.push(
+        json!({"choices": [], "usage": {"completion_tokens": model.deltas.len()}}).to_string(),
+    );
+    events.push("[DONE]".to_string());
+    let delay = model.delay;
+    let body = stream::unfold(events.into_iter(), move |mut events| async move {
+        let event = events.next()?;
+        actix_web::rt::time::sleep(delay).await;
+        let chunk = web::Bytes::from(format!("data: {}\n\n", event));
+        Some((Ok::<_, actix_web::Error>(chunk), events))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(body)
+}
+
+/// Serves the apps `app` makes on a free local port, on the current actix system, and
+/// returns the address.
+pub fn serve<F, T>(app: F) -> SocketAddr
+where
+    F: Fn() -> App<T> + Send + Clone + 'static,
+    T: ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        > + 'static,
+{
+    start(app, None)
+}
+
+/// `serve` over HTTPS, with `tls`.
+pub fn serve_tls<F, T>(tls: ServerConfig, app: F) -> SocketAddr
+where
+    F: Fn() -> App<T> + Send + Clone + 'static,
+    T: ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        > + 'static,
+{
+    start(app, Some(tls))
+}
+
+fn start<F, T>(app: F, tls: Option<ServerConfig>) -> SocketAddr
+where
+    F: Fn() -> App<T> + Send + Clone + 'static,
+    T: ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        > + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = HttpServer::new(app).workers(1).disable_signals();
+    let server = match tls {
+        Some(tls) => server.listen_rustls_0_23(listener, tls),
+        None => server.listen(listener),
+    };
+    actix_web::rt::spawn(server.unwrap().run());
+    address
+}
+
+/// The app state of the chat routes, with a database in `dir`, answering with the
+/// `FakeModel` served at `base_url`.
+#[derive(Clone)]
+pub struct ChatState {
+    pub models: web::Data<Models>,
+    pub book: web::Data<SavedBook>,
+    pub sessions: web::Data<ChatSessions>,
+    pub generations: web::Data<Generations>,
+    pub limiter: web::Data<RateLimiter>,
+    pub limits: web::Data<ChatLimits>,
+    pub generation_defaults: web::Data<GenerationParams>,
+}
+
+impl ChatState {
+    /// Waits until the model is loaded, which for a remote one takes no time.
+    pub async fn new(dir: &Path, base_url: String, limits: RateLimits) -> ChatState {
+        let url = dir.join("lila.db").to_string_lossy().into_owned();
+        let pool = build_pool(&url, 4, None).unwrap();
+        let models = Models::new(
+            ModelConfig {
+                allowed: vec![FAKE_MODEL.to_string()],
+                default: FAKE_MODEL.to_string(),
+                max_loaded: 1,
+            },
+            Backend::OpenAi {
+                base_url,
+                api_key_env: "LILA_TEST_API_KEY".to_string(),
+            },
+        );
+        while let Err(e) = models.get(FAKE_MODEL) {
+            assert!(e.contains("loading"), "{}", e);
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        ChatState {
+            models: web::Data::new(models),
+            book: web::Data::new(SavedBook {
+                db_path: dir.join("lila.db"),
+                pool: pool.clone(),
+                retriever: Retriever::new(dir.join("models"), 1000),
+            }),
+            sessions: web::Data::new(ChatSessions::new(pool, 2000)),
+            generations: web::Data::new(Generations::new(
+                limits.max_generations,
+                limits.max_queued,
+            )),
+            limiter: web::Data::new(RateLimiter::new(limits)),
+            limits: web::Data::new(ChatLimits {
+                max_context_bytes: DEFAULT_MAX_CONTEXT_BYTES,
+            }),
+            generation_defaults: web::Data::new(GenerationParams::default()),
+        }
+    }
+
+    /// Adds the state to an app, for `App::configure`.
+    pub fn configure(&self, config: &mut web::ServiceConfig) {
+        config
+            .app_data(self.models.clone())
+            .app_data(self.book.clone())
+            .app_data(self.sessions.clone())
+            .app_data(self.generations.clone())
+            .app_data(self.limiter.clone())
+            .app_data(self.limits.clone())
+            .app_data(self.generation_defaults.clone());
+    }
+}
+
+/// Limits letting every request of a test through, with `max_generations` answered at
+/// once.
+pub fn generous_limits(max_generations: usize) -> RateLimits {
+    RateLimits {
+        requests_per_minute: 1000,
+        burst: 100,
+        max_generations,
+        max_queued: 100,
+    }
+}
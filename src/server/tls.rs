@@ -0,0 +1,216 @@
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::utils::config::Config;
+
+/// The PEM files the server serves HTTPS with.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    /// Certificate chain, the server's certificate first.
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsFiles {
+    /// `--tls-cert` and `--tls-key`, else the `tls_cert` and `tls_key` in the `[server]`
+    /// section of `config` (a `Lila.toml`). `None` means plain HTTP; giving only one of the
+    /// two is an error.
+    pub fn load(
+        cert: Option<PathBuf>,
+        key: Option<PathBuf>,
+        config: &Config,
+    ) -> Result<Option<TlsFiles>, String> {
+        let config_cert = config_path(config, "tls_cert", config.server.tls_cert.as_deref())?;
+        let config_key = config_path(config, "tls_key", config.server.tls_key.as_deref())?;
+        match (cert.or(config_cert), key.or(config_key)) {
+            (Some(cert), Some(key)) => Ok(Some(TlsFiles { cert, key })),
+            (None, None) => Ok(None),
+            _ => Err(
+                "HTTPS needs both a certificate and a key: give --tls-cert and --tls-key, or server.tls_cert and server.tls_key"
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// The rustls configuration serving the certificate with the key, or why they cannot
+    /// be read or do not belong together.
+    pub fn server_config(&self) -> Result<ServerConfig, String> {
+        let certs = read_certs(&self.cert)?;
+        let key = read_key(&self.key)?;
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("cannot set up TLS: {}", e))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| match e {
+                rustls::Error::InconsistentKeys(_) => format!(
+                    "key {} does not belong to certificate {}",
+                    self.key.display(),
+                    self.cert.display()
+                ),
+                e => format!(
+                    "cannot use certificate {} with key {}: {}",
+                    self.cert.display(),
+                    self.key.display(),
+                    e
+                ),
+            })
+    }
+}
+
+/// `path`, the `key` of the `[server]` section of `config`, relative to its folder.
+fn config_path(config: &Config, key: &str, path: Option<&Path>) -> Result<Option<PathBuf>, String> {
+    match path {
+        None => Ok(None),
+        Some(path) if !path.as_os_str().is_empty() => Ok(Some(config.resolve(path))),
+        Some(_) => Err(format!(
+            "invalid {}: server.{} must be the path of a PEM file",
+            config.path().display(),
+            key
+        )),
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("could not read certificate {}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("could not read certificate {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("no PEM certificate in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let file =
+        File::open(path).map_err(|e| format!("could not read key {}: {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("could not read key {}: {}", path.display(), e))?
+        .ok_or_else(|| format!("no PEM private key in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::serve_tls;
+    use actix_web::{web, App, HttpResponse};
+    use std::fs;
+
+    /// A certificate for `localhost` signed by its own key, written to `dir` as PEM files
+    /// named after `name`.
+    fn self_signed(dir: &Path, name: &str) -> TlsFiles {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let files = TlsFiles {
+            cert: dir.join(format!("{}.crt", name)),
+            key: dir.join(format!("{}.key", name)),
+        };
+        fs::write(&files.cert, generated.cert.pem()).unwrap();
+        fs::write(&files.key, generated.key_pair.serialize_pem()).unwrap();
+        files
+    }
+
+    #[actix_web::test]
+    async fn serves_https_with_a_self_signed_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = self_signed(dir.path(), "server").server_config().unwrap();
+        let address = serve_tls(config, || {
+            App::new().route(
+                "/ping",
+                web::get().to(|| async { HttpResponse::Ok().body("pong") }),
+            )
+        });
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{}/ping", address.port());
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "pong");
+
+        // Plain HTTP is not served, and the certificate is not trusted without the test's
+        // leniency.
+        let plain = format!("http://localhost:{}/ping", address.port());
+        assert!(client.get(&plain).send().await.is_err());
+        assert!(reqwest::get(&url).await.is_err());
+    }
+
+    #[test]
+    fn a_key_of_another_certificate_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = self_signed(dir.path(), "server");
+        let other = self_signed(dir.path(), "other");
+        let mismatched = TlsFiles {
+            cert: server.cert,
+            key: other.key,
+        };
+        let error = mismatched.server_config().unwrap_err();
+        assert!(
+            error.contains("does not belong to certificate"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn unreadable_files_are_explained() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = self_signed(dir.path(), "server");
+        let missing = TlsFiles {
+            cert: dir.path().join("missing.crt"),
+            key: server.key.clone(),
+        };
+        let error = missing.server_config().unwrap_err();
+        assert!(error.starts_with("could not read certificate"), "{}", error);
+
+        let empty = dir.path().join("empty.pem");
+        fs::write(&empty, "").unwrap();
+        let no_cert = TlsFiles {
+            cert: empty.clone(),
+            key: server.key.clone(),
+        };
+        assert!(no_cert
+            .server_config()
+            .unwrap_err()
+            .starts_with("no PEM certificate"));
+        let no_key = TlsFiles {
+            cert: server.cert,
+            key: empty,
+        };
+        assert!(no_key
+            .server_config()
+            .unwrap_err()
+            .starts_with("no PEM private key"));
+    }
+
+    #[test]
+    fn config_paths_are_relative_to_lila_toml_and_both_are_needed() {
+        let config = Config::parse(
+            Path::new("/book/Lila.toml"),
+            "[server]\ntls_cert = \"certs/book.crt\"\ntls_key = \"certs/book.key\"\n",
+        )
+        .unwrap()
+        .0;
+        let files = TlsFiles::load(None, None, &config).unwrap().unwrap();
+        assert_eq!(files.cert, Path::new("/book/certs/book.crt"));
+        assert_eq!(files.key, Path::new("/book/certs/book.key"));
+
+        let flag = TlsFiles::load(Some(PathBuf::from("cli.crt")), None, &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(flag.cert, Path::new("cli.crt"));
+        assert_eq!(flag.key, Path::new("/book/certs/book.key"));
+
+        let no_config = Config::parse(Path::new("/book/Lila.toml"), "").unwrap().0;
+        assert!(TlsFiles::load(None, None, &no_config).unwrap().is_none());
+        let error = TlsFiles::load(None, Some(PathBuf::from("k.pem")), &no_config).unwrap_err();
+        assert!(error.starts_with("HTTPS needs both"), "{}", error);
+    }
+}
@@ -0,0 +1,533 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::{AggregatedMessage, CloseCode, CloseReason, ProtocolError, Session};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::server::backend::ChatModel;
+use crate::server::chat::{
+    conversation, generate, task_failed, ApiError, ChatArgs, ServerError, Turn,
+};
+use crate::server::context::Source;
+use crate::server::generation::GenerationParams;
+use crate::server::models::Models;
+use crate::server::rate_limit::{client_key, rate_limited, server_busy, RateLimiter};
+use crate::server::sessions::{unknown_session, ChatSessions};
+use crate::server::shutdown::Generations;
+use crate::server::start::{ChatLimits, ChatRequest, SavedBook};
+
+/// How often the server pings an idle socket.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a client may stay silent, pongs included, before its socket is closed.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Query of `/ws/chat`.
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    /// Session to continue, from `POST /sessions` or an earlier socket; a new one is
+    /// started without it.
+    pub session: Option<String>,
+}
+
+/// A frame sent by the client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    /// Ask the model, with the fields of a `/chat` request.
+    Message(Box<ChatRequest>),
+    /// Stop the answer being generated.
+    Stop,
+}
+
+/// A frame sent to the client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerFrame {
+    /// The session the socket carries.
+    Session {
+        session_id: String,
+    },
+    /// The answer waits for others to finish; `position` 1 is next.
+    Queued {
+        position: usize,
+    },
+    Started,
+    Delta {
+        content: String,
+    },
+    Done {
+        seconds: f64,
+        tokens: Option<usize>,
+        sources: Vec<Source>,
+        generation: GenerationParams,
+    },
+    /// The answer was stopped by the client; what was generated is not kept.
+    Stopped {
+        seconds: f64,
+    },
+    Error {
+        #[serde(flatten)]
+        error: ApiError,
+    },
+}
+
+impl ServerFrame {
+    /// Whether the answer in progress ends with this frame.
+    fn ends_answer(&self) -> bool {
+        matches!(
+            self,
+            ServerFrame::Done { .. } | ServerFrame::Stopped { .. } | ServerFrame::Error { .. }
+        )
+    }
+}
+
+/// What a socket needs from the app to answer.
+struct ChatSocket {
+    session_id: String,
+    book: web::Data<SavedBook>,
+    models: web::Data<Models>,
+    generations: web::Data<Generations>,
+    limits: web::Data<ChatLimits>,
+    sessions: web::Data<ChatSessions>,
+    limiter: web::Data<RateLimiter>,
+    generation_defaults: web::Data<GenerationParams>,
+    /// Who opened the socket, for the rate limit.
+    client: String,
+}
+
+/// Opens a chat socket for session `session` (a new one without it). The client sends
+/// `{"type": "message", "prompt": ...}` with the fields of a `/chat` request, and
+/// `{"type": "stop"}` to stop the answer in progress; the server answers with `started`,
+/// `delta` and `done`, `stopped` or `error` frames. Each answered prompt is added to the
+/// session, and later prompts are asked after its latest turns.
+pub async fn ws_chat_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<WsQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let sessions: web::Data<ChatSessions> = app_data(&req)?;
+    let requested = query.into_inner().session;
+    let (lookup, wanted) = (sessions.clone(), requested.clone());
+    let opened = web::block(move || match wanted {
+        Some(id) => Ok(lookup.exists(&id)?.then_some(id)),
+        None => lookup.create().map(Some),
+    })
+    .await
+    .map_err(ServerError::internal)?
+    .map_err(ServerError::internal)?;
+    let Some(session_id) = opened else {
+        return Err(unknown_session(requested.as_deref().unwrap_or_default()).into());
+    };
+    let socket = ChatSocket {
+        session_id,
+        book: app_data(&req)?,
+        models: app_data(&req)?,
+        generations: app_data(&req)?,
+        limits: app_data(&req)?,
+        sessions,
+        limiter: app_data(&req)?,
+        generation_defaults: app_data(&req)?,
+        client: client_key(&req),
+    };
+    let max_bytes = socket.limits.body_bytes();
+    let (response, session, messages) = actix_ws::handle(&req, body)
+        .map_err(|e| ServerError::BadRequest(ApiError::new("invalid_request", e.to_string())))?;
+    let messages = messages
+        .max_frame_size(max_bytes)
+        .aggregate_continuations()
+        .max_continuation_size(max_bytes);
+    actix_web::rt::spawn(run_socket(socket, session, messages));
+    Ok(response)
+}
+
+fn app_data<T: 'static>(req: &HttpRequest) -> Result<web::Data<T>, actix_web::Error> {
+    req.app_data::<web::Data<T>>().cloned().ok_or_else(|| {
+        ServerError::internal(format!("{} is not configured", std::any::type_name::<T>())).into()
+    })
+}
+
+/// Serves one socket until the client leaves, stops answering pings, or the server stops.
+async fn run_socket(
+    socket: ChatSocket,
+    mut session: Session,
+    mut messages: actix_ws::AggregatedMessageStream,
+) {
+    let (frames_tx, mut frames) = mpsc::unbounded_channel::<ServerFrame>();
+    // Set to stop the answer in progress; `None` while there is none.
+    let mut answering: Option<Arc<AtomicBool>> = None;
+    let mut stopping = socket.generations.stopping();
+    let mut heartbeat =
+        tokio::time::interval_at(tokio::time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let mut last_heard = Instant::now();
+
+    let greeting = ServerFrame::Session {
+        session_id: socket.session_id.clone(),
+    };
+    if send(&mut session, &greeting).await.is_err() {
+        return;
+    }
+
+    let reason = loop {
+        tokio::select! {
+            message = messages.recv() => {
+                last_heard = Instant::now();
+                let message = match message {
+                    None => break None,
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => {
+                        let (code, error) = match e {
+                            ProtocolError::Overflow => (
+                                CloseCode::Size,
+                                ApiError::new(
+                                    "message_too_large",
+                                    format!("messages are limited to {} bytes", socket.limits.body_bytes()),
+                                ),
+                            ),
+                            e => (CloseCode::Protocol, ApiError::new("invalid_frame", e.to_string())),
+                        };
+                        let _ = send(&mut session, &ServerFrame::Error { error }).await;
+                        break Some(code.into());
+                    }
+                };
+                let sent = match message {
+                    AggregatedMessage::Text(text) => {
+                        let reply = receive(&socket, &text, &mut answering, &frames_tx);
+                        match reply {
+                            Some(frame) => send(&mut session, &frame).await,
+                            None => Ok(()),
+                        }
+                    }
+                    AggregatedMessage::Binary(_) => {
+                        let error = ApiError::new("invalid_frame", "frames must be JSON text");
+                        send(&mut session, &ServerFrame::Error { error }).await
+                    }
+                    AggregatedMessage::Ping(bytes) => session.pong(&bytes).await,
+                    AggregatedMessage::Pong(_) => Ok(()),
+                    AggregatedMessage::Close(reason) => break reason,
+                };
+                if sent.is_err() {
+                    break None;
+                }
+            }
+            Some(frame) = frames.recv() => {
+                if frame.ends_answer() {
+                    answering = None;
+                }
+                if send(&mut session, &frame).await.is_err() {
+                    break None;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() > CLIENT_TIMEOUT {
+                    break Some(CloseReason {
+                        code: CloseCode::Away,
+                        description: Some("no answer to pings".to_string()),
+                    });
+                }
+                if session.ping(b"").await.is_err() {
+                    break None;
+                }
+            }
+            _ = stopping.wait_for(|stopping| *stopping), if answering.is_none() => {
+                break Some(CloseReason {
+                    code: CloseCode::Restart,
+                    description: Some("the server is stopping".to_string()),
+                });
+            }
+        }
+    };
+    if let Some(stop) = answering {
+        stop.store(true, Ordering::SeqCst);
+    }
+    let _ = session.close(reason).await;
+}
+
+/// Handles the client frame `text`: starts answering a message, or stops the answer in
+/// progress. Returns the frame to answer with at once, if any.
+fn receive(
+    socket: &ChatSocket,
+    text: &str,
+    answering: &mut Option<Arc<AtomicBool>>,
+    frames: &mpsc::UnboundedSender<ServerFrame>,
+) -> Option<ServerFrame> {
+    let frame = match serde_json::from_str::<ClientFrame>(text) {
+        Ok(frame) => frame,
+        Err(e) => {
+            let error = ApiError::new("invalid_request", format!("invalid frame: {}", e));
+            return Some(ServerFrame::Error { error });
+        }
+    };
+    let request = match frame {
+        ClientFrame::Stop => {
+            if let Some(stop) = answering {
+                stop.store(true, Ordering::SeqCst);
+            }
+            return None;
+        }
+        ClientFrame::Message(request) => request,
+    };
+    if answering.is_some() {
+        let error = ApiError::new(
+            "busy",
+            "an answer is in progress; wait for it or stop it first",
+        );
+        return Some(ServerFrame::Error { error });
+    }
+    if let Err(error) = request.validate(&socket.limits) {
+        return Some(ServerFrame::Error { error });
+    }
+    if let Err(wait) = socket.limiter.check(&socket.client) {
+        let error = rate_limited(wait);
+        return Some(ServerFrame::Error { error });
+    }
+    let model_id = match socket.models.resolve(request.model.as_deref()) {
+        Ok(model_id) => model_id,
+        Err(error) => return Some(ServerFrame::Error { error }),
+    };
+    let model = match socket.models.get(&model_id) {
+        Ok(model) => model,
+        Err(e) => {
+            let error = ApiError::new("model_unavailable", e);
+            return Some(ServerFrame::Error { error });
+        }
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    *answering = Some(stop.clone());
+    let args = request.to_args(&socket.book, &socket.generation_defaults);
+    let sessions = socket.sessions.clone();
+    let generations = socket.generations.clone();
+    let session_id = socket.session_id.clone();
+    let frames = frames.clone();
+    actix_web::rt::spawn(async move {
+        let Some(queued) = generations.enqueue() else {
+            let error = server_busy(generations.max(), generations.max_queued());
+            let _ = frames.send(ServerFrame::Error { error });
+            return;
+        };
+        if queued.position() > 0 {
+            let position = queued.position();
+            let _ = frames.send(ServerFrame::Queued { position });
+        }
+        let generation = queued.start().await;
+        let _ = frames.send(ServerFrame::Started);
+        let answered = answer(args, &sessions, &session_id, &model, &stop, |content| {
+            let _ = frames.send(ServerFrame::Delta {
+                content: content.to_string(),
+            });
+        })
+        .await;
+        generation.finish();
+        let frame = answered.unwrap_or_else(|error| ServerFrame::Error { error });
+        let _ = frames.send(frame);
+    });
+    None
+}
+
+/// Answers `args` after the latest turns of session `session_id`, passing the answer to
+/// `on_delta` piece by piece, and adds the turn to the session unless stopped. Returns the
+/// `done` or `stopped` frame.
+async fn answer(
+    args: ChatArgs,
+    sessions: &ChatSessions,
+    session_id: &str,
+    model: &ChatModel,
+    stop: &AtomicBool,
+    on_delta: impl FnMut(&str),
+) -> Result<ServerFrame, ApiError> {
+    let started = Instant::now();
+    let prompt = args.prompt.clone().unwrap_or_default();
+    let generation = args.generation.clone();
+    // The history and the context are read from the database, off the async threads.
+    let (lookup, id) = (sessions.clone(), session_id.to_string());
+    let (messages, sources) = task::spawn_blocking(move || {
+        let history = lookup
+            .history(&id)
+            .map_err(|e| ApiError::new("internal_error", e))?
+            .unwrap_or_default();
+        conversation(&args, &history)
+    })
+    .await
+    .unwrap_or_else(|e| Err(task_failed(e)))?;
+    let answer = generate(model, messages, stop, on_delta).await?;
+    let seconds = started.elapsed().as_secs_f64();
+    if answer.stopped {
+        println!("Stopped after {:.1}s", seconds);
+        return Ok(ServerFrame::Stopped { seconds });
+    }
+    println!("Answered in {:.1}s", seconds);
+    let turn = Turn {
+        prompt,
+        answer: answer.text,
+    };
+    let (recorder, id) = (sessions.clone(), session_id.to_string());
+    task::spawn_blocking(move || recorder.record(&id, &turn))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+        .map_err(|e| ApiError::new("internal_error", e))?;
+    Ok(ServerFrame::Done {
+        seconds,
+        tokens: answer.tokens,
+        sources,
+        generation,
+    })
+}
+
+async fn send(session: &mut Session, frame: &ServerFrame) -> Result<(), actix_ws::Closed> {
+    let text = serde_json::to_string(frame).expect("frames serialize to JSON");
+    session.text(text).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{generous_limits, serve, ChatState, FakeModel};
+    use actix_web::App;
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Serves `/ws/chat` answering with `model`; returns the socket's URL and the state.
+    async fn start(dir: &std::path::Path, model: FakeModel) -> (String, ChatState) {
+        let state = ChatState::new(dir, model.serve(), generous_limits(1)).await;
+        let shared = state.clone();
+        let address = serve(move || {
+            let state = shared.clone();
+            App::new()
+                .configure(move |config| state.configure(config))
+                .route("/ws/chat", web::get().to(ws_chat_handler))
+        });
+        (format!("ws://{}/ws/chat", address), state)
+    }
+
+    /// The next text frame from the server, as JSON.
+    async fn next_frame(client: &mut Client) -> Value {
+        loop {
+            match client.next().await.expect("the socket is open").unwrap() {
+                Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                Message::Ping(_) | Message::Pong(_) => continue,
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+    }
+
+    async fn ask(client: &mut Client, prompt: &str) {
+        let frame = json!({"type": "message", "prompt": prompt, "file_content": "A book."});
+        client.send(Message::text(frame.to_string())).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn answers_stream_and_accumulate_in_the_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, state) = start(dir.path(), FakeModel::answering(&["Hello", ", world"])).await;
+        let (mut client, _) = connect_async(&url).await.unwrap();
+
+        let greeting = next_frame(&mut client).await;
+        assert_eq!(greeting["type"], "session");
+        let session_id = greeting["session_id"].as_str().unwrap().to_string();
+
+        for round in 1..=2 {
+            ask(&mut client, &format!("Question {}", round)).await;
+            assert_eq!(next_frame(&mut client).await["type"], "started");
+            let mut answer = String::new();
+            let done = loop {
+                let frame = next_frame(&mut client).await;
+                match frame["type"].as_str().unwrap() {
+                    "delta" => answer.push_str(frame["content"].as_str().unwrap()),
+                    _ => break frame,
+                }
+            };
+            assert_eq!(done["type"], "done", "{}", done);
+            assert_eq!(done["tokens"], 2);
+            assert_eq!(answer, "Hello, world");
+        }
+
+        let history = state.sessions.history(&session_id).unwrap().unwrap();
+        let prompts: Vec<&str> = history.iter().map(|turn| turn.prompt.as_str()).collect();
+        assert_eq!(prompts, ["Question 1", "Question 2"]);
+
+        // The session carries over to a new socket.
+        let (mut again, _) = connect_async(format!("{}?session={}", url, session_id))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_frame(&mut again).await["session_id"],
+            session_id.as_str()
+        );
+    }
+
+    #[actix_web::test]
+    async fn stop_cancels_the_answer_in_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let slow = FakeModel {
+            deltas: vec!["word ".to_string(); 500],
+            delay: Duration::from_millis(10),
+            ..FakeModel::answering(&[])
+        };
+        let (url, state) = start(dir.path(), slow).await;
+        let (mut client, _) = connect_async(&url).await.unwrap();
+        let session_id = next_frame(&mut client).await["session_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        ask(&mut client, "Talk for a while").await;
+        assert_eq!(next_frame(&mut client).await["type"], "started");
+        assert_eq!(next_frame(&mut client).await["type"], "delta");
+        client
+            .send(Message::text(json!({"type": "stop"}).to_string()))
+            .await
+            .unwrap();
+        let end = loop {
+            let frame = next_frame(&mut client).await;
+            if frame["type"] != "delta" {
+                break frame;
+            }
+        };
+        assert_eq!(end["type"], "stopped", "{}", end);
+        assert!(end["seconds"].as_f64().unwrap() < 4.0);
+        let history = state.sessions.history(&session_id).unwrap().unwrap();
+        assert!(history.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn invalid_frames_are_answered_with_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, _state) = start(dir.path(), FakeModel::answering(&["Hi"])).await;
+        let (mut client, _) = connect_async(&url).await.unwrap();
+        next_frame(&mut client).await;
+
+        client.send(Message::text("not json")).await.unwrap();
+        let error = next_frame(&mut client).await;
+        assert_eq!(
+            (&error["type"], &error["code"]),
+            (&json!("error"), &json!("invalid_request"))
+        );
+
+        client
+            .send(Message::text(
+                json!({"type": "message", "prompt": " "}).to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(next_frame(&mut client).await["code"], "empty_prompt");
+
+        client.send(Message::binary(vec![1, 2, 3])).await.unwrap();
+        assert_eq!(next_frame(&mut client).await["code"], "invalid_frame");
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_session_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, _state) = start(dir.path(), FakeModel::answering(&["Hi"])).await;
+        let refused = connect_async(format!("{}?session=missing", url)).await;
+        assert!(refused.is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// Suffix appended to a file's name for a backup written next to it.
+pub const BACKUP_SUFFIX: &str = "bak";
+
+/// Folder under a backup directory that holds files from outside the current directory.
+const OUTSIDE_PROJECT: &str = "_root";
+
+/// Where copies of files are saved before they are rewritten in place.
+#[derive(Debug, Clone)]
+pub enum Backup {
+    /// Next to the file, as `<file>.bak`.
+    Beside,
+    /// Under this directory, mirroring the file's path relative to the current directory.
+    Dir(PathBuf),
+}
+
+impl Backup {
+    /// Backups into a new `<backups_root>/<unix timestamp>` folder, one per run.
+    pub fn timestamped(backups_root: &Path) -> Backup {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Backup::Dir(backups_root.join(secs.to_string()))
+    }
+
+    /// Copies `path` to its backup location; call it right before the file is overwritten.
+    pub fn save(&self, path: &Path) -> io::Result<PathBuf> {
+        let target = match self {
+            Backup::Beside => beside_path(path),
+            Backup::Dir(dir) => dir.join(relative_to_cwd(path)?),
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(path, &target)?;
+        Ok(target)
+    }
+}
+
+/// Returns `<file>.bak` for `path`.
+fn beside_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(BACKUP_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Path of `path` relative to the current directory; files outside it are kept under `_root`.
+fn relative_to_cwd(path: &Path) -> io::Result<PathBuf> {
+    let cwd = env::current_dir()?.canonicalize()?;
+    let absolute = path.canonicalize()?;
+    if let Ok(relative) = absolute.strip_prefix(&cwd) {
+        return Ok(relative.to_path_buf());
+    }
+    let mut relative = PathBuf::from(OUTSIDE_PROJECT);
+    relative.extend(
+        absolute
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_))),
+    );
+    Ok(relative)
+}
+
+/// Copies every `<file>.md.bak` under `folder` back over `<file>.md` and removes the backup.
+pub fn restore_beside(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut restored = Vec::new();
+    for entry in WalkDir::new(folder).sort_by_file_name() {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type().is_file()
+            || path.extension().and_then(|e| e.to_str()) != Some(BACKUP_SUFFIX)
+        {
+            continue;
+        }
+        let original = path.with_extension("");
+        if original.extension().and_then(|e| e.to_str()) != Some("md") {
+            continue;
+        }
+        fs::copy(path, &original)?;
+        fs::remove_file(path)?;
+        restored.push(original);
+    }
+    Ok(restored)
+}
+
+/// Copies the files of the backup in `backup_dir` back to where they came from.
+pub fn restore_dir(backup_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let cwd = env::current_dir()?;
+    let mut restored = Vec::new();
+    for entry in WalkDir::new(backup_dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(backup_dir)
+            .map_err(io::Error::other)?;
+        let original = match relative.strip_prefix(OUTSIDE_PROJECT) {
+            Ok(outside) => Path::new("/").join(outside),
+            Err(_) => cwd.join(relative),
+        };
+        if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(entry.path(), &original)?;
+        restored.push(original);
+    }
+    Ok(restored)
+}
+
+/// Returns the most recent timestamped backup under `backups_root`, if any.
+pub fn latest_backup(backups_root: &Path) -> io::Result<Option<PathBuf>> {
+    if !backups_root.is_dir() {
+        return Ok(None);
+    }
+    let mut latest: Option<(u64, PathBuf)> = None;
+    for entry in fs::read_dir(backups_root)? {
+        let path = entry?.path();
+        let stamp = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.parse::<u64>().ok());
+        if let Some(stamp) = stamp {
+            if path.is_dir() && latest.as_ref().is_none_or(|(s, _)| stamp > *s) {
+                latest = Some((stamp, path));
+            }
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
@@ -0,0 +1,476 @@
+use colored::Colorize;
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::server::generation::GenerationParams;
+
+/// Name of the file configuring a project.
+pub const CONFIG_FILE: &str = "Lila.toml";
+
+/// The configuration `main` loaded, for code it does not hand the configuration to.
+static CURRENT: OnceLock<Config> = OnceLock::new();
+
+/// The settings of a project's `Lila.toml`, read once and handed to the commands. A missing
+/// file, section or key keeps its default, which each command decides; the ranges of the
+/// values are checked by the commands too, while their types are checked here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The file read, or where it would be when there is none.
+    #[serde(skip)]
+    path: PathBuf,
+    pub project: Option<ProjectSection>,
+    pub compliance: Option<ComplianceSection>,
+    pub ai_guidance: AiGuidanceSection,
+    pub development: Option<DevelopmentSection>,
+    /// `[dependencies.<language>]` tables, written by `lila init`.
+    pub dependencies: Option<BTreeMap<String, toml::Table>>,
+    pub format: FormatSection,
+    pub server: ServerSection,
+    pub ai: AiSection,
+    pub database: DatabaseSection,
+    pub assets: AssetsSection,
+    pub history: HistorySection,
+    pub weave: WeaveSection,
+    pub render: RenderSection,
+    pub tangle: TangleSection,
+}
+
+/// `[project]`: what the project is, told to the model.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProjectSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+}
+
+/// `[compliance]`: the guidelines the project follows.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ComplianceSection {
+    pub iso: Vec<String>,
+    pub bsi: Vec<String>,
+}
+
+/// `[ai_guidance]`: how the model is told to behave.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AiGuidanceSection {
+    pub code_of_conduct: Option<String>,
+    /// Replaces lila's default system prompt.
+    pub system_prompt: Option<String>,
+}
+
+/// `[development]`: what the project is written in and runs on.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DevelopmentSection {
+    /// Languages, with the version in use, like `rust~=1.71`.
+    pub languages: Vec<String>,
+    pub operating_systems: Vec<String>,
+    pub architecture: Vec<String>,
+}
+
+/// `[format]`: how `lila edit` formats code blocks.
+#[derive(Debug, Default)]
+pub struct FormatSection {
+    pub timeout_secs: Option<i64>,
+    pub rust_edition: Option<String>,
+    pub rustfmt_config: Option<PathBuf>,
+    pub line_width: Option<i64>,
+    /// Formatters `lila init` found installed.
+    pub available: Option<Vec<String>>,
+    /// `[format.<language>]` tables, by the language's tag.
+    pub languages: BTreeMap<String, FormatLanguageSection>,
+}
+
+/// Read key by key rather than with `#[serde(flatten)]`, which would lose the line and the
+/// key of a wrong value in a `[format.<language>]` table, and hide its unknown keys.
+impl<'de> Deserialize<'de> for FormatSection {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FormatVisitor;
+
+        impl<'de> Visitor<'de> for FormatVisitor {
+            type Value = FormatSection;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a table")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FormatSection, A::Error> {
+                let mut format = FormatSection::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "timeout_secs" => format.timeout_secs = map.next_value()?,
+                        "rust_edition" => format.rust_edition = map.next_value()?,
+                        "rustfmt_config" => format.rustfmt_config = map.next_value()?,
+                        "line_width" => format.line_width = map.next_value()?,
+                        "available" => format.available = map.next_value()?,
+                        _ => {
+                            let section = map.next_value()?;
+                            format.languages.insert(key, section);
+                        }
+                    }
+                }
+                Ok(format)
+            }
+        }
+
+        deserializer.deserialize_map(FormatVisitor)
+    }
+}
+
+/// `[format.<language>]`: the formatter of a language, or its line width.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FormatLanguageSection {
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub line_width: Option<i64>,
+}
+
+/// `[server]`: how `lila server`, and `lila chat` where it applies, answer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerSection {
+    pub host: Option<String>,
+    pub port: Option<i64>,
+    pub workers: Option<i64>,
+    pub cors_origins: Option<Vec<String>>,
+    pub token: Option<String>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub default_model: Option<String>,
+    pub models: Option<Vec<String>>,
+    pub max_loaded_models: Option<i64>,
+    pub requests_per_minute: Option<i64>,
+    pub burst: Option<i64>,
+    pub max_concurrent_generations: Option<i64>,
+    pub max_queued_generations: Option<i64>,
+    pub max_context_bytes: Option<i64>,
+    pub max_render_bytes: Option<i64>,
+    pub sanitize_html: Option<bool>,
+    pub context_tokens: Option<i64>,
+    pub history_tokens: Option<i64>,
+    pub shutdown_grace_seconds: Option<i64>,
+    pub generation: Option<GenerationParams>,
+}
+
+/// `[ai]`: where the models run.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AiSection {
+    pub backend: Option<String>,
+    pub base_url: Option<String>,
+    pub api_key_env: Option<String>,
+    pub isq: Option<String>,
+    pub generation: Option<GenerationParams>,
+}
+
+/// `[database]`: how the database is opened.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DatabaseSection {
+    pub pool_size: Option<i64>,
+    pub key_file: Option<PathBuf>,
+}
+
+/// `[assets]`: the images saved with the Markdown files.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AssetsSection {
+    pub max_bytes: Option<i64>,
+}
+
+/// `[history]`: the versions kept of each saved file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HistorySection {
+    pub max_versions: Option<i64>,
+}
+
+/// `[weave]`: how `lila weave` writes the book. It has no settings yet; the section is
+/// known so a `[weave]` table is not warned about.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WeaveSection {}
+
+/// `[render]`: how Markdown is rendered to HTML.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RenderSection {
+    /// Syntect theme code blocks are highlighted with, like `InspiredGitHub`.
+    pub theme: Option<String>,
+}
+
+/// `[tangle]`: how `lila tangle` writes the code. It has no settings yet; the section is
+/// known so a `[tangle]` table is not warned about.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TangleSection {}
+
+impl Config {
+    /// The `Lila.toml` of the current folder, else of the nearest folder above it; the
+    /// defaults without one. Keys lila does not know are warned about rather than refused,
+    /// so a file written for a newer lila still works.
+    pub fn load() -> Result<Config, String> {
+        let cwd = std::env::current_dir()
+            .map_err(|e| format!("could not find the current folder: {}", e))?;
+        Config::find(&cwd)
+    }
+
+    /// `load` from `cwd`.
+    fn find(cwd: &Path) -> Result<Config, String> {
+        let found = cwd
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file());
+        match found {
+            Some(path) => Config::read(&path),
+            None => Ok(Config {
+                path: cwd.join(CONFIG_FILE),
+                ..Config::default()
+            }),
+        }
+    }
+
+    /// The settings of the `Lila.toml` at `path`, or why they are invalid: the key, the
+    /// line and what the value must be.
+    pub fn read(path: &Path) -> Result<Config, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let (config, unknown) = Config::parse(path, &content)?;
+        for key in unknown {
+            eprintln!(
+                "{} unknown key {} in {} is ignored",
+                "Warning:".yellow(),
+                key,
+                path.display()
+            );
+        }
+        Ok(config)
+    }
+
+    /// The settings in `content`, as read from `path`, and the dotted keys lila does not
+    /// know.
+    pub fn parse(path: &Path, content: &str) -> Result<(Config, Vec<String>), String> {
+        let mut unknown = Vec::new();
+        let mut ignored = |key: serde_ignored::Path| unknown.push(key.to_string());
+        let deserializer =
+            serde_ignored::Deserializer::new(toml::Deserializer::new(content), &mut ignored);
+        let mut config: Config = serde_path_to_error::deserialize(deserializer)
+            .map_err(|e| invalid(path, content, e))?;
+        config.path = path.to_path_buf();
+        Ok((config, unknown))
+    }
+
+    /// Keeps `config` for `Config::current`, returning it for the rest of the process.
+    pub fn install(config: Config) -> &'static Config {
+        CURRENT.get_or_init(|| config)
+    }
+
+    /// The configuration `main` installed; loaded here if it did not, the defaults standing
+    /// in for an invalid file after a warning.
+    pub fn current() -> &'static Config {
+        CURRENT.get_or_init(|| {
+            Config::load().unwrap_or_else(|e| {
+                eprintln!("{} {}; using the defaults", "Warning:".yellow(), e);
+                Config::default()
+            })
+        })
+    }
+
+    /// The file the settings come from, named in errors.
+    pub fn path(&self) -> &Path {
+        if self.path.as_os_str().is_empty() {
+            Path::new(CONFIG_FILE)
+        } else {
+            &self.path
+        }
+    }
+
+    /// `path` as given in the file: relative paths are relative to the file's folder.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        match self.path().parent() {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+/// The message for `error`, met reading `content` from `path`.
+fn invalid(
+    path: &Path,
+    content: &str,
+    error: serde_path_to_error::Error<toml::de::Error>,
+) -> String {
+    let key = error.path().to_string();
+    let error = error.into_inner();
+    let Some(span) = error.span().filter(|_| key != ".") else {
+        return format!("could not parse {}: {}", path.display(), error);
+    };
+    let line = content[..span.start].matches('\n').count() + 1;
+    format!(
+        "invalid {} (line {}): {}: {}",
+        path.display(),
+        line,
+        key,
+        error.message().trim()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<(Config, Vec<String>), String> {
+        Config::parse(Path::new("/book/Lila.toml"), content)
+    }
+
+    #[test]
+    fn sections_are_read_into_their_types() {
+        let (config, unknown) = parse(
+            "\
+[project]
+context = \"Physics engine\"
+
+[development]
+languages = [\"rust~=1.74\", \"python\"]
+
+[dependencies.rust]
+serde = { version = \"1\", features = [\"derive\"] }
+
+[format]
+timeout_secs = 5
+[format.python]
+command = \"black\"
+args = [\"-q\", \"-\"]
+
+[server]
+port = 8080
+models = [\"a\", \"b\"]
+sanitize_html = false
+
+[ai.generation]
+temperature = 0.2
+seed = 7
+",
+        )
+        .unwrap();
+        assert!(unknown.is_empty(), "{:?}", unknown);
+        assert_eq!(
+            config.project.unwrap().context.as_deref(),
+            Some("Physics engine")
+        );
+        assert_eq!(
+            config.development.unwrap().languages,
+            ["rust~=1.74", "python"]
+        );
+        assert!(config.dependencies.unwrap()["rust"].contains_key("serde"));
+        assert_eq!(config.format.timeout_secs, Some(5));
+        let python = &config.format.languages["python"];
+        assert_eq!(python.command.as_deref(), Some("black"));
+        assert_eq!(
+            python.args.as_deref(),
+            Some(&["-q".to_string(), "-".to_string()][..])
+        );
+        assert_eq!(config.server.port, Some(8080));
+        assert_eq!(config.server.sanitize_html, Some(false));
+        let generation = config.ai.generation.unwrap();
+        assert_eq!(
+            (generation.temperature, generation.seed),
+            (Some(0.2), Some(7))
+        );
+    }
+
+    #[test]
+    fn a_wrong_type_names_the_file_line_key_and_type() {
+        let error = parse("[project]\ncontext = \"x\"\n\n[server]\nport = \"8080\"\n").unwrap_err();
+        assert!(
+            error.starts_with("invalid /book/Lila.toml (line 5): server.port:"),
+            "{}",
+            error
+        );
+        assert!(error.contains("expected i64"), "{}", error);
+
+        let error = parse("[format.rust]\nargs = \"--edition\"\n").unwrap_err();
+        assert!(error.contains("(line 2): format.rust.args:"), "{}", error);
+        assert!(error.contains("expected a sequence"), "{}", error);
+    }
+
+    #[test]
+    fn a_syntax_error_names_the_file() {
+        let error = parse("[server\nport = 1\n").unwrap_err();
+        assert!(
+            error.starts_with("could not parse /book/Lila.toml"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_not_refused() {
+        let (config, unknown) = parse(
+            "\
+[render]
+theme = \"InspiredGitHub\"
+toc_depth = 3
+
+[weave]
+
+[tangle]
+
+[server]
+prot = 80
+port = 81
+
+[format.rust]
+comand = \"rustfmt\"
+",
+        )
+        .unwrap();
+        assert_eq!(config.server.port, Some(81));
+        assert_eq!(config.render.theme.as_deref(), Some("InspiredGitHub"));
+        assert_eq!(
+            unknown,
+            ["render.toc_depth", "server.prot", "format.rust.comand"]
+        );
+    }
+
+    #[test]
+    fn load_finds_lila_toml_above_the_current_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("doc").join("chapters");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE),
+            "[database]\nkey_file = \"secrets/db.key\"\n",
+        )
+        .unwrap();
+
+        let config = Config::find(&nested).unwrap();
+        assert_eq!(config.path(), dir.path().join(CONFIG_FILE));
+        let key_file = config.database.key_file.as_deref().unwrap();
+        assert_eq!(config.resolve(key_file), dir.path().join("secrets/db.key"));
+        assert_eq!(
+            config.resolve(Path::new("/etc/db.key")),
+            Path::new("/etc/db.key")
+        );
+    }
+
+    #[test]
+    fn without_lila_toml_the_defaults_are_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::find(dir.path()).unwrap();
+        assert_eq!(config.path(), dir.path().join(CONFIG_FILE));
+        assert!(config.project.is_none());
+        assert_eq!(config.server.port, None);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::schema::assets;
+use crate::utils::config::Config;
+use crate::utils::database::models::Asset;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use regex::Regex;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Largest image saved unless `[assets] max_bytes` says otherwise.
+pub const DEFAULT_MAX_ASSET_BYTES: u64 = 10 * 1024 * 1024;
+
+/// The `max_bytes` in the `[assets]` section of `config` (a `Lila.toml`), else
+/// `DEFAULT_MAX_ASSET_BYTES`.
+pub fn max_asset_bytes(config: &Config) -> Result<u64, String> {
+    match config.assets.max_bytes {
+        None => Ok(DEFAULT_MAX_ASSET_BYTES),
+        Some(max) if max > 0 => Ok(max as u64),
+        Some(_) => Err(format!(
+            "invalid {}: assets.max_bytes must be a positive integer",
+            config.path().display()
+        )),
+    }
+}
+
+/// The local images `markdown` references, as written, in order and without repeats:
+/// `![alt](path)` and `<img src="path">` outside code fences. URLs and data URIs are left
+/// out, and so is any `?query` or `#fragment`.
+pub fn image_references(markdown: &str) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(
+            r#"!\[[^\]]*\]\(\s*<?([^)\s>]+)>?(?:\s+"[^"]*")?\s*\)|<img\b[^>]*?\bsrc\s*=\s*["']([^"']+)["']"#,
+        )
+        .expect("valid image pattern")
+    });
+
+    let mut references: Vec<String> = Vec::new();
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        for captures in pattern.captures_iter(line) {
+            let Some(target) = captures.get(1).or_else(|| captures.get(2)) else {
+                continue;
+            };
+            let target = target.as_str();
+            if target.contains("://")
+                || target.starts_with("//")
+                || target.starts_with("data:")
+                || target.starts_with('#')
+            {
+                continue;
+            }
+            let path = target
+                .split(['?', '#'])
+                .next()
+                .unwrap_or(target)
+                .replace("%20", " ");
+            if !path.is_empty() && !references.contains(&path) {
+                references.push(path);
+            }
+        }
+    }
+    references
+}
+
+/// The MIME type of an image, from the extension of `path`.
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Saves `content` as the asset `file_path` of `project_id`, unless it is saved with the
+/// same `hash` already. Returns whether anything was written.
+pub fn save_asset(
+    conn: &mut SqliteConnection,
+    project_id: i32,
+    file_path: &str,
+    mime_type: &str,
+    hash: &str,
+    content: &[u8],
+) -> QueryResult<bool> {
+    use assets::dsl as a;
+
+    let existing: Option<(i32, String)> = a::assets
+        .filter(a::project_id.eq(project_id))
+        .filter(a::file_path.eq(file_path))
+        .select((a::id, a::content_hash))
+        .first(conn)
+        .optional()?;
+    match existing {
+        Some((_, saved)) if saved == hash => Ok(false),
+        Some((id, _)) => {
+            diesel::update(a::assets.find(id))
+                .set((
+                    a::mime_type.eq(mime_type),
+                    a::content_hash.eq(hash),
+                    a::content.eq(content),
+                ))
+                .execute(conn)?;
+            Ok(true)
+        }
+        None => {
+            diesel::insert_into(a::assets)
+                .values((
+                    a::project_id.eq(project_id),
+                    a::file_path.eq(file_path),
+                    a::mime_type.eq(mime_type),
+                    a::content_hash.eq(hash),
+                    a::content.eq(content),
+                ))
+                .execute(conn)?;
+            Ok(true)
+        }
+    }
+}
+
+/// The asset saved under `id`, if any.
+pub fn find_asset(conn: &mut SqliteConnection, id: i32) -> QueryResult<Option<Asset>> {
+    assets::table.find(id).first(conn).optional()
+}
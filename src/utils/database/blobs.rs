@@ -0,0 +1,52 @@
+use crate::schema::blobs;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::sqlite::SqliteConnection;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+define_sql_function! {
+    /// `content_hash` in SQL, for the migration that hashes rows saved without a hash.
+    fn lila_sha256(content: Text) -> Text;
+}
+
+/// SHA-256 of `content`, in hex: the key content is stored under in `blobs`.
+pub fn content_hash(content: &str) -> String {
+    bytes_hash(content.as_bytes())
+}
+
+/// SHA-256 of `bytes`, in hex.
+pub fn bytes_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Makes `lila_sha256` available to the migrations run on `conn`.
+pub(crate) fn register_hash_function(conn: &mut SqliteConnection) -> QueryResult<()> {
+    lila_sha256_utils::register_impl(conn, |content: String| content_hash(&content))
+}
+
+/// Stores each `(hash, content)` not stored yet, with one insert for all of them, and
+/// returns how many were new. Content already saved for another file is not written again.
+pub fn store_blobs(conn: &mut SqliteConnection, contents: &[(&str, &str)]) -> QueryResult<usize> {
+    let hashes: Vec<&str> = contents.iter().map(|(hash, _)| *hash).collect();
+    let stored: HashSet<String> = blobs::table
+        .filter(blobs::hash.eq_any(&hashes))
+        .select(blobs::hash)
+        .load(conn)?
+        .into_iter()
+        .collect();
+
+    let mut seen = HashSet::new();
+    let rows: Vec<_> = contents
+        .iter()
+        .filter(|(hash, _)| !stored.contains(*hash) && seen.insert(*hash))
+        .map(|(hash, content)| (blobs::hash.eq(*hash), blobs::content.eq(*content)))
+        .collect();
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    diesel::insert_into(blobs::table).values(rows).execute(conn)
+}
@@ -1,17 +1,380 @@
+use crate::utils::config::Config;
+use crate::utils::database::blobs::register_hash_function;
+use crate::utils::database::projects::{adopt_unassigned, legacy_project_name};
+use diesel::connection::SimpleConnection;
+use diesel::migration::MigrationSource;
 use diesel::prelude::*;
-use diesel::sqlite::SqliteConnection;
+use diesel::r2d2::{self, CustomizeConnection};
+use diesel::sql_query;
+use diesel::sql_types::Text;
+use diesel::sqlite::{Sqlite, SqliteConnection};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use dotenvy::dotenv;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
 
-/// Establish a connection to the SQLite database at `db_path`.
-pub fn establish_connection(db_path: &str) -> SqliteConnection {
-    SqliteConnection::establish(db_path)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", db_path))
+/// Settings applied to every SQLite connection lila opens:
+/// a busy timeout instead of immediate "database is locked" errors (first, so switching
+/// a new database to WAL waits for another process doing the same), WAL so the server can
+/// read while a save writes, enforced foreign keys, and `synchronous=NORMAL`, which is
+/// safe with WAL and much faster than the default.
+const CONNECTION_PRAGMAS: &str = "PRAGMA busy_timeout = 5000; \
+     PRAGMA journal_mode = WAL; \
+     PRAGMA foreign_keys = ON; \
+     PRAGMA synchronous = NORMAL;";
+
+/// Version of the migration that adds projects; the files saved before it are assigned
+/// to a project as it is applied, see `adopt_legacy_files`.
+const PROJECTS_MIGRATION: &str = "20261016150000";
+
+/// Environment variable holding the key of an encrypted database.
+pub const DB_KEY_VAR: &str = "LILA_DB_KEY";
+
+/// The key of an encrypted database; kept out of `Debug` output.
+#[derive(Clone)]
+pub struct DatabaseKey(String);
+
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DatabaseKey(..)")
+    }
+}
+
+impl DatabaseKey {
+    /// The key from `LILA_DB_KEY`, else from the file named by `key_file` in the
+    /// `[database]` section of `config` (a `Lila.toml`). `None` means no encryption.
+    pub fn load(config: &Config) -> Result<Option<DatabaseKey>, String> {
+        if let Ok(key) = std::env::var(DB_KEY_VAR) {
+            if !key.is_empty() {
+                return Ok(Some(DatabaseKey(key)));
+            }
+        }
+        let Some(key_file) = &config.database.key_file else {
+            return Ok(None);
+        };
+        let key_file = config.resolve(key_file);
+        let key = fs::read_to_string(&key_file).map_err(|e| {
+            format!(
+                "could not read the database key file {}: {}",
+                key_file.display(),
+                e
+            )
+        })?;
+        match key.trim() {
+            "" => Err(format!(
+                "the database key file {} is empty",
+                key_file.display()
+            )),
+            key => Ok(Some(DatabaseKey(key.to_string()))),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct CipherVersion {
+    #[diesel(sql_type = Text)]
+    #[allow(dead_code)]
+    cipher_version: String,
+}
+
+/// Applies lila's connection settings, to plain connections and, as an r2d2
+/// connection customizer, to pooled ones. With a `key`, the database is unlocked with
+/// SQLCipher first, as it must be before any other statement.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionCustomizer {
+    pub key: Option<DatabaseKey>,
+}
+
+impl ConnectionCustomizer {
+    pub fn apply(&self, conn: &mut SqliteConnection) -> Result<(), ConnectionError> {
+        if let Some(key) = &self.key {
+            unlock(conn, key)?;
+        }
+        conn.batch_execute(CONNECTION_PRAGMAS)
+            .map_err(ConnectionError::CouldntSetupConfiguration)
+    }
+}
+
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for ConnectionCustomizer {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        self.apply(conn).map_err(r2d2::Error::ConnectionError)
+    }
+}
+
+/// Sets the SQLCipher `key` on `conn` and checks that it opens the database.
+///
+/// A SQLite without SQLCipher ignores `PRAGMA key` and would write plaintext, so that
+/// is an error rather than a silent fallback.
+fn unlock(conn: &mut SqliteConnection, key: &DatabaseKey) -> Result<(), ConnectionError> {
+    let versions: Vec<CipherVersion> = sql_query("PRAGMA cipher_version")
+        .load(conn)
+        .map_err(ConnectionError::CouldntSetupConfiguration)?;
+    if versions.is_empty() {
+        return Err(ConnectionError::BadConnection(format!(
+            "{} is set, but database encryption is not compiled in; \
+             rebuild lila with `--features sqlcipher`",
+            DB_KEY_VAR
+        )));
+    }
+    conn.batch_execute(&format!("PRAGMA key = '{}';", key.0.replace('\'', "''")))
+        .map_err(ConnectionError::CouldntSetupConfiguration)?;
+    // SQLCipher only notices a wrong key when the first page is read.
+    conn.batch_execute("SELECT COUNT(*) FROM sqlite_master;")
+        .map_err(|_| {
+            ConnectionError::BadConnection(
+                "cannot open the database: the key is wrong or the file is not encrypted"
+                    .to_string(),
+            )
+        })
+}
+
+/// Establish a connection to the SQLite database at `database_url`, with the settings
+/// of `ConnectionCustomizer` and the key from `DatabaseKey::load`, if any.
+pub fn establish_connection(database_url: &str) -> ConnectionResult<SqliteConnection> {
+    dotenv().ok();
+    let key = DatabaseKey::load(Config::current()).map_err(ConnectionError::BadConnection)?;
+    let mut conn = SqliteConnection::establish(database_url)?;
+    ConnectionCustomizer { key }.apply(&mut conn)?;
+    Ok(conn)
+}
+
+/// Exit code of a command refused because its database is newer than this binary.
+pub const EXIT_NEWER_SCHEMA: i32 = 3;
+
+/// Why `run_migrations` failed.
+#[derive(Debug)]
+pub enum MigrationError {
+    /// The database has migrations this binary does not know: a newer lila created it.
+    NewerSchema { unknown: Vec<String> },
+    /// `migration` failed; none of the pending migrations were applied.
+    Failed { migration: String, message: String },
+    /// The list of applied migrations could not be read.
+    Database(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::NewerSchema { unknown } => write!(
+                f,
+                "the database was created by a newer version of lila (unknown migrations: {}); \
+                 please upgrade lila or point --db at a different file",
+                unknown.join(", ")
+            ),
+            MigrationError::Failed { migration, message } => write!(
+                f,
+                "migration {} failed: {} (the database was left as it was)",
+                migration, message
+            ),
+            MigrationError::Database(message) => {
+                write!(f, "cannot read the applied migrations: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<diesel::result::Error> for MigrationError {
+    fn from(e: diesel::result::Error) -> Self {
+        MigrationError::Database(e.to_string())
+    }
 }
 
 /// Run any pending migrations on the given connection.
-pub fn run_migrations(conn: &mut SqliteConnection) {
-    conn.run_pending_migrations(MIGRATIONS)
-        .expect("Failed to run Diesel migrations");
+///
+/// The migrations are embedded in the binary, so this needs no Diesel CLI. A database
+/// with migrations this binary does not have is refused before anything is changed, and
+/// the pending migrations are applied in one transaction, so a failure applies none.
+pub fn run_migrations(conn: &mut SqliteConnection) -> Result<(), MigrationError> {
+    // Migrations that rebuild a table drop it, which with foreign keys on would cascade
+    // to the rows referencing it. SQLite ignores this pragma inside a transaction, so it
+    // is switched here, around the migrations, as SQLite's docs on schema changes advise.
+    register_hash_function(conn)?;
+    conn.batch_execute("PRAGMA foreign_keys = OFF;")?;
+    let result = apply_pending_migrations(conn);
+    conn.batch_execute("PRAGMA foreign_keys = ON;")?;
+    result
+}
+
+/// The names of the migrations `run_migrations` would apply, without applying them, or
+/// why it would refuse the database.
+pub fn pending_migrations(conn: &mut SqliteConnection) -> Result<Vec<String>, MigrationError> {
+    check_known_migrations(conn)?;
+    Ok(conn
+        .pending_migrations(MIGRATIONS)
+        .map_err(|e| MigrationError::Database(e.to_string()))?
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect())
+}
+
+/// Refuses a database with migrations this binary does not know.
+fn check_known_migrations(conn: &mut SqliteConnection) -> Result<(), MigrationError> {
+    let known: HashSet<String> = MigrationSource::<Sqlite>::migrations(&MIGRATIONS)
+        .map_err(|e| MigrationError::Database(e.to_string()))?
+        .iter()
+        .map(|m| m.name().version().to_string())
+        .collect();
+    let applied = conn
+        .applied_migrations()
+        .map_err(|e| MigrationError::Database(e.to_string()))?;
+    let unknown: Vec<String> = applied
+        .iter()
+        .map(|v| v.to_string())
+        .filter(|v| !known.contains(v))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(MigrationError::NewerSchema { unknown });
+    }
+    Ok(())
+}
+
+fn apply_pending_migrations(conn: &mut SqliteConnection) -> Result<(), MigrationError> {
+    check_known_migrations(conn)?;
+
+    let pending = |conn: &mut SqliteConnection| {
+        conn.pending_migrations(MIGRATIONS)
+            .map_err(|e| MigrationError::Database(e.to_string()))
+    };
+    if pending(conn)?.is_empty() {
+        return Ok(());
+    }
+    // Another lila may be migrating the same database: take the write lock first and
+    // only then see what is still pending.
+    conn.immediate_transaction(|conn| {
+        for migration in &pending(conn)? {
+            conn.run_migration(migration.as_ref())
+                .map_err(|e| MigrationError::Failed {
+                    migration: migration.name().to_string(),
+                    message: e.to_string(),
+                })?;
+            if migration.name().version().to_string() == PROJECTS_MIGRATION {
+                adopt_legacy_files(conn).map_err(|e| MigrationError::Failed {
+                    migration: migration.name().to_string(),
+                    message: e.to_string(),
+                })?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[derive(QueryableByName)]
+struct DatabaseFile {
+    #[diesel(sql_type = Text)]
+    file: String,
+}
+
+/// Assigns the files saved before projects existed to the project `legacy_project_name`
+/// derives from the location of the database `conn` is open on.
+fn adopt_legacy_files(conn: &mut SqliteConnection) -> QueryResult<usize> {
+    let files: Vec<DatabaseFile> =
+        sql_query("SELECT file FROM pragma_database_list WHERE name = 'main'").load(conn)?;
+    let db_path = files
+        .into_iter()
+        .next()
+        .map(|db| db.file)
+        .unwrap_or_default();
+    adopt_unassigned(conn, &legacy_project_name(Path::new(&db_path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database::pool::build_pool;
+
+    #[derive(QueryableByName)]
+    struct PragmaValue {
+        #[diesel(sql_type = Text)]
+        value: String,
+    }
+
+    /// The value of `PRAGMA <name>`, whose result column is `column`, as text.
+    fn pragma(conn: &mut SqliteConnection, name: &str, column: &str) -> String {
+        let query = format!(
+            "SELECT CAST({} AS TEXT) AS value FROM pragma_{}",
+            column, name
+        );
+        let rows: Vec<PragmaValue> = sql_query(query).load(conn).unwrap();
+        rows.into_iter().next().unwrap().value
+    }
+
+    fn assert_lila_settings(conn: &mut SqliteConnection) {
+        assert_eq!(pragma(conn, "journal_mode", "journal_mode"), "wal");
+        assert_eq!(pragma(conn, "foreign_keys", "foreign_keys"), "1");
+        assert_eq!(pragma(conn, "busy_timeout", "timeout"), "5000");
+        // 1 is NORMAL.
+        assert_eq!(pragma(conn, "synchronous", "synchronous"), "1");
+    }
+
+    #[test]
+    fn a_fresh_connection_has_the_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = dir.path().join("lila.db").to_string_lossy().into_owned();
+        let mut conn = SqliteConnection::establish(&url).unwrap();
+        ConnectionCustomizer::default().apply(&mut conn).unwrap();
+        assert_lila_settings(&mut conn);
+    }
+
+    #[test]
+    fn pooled_connections_have_the_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = dir.path().join("lila.db").to_string_lossy().into_owned();
+        let pool = build_pool(&url, 2, None).unwrap();
+        let mut first = pool.get().unwrap();
+        let mut second = pool.get().unwrap();
+        assert_lila_settings(&mut first);
+        assert_lila_settings(&mut second);
+    }
+
+    fn key(key: &str) -> ConnectionCustomizer {
+        ConnectionCustomizer {
+            key: Some(DatabaseKey(key.to_string())),
+        }
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[test]
+    fn a_key_without_sqlcipher_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = dir.path().join("lila.db").to_string_lossy().into_owned();
+        let mut conn = SqliteConnection::establish(&url).unwrap();
+        let error = key("secret").apply(&mut conn).unwrap_err().to_string();
+        assert!(error.contains("not compiled in"), "{}", error);
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn an_encrypted_database_needs_its_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lila.db");
+        let url = path.to_string_lossy().into_owned();
+        {
+            let mut conn = SqliteConnection::establish(&url).unwrap();
+            key("secret").apply(&mut conn).unwrap();
+            conn.batch_execute(
+                "CREATE TABLE notes (text TEXT); INSERT INTO notes VALUES ('private');",
+            )
+            .unwrap();
+        }
+        assert!(!fs::read(&path).unwrap().starts_with(b"SQLite format 3"));
+
+        let mut without_key = SqliteConnection::establish(&url).unwrap();
+        assert!(without_key
+            .batch_execute("SELECT COUNT(*) FROM notes;")
+            .is_err());
+
+        let mut wrong_key = SqliteConnection::establish(&url).unwrap();
+        let error = key("guess").apply(&mut wrong_key).unwrap_err().to_string();
+        assert!(error.contains("the key is wrong"), "{}", error);
+
+        let mut with_key = SqliteConnection::establish(&url).unwrap();
+        key("secret").apply(&mut with_key).unwrap();
+        with_key.batch_execute("SELECT text FROM notes;").unwrap();
+    }
 }
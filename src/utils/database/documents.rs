@@ -0,0 +1,123 @@
+use crate::schema::{blobs, metadata};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use regex::Regex;
+use serde::Serialize;
+
+/// A saved Markdown file, without its content.
+#[derive(Debug, Serialize, Queryable)]
+pub struct DocumentSummary {
+    pub id: i32,
+    pub path: String,
+    pub language: Option<String>,
+    pub size_bytes: Option<i64>,
+    /// File modification time, in seconds since the Unix epoch.
+    pub modified_at: Option<i64>,
+}
+
+/// Which saved files `list_documents` returns.
+#[derive(Debug, Default)]
+pub struct DocumentFilter<'a> {
+    /// Only files whose saved path starts with this prefix.
+    pub path_prefix: Option<&'a str>,
+    /// Only files of this project; `None` for all projects.
+    pub project_id: Option<i32>,
+    /// Files to leave out before the first one returned.
+    pub offset: i64,
+    pub limit: i64,
+}
+
+/// The saved files matching `filter`, ordered by path.
+pub fn list_documents(
+    conn: &mut SqliteConnection,
+    filter: &DocumentFilter,
+) -> QueryResult<Vec<DocumentSummary>> {
+    let mut query = metadata::table
+        .select((
+            metadata::id,
+            metadata::file_path,
+            metadata::language,
+            metadata::size_bytes,
+            metadata::modified_at,
+        ))
+        .order(metadata::file_path.asc())
+        .offset(filter.offset)
+        .limit(filter.limit)
+        .into_boxed();
+    if let Some(id) = filter.project_id {
+        query = query.filter(metadata::project_id.eq(id));
+    }
+    if let Some(prefix) = filter.path_prefix {
+        let pattern = format!(
+            "{}%",
+            prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        query = query.filter(metadata::file_path.like(pattern).escape('\\'));
+    }
+    query.load(conn)
+}
+
+/// The ids of the saved files whose paths start with any of `path_prefixes` (all files
+/// when empty), of project `project_id` (all projects if `None`), ordered by path.
+pub fn document_ids_under(
+    conn: &mut SqliteConnection,
+    path_prefixes: &[String],
+    project_id: Option<i32>,
+) -> QueryResult<Vec<i32>> {
+    let mut query = metadata::table
+        .select((metadata::id, metadata::file_path))
+        .order(metadata::file_path.asc())
+        .into_boxed();
+    if let Some(id) = project_id {
+        query = query.filter(metadata::project_id.eq(id));
+    }
+    let files: Vec<(i32, String)> = query.load(conn)?;
+    Ok(files
+        .into_iter()
+        .filter(|(_, path)| {
+            path_prefixes.is_empty() || path_prefixes.iter().any(|p| path.starts_with(p.as_str()))
+        })
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// The path and stored content of the file saved under `id`, if any.
+pub fn document_content(
+    conn: &mut SqliteConnection,
+    id: i32,
+) -> QueryResult<Option<(String, String)>> {
+    metadata::table
+        .inner_join(blobs::table)
+        .filter(metadata::id.eq(id))
+        .select((metadata::file_path, blobs::content))
+        .first(conn)
+        .optional()
+}
+
+/// Those of the saved files `ids` whose content matches all of `patterns`, or any of them
+/// with `any`, in the same order.
+pub fn documents_matching(
+    conn: &mut SqliteConnection,
+    ids: &[i32],
+    patterns: &[Regex],
+    any: bool,
+) -> QueryResult<Vec<i32>> {
+    let mut matching = Vec::new();
+    for &id in ids {
+        let Some((_, content)) = document_content(conn, id)? else {
+            continue;
+        };
+        let matches = if any {
+            patterns.iter().any(|pattern| pattern.is_match(&content))
+        } else {
+            patterns.iter().all(|pattern| pattern.is_match(&content))
+        };
+        if matches {
+            matching.push(id);
+        }
+    }
+    Ok(matching)
+}
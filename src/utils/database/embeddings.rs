@@ -0,0 +1,240 @@
+use crate::schema::{blobs, embeddings, metadata};
+use crate::utils::database::models::ChunkEmbedding;
+use crate::utils::embedding::Embedder;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+
+/// Longest chunk, in bytes; longer sections are split at blank lines. Small enough that
+/// a chunk fits the embedding model's 512 tokens.
+const MAX_CHUNK_BYTES: usize = 1500;
+
+/// A piece of a Markdown file embedded on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Byte range in the content.
+    pub start: usize,
+    pub end: usize,
+    /// Text of the heading the chunk is under.
+    pub heading: Option<String>,
+}
+
+/// A chunk returned by `retrieve`, with its text and where it comes from.
+#[derive(Debug, Serialize)]
+pub struct RetrievedChunk {
+    pub file_path: String,
+    pub heading: Option<String>,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    /// Cosine similarity to the query; higher is closer.
+    pub score: f32,
+}
+
+/// Splits `content` into chunks, one per heading section (headings inside code fences
+/// don't count), with sections longer than `MAX_CHUNK_BYTES` split at blank lines.
+/// Chunks with nothing but whitespace are left out.
+pub fn chunk_markdown(content: &str) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut in_fence = false;
+    let mut start = 0;
+    let mut heading = None;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence {
+            if let Some(text) = heading_text(trimmed) {
+                push_section(&mut chunks, content, start, offset, &heading);
+                start = offset;
+                heading = Some(text);
+            }
+        }
+        offset += line.len();
+    }
+    push_section(&mut chunks, content, start, content.len(), &heading);
+    chunks
+}
+
+/// The text of an ATX heading line (`## Title`), or `None` for any other line.
+fn heading_text(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.starts_with(' ') || rest.trim().is_empty()) {
+        return None;
+    }
+    Some(rest.trim().trim_end_matches('#').trim().to_string())
+}
+
+fn push_section(
+    chunks: &mut Vec<Chunk>,
+    content: &str,
+    start: usize,
+    end: usize,
+    heading: &Option<String>,
+) {
+    let mut piece_start = start;
+    while piece_start < end {
+        let mut piece_end = end;
+        if end - piece_start > MAX_CHUNK_BYTES {
+            let mut limit = piece_start + MAX_CHUNK_BYTES;
+            while !content.is_char_boundary(limit) {
+                limit -= 1;
+            }
+            piece_end = content[piece_start..limit]
+                .rfind("\n\n")
+                .map(|i| piece_start + i + 2)
+                .unwrap_or(limit);
+        }
+        if !content[piece_start..piece_end].trim().is_empty() {
+            chunks.push(Chunk {
+                start: piece_start,
+                end: piece_end,
+                heading: heading.clone(),
+            });
+        }
+        piece_start = piece_end;
+    }
+}
+
+/// Hash of the content the file saved under `metadata_id` was last indexed from with
+/// `model`, if it was.
+pub fn indexed_hash(
+    conn: &mut SqliteConnection,
+    metadata_id: i32,
+    model: &str,
+) -> QueryResult<Option<String>> {
+    embeddings::table
+        .filter(embeddings::metadata_id.eq(metadata_id))
+        .filter(embeddings::model.eq(model))
+        .select(embeddings::content_hash)
+        .first(conn)
+        .optional()
+}
+
+/// Replaces the stored chunks of the file saved under `metadata_id` with `chunks` and
+/// their `vectors`, computed by `model` from the content with `content_hash`.
+pub fn replace_embeddings(
+    conn: &mut SqliteConnection,
+    metadata_id: i32,
+    content_hash: &str,
+    model: &str,
+    chunks: &[Chunk],
+    vectors: &[Vec<f32>],
+) -> QueryResult<()> {
+    use embeddings::dsl as e;
+
+    diesel::delete(e::embeddings.filter(e::metadata_id.eq(metadata_id))).execute(conn)?;
+    let rows: Vec<_> = chunks
+        .iter()
+        .zip(vectors)
+        .map(|(chunk, vector)| {
+            (
+                e::metadata_id.eq(metadata_id),
+                e::chunk_start.eq(chunk.start as i32),
+                e::chunk_end.eq(chunk.end as i32),
+                e::heading.eq(chunk.heading.as_deref()),
+                e::content_hash.eq(content_hash),
+                e::model.eq(model),
+                e::vector.eq(vector_to_blob(vector)),
+            )
+        })
+        .collect();
+    diesel::insert_into(e::embeddings)
+        .values(rows)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The `k` stored chunks closest to `query`, most similar first, embedded with
+/// `embedder`. With `project_id`, only chunks of that project's files are considered.
+///
+/// Similarity is computed here over every stored vector, which is fast enough for the
+/// few thousand chunks of a book.
+pub fn retrieve(
+    conn: &mut SqliteConnection,
+    embedder: &mut dyn Embedder,
+    query: &str,
+    k: usize,
+    project_id: Option<i32>,
+) -> io::Result<Vec<RetrievedChunk>> {
+    let query_vector = embedder
+        .embed(&[query])?
+        .pop()
+        .ok_or_else(|| io::Error::other("the embedding model returned no vector"))?;
+
+    let mut stored = embeddings::table
+        .inner_join(metadata::table)
+        .filter(embeddings::model.eq(embedder.model_name()))
+        .select(embeddings::all_columns)
+        .into_boxed();
+    if let Some(id) = project_id {
+        stored = stored.filter(metadata::project_id.eq(id));
+    }
+    let stored: Vec<ChunkEmbedding> = stored.load(conn).map_err(io::Error::other)?;
+
+    let mut scored: Vec<(f32, ChunkEmbedding)> = stored
+        .into_iter()
+        .map(|row| (cosine(&query_vector, &blob_to_vector(&row.vector)), row))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.truncate(k);
+
+    let mut contents: HashMap<i32, (String, String)> = HashMap::new();
+    let mut chunks = Vec::new();
+    for (score, row) in scored {
+        let (file_path, content) = match contents.entry(row.metadata_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                metadata::table
+                    .inner_join(blobs::table)
+                    .filter(metadata::id.eq(row.metadata_id))
+                    .select((metadata::file_path, blobs::content))
+                    .first::<(String, String)>(conn)
+                    .map_err(io::Error::other)?,
+            ),
+        };
+        let (start, end) = (row.chunk_start as usize, row.chunk_end as usize);
+        // A file saved again since it was indexed may no longer have this range.
+        let Some(text) = content.get(start..end) else {
+            continue;
+        };
+        chunks.push(RetrievedChunk {
+            file_path: file_path.clone(),
+            heading: row.heading,
+            start,
+            end,
+            text: text.to_string(),
+            score,
+        });
+    }
+    Ok(chunks)
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        0.0
+    } else {
+        dot / denominator
+    }
+}
+
+fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn blob_to_vector(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
@@ -0,0 +1,64 @@
+use crate::schema::{content_history, metadata};
+use crate::utils::database::models::ContentHistory;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Stores `content` as a new version of the file saved under `metadata_id`, then drops
+/// its oldest versions beyond `max_versions` (no limit when `None`).
+pub fn record_version(
+    conn: &mut SqliteConnection,
+    metadata_id: i32,
+    content: &str,
+    content_hash: &str,
+    max_versions: Option<usize>,
+) -> QueryResult<()> {
+    use content_history::dsl as h;
+
+    let saved_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    diesel::insert_into(h::content_history)
+        .values((
+            h::metadata_id.eq(metadata_id),
+            h::content.eq(content),
+            h::content_hash.eq(content_hash),
+            h::saved_at.eq(saved_at),
+        ))
+        .execute(conn)?;
+
+    if let Some(max) = max_versions {
+        let kept: Vec<i32> = h::content_history
+            .filter(h::metadata_id.eq(metadata_id))
+            .order(h::id.desc())
+            .limit(max as i64)
+            .select(h::id)
+            .load(conn)?;
+        diesel::delete(
+            h::content_history
+                .filter(h::metadata_id.eq(metadata_id))
+                .filter(h::id.ne_all(kept)),
+        )
+        .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// The stored versions of the file saved under any of `file_paths`, oldest first.
+pub fn file_versions(
+    conn: &mut SqliteConnection,
+    file_paths: &[String],
+) -> QueryResult<Vec<ContentHistory>> {
+    use content_history::dsl as h;
+    use metadata::dsl as m;
+
+    let ids: Vec<i32> = m::metadata
+        .filter(m::file_path.eq_any(file_paths))
+        .select(m::id)
+        .load(conn)?;
+    h::content_history
+        .filter(h::metadata_id.eq_any(ids))
+        .order(h::id.asc())
+        .load(conn)
+}
@@ -1,2 +1,12 @@
+pub mod assets;
+pub mod blobs;
 pub mod db;
+pub mod documents;
+pub mod embeddings;
+pub mod history;
 pub mod models;
+pub mod pool;
+pub mod projects;
+pub mod search;
+pub mod sessions;
+pub mod tags;
@@ -1,4 +1,7 @@
-use crate::schema::{file_content, metadata};
+use crate::schema::{
+    assets, blobs, chat_messages, chat_sessions, content_history, embeddings, file_tags,
+    html_content, html_metadata, metadata, projects, tags,
+};
 use diesel::prelude::*;
 use diesel::Queryable;
 
@@ -8,13 +11,159 @@ use diesel::Queryable;
 pub struct Metadata {
     pub id: i32,
     pub file_path: String,
+    /// SHA-256 of the content saved last, in hex: the key of its row in `blobs`.
+    pub content_hash: Option<String>,
+    /// Front matter `language`, else the most common code block language.
+    pub language: Option<String>,
+    pub size_bytes: Option<i64>,
+    /// Modification time in seconds since the Unix epoch.
+    pub modified_at: Option<i64>,
+    /// Front matter `title`, else the first `# ` heading.
+    pub title: Option<String>,
+    /// `None` only for rows saved before projects existed, see `utils::database::projects`.
+    pub project_id: Option<i32>,
 }
 
-/// Represents a row in the `file_content` table
+impl Metadata {
+    pub fn details(&self) -> FileDetails {
+        FileDetails {
+            language: self.language.clone(),
+            size_bytes: self.size_bytes,
+            modified_at: self.modified_at,
+            title: self.title.clone(),
+        }
+    }
+}
+
+/// The `metadata` columns describing a file, refreshed on every save
+#[derive(Debug, PartialEq, Insertable, AsChangeset)]
+#[diesel(table_name = metadata, treat_none_as_null = true)]
+pub struct FileDetails {
+    pub language: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub modified_at: Option<i64>,
+    pub title: Option<String>,
+}
+
+/// Content saved once for every file that has it, in the `blobs` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = blobs)]
+pub struct Blob {
+    /// Same as `metadata.content_hash` of those files.
+    pub hash: String,
+    pub content: String,
+}
+
+/// An image referenced by saved Markdown, in the `assets` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = assets)]
+pub struct Asset {
+    pub id: i32,
+    pub project_id: i32,
+    /// Stored like `Metadata::file_path`.
+    pub file_path: String,
+    pub mime_type: String,
+    pub content_hash: String,
+    pub content: Vec<u8>,
+}
+
+/// A conversation with the chat server, in the `chat_sessions` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = chat_sessions)]
+pub struct ChatSession {
+    pub id: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+    /// When a message was last added, in seconds since the Unix epoch.
+    pub updated_at: i64,
+}
+
+/// A prompt or an answer of a chat session, in the `chat_messages` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = chat_messages)]
+pub struct ChatMessage {
+    pub id: i32,
+    pub session_id: String,
+    /// `user` or `assistant`.
+    pub role: String,
+    pub content: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// A saved version of a Markdown file, in the `content_history` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = content_history)]
+pub struct ContentHistory {
+    /// Revision number shown by `lila history` and taken by `lila diff --rev`.
+    pub id: i32,
+    pub metadata_id: i32,
+    pub content: String,
+    pub content_hash: String,
+    /// Seconds since the Unix epoch.
+    pub saved_at: i64,
+}
+
+/// A chunk of a saved file and its vector, in the `embeddings` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = embeddings)]
+pub struct ChunkEmbedding {
+    pub id: i32,
+    pub metadata_id: i32,
+    /// Byte range of the chunk in the saved content.
+    pub chunk_start: i32,
+    pub chunk_end: i32,
+    /// The heading the chunk is under, if any.
+    pub heading: Option<String>,
+    /// Hash of the content the chunk was cut from.
+    pub content_hash: String,
+    /// Embedding model that computed `vector`; vectors of different models never compare.
+    pub model: String,
+    /// Little-endian f32s.
+    pub vector: Vec<u8>,
+}
+
+/// Represents a row in the `projects` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = projects)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+}
+
+/// Represents a row in the `tags` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = tags)]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+}
+
+/// Links a saved file to a tag, in the `file_tags` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = file_tags)]
+pub struct FileTag {
+    pub metadata_id: i32,
+    pub tag_id: i32,
+    /// `front_matter` or `manual`, see `utils::database::tags`.
+    pub source: String,
+}
+
+/// Represents a row in the `html_metadata` table
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = html_metadata)]
+pub struct HtmlMetadata {
+    pub id: i32,
+    pub file_path: String,
+    /// SHA-256 of the content saved last, in hex.
+    pub content_hash: Option<String>,
+}
+
+/// Represents a row in the `html_content` table
 #[derive(Queryable, Insertable)]
-#[diesel(table_name = file_content)]
-pub struct FileContent {
-    // Same primary key as `metadata.id`
+#[diesel(table_name = html_content)]
+pub struct HtmlContent {
+    // Same primary key as `html_metadata.id`
     pub id: i32,
     pub content: String,
 }
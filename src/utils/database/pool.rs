@@ -0,0 +1,129 @@
+use crate::utils::config::Config;
+use crate::utils::database::db::{run_migrations, ConnectionCustomizer, DatabaseKey};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sqlite::SqliteConnection;
+use dotenvy::dotenv;
+
+/// Connections a pool holds at most unless `[database] pool_size` says otherwise.
+/// SQLite has one writer at a time, so more connections mostly means more waiting.
+pub const DEFAULT_POOL_SIZE: u32 = 4;
+
+pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+
+/// The `pool_size` in the `[database]` section of `config` (a `Lila.toml`), else
+/// `DEFAULT_POOL_SIZE`.
+pub fn pool_size(config: &Config) -> Result<u32, String> {
+    match config.database.pool_size {
+        None => Ok(DEFAULT_POOL_SIZE),
+        Some(size) if (1..=i64::from(u32::MAX)).contains(&size) => Ok(size as u32),
+        Some(_) => Err(format!(
+            "invalid {}: database.pool_size must be a positive integer",
+            config.path().display()
+        )),
+    }
+}
+
+/// A pool of at most `size` connections to the SQLite database at `database_url`, each
+/// set up by `ConnectionCustomizer` with `key`. The migrations are run once here, so
+/// connections taken from the pool can be used right away.
+pub fn build_pool(
+    database_url: &str,
+    size: u32,
+    key: Option<DatabaseKey>,
+) -> Result<DbPool, String> {
+    let customizer = ConnectionCustomizer { key };
+    // r2d2 retries a failing connection until its timeout, so a bad path or key is
+    // checked on a connection of our own first, which also runs the migrations.
+    let mut conn = SqliteConnection::establish(database_url).map_err(|e| e.to_string())?;
+    customizer.apply(&mut conn).map_err(|e| e.to_string())?;
+    run_migrations(&mut conn).map_err(|e| e.to_string())?;
+    drop(conn);
+
+    Pool::builder()
+        .max_size(size)
+        .min_idle(Some(0))
+        .connection_customizer(Box::new(customizer))
+        .build(ConnectionManager::<SqliteConnection>::new(database_url))
+        .map_err(|e| e.to_string())
+}
+
+/// `build_pool` with the pool size and database key configured in `Lila.toml`.
+pub fn open_pool(database_url: &str) -> Result<DbPool, String> {
+    dotenv().ok();
+    let config = Config::current();
+    build_pool(database_url, pool_size(config)?, DatabaseKey::load(config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::save::{save_files_to_db, SaveOptions};
+    use crate::schema::metadata;
+    use crate::utils::database::projects::project_id;
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn reads_during_a_save_are_never_locked_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let doc = dir.path().join("doc");
+        fs::create_dir(&doc).unwrap();
+        let files: Vec<String> = (0..300)
+            .map(|i| {
+                let path = doc.join(format!("chapter-{:03}.md", i));
+                fs::write(&path, format!("# Chapter {}\n", i)).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect();
+        let url = dir.path().join("lila.db").to_string_lossy().into_owned();
+        let pool = build_pool(&url, DEFAULT_POOL_SIZE, None).unwrap();
+        let project = project_id(&mut pool.get().unwrap(), "book").unwrap();
+        let options = SaveOptions {
+            max_versions: None,
+            max_asset_bytes: 0,
+            quiet: true,
+        };
+
+        let saving = AtomicBool::new(true);
+        let reads = thread::scope(|scope| {
+            let readers: Vec<_> = (1..DEFAULT_POOL_SIZE)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut reads = 0;
+                        while saving.load(Ordering::Relaxed) {
+                            let mut conn = pool.get().unwrap();
+                            metadata::table
+                                .select(metadata::file_path)
+                                .load::<String>(&mut conn)
+                                .unwrap_or_else(|e| panic!("read failed during a save: {}", e));
+                            reads += 1;
+                        }
+                        reads
+                    })
+                })
+                .collect();
+
+            let mut conn = pool.get().unwrap();
+            for round in 0..5 {
+                for (i, file) in files.iter().enumerate() {
+                    fs::write(file, format!("# Chapter {}, draft {}\n", i, round)).unwrap();
+                }
+                save_files_to_db(&files, &doc, project, &mut conn, &options).unwrap();
+            }
+            saving.store(false, Ordering::Relaxed);
+            readers
+                .into_iter()
+                .map(|reader| reader.join().unwrap())
+                .sum::<usize>()
+        });
+
+        assert!(reads > 0);
+        let saved: i64 = metadata::table
+            .count()
+            .get_result(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(saved, 300);
+    }
+}
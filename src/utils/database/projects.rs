@@ -0,0 +1,158 @@
+use crate::schema::{metadata, projects};
+use crate::utils::utils::current_project_name;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Which projects' saved files a command works on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProjectScope {
+    Project(String),
+    All,
+}
+
+impl ProjectScope {
+    /// The named project, or the current one.
+    pub fn project_or_current(name: Option<String>) -> ProjectScope {
+        ProjectScope::Project(name.unwrap_or_else(current_project_name))
+    }
+
+    /// The project id to filter `metadata.project_id` by: `Some(None)` for all projects,
+    /// `Some(Some(id))` for a named one, and `None` when the database has no project of that
+    /// name, so there is nothing of it to read. Nothing is written; `project_id` creates a
+    /// project when a file is saved in it.
+    pub fn resolve(&self, conn: &mut SqliteConnection) -> QueryResult<Option<Option<i32>>> {
+        match self {
+            ProjectScope::Project(name) => Ok(projects::table
+                .filter(projects::name.eq(name))
+                .select(projects::id)
+                .first(conn)
+                .optional()?
+                .map(Some)),
+            ProjectScope::All => Ok(Some(None)),
+        }
+    }
+}
+
+impl fmt::Display for ProjectScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectScope::Project(name) => write!(f, "project \"{}\"", name),
+            ProjectScope::All => write!(f, "all projects"),
+        }
+    }
+}
+
+/// Id of the project called `name`, created if it does not exist yet.
+pub fn project_id(conn: &mut SqliteConnection, name: &str) -> QueryResult<i32> {
+    diesel::insert_or_ignore_into(projects::table)
+        .values(projects::name.eq(name))
+        .execute(conn)?;
+    projects::table
+        .filter(projects::name.eq(name))
+        .select(projects::id)
+        .first(conn)
+}
+
+/// Names of all projects by id.
+pub fn project_names(conn: &mut SqliteConnection) -> QueryResult<HashMap<i32, String>> {
+    Ok(projects::table
+        .select((projects::id, projects::name))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect())
+}
+
+/// Assigns the files saved before projects existed to the project called `name`.
+/// Returns how many there were. Run once, by the migration that adds projects.
+pub fn adopt_unassigned(conn: &mut SqliteConnection, name: &str) -> QueryResult<usize> {
+    let unassigned = metadata::table.filter(metadata::project_id.is_null());
+    let count: i64 = unassigned.count().get_result(conn)?;
+    if count == 0 {
+        return Ok(0);
+    }
+    let id = project_id(conn, name)?;
+    diesel::update(unassigned)
+        .set(metadata::project_id.eq(id))
+        .execute(conn)
+}
+
+/// The project files saved before projects existed belong to: the project folder of a
+/// per-project database (`~/.lila/<project>/lila.db`), else the current project.
+pub fn legacy_project_name(db_path: &Path) -> String {
+    let folder = db_path.parent().filter(|p| {
+        p.parent().and_then(|lila| lila.file_name()) == Some(std::ffi::OsStr::new(".lila"))
+    });
+    match folder.and_then(|p| p.file_name()) {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => current_project_name(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database::db::{run_migrations, MIGRATIONS};
+    use diesel::migration::MigrationSource;
+    use diesel::sql_query;
+    use diesel::sqlite::Sqlite;
+    use diesel_migrations::MigrationHarness;
+    use std::fs;
+
+    fn migrated() -> SqliteConnection {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn resolving_an_unknown_project_writes_nothing() {
+        let mut conn = migrated();
+        let scope = ProjectScope::Project("nowhere".to_string());
+        assert_eq!(scope.resolve(&mut conn).unwrap(), None);
+        assert!(project_names(&mut conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resolve_finds_the_project_a_save_created() {
+        let mut conn = migrated();
+        let id = project_id(&mut conn, "book").unwrap();
+        assert_eq!(project_id(&mut conn, "book").unwrap(), id);
+        let scope = ProjectScope::Project("book".to_string());
+        assert_eq!(scope.resolve(&mut conn).unwrap(), Some(Some(id)));
+        assert_eq!(ProjectScope::All.resolve(&mut conn).unwrap(), Some(None));
+    }
+
+    #[test]
+    fn migrating_assigns_older_files_to_the_project_of_the_database_folder() {
+        let home = tempfile::tempdir().unwrap();
+        let folder = home.path().join(".lila").join("cookbook");
+        fs::create_dir_all(&folder).unwrap();
+        let db_path = folder.join("lila.db");
+        let mut conn = SqliteConnection::establish(&db_path.to_string_lossy()).unwrap();
+        // A database from before projects existed, with a file saved in it.
+        conn.applied_migrations().unwrap(); // creates the table recording them
+        let migrations = MigrationSource::<Sqlite>::migrations(&MIGRATIONS).unwrap();
+        for migration in migrations
+            .iter()
+            .filter(|m| m.name().version().to_string().as_str() < "20261016150000")
+        {
+            conn.run_migration(migration.as_ref()).unwrap();
+        }
+        sql_query("INSERT INTO metadata (file_path) VALUES ('intro.md')")
+            .execute(&mut conn)
+            .unwrap();
+
+        run_migrations(&mut conn).unwrap();
+        let ids: Vec<Option<i32>> = metadata::table
+            .select(metadata::project_id)
+            .load(&mut conn)
+            .unwrap();
+        let names = project_names(&mut conn).unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(names.get(&ids[0].unwrap()).unwrap(), "cookbook");
+        assert_eq!(names.len(), 1);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::utils::database::tags::files_with_tags;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Double, Integer, Nullable, Text};
+use diesel::sqlite::SqliteConnection;
+use serde::Serialize;
+use std::fmt;
+
+/// Lines of a matching file shown per search hit.
+const LINES_PER_HIT: usize = 3;
+
+/// Why a full-text search could not run.
+#[derive(Debug)]
+pub enum SearchError {
+    /// The SQLite library lila is linked against was built without FTS5.
+    FtsUnavailable,
+    /// The query has no words to search for.
+    EmptyQuery,
+    /// FTS5 could not parse the query.
+    InvalidQuery(String),
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::FtsUnavailable => write!(
+                f,
+                "full-text search is unavailable: this SQLite build has no FTS5 support"
+            ),
+            SearchError::EmptyQuery => write!(f, "the search query is empty"),
+            SearchError::InvalidQuery(e) => write!(f, "invalid search query: {}", e),
+            SearchError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<diesel::result::Error> for SearchError {
+    fn from(e: diesel::result::Error) -> Self {
+        if e.to_string().contains("no such module: fts5") {
+            SearchError::FtsUnavailable
+        } else {
+            SearchError::Database(e)
+        }
+    }
+}
+
+/// A file matching a search, with the lines that contain the query's words.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub file_path: String,
+    /// Project the file was saved to.
+    pub project: Option<String>,
+    /// bm25 score; lower is more relevant.
+    pub rank: f64,
+    pub lines: Vec<MatchedLine>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchedLine {
+    /// 1-based line number in the saved content.
+    pub line: usize,
+    pub text: String,
+}
+
+/// Put around the matched words in `SnippetHit::snippet`.
+pub const SNIPPET_START: char = '\u{2}';
+pub const SNIPPET_END: char = '\u{3}';
+
+/// A file matching a query, with an excerpt around the match.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct SnippetHit {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub path: String,
+    /// bm25 score; lower is more relevant.
+    #[diesel(sql_type = Double)]
+    pub score: f64,
+    /// A few words around the match, the matched ones between `SNIPPET_START` and
+    /// `SNIPPET_END`.
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+#[derive(QueryableByName)]
+struct SearchRow {
+    #[diesel(sql_type = Text)]
+    file_path: String,
+    #[diesel(sql_type = Text)]
+    content: String,
+    #[diesel(sql_type = Double)]
+    rank: f64,
+    #[diesel(sql_type = Nullable<Text>)]
+    project: Option<String>,
+}
+
+/// Creates the `file_content_fts` index if it is missing and fills it from the saved files.
+///
+/// The index is created here rather than in a migration so that a SQLite without FTS5
+/// only disables search instead of every command. Creation runs under the write lock and
+/// checks again, so connections racing to create the index fill it only once.
+pub fn ensure_search_index(conn: &mut SqliteConnection) -> Result<(), SearchError> {
+    if search_index_exists(conn)? {
+        return Ok(());
+    }
+    conn.immediate_transaction::<(), SearchError, _>(|conn| {
+        if search_index_exists(conn)? {
+            return Ok(());
+        }
+        sql_query("CREATE VIRTUAL TABLE file_content_fts USING fts5(file_path, content)")
+            .execute(conn)?;
+        sql_query(
+            "INSERT INTO file_content_fts (rowid, file_path, content) \
+             SELECT m.id, m.file_path, c.content FROM metadata m JOIN blobs c ON c.hash = m.content_hash",
+        )
+        .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Whether the `file_content_fts` index has been created.
+fn search_index_exists(conn: &mut SqliteConnection) -> QueryResult<bool> {
+    let exists: Count = sql_query(
+        "SELECT COUNT(*) AS count FROM sqlite_master WHERE type = 'table' AND name = 'file_content_fts'",
+    )
+    .get_result(conn)?;
+    Ok(exists.count > 0)
+}
+
+/// Replaces the indexed text of the file saved under `id`.
+pub fn index_file(
+    conn: &mut SqliteConnection,
+    id: i32,
+    file_path: &str,
+    content: &str,
+) -> QueryResult<()> {
+    sql_query("DELETE FROM file_content_fts WHERE rowid = ?")
+        .bind::<Integer, _>(id)
+        .execute(conn)?;
+    sql_query("INSERT INTO file_content_fts (rowid, file_path, content) VALUES (?, ?, ?)")
+        .bind::<Integer, _>(id)
+        .bind::<Text, _>(file_path)
+        .bind::<Text, _>(content)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Which saved files `search_files` looks at.
+#[derive(Debug, Default)]
+pub struct SearchFilter<'a> {
+    /// Only files whose saved path starts with this prefix.
+    pub path_prefix: Option<&'a str>,
+    /// Only files carrying every one of these tags.
+    pub tags: &'a [String],
+    /// Only files of this project; `None` for all projects.
+    pub project_id: Option<i32>,
+}
+
+/// Changes the path indexed for the file saved under `id`, if the index exists.
+pub fn rename_indexed_file(
+    conn: &mut SqliteConnection,
+    id: i32,
+    file_path: &str,
+) -> QueryResult<()> {
+    if !search_index_exists(conn)? {
+        return Ok(());
+    }
+    sql_query("UPDATE file_content_fts SET file_path = ? WHERE rowid = ?")
+        .bind::<Text, _>(file_path)
+        .bind::<Integer, _>(id)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Searches the saved Markdown for files containing every word of `query`, best match first.
+///
+/// Only files passing `filter` are considered. This is the one search used by
+/// `lila search` and meant for the server and chat context retrieval as well.
+pub fn search_files(
+    conn: &mut SqliteConnection,
+    query: &str,
+    limit: usize,
+    filter: &SearchFilter,
+) -> Result<Vec<SearchHit>, SearchError> {
+    let words = query_words(query);
+    if words.is_empty() {
+        return Err(SearchError::EmptyQuery);
+    }
+    ensure_search_index(conn)?;
+
+    // Quote every word so punctuation in the query is never read as FTS5 syntax.
+    let fts_query = words
+        .iter()
+        .map(|w| format!("\"{}\"", w))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let prefix_pattern = format!(
+        "{}%",
+        filter
+            .path_prefix
+            .unwrap_or("")
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    // The ids of the tagged files, as a JSON array for `json_each`.
+    let tagged_ids = if filter.tags.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(&files_with_tags(conn, filter.tags)?).unwrap_or_default())
+    };
+
+    let rows: Vec<SearchRow> = sql_query(
+        "SELECT file_path, content, bm25(file_content_fts) AS rank, \
+         (SELECT p.name FROM metadata m JOIN projects p ON p.id = m.project_id \
+          WHERE m.id = file_content_fts.rowid) AS project \
+         FROM file_content_fts \
+         WHERE file_content_fts MATCH ? AND file_path LIKE ? ESCAPE '\\' \
+         AND (? IS NULL OR rowid IN (SELECT value FROM json_each(?))) \
+         AND (? IS NULL OR rowid IN (SELECT id FROM metadata WHERE project_id = ?)) \
+         ORDER BY rank LIMIT ?",
+    )
+    .bind::<Text, _>(&fts_query)
+    .bind::<Text, _>(&prefix_pattern)
+    .bind::<Nullable<Text>, _>(&tagged_ids)
+    .bind::<Nullable<Text>, _>(&tagged_ids)
+    .bind::<Nullable<Integer>, _>(filter.project_id)
+    .bind::<Nullable<Integer>, _>(filter.project_id)
+    .bind::<BigInt, _>(limit as i64)
+    .load(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| SearchHit {
+            lines: matching_lines(&row.content, &words),
+            file_path: row.file_path,
+            project: row.project,
+            rank: row.rank,
+        })
+        .collect())
+}
+
+/// The `limit` saved files best matching `query`, written in FTS5 query syntax (`"a
+/// phrase"`, `prefix*`, `a OR b`, `NEAR(a b)`), with an excerpt of each made by FTS5
+/// from the index alone, so no content is loaded. With `project_id`, only the files of
+/// that project are searched.
+pub fn search_snippets(
+    conn: &mut SqliteConnection,
+    query: &str,
+    limit: usize,
+    project_id: Option<i32>,
+) -> Result<Vec<SnippetHit>, SearchError> {
+    if query.trim().is_empty() {
+        return Err(SearchError::EmptyQuery);
+    }
+    ensure_search_index(conn)?;
+
+    sql_query(
+        "SELECT rowid AS id, file_path AS path, bm25(file_content_fts) AS score, \
+         snippet(file_content_fts, 1, char(2), char(3), '…', 16) AS snippet \
+         FROM file_content_fts \
+         WHERE file_content_fts MATCH ? \
+         AND (? IS NULL OR rowid IN (SELECT id FROM metadata WHERE project_id = ?)) \
+         ORDER BY rank LIMIT ?",
+    )
+    .bind::<Text, _>(query)
+    .bind::<Nullable<Integer>, _>(project_id)
+    .bind::<Nullable<Integer>, _>(project_id)
+    .bind::<BigInt, _>(limit as i64)
+    .load(conn)
+    .map_err(|e| {
+        let message = e.to_string();
+        // How SQLite reports a query FTS5 cannot parse; "no such column" is a word
+        // before a `:` read as a column filter.
+        if message.starts_with("fts5:")
+            || message.starts_with("no such column")
+            || message.starts_with("unknown special query")
+            || message.starts_with("unterminated string")
+            || message.contains("syntax error")
+        {
+            SearchError::InvalidQuery(message)
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// Lower-cased words of `query`, without quotes.
+pub fn query_words(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .map(|w| w.replace('"', "").to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// The first lines of `content` containing any of `words`.
+fn matching_lines(content: &str, words: &[String]) -> Vec<MatchedLine> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, text)| {
+            let lower = text.to_lowercase();
+            words.iter().any(|w| lower.contains(w.as_str()))
+        })
+        .take(LINES_PER_HIT)
+        .map(|(index, text)| MatchedLine {
+            line: index + 1,
+            text: text.trim().to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,117 @@
+use crate::schema::{chat_messages, chat_sessions};
+use crate::utils::database::models::{ChatMessage, ChatSession};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Starts a chat session and returns its id.
+pub fn create_session(conn: &mut SqliteConnection) -> QueryResult<String> {
+    let now = now();
+    let session = ChatSession {
+        id: new_session_id(),
+        created_at: now,
+        updated_at: now,
+    };
+    diesel::insert_into(chat_sessions::table)
+        .values(&session)
+        .execute(conn)?;
+    Ok(session.id)
+}
+
+pub fn session_exists(conn: &mut SqliteConnection, id: &str) -> QueryResult<bool> {
+    Ok(chat_sessions::table
+        .find(id)
+        .select(chat_sessions::id)
+        .first::<String>(conn)
+        .optional()?
+        .is_some())
+}
+
+/// The prompts of session `id` and their answers, oldest first.
+pub fn session_turns(conn: &mut SqliteConnection, id: &str) -> QueryResult<Vec<(String, String)>> {
+    let messages: Vec<ChatMessage> = chat_messages::table
+        .filter(chat_messages::session_id.eq(id))
+        .order(chat_messages::id.asc())
+        .load(conn)?;
+    let mut turns = Vec::new();
+    let mut prompt = None;
+    for message in messages {
+        match message.role.as_str() {
+            "user" => prompt = Some(message.content),
+            "assistant" => {
+                if let Some(prompt) = prompt.take() {
+                    turns.push((prompt, message.content));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(turns)
+}
+
+/// Adds `prompt` and the `answer` it got to session `id`.
+pub fn append_turn(
+    conn: &mut SqliteConnection,
+    id: &str,
+    prompt: &str,
+    answer: &str,
+) -> QueryResult<()> {
+    use chat_messages::dsl as m;
+
+    let now = now();
+    conn.transaction(|conn| {
+        diesel::insert_into(m::chat_messages)
+            .values(&vec![
+                (
+                    m::session_id.eq(id),
+                    m::role.eq("user"),
+                    m::content.eq(prompt),
+                    m::created_at.eq(now),
+                ),
+                (
+                    m::session_id.eq(id),
+                    m::role.eq("assistant"),
+                    m::content.eq(answer),
+                    m::created_at.eq(now),
+                ),
+            ])
+            .execute(conn)?;
+        diesel::update(chat_sessions::table.find(id))
+            .set(chat_sessions::updated_at.eq(now))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Deletes session `id` and its messages; `false` if there was no such session.
+pub fn delete_session(conn: &mut SqliteConnection, id: &str) -> QueryResult<bool> {
+    let deleted = diesel::delete(chat_sessions::table.find(id)).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// An id no other session of this database has.
+fn new_session_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    let digest = Sha256::digest(
+        format!(
+            "{}-{}-{}",
+            std::process::id(),
+            nanos,
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        )
+        .as_bytes(),
+    );
+    digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
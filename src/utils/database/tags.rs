@@ -0,0 +1,135 @@
+use crate::schema::{blobs, file_tags, metadata, tags};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::collections::{BTreeMap, HashSet};
+
+/// Source of tags read from a file's front matter; `save` keeps these in step with the file.
+pub const SOURCE_FRONT_MATTER: &str = "front_matter";
+/// Source of tags added with `lila tag add`; `save` leaves these alone.
+pub const SOURCE_MANUAL: &str = "manual";
+
+/// Tags are compared without surrounding whitespace or case.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Id of the tag called `name`, created if it does not exist yet.
+fn tag_id(conn: &mut SqliteConnection, name: &str) -> QueryResult<i32> {
+    diesel::insert_or_ignore_into(tags::table)
+        .values(tags::name.eq(name))
+        .execute(conn)?;
+    tags::table
+        .filter(tags::name.eq(name))
+        .select(tags::id)
+        .first(conn)
+}
+
+/// Links the file saved under `metadata_id` to `tag`. Returns false if it already was.
+pub fn add_tag(
+    conn: &mut SqliteConnection,
+    metadata_id: i32,
+    tag: &str,
+    source: &str,
+) -> QueryResult<bool> {
+    let tag_id = tag_id(conn, &normalize_tag(tag))?;
+    let inserted = diesel::insert_or_ignore_into(file_tags::table)
+        .values((
+            file_tags::metadata_id.eq(metadata_id),
+            file_tags::tag_id.eq(tag_id),
+            file_tags::source.eq(source),
+        ))
+        .execute(conn)?;
+    Ok(inserted > 0)
+}
+
+/// Unlinks the file saved under `metadata_id` from `tag`. Returns false if it was not linked.
+pub fn remove_tag(conn: &mut SqliteConnection, metadata_id: i32, tag: &str) -> QueryResult<bool> {
+    let tag_ids = tags::table
+        .filter(tags::name.eq(normalize_tag(tag)))
+        .select(tags::id);
+    let removed = diesel::delete(
+        file_tags::table
+            .filter(file_tags::metadata_id.eq(metadata_id))
+            .filter(file_tags::tag_id.eq_any(tag_ids)),
+    )
+    .execute(conn)?;
+    Ok(removed > 0)
+}
+
+/// Makes the front matter tags of the file saved under `metadata_id` exactly `names`;
+/// tags added by hand are kept.
+pub fn sync_front_matter_tags(
+    conn: &mut SqliteConnection,
+    metadata_id: i32,
+    names: &[String],
+) -> QueryResult<()> {
+    let wanted: HashSet<String> = names.iter().map(|n| normalize_tag(n)).collect();
+    let current: Vec<(i32, String)> = file_tags::table
+        .inner_join(tags::table)
+        .filter(file_tags::metadata_id.eq(metadata_id))
+        .filter(file_tags::source.eq(SOURCE_FRONT_MATTER))
+        .select((tags::id, tags::name))
+        .load(conn)?;
+
+    for (tag_id, name) in &current {
+        if !wanted.contains(name) {
+            diesel::delete(
+                file_tags::table
+                    .filter(file_tags::metadata_id.eq(metadata_id))
+                    .filter(file_tags::tag_id.eq(tag_id)),
+            )
+            .execute(conn)?;
+        }
+    }
+    for name in wanted {
+        if !name.is_empty() && !current.iter().any(|(_, n)| *n == name) {
+            add_tag(conn, metadata_id, &name, SOURCE_FRONT_MATTER)?;
+        }
+    }
+    Ok(())
+}
+
+/// The tags of every tagged file, sorted, by `metadata_id`.
+pub fn tags_by_file(conn: &mut SqliteConnection) -> QueryResult<BTreeMap<i32, Vec<String>>> {
+    let rows: Vec<(i32, String)> = file_tags::table
+        .inner_join(tags::table)
+        .select((file_tags::metadata_id, tags::name))
+        .order((file_tags::metadata_id, tags::name))
+        .load(conn)?;
+    let mut by_file: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+    for (id, name) in rows {
+        by_file.entry(id).or_default().push(name);
+    }
+    Ok(by_file)
+}
+
+/// Ids of the saved files carrying every one of `names`.
+pub fn files_with_tags(conn: &mut SqliteConnection, names: &[String]) -> QueryResult<Vec<i32>> {
+    let wanted: HashSet<String> = names.iter().map(|n| normalize_tag(n)).collect();
+    Ok(tags_by_file(conn)?
+        .into_iter()
+        .filter(|(_, tags)| wanted.iter().all(|w| tags.contains(w)))
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// Path and saved content of the files carrying every one of `names`, sorted by path.
+/// With `project_id`, only the files of that project.
+pub fn tagged_content(
+    conn: &mut SqliteConnection,
+    names: &[String],
+    project_id: Option<i32>,
+) -> QueryResult<Vec<(String, String)>> {
+    let ids = files_with_tags(conn, names)?;
+    let mut query = metadata::table
+        .inner_join(blobs::table)
+        .filter(metadata::id.eq_any(ids))
+        .into_boxed();
+    if let Some(id) = project_id {
+        query = query.filter(metadata::project_id.eq(id));
+    }
+    query
+        .select((metadata::file_path, blobs::content))
+        .order(metadata::file_path)
+        .load(conn)
+}
@@ -0,0 +1,487 @@
+use std::fs;
+use std::path::Path;
+use toml::{Table, Value};
+
+/// The `[dependencies]` of the `Cargo.toml` in `dir`, as written there; `None` without one.
+pub fn rust_dependencies(dir: &Path) -> Result<Option<Table>, String> {
+    let path = dir.join("Cargo.toml");
+    let Some(content) = read_manifest(&path)? else {
+        return Ok(None);
+    };
+    let manifest = parse_toml(&path, &content)?;
+    let dependencies = match manifest.get("dependencies") {
+        Some(Value::Table(dependencies)) => dependencies.clone(),
+        Some(_) => {
+            return Err(format!(
+                "invalid {}: dependencies must be a table",
+                path.display()
+            ))
+        }
+        None => Table::new(),
+    };
+    Ok(Some(dependencies))
+}
+
+/// The dependencies of the Python project in `dir`, by normalized name, with their version
+/// specifiers as strings (`"*"` for any version). They are read from the `[project]`
+/// `dependencies` and `[tool.poetry.dependencies]` of `pyproject.toml`, else from
+/// `requirements.txt`; `None` without either file.
+pub fn python_dependencies(dir: &Path) -> Result<Option<Table>, String> {
+    let pyproject = dir.join("pyproject.toml");
+    let mut found = None;
+    if let Some(content) = read_manifest(&pyproject)? {
+        let manifest = parse_toml(&pyproject, &content)?;
+        let mut dependencies = Table::new();
+        let pep621 = manifest
+            .get("project")
+            .and_then(|project| project.get("dependencies"));
+        match pep621 {
+            Some(Value::Array(requirements)) => {
+                for requirement in requirements {
+                    let parsed = requirement.as_str().and_then(parse_requirement);
+                    let Some((name, specifier)) = parsed else {
+                        return Err(format!(
+                            "invalid {}: project.dependencies: {} is not a requirement like \"requests>=2.31\"",
+                            pyproject.display(),
+                            requirement
+                        ));
+                    };
+                    dependencies.insert(name, Value::String(specifier));
+                }
+            }
+            Some(_) => {
+                return Err(format!(
+                    "invalid {}: project.dependencies must be an array of strings",
+                    pyproject.display()
+                ))
+            }
+            None => {}
+        }
+        let poetry = manifest
+            .get("tool")
+            .and_then(|tool| tool.get("poetry"))
+            .and_then(|poetry| poetry.get("dependencies"));
+        match poetry {
+            Some(Value::Table(table)) => {
+                for (name, value) in table {
+                    // The Python versions the project supports, not a package.
+                    if name.eq_ignore_ascii_case("python") {
+                        continue;
+                    }
+                    let specifier = poetry_specifier(value).ok_or_else(|| {
+                        format!(
+                            "invalid {}: tool.poetry.dependencies.{} must be a version or a table with one",
+                            pyproject.display(),
+                            name
+                        )
+                    })?;
+                    dependencies.insert(normalize_python_name(name), Value::String(specifier));
+                }
+            }
+            Some(_) => {
+                return Err(format!(
+                    "invalid {}: tool.poetry.dependencies must be a table",
+                    pyproject.display()
+                ))
+            }
+            None => {}
+        }
+        if !dependencies.is_empty() {
+            return Ok(Some(dependencies));
+        }
+        found = Some(dependencies);
+    }
+
+    let requirements = dir.join("requirements.txt");
+    let Some(content) = read_manifest(&requirements)? else {
+        return Ok(found);
+    };
+    let mut dependencies = Table::new();
+    for (number, line) in joined_lines(&content) {
+        // Comments, options such as `-r other.txt` or `--index-url`, and archives or folders
+        // given by their location name no package. Options of a requirement, like the
+        // `--hash` pip-compile adds, follow it.
+        let line = line.split(" #").next().unwrap_or(&line);
+        let line = line.split(" --").next().unwrap_or(line).trim();
+        if line.is_empty()
+            || line.starts_with(['#', '-', '.', '/'])
+            || (line.contains("://") && !line.contains('@'))
+        {
+            continue;
+        }
+        let Some((name, specifier)) = parse_requirement(line) else {
+            return Err(format!(
+                "invalid {} (line {}): {} is not a requirement like \"requests>=2.31\"",
+                requirements.display(),
+                number,
+                line
+            ));
+        };
+        dependencies.insert(name, Value::String(specifier));
+    }
+    Ok(Some(dependencies))
+}
+
+/// The lines of a requirements file, those ending in `\` joined to the next as pip joins
+/// them, each with the number of its first line.
+fn joined_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut continued: Option<(usize, String)> = None;
+    for (index, line) in content.lines().enumerate() {
+        let (number, mut joined) = continued.take().unwrap_or((index + 1, String::new()));
+        match line.trim_end().strip_suffix('\\') {
+            Some(part) => {
+                joined.push_str(part);
+                continued = Some((number, joined));
+            }
+            None => {
+                joined.push_str(line);
+                lines.push((number, joined));
+            }
+        }
+    }
+    lines.extend(continued);
+    lines
+}
+
+/// The `dependencies` of the `package.json` in `dir`, with their version ranges; `None`
+/// without one.
+pub fn node_dependencies(dir: &Path) -> Result<Option<Table>, String> {
+    let path = dir.join("package.json");
+    let Some(content) = read_manifest(&path)? else {
+        return Ok(None);
+    };
+    let manifest: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| format!("could not parse {}: {}", path.display(), e))?;
+    let mut dependencies = Table::new();
+    match manifest.get("dependencies") {
+        Some(serde_json::Value::Object(object)) => {
+            for (name, range) in object {
+                let Some(range) = range.as_str() else {
+                    return Err(format!(
+                        "invalid {}: dependencies.{} must be a string",
+                        path.display(),
+                        name
+                    ));
+                };
+                let range = range.trim();
+                let range = if range.is_empty() { "*" } else { range };
+                dependencies.insert(name.clone(), Value::String(range.to_string()));
+            }
+        }
+        Some(_) => {
+            return Err(format!(
+                "invalid {}: dependencies must be an object",
+                path.display()
+            ))
+        }
+        None => {}
+    }
+    Ok(Some(dependencies))
+}
+
+/// The content of the manifest at `path`, `None` if there is none.
+fn read_manifest(path: &Path) -> Result<Option<String>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("could not read {}: {}", path.display(), e)),
+    }
+}
+
+/// The TOML in `content`, read from `path`, or the line it is invalid at and why.
+fn parse_toml(path: &Path, content: &str) -> Result<Table, String> {
+    toml::from_str(content).map_err(|e: toml::de::Error| match e.span() {
+        Some(span) => format!(
+            "could not parse {} (line {}): {}",
+            path.display(),
+            content[..span.start].matches('\n').count() + 1,
+            e.message().trim()
+        ),
+        None => format!("could not parse {}: {}", path.display(), e.message().trim()),
+    })
+}
+
+/// The normalized name and the version specifier of a PEP 508 requirement such as
+/// `Flask[async] >= 2.0, <3; python_version >= "3.8"`: `("flask", ">=2.0,<3")`. Extras and
+/// environment markers are left out; a requirement by URL gives `"@ <url>"`.
+fn parse_requirement(requirement: &str) -> Option<(String, String)> {
+    let requirement = requirement.split(';').next()?.trim();
+    let end = requirement
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(requirement.len());
+    let (name, rest) = requirement.split_at(end);
+    if name.is_empty() {
+        return None;
+    }
+    let mut rest = rest.trim_start();
+    if rest.starts_with('[') {
+        rest = rest[rest.find(']')? + 1..].trim_start();
+    }
+    let specifier = if let Some(url) = rest.strip_prefix('@') {
+        format!("@ {}", url.trim())
+    } else {
+        let rest = rest.trim_start_matches('(').trim_end_matches(')');
+        let specifier: String = rest.chars().filter(|c| !c.is_whitespace()).collect();
+        if !specifier.is_empty() && !specifier.starts_with(['=', '<', '>', '!', '~']) {
+            return None;
+        }
+        specifier
+    };
+    let specifier = if specifier.is_empty() {
+        "*".to_string()
+    } else {
+        specifier
+    };
+    Some((normalize_python_name(name), specifier))
+}
+
+/// `name` as PEP 503 compares package names: lowercase, with runs of `-`, `_` and `.` as `-`.
+fn normalize_python_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !normalized.ends_with('-') {
+                normalized.push('-');
+            }
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+        }
+    }
+    normalized
+}
+
+/// The version of a `[tool.poetry.dependencies]` entry: the string itself, the `version` of
+/// a table, or where a table without one comes from, as `git+<url>` or `path:<path>`.
+fn poetry_specifier(value: &Value) -> Option<String> {
+    match value {
+        Value::String(version) => Some(version.trim().to_string()),
+        Value::Table(table) => {
+            if let Some(version) = table.get("version") {
+                return version.as_str().map(|version| version.trim().to_string());
+            }
+            let source = ["git", "path", "url"]
+                .iter()
+                .find_map(|key| Some((key, table.get(*key)?.as_str()?)));
+            match source {
+                Some((&"git", url)) => Some(format!("git+{}", url)),
+                Some((kind, location)) => Some(format!("{}:{}", kind, location)),
+                None => Some("*".to_string()),
+            }
+        }
+        // Alternatives by platform or Python version: the first one stands for them.
+        Value::Array(alternatives) => alternatives.first().and_then(poetry_specifier),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder holding `files`, `(name, content)` pairs.
+    fn project(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    /// `dependencies` as `name = specifier` lines, sorted.
+    fn listed(dependencies: Table) -> Vec<String> {
+        dependencies
+            .iter()
+            .map(|(name, specifier)| format!("{} = {}", name, specifier.as_str().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn requirements_txt() {
+        let dir = project(&[(
+            "requirements.txt",
+            "\
+# Pinned for the docs build
+-r base.txt
+--index-url https://pypi.example.com/simple
+Flask[async] >= 2.0, <3 ; python_version >= \"3.8\"
+requests==2.31.0  # HTTP
+Jinja2
+zope.interface (>=5)
+mylib @ https://example.com/mylib-1.0.tar.gz
+./vendor/local-package
+https://example.com/archive.zip
+",
+        )]);
+        let dependencies = python_dependencies(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            listed(dependencies),
+            [
+                "flask = >=2.0,<3",
+                "jinja2 = *",
+                "mylib = @ https://example.com/mylib-1.0.tar.gz",
+                "requests = ==2.31.0",
+                "zope-interface = >=5",
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_pinned_requirements_txt() {
+        let dir = project(&[(
+            "requirements.txt",
+            "\
+#
+# This file is autogenerated by pip-compile with Python 3.11
+#
+certifi==2024.2.2 \\
+    --hash=sha256:0569859f95fc761b18b45ef421b1290a0f65f147e92a1e5eb3e635f9a5e4e66f \\
+    --hash=sha256:dc383c07b76109f368f6106eee2b593b04a011ea4d55f652c6ca24a754d1cdd1
+    # via requests
+requests==2.31.0 \\
+    --hash=sha256:58cd2187c01e70e6e26505bca751777aa9f2ee0b7f4300988b709f44e013003f
+    # via -r requirements.in
+urllib3[socks] >= 2.0 \\
+    ; python_version >= \"3.8\"
+",
+        )]);
+        let dependencies = python_dependencies(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            listed(dependencies),
+            [
+                "certifi = ==2024.2.2",
+                "requests = ==2.31.0",
+                "urllib3 = >=2.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_continued_requirement_is_named_by_its_first_line() {
+        let dir = project(&[("requirements.txt", "requests\nnot a \\\n    requirement\n")]);
+        let error = python_dependencies(dir.path()).unwrap_err();
+        assert!(error.contains("requirements.txt (line 2)"), "{}", error);
+    }
+
+    #[test]
+    fn requirements_txt_names_the_line_it_cannot_read() {
+        let dir = project(&[("requirements.txt", "requests\nnot a requirement\n")]);
+        let error = python_dependencies(dir.path()).unwrap_err();
+        assert!(error.contains("requirements.txt (line 2)"), "{}", error);
+    }
+
+    #[test]
+    fn pyproject_pep_621() {
+        let dir = project(&[
+            (
+                "pyproject.toml",
+                "\
+[project]
+name = \"book-tools\"
+dependencies = [
+    \"numpy>=1.26\",
+    \"Typing_Extensions\",
+    \"pandas[excel] ~= 2.1; sys_platform != 'win32'\",
+]
+",
+            ),
+            // Left alone when pyproject.toml lists dependencies.
+            ("requirements.txt", "django\n"),
+        ]);
+        let dependencies = python_dependencies(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            listed(dependencies),
+            ["numpy = >=1.26", "pandas = ~=2.1", "typing-extensions = *"]
+        );
+    }
+
+    #[test]
+    fn pyproject_poetry() {
+        let dir = project(&[(
+            "pyproject.toml",
+            "\
+[tool.poetry.dependencies]
+python = \"^3.10\"
+requests = \"^2.31\"
+black = { version = \" 24.1 \", optional = true }
+internal = { git = \"https://git.example.com/internal.git\", branch = \"main\" }
+shared = { path = \"../shared\" }
+numpy = [
+    { version = \"<1.25\", python = \"<3.9\" },
+    { version = \">=1.25\", python = \">=3.9\" },
+]
+",
+        )]);
+        let dependencies = python_dependencies(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            listed(dependencies),
+            [
+                "black = 24.1",
+                "internal = git+https://git.example.com/internal.git",
+                "numpy = <1.25",
+                "requests = ^2.31",
+                "shared = path:../shared",
+            ]
+        );
+    }
+
+    #[test]
+    fn pyproject_without_dependencies_falls_back_to_requirements_txt() {
+        let dir = project(&[
+            ("pyproject.toml", "[tool.black]\nline-length = 100\n"),
+            ("requirements.txt", "django>=5\n"),
+        ]);
+        let dependencies = python_dependencies(dir.path()).unwrap().unwrap();
+        assert_eq!(listed(dependencies), ["django = >=5"]);
+    }
+
+    #[test]
+    fn malformed_pyproject_names_the_line() {
+        let dir = project(&[("pyproject.toml", "[project]\ndependencies = [\"numpy\"\n")]);
+        let error = python_dependencies(dir.path()).unwrap_err();
+        assert!(error.starts_with("could not parse"), "{}", error);
+        assert!(error.contains("(line "), "{}", error);
+    }
+
+    #[test]
+    fn package_json() {
+        let dir = project(&[(
+            "package.json",
+            r#"{
+  "name": "book-site",
+  "dependencies": {
+    "react": "^18.2.0",
+    "@types/node": " ~20.1 ",
+    "left-pad": ""
+  },
+  "devDependencies": {
+    "vite": "^5.0.0"
+  }
+}"#,
+        )]);
+        let dependencies = node_dependencies(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            listed(dependencies),
+            ["@types/node = ~20.1", "left-pad = *", "react = ^18.2.0"]
+        );
+    }
+
+    #[test]
+    fn malformed_package_json_is_an_error() {
+        let dir = project(&[("package.json", "{\"dependencies\": {\"react\": 18}}")]);
+        let error = node_dependencies(dir.path()).unwrap_err();
+        assert!(
+            error.contains("dependencies.react must be a string"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn missing_manifests_are_none() {
+        let dir = project(&[]);
+        assert!(python_dependencies(dir.path()).unwrap().is_none());
+        assert!(node_dependencies(dir.path()).unwrap().is_none());
+        assert!(rust_dependencies(dir.path()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::commands::edit::formatter::CodeLanguage;
+use ignore::WalkBuilder;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Folders below the project's root that are looked into.
+const MAX_DEPTH: usize = 8;
+/// Files looked at before the walk stops, so a huge monorepo is still quick.
+const MAX_FILES: usize = 20_000;
+/// Files of a language needed to propose it without a marker file, at least.
+const MIN_FILES: usize = 2;
+/// Share of the source files a language needs to be proposed without a marker file.
+const MIN_SHARE: f64 = 0.05;
+/// Folders of dependencies and build output, skipped even when no `.gitignore` says so.
+const SKIPPED_FOLDERS: &[&str] = &["node_modules", "target", "__pycache__", "vendor", "dist"];
+/// Files that show a project of the language is rooted in their folder.
+const MARKERS: &[(&str, CodeLanguage)] = &[
+    ("Cargo.toml", CodeLanguage::Rust),
+    ("pyproject.toml", CodeLanguage::Python),
+    ("setup.py", CodeLanguage::Python),
+    ("requirements.txt", CodeLanguage::Python),
+    ("package.json", CodeLanguage::JavaScript),
+    ("tsconfig.json", CodeLanguage::TypeScript),
+    ("go.mod", CodeLanguage::Go),
+];
+
+/// A language found in a project.
+#[derive(Debug, Clone)]
+pub struct DetectedLanguage {
+    pub language: CodeLanguage,
+    /// Files with one of its extensions.
+    pub files: usize,
+    /// Its marker files, like `Cargo.toml`, shallowest first.
+    pub markers: Vec<PathBuf>,
+    /// Whether it is proposed: it has a marker file, or enough files.
+    pub proposed: bool,
+}
+
+impl DetectedLanguage {
+    /// The folder of its shallowest marker file, where its versions are pinned.
+    pub fn root(&self) -> Option<&Path> {
+        self.markers.first().and_then(|marker| marker.parent())
+    }
+}
+
+/// The languages of a project, by how many of its files are in them.
+#[derive(Debug, Default)]
+pub struct Detection {
+    /// The languages found, those with the most files first.
+    pub languages: Vec<DetectedLanguage>,
+    /// Whether the walk stopped at `MAX_FILES` before seeing every file.
+    pub truncated: bool,
+}
+
+impl Detection {
+    /// The language `language` as found, if it was.
+    pub fn get(&self, language: CodeLanguage) -> Option<&DetectedLanguage> {
+        self.languages
+            .iter()
+            .find(|found| found.language == language)
+    }
+
+    /// The proposed languages, by their tag.
+    pub fn proposed(&self) -> Vec<String> {
+        self.languages
+            .iter()
+            .filter(|found| found.proposed)
+            .map(|found| found.language.tag().to_string())
+            .collect()
+    }
+}
+
+/// Counts the source files under `root` by language, skipping what `.gitignore` files
+/// and hidden folders leave out, down to `MAX_DEPTH` folders and up to `MAX_FILES` files.
+/// Markdown and JSON are left out, as documentation and data rather than code.
+pub fn detect_languages(root: &Path) -> Detection {
+    let walker = WalkBuilder::new(root)
+        .max_depth(Some(MAX_DEPTH))
+        .require_git(false)
+        .filter_entry(|entry| {
+            !(entry.file_type().is_some_and(|kind| kind.is_dir())
+                && entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| SKIPPED_FOLDERS.contains(&name)))
+        })
+        .build();
+
+    let mut files: BTreeMap<CodeLanguage, usize> = BTreeMap::new();
+    let mut markers: BTreeMap<CodeLanguage, Vec<PathBuf>> = BTreeMap::new();
+    let mut seen = 0;
+    let mut truncated = false;
+    for entry in walker.filter_map(Result::ok) {
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+            continue;
+        }
+        if seen == MAX_FILES {
+            truncated = true;
+            break;
+        }
+        seen += 1;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy();
+        if let Some((_, language)) = MARKERS.iter().find(|(marker, _)| *marker == name) {
+            // A package.json beside a tsconfig.json is the one of a TypeScript project.
+            let typescript =
+                name == "package.json" && path.with_file_name("tsconfig.json").is_file();
+            if !typescript {
+                markers
+                    .entry(*language)
+                    .or_default()
+                    .push(path.to_path_buf());
+            }
+        }
+        let language = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(CodeLanguage::Unknown, CodeLanguage::from_extension);
+        if !matches!(
+            language,
+            CodeLanguage::Unknown | CodeLanguage::Markdown | CodeLanguage::Json
+        ) {
+            *files.entry(language).or_default() += 1;
+        }
+    }
+
+    let total: usize = files.values().sum();
+    let mut languages: Vec<DetectedLanguage> = files
+        .keys()
+        .chain(markers.keys())
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|language| {
+            let count = files.get(&language).copied().unwrap_or(0);
+            let mut found = markers.remove(&language).unwrap_or_default();
+            found.sort_by_key(|marker| marker.components().count());
+            let share = count as f64 / total.max(1) as f64;
+            DetectedLanguage {
+                language,
+                files: count,
+                proposed: !found.is_empty() || (count >= MIN_FILES && share >= MIN_SHARE),
+                markers: found,
+            }
+        })
+        .collect();
+    languages.sort_by(|a, b| b.files.cmp(&a.files).then(a.language.cmp(&b.language)));
+    Detection {
+        languages,
+        truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Writes an empty file at each of `paths` under `root`.
+    fn touch(root: &Path, paths: &[&str]) {
+        for path in paths {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+    }
+
+    fn languages(detection: &Detection) -> Vec<(CodeLanguage, usize, bool)> {
+        detection
+            .languages
+            .iter()
+            .map(|found| (found.language, found.files, found.proposed))
+            .collect()
+    }
+
+    #[test]
+    fn languages_are_counted_by_extension_most_files_first() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(
+            dir.path(),
+            &[
+                "src/main.rs",
+                "src/lib.rs",
+                "src/parse.rs",
+                "scripts/build.py",
+                "scripts/release.py",
+                "README.md",
+                "data/fixture.json",
+                "LICENSE",
+            ],
+        );
+        let detection = detect_languages(dir.path());
+        assert_eq!(
+            languages(&detection),
+            [
+                (CodeLanguage::Rust, 3, true),
+                (CodeLanguage::Python, 2, true)
+            ]
+        );
+        assert!(!detection.truncated);
+        assert_eq!(detection.proposed(), ["rust", "python"]);
+    }
+
+    #[test]
+    fn a_few_stray_files_are_not_proposed() {
+        let dir = tempfile::tempdir().unwrap();
+        let sources: Vec<String> = (0..40).map(|n| format!("src/file{}.py", n)).collect();
+        let mut paths: Vec<&str> = sources.iter().map(String::as_str).collect();
+        // One file is too few, and two are too small a share of 42.
+        paths.extend(["tools/run.sh", "tools/a.go", "tools/b.go"]);
+        touch(dir.path(), &paths);
+        let detection = detect_languages(dir.path());
+        assert_eq!(
+            languages(&detection),
+            [
+                (CodeLanguage::Python, 40, true),
+                (CodeLanguage::Go, 2, false),
+                (CodeLanguage::Shell, 1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_marker_file_proposes_its_language_shallowest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(
+            dir.path(),
+            &[
+                "crates/core/Cargo.toml",
+                "Cargo.toml",
+                "web/package.json",
+                "app/package.json",
+                "app/tsconfig.json",
+                "app/index.ts",
+            ],
+        );
+        let detection = detect_languages(dir.path());
+        let rust = detection.get(CodeLanguage::Rust).unwrap();
+        assert_eq!((rust.files, rust.proposed), (0, true));
+        assert_eq!(
+            rust.markers,
+            [
+                dir.path().join("Cargo.toml"),
+                dir.path().join("crates/core/Cargo.toml")
+            ]
+        );
+        assert_eq!(rust.root(), Some(dir.path()));
+        // The package.json beside a tsconfig.json is TypeScript's, not JavaScript's.
+        let javascript = detection.get(CodeLanguage::JavaScript).unwrap();
+        assert_eq!(javascript.markers, [dir.path().join("web/package.json")]);
+        let typescript = detection.get(CodeLanguage::TypeScript).unwrap();
+        assert_eq!(typescript.markers, [dir.path().join("app/tsconfig.json")]);
+    }
+
+    #[test]
+    fn ignored_and_dependency_folders_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".gitignore"), "generated/\n").unwrap();
+        touch(
+            dir.path(),
+            &[
+                "main.go",
+                "util.go",
+                "generated/a.py",
+                "generated/b.py",
+                "node_modules/left-pad/package.json",
+                "node_modules/left-pad/index.js",
+                "node_modules/left-pad/util.js",
+                "target/debug/build.rs",
+                ".venv/lib/site.py",
+            ],
+        );
+        let detection = detect_languages(dir.path());
+        assert_eq!(languages(&detection), [(CodeLanguage::Go, 2, true)]);
+    }
+}
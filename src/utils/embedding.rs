@@ -0,0 +1,48 @@
+use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use std::io;
+use std::path::Path;
+
+/// Name of the model `LocalEmbedder` runs, as stored with every vector it computes.
+pub const LOCAL_MODEL_NAME: &str = "BAAI/bge-small-en-v1.5";
+
+/// Turns text into vectors whose cosine similarity reflects how related the texts are.
+pub trait Embedder {
+    /// Name stored with each vector, so vectors of different models are never compared.
+    fn model_name(&self) -> &str;
+
+    /// One vector per text, in order.
+    fn embed(&mut self, texts: &[&str]) -> io::Result<Vec<Vec<f32>>>;
+}
+
+/// A small embedding model run locally with ONNX Runtime. It is downloaded from
+/// Hugging Face into `cache_dir` on first use.
+pub struct LocalEmbedder {
+    model: TextEmbedding,
+}
+
+impl LocalEmbedder {
+    pub fn load(cache_dir: &Path) -> io::Result<LocalEmbedder> {
+        let options = InitOptions::new(EmbeddingModel::BGESmallENV15)
+            .with_cache_dir(cache_dir.to_path_buf())
+            .with_show_download_progress(true);
+        let model = TextEmbedding::try_new(options).map_err(|e| {
+            io::Error::other(format!(
+                "could not load the embedding model {}: {}",
+                LOCAL_MODEL_NAME, e
+            ))
+        })?;
+        Ok(LocalEmbedder { model })
+    }
+}
+
+impl Embedder for LocalEmbedder {
+    fn model_name(&self) -> &str {
+        LOCAL_MODEL_NAME
+    }
+
+    fn embed(&mut self, texts: &[&str]) -> io::Result<Vec<Vec<f32>>> {
+        self.model
+            .embed(texts, None)
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
@@ -0,0 +1,154 @@
+use crate::commands::edit::process::{run_with_timeout, ChildOutcome};
+use std::hint::black_box;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long each half of the benchmark runs.
+const BENCHMARK_BUDGET: Duration = Duration::from_millis(150);
+/// Memory read by the bandwidth half of the benchmark, well above the caches of a desktop CPU.
+const BANDWIDTH_BYTES: usize = 128 << 20;
+/// Side of the matrices multiplied by the compute half of the benchmark.
+const MATRIX_SIDE: usize = 64;
+/// How long `nvidia-smi` may take to list the GPUs.
+const GPU_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A graphics card found on the machine.
+#[derive(Debug, Clone)]
+pub struct Gpu {
+    pub name: String,
+    /// Its memory in GB; on Apple silicon, the memory it shares with the CPU.
+    pub memory_gb: f64,
+}
+
+/// The largest NVIDIA GPU `nvidia-smi` lists, else the GPU of an Apple silicon Mac, whose
+/// memory is that of the machine, `total_memory_gb`. `None` when neither is found.
+pub fn detect_gpu(total_memory_gb: f64) -> Option<Gpu> {
+    let mut command = Command::new("nvidia-smi");
+    command
+        .args([
+            "--query-gpu=name,memory.total",
+            "--format=csv,noheader,nounits",
+        ])
+        .stderr(Stdio::null());
+    if let Ok(ChildOutcome::Exited(status, output)) =
+        run_with_timeout(&mut command, GPU_PROBE_TIMEOUT, Some(""))
+    {
+        let largest = String::from_utf8_lossy(&output)
+            .lines()
+            .filter(|_| status.success())
+            .filter_map(|line| {
+                let (name, memory_mib) = line.rsplit_once(',')?;
+                let memory_mib: f64 = memory_mib.trim().parse().ok()?;
+                Some(Gpu {
+                    name: name.trim().to_string(),
+                    memory_gb: memory_mib / 1024.0,
+                })
+            })
+            .max_by(|a, b| a.memory_gb.total_cmp(&b.memory_gb));
+        if largest.is_some() {
+            return largest;
+        }
+    }
+    if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        return Some(Gpu {
+            name: "Apple silicon GPU (unified memory)".to_string(),
+            memory_gb: total_memory_gb,
+        });
+    }
+    None
+}
+
+/// What the machine's CPU cores do in a few hundred milliseconds, all of them at once.
+#[derive(Debug, Clone, Copy)]
+pub struct Benchmark {
+    /// Billions of floating-point operations a second, multiplying small matrices.
+    pub gflops: f64,
+    /// GB read from memory a second.
+    pub bandwidth_gbs: f64,
+}
+
+impl Benchmark {
+    /// Measures the CPU's speed and memory bandwidth, in about 300 ms plus the time to
+    /// allocate `BANDWIDTH_BYTES`.
+    pub fn run() -> Benchmark {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Benchmark {
+            gflops: measure_gflops(threads),
+            bandwidth_gbs: measure_bandwidth(threads),
+        }
+    }
+
+    /// Roughly how many tokens a second a model of `parameters_b` billion parameters,
+    /// whose weights take `weights_gb`, generates on the CPU. Each token reads every weight
+    /// and takes two operations per parameter; half of the measured speed is reached, at
+    /// best, by the inference code.
+    pub fn tokens_per_second(&self, parameters_b: f64, weights_gb: f64) -> f64 {
+        const EFFICIENCY: f64 = 0.5;
+        let by_memory = self.bandwidth_gbs / weights_gb;
+        let by_compute = self.gflops / (2.0 * parameters_b);
+        by_memory.min(by_compute) * EFFICIENCY
+    }
+}
+
+/// The floating-point operations a second of `threads` threads multiplying matrices.
+fn measure_gflops(threads: usize) -> f64 {
+    let per_second: f64 = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let n = MATRIX_SIDE;
+                    let a: Vec<f32> = (0..n * n).map(|i| (i % 7) as f32 * 0.5).collect();
+                    let b: Vec<f32> = (0..n * n).map(|i| (i % 5) as f32 * 0.25).collect();
+                    let mut c = vec![0f32; n * n];
+                    let mut rounds = 0;
+                    let start = Instant::now();
+                    while start.elapsed() < BENCHMARK_BUDGET {
+                        for i in 0..n {
+                            for k in 0..n {
+                                let a_ik = a[i * n + k];
+                                let row = &b[k * n..(k + 1) * n];
+                                for (c_ij, b_kj) in c[i * n..(i + 1) * n].iter_mut().zip(row) {
+                                    *c_ij += a_ik * b_kj;
+                                }
+                            }
+                        }
+                        black_box(&mut c);
+                        rounds += 1;
+                    }
+                    (rounds * 2 * n * n * n) as f64 / start.elapsed().as_secs_f64()
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap_or(0.0)).sum()
+    });
+    per_second / 1e9
+}
+
+/// The bytes a second `threads` threads read from memory, summing buffers larger than the
+/// caches.
+fn measure_bandwidth(threads: usize) -> f64 {
+    let words = BANDWIDTH_BYTES / threads / std::mem::size_of::<u64>();
+    let per_second: f64 = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(move || {
+                    let buffer: Vec<u64> = (0..words as u64).collect();
+                    let mut read = 0;
+                    let start = Instant::now();
+                    while start.elapsed() < BENCHMARK_BUDGET {
+                        black_box(
+                            buffer
+                                .iter()
+                                .fold(0u64, |sum, word| sum.wrapping_add(*word)),
+                        );
+                        read += words * std::mem::size_of::<u64>();
+                    }
+                    read as f64 / start.elapsed().as_secs_f64()
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap_or(0.0)).sum()
+    });
+    per_second / 1e9
+}
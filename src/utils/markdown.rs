@@ -0,0 +1,78 @@
+use comrak::plugins::syntect::SyntectAdapter;
+use comrak::{markdown_to_html_with_plugins, Options, Plugins};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use syntect::highlighting::ThemeSet;
+
+/// Theme code blocks are highlighted with unless `[render] theme` names another, as
+/// `lila show` highlights on a terminal.
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+/// How Markdown becomes HTML.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions {
+    /// Render as a chapter of the book: headings get ids to link to, and footnotes are
+    /// collected at the end.
+    pub book: bool,
+    /// Leave out raw HTML and `javascript:` links instead of passing them through.
+    pub sanitize: bool,
+    /// Theme code blocks are highlighted with, one of `highlight_themes`.
+    pub theme: &'static str,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            book: false,
+            sanitize: false,
+            theme: DEFAULT_THEME,
+        }
+    }
+}
+
+/// The themes code blocks can be highlighted with: syntect's default ones.
+fn theme_set() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// The names of the themes code blocks can be highlighted with, sorted.
+pub fn highlight_themes() -> impl Iterator<Item = &'static str> {
+    theme_set().themes.keys().map(String::as_str)
+}
+
+/// The theme named `name`, if code blocks can be highlighted with it.
+pub fn highlight_theme(name: &str) -> Option<&'static str> {
+    highlight_themes().find(|theme| *theme == name)
+}
+
+/// The HTML body (not a full document) of `markdown`: GitHub flavoured, without its YAML
+/// front matter, with fenced code highlighted by syntect. Reads no files; highlighting
+/// large code blocks is slow, so call it off the async threads.
+pub fn markdown_to_html(markdown: &str, render: RenderOptions) -> String {
+    // An adapter loads every syntax and theme, so there is one per theme, made once.
+    static HIGHLIGHTERS: OnceLock<Mutex<HashMap<&str, Arc<SyntectAdapter>>>> = OnceLock::new();
+    let highlighter = HIGHLIGHTERS
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(render.theme)
+        .or_insert_with(|| Arc::new(SyntectAdapter::new(Some(render.theme))))
+        .clone();
+
+    let mut options = Options::default();
+    options.extension.strikethrough = true;
+    options.extension.table = true;
+    options.extension.autolink = true;
+    options.extension.tasklist = true;
+    options.extension.front_matter_delimiter = Some("---".to_string());
+    if render.book {
+        options.extension.header_ids = Some(String::new());
+        options.extension.footnotes = true;
+    }
+    options.render.unsafe_ = !render.sanitize;
+
+    let mut plugins = Plugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(highlighter.as_ref());
+    markdown_to_html_with_plugins(markdown, &options, &plugins)
+}
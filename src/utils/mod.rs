@@ -1,2 +1,11 @@
+pub mod backup;
+pub mod config;
 pub mod database;
+pub mod dependencies;
+pub mod detect;
+pub mod embedding;
+pub mod hardware;
+pub mod markdown;
+pub mod progress;
+pub mod tokens;
 pub mod utils;
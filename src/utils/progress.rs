@@ -0,0 +1,120 @@
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal};
+use std::time::{Duration, Instant};
+
+/// How often progress is printed when there is no progress bar.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What saving did with one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveOutcome {
+    Inserted,
+    Updated,
+    Unchanged,
+}
+
+/// Progress of a save: a bar with the current file and counters on a terminal, else a
+/// line of text every `PLAIN_INTERVAL`.
+pub struct SaveProgress {
+    bar: Option<ProgressBar>,
+    total: usize,
+    processed: usize,
+    inserted: usize,
+    updated: usize,
+    unchanged: usize,
+    skipped: usize,
+    last_printed: Instant,
+}
+
+impl SaveProgress {
+    /// Progress of saving `total` files. The bar is only drawn when stdout is a terminal
+    /// and `quiet` is not set.
+    pub fn new(total: usize, quiet: bool) -> SaveProgress {
+        let bar = (!quiet && io::stdout().is_terminal()).then(|| {
+            let bar =
+                ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stdout());
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{bar:30.cyan/blue} {pos}/{len} [{elapsed_precise}, ETA {eta}] {msg}",
+                )
+                .expect("valid progress template")
+                .progress_chars("=> "),
+            );
+            bar.enable_steady_tick(Duration::from_millis(200));
+            bar
+        });
+        SaveProgress {
+            bar,
+            total,
+            processed: 0,
+            inserted: 0,
+            updated: 0,
+            unchanged: 0,
+            skipped: 0,
+            last_printed: Instant::now(),
+        }
+    }
+
+    /// Counts `path` as saved with `outcome`.
+    pub fn saved(&mut self, path: &str, outcome: SaveOutcome) {
+        match outcome {
+            SaveOutcome::Inserted => self.inserted += 1,
+            SaveOutcome::Updated => self.updated += 1,
+            SaveOutcome::Unchanged => self.unchanged += 1,
+        }
+        self.advance(path);
+    }
+
+    /// Counts `path` as skipped, printing `reason` as a warning.
+    pub fn skipped(&mut self, path: &str, reason: &str) {
+        self.warn(&format!("skipping {}: {}", path, reason));
+        self.skipped += 1;
+        self.advance(path);
+    }
+
+    /// Prints a warning above the bar, or to stderr when there is none.
+    pub fn warn(&self, message: &str) {
+        let line = format!("{} {}", "Warning:".yellow(), message);
+        match &self.bar {
+            Some(bar) => bar.println(line),
+            None => eprintln!("{}", line),
+        }
+    }
+
+    /// Removes the bar, so the summary is printed in its place.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn advance(&mut self, path: &str) {
+        self.processed += 1;
+        let counters = format!(
+            "{} inserted, {} updated, {} unchanged",
+            self.inserted, self.updated, self.unchanged
+        );
+        match &self.bar {
+            Some(bar) => {
+                bar.inc(1);
+                bar.set_message(format!("{} | {}", counters, path));
+            }
+            None if self.last_printed.elapsed() >= PLAIN_INTERVAL => {
+                println!(
+                    "Saving: {}/{} files ({})",
+                    self.processed, self.total, counters
+                );
+                self.last_printed = Instant::now();
+            }
+            None => {}
+        }
+    }
+}
+
+impl Drop for SaveProgress {
+    /// Clears the bar when a save stops on an error, so the error is not printed after it.
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+/// Roughly how many tokens `text` is: about four bytes each for English text and code.
+pub fn estimated_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Counts tokens with the tokenizer of a model, or estimates them without one.
+pub struct TokenCounter {
+    tokenizer: Option<Tokenizer>,
+}
+
+impl TokenCounter {
+    /// A counter estimating tokens from the length of the text.
+    pub fn estimate() -> TokenCounter {
+        TokenCounter { tokenizer: None }
+    }
+
+    /// A counter using the tokenizer in `tokenizer_json`, a Hugging Face `tokenizer.json`.
+    pub fn load(tokenizer_json: &Path) -> Result<TokenCounter, String> {
+        let mut tokenizer = Tokenizer::from_file(tokenizer_json)
+            .map_err(|e| format!("could not load {}: {}", tokenizer_json.display(), e))?;
+        // Count every token, however long the text, rather than as many as the model reads.
+        tokenizer
+            .with_truncation(None)
+            .map_err(|e| format!("could not load {}: {}", tokenizer_json.display(), e))?;
+        Ok(TokenCounter {
+            tokenizer: Some(tokenizer),
+        })
+    }
+
+    /// Whether tokens are counted with a tokenizer rather than estimated.
+    pub fn exact(&self) -> bool {
+        self.tokenizer.is_some()
+    }
+
+    /// Tokens of `text`, without the special tokens around a message.
+    pub fn count(&self, text: &str) -> usize {
+        let Some(tokenizer) = &self.tokenizer else {
+            return estimated_tokens(text);
+        };
+        match tokenizer.encode(text, false) {
+            Ok(encoding) => encoding.len(),
+            Err(_) => estimated_tokens(text),
+        }
+    }
+}
@@ -1,8 +1,21 @@
+use crate::commands::init::OUTPUT_PATH_VAR;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use walkdir::WalkDir;
 
+/// Checks if a given command is available on the user's system
+/// by attempting `command --version` (or another trivial arg).
+pub fn check_program_availability(program: &str) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .is_ok()
+}
+
 pub fn process_protocol_aimm(app_folder: &Path) -> io::Result<()> {
     let mut folders_to_process = Vec::new();
 
@@ -76,3 +89,101 @@ pub fn combine_folders(folders: &[PathBuf], dest_folder: &PathBuf) -> io::Result
     }
     Ok(())
 }
+
+/// Name of the project lila works on: the name of the current directory.
+pub fn current_project_name() -> String {
+    let current_dir = std::env::current_dir().expect("Failed to get current directory");
+    current_dir
+        .file_name()
+        .unwrap_or_else(|| std::ffi::OsStr::new("default"))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Folder lila keeps the shared database and each project's folder in: `~/.lila`.
+pub fn lila_home() -> PathBuf {
+    let home = dirs::home_dir().expect("Could not determine the home directory");
+    home.join(".lila")
+}
+
+/// Folder lila keeps the output of the current project in: `~/.lila/<project>`.
+pub fn default_root() -> PathBuf {
+    lila_home().join(current_project_name())
+}
+
+/// The database `--db` names, else `lila.db` in the `LILA_OUTPUT_PATH` folder, else
+/// `~/.lila/lila.db`, which all projects share.
+pub fn db_path(db: Option<&str>) -> PathBuf {
+    let output_path = std::env::var_os(OUTPUT_PATH_VAR).map(PathBuf::from);
+    resolve_db_path(db, output_path.as_deref(), &lila_home())
+}
+
+/// `db_path` with `LILA_OUTPUT_PATH` given as `output_path`, empty when unset, and `~/.lila`
+/// as `lila_home`.
+fn resolve_db_path(db: Option<&str>, output_path: Option<&Path>, lila_home: &Path) -> PathBuf {
+    match (db, output_path) {
+        (Some(db), _) => PathBuf::from(db),
+        (None, Some(folder)) if !folder.as_os_str().is_empty() => folder.join("lila.db"),
+        (None, _) => lila_home.join("lila.db"),
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC `YYYY-MM-DD HH:MM` string.
+pub fn format_timestamp(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let time = secs.rem_euclid(86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn db_flag_comes_first() {
+        let path = resolve_db_path(
+            Some("/srv/book.db"),
+            Some(Path::new("/srv/output")),
+            Path::new("/home/ada/.lila"),
+        );
+        assert_eq!(path, PathBuf::from("/srv/book.db"));
+    }
+
+    #[test]
+    fn output_path_comes_next() {
+        let path = resolve_db_path(
+            None,
+            Some(Path::new("/srv/output")),
+            Path::new("/home/ada/.lila"),
+        );
+        assert_eq!(path, PathBuf::from("/srv/output/lila.db"));
+    }
+
+    #[test]
+    fn shared_database_is_the_default() {
+        let home = Path::new("/home/ada/.lila");
+        let expected = PathBuf::from("/home/ada/.lila/lila.db");
+        assert_eq!(resolve_db_path(None, None, home), expected);
+        // An empty LILA_OUTPUT_PATH counts as unset.
+        assert_eq!(resolve_db_path(None, Some(Path::new("")), home), expected);
+    }
+}